@@ -0,0 +1,312 @@
+// This module tracks how accurate the forecasts are once the actual
+// values arrive. Forecasts requested with a `series` id are recorded,
+// and `POST /actuals` later matches observed values of that series
+// against them by timestamp. The rolling MAE and MAPE of each series
+// are served as Prometheus metrics under `/metrics` and as JSON under
+// `/accuracy`.
+// The recorded forecasts and errors are kept in the state directory
+// (see state.rs).
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use wasi::http::types::ErrorCode;
+use wasi_nn_demo_lib::interface;
+
+use crate::{
+    error::Error,
+    fit::Fit,
+    forecast_timestamps,
+    measures::{mae, mape},
+    model::{self, ModelSpec},
+    numeric_data_points, state, HttpHandler,
+};
+
+const STATE_FILE: &str = "accuracy.json";
+// The errors are computed over this many of the most recent values
+// matched for a series
+const ROLLING_WINDOW: usize = 256;
+
+#[derive(Default, Serialize, Deserialize)]
+struct State {
+    series: BTreeMap<String, SeriesState>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct SeriesState {
+    // Forecast values that have no actual value yet, by timestamp
+    pending: BTreeMap<DateTime<Utc>, f32>,
+    // The most recent pairs of forecast and actual value
+    matched: VecDeque<(f32, f32)>,
+    // The most recent actual values with the forecast for the same
+    // timestamp, if there was one. This is only kept for plotting (see
+    // grafana.rs), older state files do not have it.
+    #[serde(default)]
+    history: BTreeMap<DateTime<Utc>, Observation>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Observation {
+    actual: f32,
+    forecast: Option<f32>,
+}
+
+// The recorded values of a series, by timestamp
+#[cfg(feature = "grafana")]
+pub struct RecordedValues {
+    pub actuals: BTreeMap<DateTime<Utc>, f32>,
+    // Forecasts that were matched against actual values and those that
+    // are still pending
+    pub forecasts: BTreeMap<DateTime<Utc>, f32>,
+}
+
+#[derive(Serialize)]
+pub struct SeriesAccuracy {
+    // Both errors are not defined before any values were matched
+    mae: Option<f32>,
+    mape: Option<f32>,
+    samples: usize,
+}
+
+#[derive(Serialize)]
+pub struct ActualsReport {
+    // The number of actual values that matched a recorded forecast
+    matched: usize,
+    accuracy: SeriesAccuracy,
+}
+
+impl HttpHandler {
+    // Forecasts like `forecast_steps` and records the forecast values of the
+    // series. The timestamps of the forecast values continue the time
+    // step of the window (see `forecast_timestamps`).
+    pub fn forecast_and_record(
+        &mut self,
+        model: Option<&ModelSpec>,
+        fit: Option<&Fit>,
+        series: &str,
+        input: interface::DataWindow,
+        horizon: u32,
+        emit: impl FnMut(&[f32]) -> Result<(), ErrorCode>,
+    ) -> Result<Vec<f32>, Error> {
+        let timestamps = forecast_timestamps(&input, horizon).ok_or_else(|| {
+            Error::BadRequest(
+                "Recording a forecast requires data points with increasing timestamps".into(),
+            )
+        })?;
+        let values = self.forecast_steps(model, fit, Some(series), input, horizon, emit)?;
+
+        // At most this many forecast values are kept per series while
+        // waiting for their actual values
+        let max_pending = 2 * model::shape()?.max_horizon() as usize;
+        let mut state = State::load()?;
+        let pending = &mut state.series.entry(series.to_string()).or_default().pending;
+        pending.extend(timestamps.into_iter().zip(values.iter().copied()));
+        while pending.len() > max_pending {
+            pending.pop_first();
+        }
+        state.save()?;
+
+        Ok(values)
+    }
+
+    // Matches the actual values in the window against the recorded
+    // forecasts of the series
+    pub fn record_actuals(
+        &mut self,
+        series: &str,
+        input: interface::DataWindow,
+    ) -> Result<ActualsReport, Error> {
+        let mut state = State::load()?;
+        let series_state = state
+            .series
+            .get_mut(series)
+            .ok_or_else(|| Error::BadRequest(format!("No forecasts recorded for {series}")))?;
+
+        let mut matched = 0;
+        let mut latest = None;
+        for (data_point, actual) in numeric_data_points(&input) {
+            let Some(timestamp) = data_point.timestamp else {
+                continue;
+            };
+            let forecast = series_state.pending.remove(&timestamp);
+            if let Some(forecast) = forecast {
+                series_state.matched.push_back((forecast, actual));
+                matched += 1;
+            }
+            series_state
+                .history
+                .insert(timestamp, Observation { actual, forecast });
+            latest = latest.max(Some(timestamp));
+        }
+
+        // Forecasts for timestamps before the latest actual value will
+        // not be matched anymore
+        if let Some(latest) = latest {
+            series_state.pending = series_state.pending.split_off(&latest);
+        }
+        while series_state.matched.len() > ROLLING_WINDOW {
+            series_state.matched.pop_front();
+        }
+        while series_state.history.len() > ROLLING_WINDOW {
+            series_state.history.pop_first();
+        }
+
+        let accuracy = series_state.accuracy();
+        state.save()?;
+        Ok(ActualsReport { matched, accuracy })
+    }
+
+    // The accuracy of all series, by series id
+    pub fn accuracy(&mut self) -> Result<BTreeMap<String, SeriesAccuracy>, Error> {
+        Ok(State::load()?
+            .series
+            .iter()
+            .map(|(series, state)| (series.clone(), state.accuracy()))
+            .collect())
+    }
+
+    // The recorded values of all series, by series id
+    #[cfg(feature = "grafana")]
+    pub fn recorded_values(&mut self) -> Result<BTreeMap<String, RecordedValues>, Error> {
+        Ok(State::load()?
+            .series
+            .into_iter()
+            .map(|(series, state)| {
+                let actuals = state
+                    .history
+                    .iter()
+                    .map(|(&timestamp, observation)| (timestamp, observation.actual))
+                    .collect();
+                let forecasts = state
+                    .history
+                    .iter()
+                    .filter_map(|(&timestamp, observation)| {
+                        Some((timestamp, observation.forecast?))
+                    })
+                    .chain(state.pending)
+                    .collect();
+                (series, RecordedValues { actuals, forecasts })
+            })
+            .collect())
+    }
+}
+
+// Deletes the forecasts and actual values recorded for timestamps
+// before the cutoff, and the series that have none left (see
+// retention.rs)
+#[cfg(feature = "retention")]
+pub fn expire(cutoff: DateTime<Utc>) -> Result<(), Error> {
+    let mut state = State::load()?;
+    for series_state in state.series.values_mut() {
+        let history = series_state.history.split_off(&cutoff);
+        let expired = std::mem::replace(&mut series_state.history, history);
+        series_state.pending = series_state.pending.split_off(&cutoff);
+        // The matched pairs have no timestamps, but the recent ones
+        // were added in the same order as the observations with a
+        // forecast. If observations expired, every pair before those
+        // of the remaining observations is at least as old.
+        if !expired.is_empty() {
+            let remaining = series_state
+                .history
+                .values()
+                .filter(|observation| observation.forecast.is_some())
+                .count();
+            let excess = series_state.matched.len().saturating_sub(remaining);
+            series_state.matched.drain(..excess);
+        }
+    }
+    state.series.retain(|_, series_state| {
+        !(series_state.pending.is_empty()
+            && series_state.matched.is_empty()
+            && series_state.history.is_empty())
+    });
+    state.save()
+}
+
+impl State {
+    fn load() -> Result<Self, Error> {
+        state::load(STATE_FILE)
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        state::save(STATE_FILE, self)
+    }
+}
+
+impl SeriesState {
+    fn accuracy(&self) -> SeriesAccuracy {
+        let (forecast, actual): (Vec<_>, Vec<_>) = self.matched.iter().copied().unzip();
+        SeriesAccuracy {
+            mae: (!actual.is_empty()).then(|| mae(&forecast, &actual)),
+            mape: mape(&forecast, &actual),
+            samples: actual.len(),
+        }
+    }
+}
+
+pub fn actuals_report_to_vec(report: &ActualsReport) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(report)
+        .map_err(|e| Error::internal(format!("Error serializing actuals report: {e}")))
+}
+
+pub fn accuracy_to_vec(accuracy: &BTreeMap<String, SeriesAccuracy>) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(accuracy)
+        .map_err(|e| Error::internal(format!("Error serializing accuracy: {e}")))
+}
+
+// The accuracy in the Prometheus text exposition format
+pub fn accuracy_to_prometheus(accuracy: &BTreeMap<String, SeriesAccuracy>) -> String {
+    let mut out = String::new();
+    write_metric(
+        &mut out,
+        "forecast_mae",
+        "Rolling mean absolute error of the forecast",
+        accuracy,
+        |a| a.mae,
+    );
+    write_metric(
+        &mut out,
+        "forecast_mape",
+        "Rolling mean absolute percentage error of the forecast",
+        accuracy,
+        |a| a.mape,
+    );
+    write_metric(
+        &mut out,
+        "forecast_matched_values",
+        "Number of actual values the rolling errors are computed from",
+        accuracy,
+        |a| Some(a.samples as f32),
+    );
+    out
+}
+
+// Writes one gauge with a sample for each series that has a value
+fn write_metric(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    accuracy: &BTreeMap<String, SeriesAccuracy>,
+    value: impl Fn(&SeriesAccuracy) -> Option<f32>,
+) {
+    // Writing to a String cannot fail
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge");
+    for (series, accuracy) in accuracy {
+        if let Some(value) = value(accuracy) {
+            let _ = writeln!(out, "{name}{{series=\"{}\"}} {value}", escape_label(series));
+        }
+    }
+}
+
+// Label values are quoted, so backslashes, quotes and line breaks must
+// be escaped
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
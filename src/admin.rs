@@ -0,0 +1,422 @@
+// This module lists the models of the component with `GET
+// /admin/models`, so that devices in the field can be debugged without
+// logging in to them. The list contains the forecasting model (see
+// model.rs), the models of the routing rules (see routing.rs) and of
+// the registry (see registry.rs), and every other model that was
+// loaded, together with the outcome of the last attempt to load it and
+// how it has been used since.
+//
+// A bad model can be pulled from service with `POST
+// /admin/models/{id}/disable` (and put back with `.../enable`), without
+// removing its files. The id is the name of its (first) file without
+// the extension. Loading a disabled model fails, so that requests for
+// it are answered with 503, or fall back to the default model (for the
+// routing rules) or the statistical forecast (see fallback.rs). The
+// same goes for models that used up their inference time (see
+// limits.rs).
+//
+// The size of the files and the inference time of each model are also
+// exported as Prometheus metrics with `GET /metrics`.
+//
+// Since the component does not keep state across requests, the usage
+// of the models is recorded in the state directory (see state.rs).
+// Recording it must not make inferences fail, so errors are only
+// logged.
+
+use std::{collections::BTreeMap, fmt::Write, fs, path::Path, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use wasi::http::types::ErrorCode;
+
+use crate::{
+    clock,
+    error::Error,
+    model::{self, ModelSpec},
+    nn::Graph,
+    state, MODELS,
+};
+
+const STATE_FILE: &str = "models.json";
+// Errors of disabled models and models that used up their inference
+// time start with these
+const DISABLED: &str = "Disabled model";
+#[cfg_attr(not(feature = "model-limits"), allow(dead_code))]
+const EXHAUSTED: &str = "Exhausted budget of model";
+// Clients are asked to try again after this many seconds, since there
+// is no telling when a model is enabled again (and inference time is
+// limited per minute)
+const RETRY_AFTER: u64 = 60;
+
+// The usage of a model, by the files it is loaded from
+#[derive(Default, Serialize, Deserialize)]
+struct Usage {
+    loaded_at: Option<DateTime<Utc>>,
+    load_latency_ms: Option<f64>,
+    // The error of the last attempt to load the model, if it failed
+    load_error: Option<String>,
+    last_used: Option<DateTime<Utc>>,
+    inferences: u64,
+    total_latency_ms: f64,
+    #[serde(default)]
+    disabled: bool,
+    // The size of the files, which stands in for the memory the model
+    // takes
+    size_bytes: Option<u64>,
+    // The inference time since the start of the current window (see
+    // limits.rs)
+    window_start: Option<DateTime<Utc>>,
+    #[serde(default)]
+    window_latency_ms: f64,
+}
+
+#[derive(Serialize)]
+pub struct ModelStatus {
+    id: String,
+    files: Vec<String>,
+    // The name the model is registered under by the runtime (see
+    // named_models.rs)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    // The tensors are only known for forecasting models
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input_tensor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output_tensor: Option<String>,
+    // "loaded", "failed" or "not loaded"
+    status: &'static str,
+    disabled: bool,
+    // Whether this instance of the component holds the model in memory
+    // (see `MODELS` in lib.rs)
+    cached: bool,
+    loaded_at: Option<DateTime<Utc>>,
+    load_latency_ms: Option<f64>,
+    load_error: Option<String>,
+    last_used: Option<DateTime<Utc>>,
+    size_bytes: Option<u64>,
+    inferences: u64,
+    total_latency_ms: f64,
+    average_latency_ms: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct ModelList {
+    models: Vec<ModelStatus>,
+}
+
+pub fn models() -> Result<ModelList, Error> {
+    // The tensors are known for the forecasting models
+    let default = model::spec()?;
+    #[cfg(feature = "routing")]
+    let routed = crate::routing::models()?
+        .into_iter()
+        .map(|model| ModelSpec {
+            files: vec![model],
+            input_tensor: default.input_tensor.clone(),
+            output_tensor: default.output_tensor.clone(),
+            variance_tensor: default.variance_tensor.clone(),
+            dynamic_batch: default.dynamic_batch,
+            shape: default.shape,
+        })
+        .collect::<Vec<_>>();
+    #[cfg(not(feature = "routing"))]
+    let routed = Vec::new();
+    #[cfg(feature = "registry")]
+    let registered = crate::registry::models()?;
+    #[cfg(not(feature = "registry"))]
+    let registered = Vec::new();
+    let forecasting: Vec<ModelSpec> = [default]
+        .into_iter()
+        .chain(registered)
+        .chain(routed)
+        .collect();
+
+    let mut usage: BTreeMap<String, Usage> = state::load(STATE_FILE)?;
+    let cached: Vec<Vec<String>> = MODELS
+        .lock()
+        .map_err(|e| Error::internal(format!("Error locking models: {e}")))?
+        .keys()
+        .cloned()
+        .collect();
+
+    let mut all: Vec<Vec<String>> = Vec::new();
+    let recorded = usage
+        .keys()
+        .map(|key| key.split(',').map(str::to_string).collect());
+    for files in forecasting
+        .iter()
+        .map(|spec| spec.files.clone())
+        .chain(recorded)
+    {
+        if !all.contains(&files) {
+            all.push(files);
+        }
+    }
+
+    let models = all
+        .into_iter()
+        .map(|files| {
+            let spec = forecasting.iter().find(|spec| spec.files == files);
+            let cached = cached.contains(&files);
+            #[cfg(feature = "named-models")]
+            let name = crate::named_models::name(&files)?;
+            #[cfg(not(feature = "named-models"))]
+            let name = None;
+
+            let usage = usage.remove(&files.join(",")).unwrap_or_default();
+            let status = match (&usage.load_error, usage.loaded_at) {
+                (Some(_), _) => "failed",
+                (None, Some(_)) => "loaded",
+                (None, None) => "not loaded",
+            };
+
+            Ok(ModelStatus {
+                id: id(&files),
+                files,
+                name,
+                input_tensor: spec.map(|spec| spec.input_tensor.clone()),
+                output_tensor: spec.map(|spec| spec.output_tensor.clone()),
+                status,
+                disabled: usage.disabled,
+                cached,
+                loaded_at: usage.loaded_at,
+                load_latency_ms: usage.load_latency_ms,
+                load_error: usage.load_error,
+                last_used: usage.last_used,
+                size_bytes: usage.size_bytes,
+                inferences: usage.inferences,
+                total_latency_ms: usage.total_latency_ms,
+                average_latency_ms: (usage.inferences > 0)
+                    .then(|| usage.total_latency_ms / usage.inferences as f64),
+            })
+        })
+        .collect::<Result<_, ErrorCode>>()?;
+
+    Ok(ModelList { models })
+}
+
+pub fn models_to_vec(models: &ModelList) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(models)
+        .map_err(|e| Error::internal(format!("Error serializing models: {e}")))
+}
+
+// Handles `POST /admin/models/{id}/disable` and `.../enable` and
+// returns the new status of the model
+pub fn switch(path: &str) -> Result<ModelStatus, Error> {
+    let (id, disabled) = match path
+        .strip_prefix("/admin/models/")
+        .and_then(|rest| rest.rsplit_once('/'))
+    {
+        Some((id, "disable")) => (id, true),
+        Some((id, "enable")) => (id, false),
+        _ => return Err(Error::BadRequest(format!("Unknown admin action {path}"))),
+    };
+    let find = |models: ModelList| models.models.into_iter().find(|model| model.id == id);
+    let model = find(models()?).ok_or_else(|| Error::BadRequest(format!("Unknown model {id}")))?;
+
+    // Under the lock of the state, so that the usage recorded by
+    // concurrent requests does not revert the switch
+    state::update(STATE_FILE, |usage: &mut BTreeMap<String, Usage>| {
+        usage.entry(model.files.join(",")).or_default().disabled = disabled;
+        Ok(())
+    })?;
+    eprintln!(
+        "Model {id} {}",
+        if disabled { "disabled" } else { "enabled" }
+    );
+
+    find(models()?).ok_or_else(|| Error::internal(format!("Model {id} disappeared")))
+}
+
+pub fn status_to_vec(status: &ModelStatus) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(status)
+        .map_err(|e| Error::internal(format!("Error serializing model status: {e}")))
+}
+
+// Fails if the model with the files is disabled, or exceeds its limits
+// (see limits.rs). If the state cannot be read, the model is assumed to
+// be enabled.
+pub fn check(files: &[String]) -> Result<(), ErrorCode> {
+    let usage = state::load::<BTreeMap<String, Usage>>(STATE_FILE)
+        .map(|mut usage| usage.remove(&files.join(",")).unwrap_or_default())
+        .unwrap_or_else(|e| {
+            eprintln!(
+                "Error checking whether model {} is disabled: {e}",
+                id(files)
+            );
+            Usage::default()
+        });
+    if usage.disabled {
+        return Err(ErrorCode::InternalError(Some(format!(
+            "{DISABLED} {}",
+            id(files)
+        ))));
+    }
+
+    #[cfg(feature = "model-limits")]
+    {
+        crate::limits::check_size(&id(files), size(files))?;
+        let exceeded = crate::limits::time_exceeded(
+            &id(files),
+            usage.window_start,
+            usage.window_latency_ms,
+            clock::now(),
+        )?;
+        if let Some(limit) = exceeded {
+            return Err(ErrorCode::InternalError(Some(format!(
+                "{EXHAUSTED} {}: {limit}",
+                id(files)
+            ))));
+        }
+    }
+    Ok(())
+}
+
+// Whether the error is due to a disabled model or one that used up its
+// inference time, which are both temporary
+pub fn is_unavailable_error(error: &ErrorCode) -> bool {
+    matches!(error, ErrorCode::InternalError(Some(message))
+        if message.starts_with(DISABLED) || message.starts_with(EXHAUSTED))
+}
+
+// Turns the errors of unavailable models into 503 responses
+pub fn unavailable(error: Error) -> Error {
+    match error {
+        Error::Internal(code) if is_unavailable_error(&code) => Error::Unavailable {
+            message: Error::Internal(code).to_string(),
+            retry_after: RETRY_AFTER,
+        },
+        error => error,
+    }
+}
+
+// The usage of the models as Prometheus metrics
+pub fn models_to_prometheus() -> String {
+    let usage: BTreeMap<String, Usage> = state::load(STATE_FILE).unwrap_or_else(|e| {
+        eprintln!("Error reading the usage of the models: {e}");
+        BTreeMap::new()
+    });
+    let ids: Vec<_> = usage
+        .iter()
+        .map(|(key, usage)| {
+            let files: Vec<String> = key.split(',').map(str::to_string).collect();
+            (id(&files), usage)
+        })
+        .collect();
+
+    let mut out = String::new();
+    // Writing to a String cannot fail
+    let _ = writeln!(
+        out,
+        "# HELP model_size_bytes Size of the files of the model\n\
+         # TYPE model_size_bytes gauge"
+    );
+    for (id, usage) in &ids {
+        if let Some(size) = usage.size_bytes {
+            let _ = writeln!(out, "model_size_bytes{{model=\"{id}\"}} {size}");
+        }
+    }
+    let _ = writeln!(
+        out,
+        "# HELP model_inferences_total Number of inferences of the model\n\
+         # TYPE model_inferences_total counter"
+    );
+    for (id, usage) in &ids {
+        let _ = writeln!(
+            out,
+            "model_inferences_total{{model=\"{id}\"}} {}",
+            usage.inferences
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP model_inference_seconds_total Cumulative inference time of the model\n\
+         # TYPE model_inference_seconds_total counter"
+    );
+    for (id, usage) in &ids {
+        let _ = writeln!(
+            out,
+            "model_inference_seconds_total{{model=\"{id}\"}} {}",
+            usage.total_latency_ms / 1000.0
+        );
+    }
+    out
+}
+
+// The id of the model with the files
+fn id(files: &[String]) -> String {
+    files
+        .first()
+        .and_then(|file| Path::new(file).file_stem())
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// The total size of the files, if they exist (named models do not
+// need to, see named_models.rs)
+fn size(files: &[String]) -> Option<u64> {
+    files
+        .iter()
+        .map(|file| fs::metadata(file).ok().map(|metadata| metadata.len()))
+        .sum()
+}
+
+// Records an attempt to load the model from the files
+pub fn record_load(files: &[String], latency: Duration, error: Option<&ErrorCode>) {
+    update(files, |usage, now| match error {
+        Some(error) => usage.load_error = Some(Error::from(error.clone()).to_string()),
+        None => {
+            usage.loaded_at = Some(now);
+            usage.load_latency_ms = Some(latency.as_secs_f64() * 1000.0);
+            usage.load_error = None;
+            usage.size_bytes = size(files);
+        }
+    });
+}
+
+// Records an inference of the graph, which must have been loaded with
+// `load_model`
+pub fn record_inference(graph: &Graph, latency: Duration) {
+    let files = match MODELS.lock() {
+        Ok(models) => models
+            .iter()
+            .find(|(_, model)| std::ptr::eq(model.as_ref(), graph))
+            .map(|(files, _)| files.clone()),
+        Err(_) => None,
+    };
+    if let Some(files) = files {
+        update(&files, |usage, now| {
+            let latency_ms = latency.as_secs_f64() * 1000.0;
+            usage.last_used = Some(now);
+            usage.inferences += 1;
+            usage.total_latency_ms += latency_ms;
+
+            #[cfg(feature = "model-limits")]
+            match usage.window_start {
+                Some(start) if now - start < crate::limits::WINDOW => {
+                    usage.window_latency_ms += latency_ms;
+                }
+                _ => {
+                    usage.window_start = Some(now);
+                    usage.window_latency_ms = latency_ms;
+                }
+            }
+        });
+    }
+}
+
+// Updates the usage of the model under the lock of the state, so that
+// concurrent requests do not lose each other's counts (which the limits
+// of limits.rs depend on) or revert a switch of the model
+fn update(files: &[String], f: impl FnOnce(&mut Usage, DateTime<Utc>)) {
+    let result = state::update(STATE_FILE, |usage: &mut BTreeMap<String, Usage>| {
+        f(usage.entry(files.join(",")).or_default(), clock::now());
+        Ok(())
+    });
+    if let Err(e) = result {
+        eprintln!(
+            "Error recording the usage of model {}: {e}",
+            files.join(",")
+        );
+    }
+}
@@ -0,0 +1,265 @@
+// AES-256-GCM decryption (FIPS 197 and NIST SP 800-38D), which is
+// used to decrypt model files that are stored encrypted on the device
+// (see encryption.rs). Like SHA-256 (see sha256.rs), it is implemented
+// here instead of pulling in a crypto crate, to keep the component
+// small. Only decryption with 96-bit nonces is needed. The
+// implementation uses table lookups, so it is not hardened against
+// timing side channels of the CPU cache.
+
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+const ROUNDS: usize = 14;
+const BLOCK_LEN: usize = 16;
+const TAG_LEN: usize = 16;
+
+// Decrypts the ciphertext followed by the authentication tag. Returns
+// `None` if the tag does not match, i.e. if the key is wrong or the
+// data was modified.
+pub fn decrypt(key: &[u8; 32], nonce: &[u8; 12], data: &[u8]) -> Option<Vec<u8>> {
+    let (ciphertext, tag) = data.split_at_checked(data.len().checked_sub(TAG_LEN)?)?;
+    let round_keys = expand_key(key);
+    let h = u128::from_be_bytes(encrypt_block(&round_keys, [0; BLOCK_LEN]));
+
+    // The first counter block is used for the tag, the following ones
+    // for the data
+    let mut counter = [0; BLOCK_LEN];
+    counter[..12].copy_from_slice(nonce);
+    counter[15] = 1;
+    let tag_mask = encrypt_block(&round_keys, counter);
+
+    let mut expected = ghash(h, ciphertext);
+    expected ^= u128::from_be_bytes(tag_mask);
+    let diff = expected
+        .to_be_bytes()
+        .iter()
+        .zip(tag)
+        .fold(0, |diff, (a, b)| diff | (a ^ b));
+    if diff != 0 {
+        return None;
+    }
+
+    let mut plaintext = Vec::with_capacity(ciphertext.len());
+    for block in ciphertext.chunks(BLOCK_LEN) {
+        let value = u32::from_be_bytes([counter[12], counter[13], counter[14], counter[15]]);
+        counter[12..].copy_from_slice(&value.wrapping_add(1).to_be_bytes());
+        let keystream = encrypt_block(&round_keys, counter);
+        plaintext.extend(block.iter().zip(keystream).map(|(c, k)| c ^ k));
+    }
+    Some(plaintext)
+}
+
+// The authentication function of GCM, without additional data
+fn ghash(h: u128, ciphertext: &[u8]) -> u128 {
+    let mut y = 0;
+    for block in ciphertext.chunks(BLOCK_LEN) {
+        let mut padded = [0; BLOCK_LEN];
+        padded[..block.len()].copy_from_slice(block);
+        y = gf_mul(y ^ u128::from_be_bytes(padded), h);
+    }
+    // The last block holds the lengths of the additional data (none)
+    // and the ciphertext in bits
+    let lengths = (ciphertext.len() as u128) * 8;
+    gf_mul(y ^ lengths, h)
+}
+
+// Multiplication in GF(2^128) with the bit order of GCM
+fn gf_mul(x: u128, y: u128) -> u128 {
+    const R: u128 = 0xe1 << 120;
+    let mut z = 0;
+    let mut v = y;
+    for i in (0..128).rev() {
+        if (x >> i) & 1 == 1 {
+            z ^= v;
+        }
+        v = if v & 1 == 1 { (v >> 1) ^ R } else { v >> 1 };
+    }
+    z
+}
+
+fn expand_key(key: &[u8; 32]) -> [[u8; BLOCK_LEN]; ROUNDS + 1] {
+    let mut words = [[0u8; 4]; 4 * (ROUNDS + 1)];
+    for (word, chunk) in words.iter_mut().zip(key.as_chunks::<4>().0) {
+        *word = *chunk;
+    }
+    let mut rcon = 1u8;
+    for i in 8..words.len() {
+        let mut word = words[i - 1];
+        if i % 8 == 0 {
+            word = [
+                SBOX[word[1] as usize] ^ rcon,
+                SBOX[word[2] as usize],
+                SBOX[word[3] as usize],
+                SBOX[word[0] as usize],
+            ];
+            rcon = xtime(rcon);
+        } else if i % 8 == 4 {
+            word = word.map(|b| SBOX[b as usize]);
+        }
+        for (b, previous) in word.iter_mut().zip(words[i - 8]) {
+            *b ^= previous;
+        }
+        words[i] = word;
+    }
+
+    let mut round_keys = [[0; BLOCK_LEN]; ROUNDS + 1];
+    for (round_key, words) in round_keys.iter_mut().zip(words.as_chunks::<4>().0) {
+        round_key.copy_from_slice(words.as_flattened());
+    }
+    round_keys
+}
+
+// Encrypts a block, which is stored column by column
+fn encrypt_block(
+    round_keys: &[[u8; BLOCK_LEN]; ROUNDS + 1],
+    block: [u8; BLOCK_LEN],
+) -> [u8; BLOCK_LEN] {
+    let mut state = xor(block, round_keys[0]);
+    for (round, round_key) in round_keys.iter().enumerate().skip(1) {
+        // SubBytes and ShiftRows
+        let mut shifted = [0; BLOCK_LEN];
+        for column in 0..4 {
+            for row in 0..4 {
+                shifted[4 * column + row] = SBOX[state[4 * ((column + row) % 4) + row] as usize];
+            }
+        }
+        state = shifted;
+
+        // MixColumns, except in the last round
+        if round < ROUNDS {
+            for column in state.as_chunks_mut::<4>().0 {
+                let [a, b, c, d] = *column;
+                let all = a ^ b ^ c ^ d;
+                column[0] ^= all ^ xtime(a ^ b);
+                column[1] ^= all ^ xtime(b ^ c);
+                column[2] ^= all ^ xtime(c ^ d);
+                column[3] ^= all ^ xtime(d ^ a);
+            }
+        }
+        state = xor(state, *round_key);
+    }
+    state
+}
+
+// Multiplication by x in GF(2^8)
+fn xtime(b: u8) -> u8 {
+    (b << 1) ^ if b & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+fn xor(a: [u8; BLOCK_LEN], b: [u8; BLOCK_LEN]) -> [u8; BLOCK_LEN] {
+    let mut out = a;
+    for (x, y) in out.iter_mut().zip(b) {
+        *x ^= y;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    const KEY: &str = "feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308";
+    const NONCE: &str = "cafebabefacedbaddecaf888";
+    const PLAINTEXT: &str = "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+                             1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b391aafd255";
+    const CIPHERTEXT: &str = "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa\
+                              8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662898015ad";
+
+    // The example of FIPS 197 (Appendix C.3)
+    #[test]
+    fn encrypts_block() {
+        let key = unhex("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .try_into()
+            .unwrap();
+        let block = unhex("00112233445566778899aabbccddeeff")
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            encrypt_block(&expand_key(&key), block).to_vec(),
+            unhex("8ea2b7ca516745bfeafc49904b496089")
+        );
+    }
+
+    // The test cases of AES-256 without additional data of the GCM
+    // specification that NIST SP 800-38D is based on (13 to 15), and
+    // test case 15 with a partial last block
+    #[test]
+    fn decrypts_known_answers() {
+        let cases = [
+            (
+                "00".repeat(32),
+                "00".repeat(12),
+                String::new(),
+                "530f8afbc74536b9a963b4f1c4cb738b".to_string(),
+            ),
+            (
+                "00".repeat(32),
+                "00".repeat(12),
+                "00".repeat(16),
+                "cea7403d4d606b6e074ec5d3baf39d18d0d1c8a799996bf0265b98b5d48ab919".to_string(),
+            ),
+            (
+                KEY.to_string(),
+                NONCE.to_string(),
+                PLAINTEXT.to_string(),
+                format!("{CIPHERTEXT}b094dac5d93471bdec1a502270e3cc6c"),
+            ),
+            (
+                KEY.to_string(),
+                NONCE.to_string(),
+                PLAINTEXT[..120].to_string(),
+                format!("{}eb9f796c8d356fc31a8433884b696f4f", &CIPHERTEXT[..120]),
+            ),
+        ];
+        for (key, nonce, plaintext, data) in cases {
+            let key = unhex(&key).try_into().unwrap();
+            let nonce = unhex(&nonce).try_into().unwrap();
+            assert_eq!(
+                decrypt(&key, &nonce, &unhex(&data)),
+                Some(unhex(&plaintext))
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_modified_data() {
+        let key = unhex(KEY).try_into().unwrap();
+        let nonce = unhex(NONCE).try_into().unwrap();
+        let data = unhex(&format!("{CIPHERTEXT}b094dac5d93471bdec1a502270e3cc6c"));
+
+        let mut ciphertext = data.clone();
+        ciphertext[0] ^= 1;
+        assert_eq!(decrypt(&key, &nonce, &ciphertext), None);
+        let mut tag = data.clone();
+        *tag.last_mut().unwrap() ^= 0x80;
+        assert_eq!(decrypt(&key, &nonce, &tag), None);
+        let mut wrong_key = key;
+        wrong_key[31] ^= 1;
+        assert_eq!(decrypt(&wrong_key, &nonce, &data), None);
+        // Shorter than a tag
+        assert_eq!(decrypt(&key, &nonce, &data[..15]), None);
+    }
+}
@@ -0,0 +1,140 @@
+// This module implements `POST /predict/aggregate`, which forecasts a
+// number of series (e.g. the power consumption of each machine in a
+// fleet) and additionally their sum and mean, to answer questions about
+// the total load and the required capacity.
+//
+// The model only predicts values, not how certain it is about them. As
+// a rough estimate of the uncertainty of each series, we use how much
+// the series changed between consecutive forecast periods in its
+// history (the error a seasonal naive forecast would have made). The
+// uncertainty of the sum combines these assuming that the errors of
+// the series are independent.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use wasi_nn_demo_lib::interface;
+
+use crate::{
+    error::Error,
+    forecast_histories,
+    http::JsonBody,
+    model::{self, ModelSpec},
+    series_from_data_window, HttpHandler,
+};
+
+// The bounds are this many standard deviations away from the forecast
+// (about 95% for normally distributed errors)
+const Z_SCORE: f32 = 1.96;
+
+#[derive(Deserialize)]
+pub struct AggregateRequest {
+    series: BTreeMap<String, interface::DataWindow>,
+}
+
+#[derive(Serialize)]
+pub struct AggregateForecast {
+    series: BTreeMap<String, Forecast>,
+    sum: Forecast,
+    mean: Forecast,
+}
+
+#[derive(Serialize)]
+struct Forecast {
+    forecast: Vec<f32>,
+    // The estimated standard deviation of the forecast errors and the
+    // resulting bounds for each step
+    stddev: f32,
+    lower: Vec<f32>,
+    upper: Vec<f32>,
+}
+
+impl Forecast {
+    fn new(forecast: Vec<f32>, stddev: f32) -> Self {
+        Self {
+            lower: forecast.iter().map(|f| f - Z_SCORE * stddev).collect(),
+            upper: forecast.iter().map(|f| f + Z_SCORE * stddev).collect(),
+            forecast,
+            stddev,
+        }
+    }
+}
+
+impl JsonBody for AggregateRequest {
+    const NAME: &'static str = "aggregate request";
+
+    fn check(self) -> Result<Self, Error> {
+        #[cfg(feature = "usage")]
+        crate::usage::record_data_points(
+            self.series.values().map(|window| window.data.len()).sum(),
+        );
+        if self.series.is_empty() {
+            return Err(Error::BadRequest("No series given".into()));
+        }
+        Ok(self)
+    }
+}
+
+pub fn forecast_to_vec(forecast: &AggregateForecast) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(forecast)
+        .map_err(|e| Error::internal(format!("Error serializing aggregate forecast: {e}")))
+}
+
+impl HttpHandler {
+    pub fn forecast_aggregate(
+        &mut self,
+        model: Option<&ModelSpec>,
+        request: AggregateRequest,
+    ) -> Result<AggregateForecast, Error> {
+        let histories: Vec<_> = request
+            .series
+            .values()
+            .map(series_from_data_window)
+            .collect();
+        let forecasts = forecast_histories(model, &histories)?;
+        let prediction_len = model::shape_of(model)?.prediction_len;
+        let stddevs: Vec<_> = histories
+            .iter()
+            .map(|h| naive_stddev(h, prediction_len))
+            .collect();
+
+        let mut sum = vec![0.0; prediction_len as usize];
+        for forecast in &forecasts {
+            for (total, value) in sum.iter_mut().zip(forecast) {
+                *total += value;
+            }
+        }
+        let sum_stddev = stddevs.iter().map(|s| s * s).sum::<f32>().sqrt();
+
+        let count = forecasts.len() as f32;
+        let mean = sum.iter().map(|total| total / count).collect();
+        let mean_stddev = sum_stddev / count;
+
+        Ok(AggregateForecast {
+            series: request
+                .series
+                .into_keys()
+                .zip(forecasts.into_iter().zip(stddevs))
+                .map(|(name, (forecast, stddev))| (name, Forecast::new(forecast, stddev)))
+                .collect(),
+            sum: Forecast::new(sum, sum_stddev),
+            mean: Forecast::new(mean, mean_stddev),
+        })
+    }
+}
+
+// The root mean square of the differences between each value and the
+// value `lag` steps before it, i.e. the length of the prediction. This
+// is zero for histories that are too short to estimate it.
+fn naive_stddev(history: &[f32], lag: u32) -> f32 {
+    let lag = lag as usize;
+    if history.len() <= lag {
+        return 0.0;
+    }
+    let squares: f32 = history
+        .iter()
+        .zip(&history[lag..])
+        .map(|(before, after)| (after - before).powi(2))
+        .sum();
+    (squares / (history.len() - lag) as f32).sqrt()
+}
@@ -0,0 +1,89 @@
+// This module evaluates alert rules on every forecast and notifies
+// webhooks about the rules that are triggered, e.g. when the forecast
+// value exceeds a limit within the next 6 steps. The rules are read
+// from the `alerts` config (see config.rs), e.g. `config/alerts.json`:
+//
+// [{ "name": "overheating", "above": 80.0, "within": 6,
+//    "webhook": "http://alerts.local/hook" }]
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config, error::Error, outgoing};
+
+const RULES_CONFIG: &str = "alerts";
+
+#[derive(Deserialize)]
+pub struct Rule {
+    name: String,
+    // The rule is triggered if a forecast value is above or below
+    // these limits (at least one of them should be given) ...
+    above: Option<f32>,
+    below: Option<f32>,
+    // ... within this many steps of the forecast (all steps if not
+    // given)
+    within: Option<usize>,
+    webhook: String,
+}
+
+// The body of the notification sent to the webhook
+#[derive(Serialize)]
+struct Notification<'a> {
+    rule: &'a str,
+    // The series id of the forecast, if the client sent one
+    series: Option<&'a str>,
+    // The first step of the forecast that triggered the rule (starting
+    // at 1) and its value
+    step: usize,
+    value: f32,
+    above: Option<f32>,
+    below: Option<f32>,
+}
+
+// Reads the alert rules. Without a config, there are no rules.
+pub fn load_rules() -> Result<Vec<Rule>, Error> {
+    Ok(config::load(RULES_CONFIG)?.unwrap_or_default())
+}
+
+// Evaluates the rules on the forecast values and notifies the webhooks
+// of the triggered rules. The forecast has already been made at this
+// point, so failed notifications are only reported on stderr and do
+// not fail the request.
+pub fn notify(rules: &[Rule], series: Option<&str>, forecast: &[f32]) {
+    for rule in rules {
+        let Some((step, value)) = rule.trigger(forecast) else {
+            continue;
+        };
+        let notification = Notification {
+            rule: &rule.name,
+            series,
+            step,
+            value,
+            above: rule.above,
+            below: rule.below,
+        };
+
+        let result = serde_json::to_vec(&notification)
+            .map_err(|e| Error::internal(format!("Error serializing notification: {e}")))
+            .and_then(|body| outgoing::post(&rule.webhook, "application/json", &body));
+        match result {
+            Ok(status) if (200..300).contains(&status) => {}
+            Ok(status) => eprintln!("Webhook of alert {} responded with {status}", rule.name),
+            Err(e) => eprintln!("Error notifying webhook of alert {}: {e}", rule.name),
+        }
+    }
+}
+
+impl Rule {
+    // Returns the first step (starting at 1) and value that trigger
+    // the rule, if any
+    fn trigger(&self, forecast: &[f32]) -> Option<(usize, f32)> {
+        forecast
+            .iter()
+            .take(self.within.unwrap_or(forecast.len()))
+            .position(|value| {
+                self.above.is_some_and(|above| *value > above)
+                    || self.below.is_some_and(|below| *value < below)
+            })
+            .map(|i| (i + 1, forecast[i]))
+    }
+}
@@ -0,0 +1,114 @@
+// This module adds a time series anomaly detection model to the
+// component, served by `POST /anomalies`. Such a model (e.g. an LSTM
+// autoencoder) is trained to reconstruct normal behavior of the time
+// series. Instead of a forecast, we return for every data point how
+// badly it was reconstructed, and flag the points where this error is
+// above a threshold as anomalies.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use wasi_nn_demo_lib::interface;
+
+use crate::{
+    error::Error,
+    nn::{GraphEncoding, Tensor},
+    numeric_data_points, HttpHandler,
+};
+
+// These constants are the parameters that are specific to the anomaly
+// detection model. No such model is included in this repository, place
+// your own model in the models directory.
+const MODEL_FORMAT: GraphEncoding = GraphEncoding::Onnx;
+const MODEL_FILES: [&str; 1] = ["models/anomaly.onnx"];
+// The labels of the input and output tensors in the model
+const INPUT_TENSOR_NAME: &str = "input";
+const OUTPUT_TENSOR_NAME: &str = "output";
+// The model reconstructs windows of this many values (1 x WINDOW_LEN x
+// 1 for both input and output)
+const WINDOW_LEN: usize = 128;
+// Points whose squared reconstruction error is above this threshold
+// are reported as anomalies, unless the client requests a different
+// threshold. This depends on the model and the scale of the data.
+pub const DEFAULT_THRESHOLD: f32 = 1.0;
+
+#[derive(Serialize)]
+pub struct AnomalyReport {
+    threshold: f32,
+    // The number of points above the threshold
+    anomalies: usize,
+    points: Vec<PointScore>,
+}
+
+#[derive(Serialize)]
+struct PointScore {
+    timestamp: Option<DateTime<Utc>>,
+    value: f32,
+    reconstruction: f32,
+    // The squared reconstruction error
+    score: f32,
+    anomaly: bool,
+}
+
+impl HttpHandler {
+    // This is the equivalent of `handle_data` for anomaly detection
+    pub fn detect_anomalies(
+        &mut self,
+        input: interface::DataWindow,
+        threshold: f32,
+    ) -> Result<AnomalyReport, Error> {
+        let graph = crate::load_model(MODEL_FORMAT, &MODEL_FILES)?;
+        let ctx = graph.init_execution_context()?;
+
+        // Like the forecasting model, this model assumes that the data
+        // points are equidistant and only looks at the values
+        let data_points = numeric_data_points(&input);
+        if data_points.is_empty() {
+            return Err(Error::BadRequest("No numeric data points".into()));
+        }
+        let values: Vec<_> = data_points.iter().map(|(_, value)| *value).collect();
+
+        // The series is reconstructed in consecutive windows. The last
+        // window is padded with its last value, the reconstruction of
+        // the padding is ignored.
+        let mut reconstruction = Vec::with_capacity(values.len());
+        for window in values.chunks(WINDOW_LEN) {
+            let mut input = window.to_vec();
+            input.resize(WINDOW_LEN, window[window.len() - 1]);
+            let input_tensor = Tensor::new(input, vec![1, WINDOW_LEN as u32, 1]);
+
+            let output_tensors = &crate::infer(&graph, || {
+                ctx.run([(INPUT_TENSOR_NAME, input_tensor)], &[OUTPUT_TENSOR_NAME])
+            })?;
+            let output: &[[f32; WINDOW_LEN]; 1] =
+                crate::reshape_output(&output_tensors[OUTPUT_TENSOR_NAME])?;
+
+            reconstruction.extend_from_slice(&output[0][..window.len()]);
+        }
+
+        let points: Vec<_> = data_points
+            .iter()
+            .zip(reconstruction)
+            .map(|((data_point, value), reconstruction)| {
+                let score = (value - reconstruction).powi(2);
+                PointScore {
+                    timestamp: data_point.timestamp,
+                    value: *value,
+                    reconstruction,
+                    score,
+                    anomaly: score > threshold,
+                }
+            })
+            .collect();
+
+        Ok(AnomalyReport {
+            threshold,
+            anomalies: points.iter().filter(|point| point.anomaly).count(),
+            points,
+        })
+    }
+}
+
+pub fn report_to_vec(report: &AnomalyReport) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(report)
+        .map_err(|e| Error::internal(format!("Error serializing anomaly report: {e}")))
+}
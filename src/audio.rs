@@ -0,0 +1,232 @@
+// This module adds an acoustic anomaly detection model to the
+// component, e.g. an autoencoder trained on the sound of a healthy
+// machine. It is used when the request body is a WAV file. The audio
+// is turned into a log-mel spectrogram, which is cut into short
+// segments that are reconstructed by the model. The worse a segment
+// is reconstructed, the more unusual it sounds.
+
+use std::{f32::consts::PI, io::Cursor};
+
+use crate::{
+    error::Error,
+    nn::{GraphEncoding, Tensor},
+    HttpHandler,
+};
+use hound::{SampleFormat, WavReader};
+use serde::Serialize;
+
+// These constants are the parameters that are specific to the
+// acoustic model. No such model is included in this repository, place
+// your own model in the models directory.
+const MODEL_FORMAT: GraphEncoding = GraphEncoding::Onnx;
+const MODEL_FILES: [&str; 1] = ["models/audio.onnx"];
+// The labels of the input and output tensors in the model
+const INPUT_TENSOR_NAME: &str = "input";
+const OUTPUT_TENSOR_NAME: &str = "output";
+// The sample rate the model was trained with. We do not resample, so
+// the uploaded audio must have exactly this rate.
+const SAMPLE_RATE: u32 = 16000;
+// The spectrogram is computed from frames of FFT_SIZE samples that are
+// HOP_LENGTH samples apart, using MEL_BANDS mel filters
+const FFT_SIZE: usize = 1024;
+const HOP_LENGTH: usize = 512;
+const MEL_BANDS: usize = 64;
+// The model gets SEGMENT_FRAMES consecutive frames of the spectrogram
+// at once (1 x SEGMENT_FRAMES * MEL_BANDS) and reconstructs them
+const SEGMENT_FRAMES: usize = 5;
+const SEGMENT_LEN: usize = SEGMENT_FRAMES * MEL_BANDS;
+
+// The anomaly score of one segment of the audio
+#[derive(Serialize)]
+pub struct AnomalyScore {
+    // Offset of the segment from the start of the audio in seconds
+    pub time: f32,
+    // Mean squared reconstruction error of the segment
+    pub score: f32,
+}
+
+impl HttpHandler {
+    // This is the equivalent of `handle_data` for audio
+    pub fn score_audio(&mut self, wav: &[u8]) -> Result<Vec<AnomalyScore>, Error> {
+        let graph = crate::load_model(MODEL_FORMAT, &MODEL_FILES)?;
+        let ctx = graph.init_execution_context()?;
+
+        let spectrogram = log_mel_spectrogram(&samples_from_wav(wav)?);
+        if spectrogram.len() < SEGMENT_FRAMES {
+            return Err(Error::BadRequest(format!(
+                "Audio too short, need at least {} samples",
+                (SEGMENT_FRAMES - 1) * HOP_LENGTH + FFT_SIZE
+            )));
+        }
+
+        // The model reconstructs one segment at a time. Segments do
+        // not overlap, the last incomplete one is ignored.
+        spectrogram
+            .as_chunks::<SEGMENT_FRAMES>()
+            .0
+            .iter()
+            .enumerate()
+            .map(|(i, segment)| {
+                let input: Vec<_> = segment.concat();
+                let input_tensor = Tensor::new(input.clone(), vec![1, SEGMENT_LEN as u32]);
+                let output_tensors = &crate::infer(&graph, || {
+                    ctx.run([(INPUT_TENSOR_NAME, input_tensor)], &[OUTPUT_TENSOR_NAME])
+                })?;
+                let output: &[[f32; SEGMENT_LEN]; 1] =
+                    crate::reshape_output(&output_tensors[OUTPUT_TENSOR_NAME])?;
+
+                let score = input
+                    .iter()
+                    .zip(output[0])
+                    .map(|(x, y)| (x - y).powi(2))
+                    .sum::<f32>()
+                    / SEGMENT_LEN as f32;
+                let time = (i * SEGMENT_FRAMES * HOP_LENGTH) as f32 / SAMPLE_RATE as f32;
+
+                Ok(AnomalyScore { time, score })
+            })
+            .collect()
+    }
+}
+
+pub fn scores_to_vec(scores: &[AnomalyScore]) -> Result<Vec<u8>, Error> {
+    #[derive(Serialize)]
+    struct Response<'a> {
+        scores: &'a [AnomalyScore],
+    }
+
+    serde_json::to_vec(&Response { scores })
+        .map_err(|e| Error::internal(format!("Error serializing scores: {e}")))
+}
+
+// Decodes the WAV file into mono samples in the range [-1, 1]
+fn samples_from_wav(wav: &[u8]) -> Result<Vec<f32>, Error> {
+    let bad_request = |e: hound::Error| Error::BadRequest(format!("Invalid WAV file: {e}"));
+
+    let reader = WavReader::new(Cursor::new(wav)).map_err(bad_request)?;
+    let spec = reader.spec();
+    if spec.sample_rate != SAMPLE_RATE {
+        return Err(Error::BadRequest(format!(
+            "Expected a sample rate of {SAMPLE_RATE} Hz, got {} Hz",
+            spec.sample_rate
+        )));
+    }
+
+    let samples: Vec<f32> = match spec.sample_format {
+        SampleFormat::Float => reader.into_samples::<f32>().collect::<Result<_, _>>(),
+        SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .into_samples::<i32>()
+                .map(|sample| sample.map(|sample| sample as f32 / scale))
+                .collect()
+        }
+    }
+    .map_err(bad_request)?;
+
+    // Multiple channels are mixed down to one
+    let channels = usize::from(spec.channels.max(1));
+    Ok(samples
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect())
+}
+
+// Computes the log-mel spectrogram, i.e. for every frame the
+// logarithm of the energy in each of the mel bands
+fn log_mel_spectrogram(samples: &[f32]) -> Vec<Vec<f32>> {
+    let window: Vec<_> = (0..FFT_SIZE)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FFT_SIZE as f32).cos())
+        .collect();
+    let filters = mel_filters();
+
+    samples
+        .windows(FFT_SIZE)
+        .step_by(HOP_LENGTH)
+        .map(|frame| {
+            let mut re: Vec<_> = frame.iter().zip(&window).map(|(x, w)| x * w).collect();
+            let mut im = vec![0f32; FFT_SIZE];
+            fft(&mut re, &mut im);
+
+            let power: Vec<_> = (0..=FFT_SIZE / 2)
+                .map(|k| re[k] * re[k] + im[k] * im[k])
+                .collect();
+
+            filters
+                .iter()
+                .map(|filter| {
+                    let energy: f32 = filter.iter().zip(&power).map(|(w, p)| w * p).sum();
+                    (energy + f32::EPSILON).ln()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Triangular filters that are equally spaced on the mel scale between
+// 0 Hz and the Nyquist frequency. Each filter holds one weight per
+// frequency bin of the FFT.
+fn mel_filters() -> Vec<Vec<f32>> {
+    let hz_to_mel = |hz: f32| 2595.0 * (1.0 + hz / 700.0).log10();
+    let mel_to_hz = |mel: f32| 700.0 * (10f32.powf(mel / 2595.0) - 1.0);
+
+    let max_mel = hz_to_mel(SAMPLE_RATE as f32 / 2.0);
+    let edges: Vec<_> = (0..MEL_BANDS + 2)
+        .map(|i| mel_to_hz(max_mel * i as f32 / (MEL_BANDS + 1) as f32))
+        .collect();
+
+    edges
+        .windows(3)
+        .map(|edges| {
+            let [lower, center, upper] = [edges[0], edges[1], edges[2]];
+            (0..=FFT_SIZE / 2)
+                .map(|k| {
+                    let hz = k as f32 * SAMPLE_RATE as f32 / FFT_SIZE as f32;
+                    ((hz - lower) / (center - lower))
+                        .min((upper - hz) / (upper - center))
+                        .max(0.0)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// An in-place iterative radix-2 FFT. The length must be a power of
+// two, which FFT_SIZE is.
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+
+    // Reorder the input in bit-reversed order
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    // Combine the transforms of increasing size
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_re, w_im) = ((angle * k as f32).cos(), (angle * k as f32).sin());
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
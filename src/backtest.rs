@@ -0,0 +1,134 @@
+// This module implements `POST /backtest`, which evaluates how well
+// the forecasting model would have performed on a long history. A
+// window of `history_len` values is slid over the history, and for
+// each position the forecast is compared to the `prediction_len`
+// values that actually followed (see `Shape` in model.rs). The results are returned as JSON, or as CSV with
+// one row per forecast value of each window:
+//
+// window_start,horizon_step,forecast,actual,error
+// 2024-05-01T00:00:00Z,1,41.5,42,-0.5
+//
+// `horizon_step` counts from 1, and `error` is the forecast minus the
+// actual value. `window_start` is empty for windows without timestamps.
+
+use std::fmt::Write;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use wasi_nn_demo_lib::interface;
+
+use crate::{
+    error::Error,
+    load_graph,
+    measures::{mae, mape},
+    model, numeric_data_points, predicted_values, tensor_from_series, HttpHandler,
+};
+
+#[derive(Serialize)]
+pub struct BacktestReport {
+    // The errors over all windows
+    pub mae: f32,
+    pub mape: Option<f32>,
+    pub windows: Vec<WindowResult>,
+}
+
+#[derive(Serialize)]
+pub struct WindowResult {
+    // The timestamp of the first value of the window
+    pub start: Option<DateTime<Utc>>,
+    pub mae: f32,
+    // The mean absolute percentage error, which is not defined if all
+    // actual values are zero
+    pub mape: Option<f32>,
+    pub forecast: Vec<f32>,
+    pub actual: Vec<f32>,
+}
+
+impl HttpHandler {
+    // Runs the forecast for every `stride`-th position of the window
+    // in the history
+    pub fn backtest(
+        &mut self,
+        input: interface::DataWindow,
+        stride: usize,
+    ) -> Result<BacktestReport, Error> {
+        let data_points = numeric_data_points(&input);
+        let values: Vec<_> = data_points.iter().map(|(_, value)| *value).collect();
+
+        let spec = model::spec()?;
+        let model::Shape {
+            history_len,
+            prediction_len,
+            ..
+        } = spec.shape;
+        let span = (history_len + prediction_len) as usize;
+        if values.len() < span {
+            return Err(Error::BadRequest(format!(
+                "History must contain at least {span} numeric data points, got {}",
+                values.len()
+            )));
+        }
+
+        let graph = load_graph()?;
+        let ctx = graph.init_execution_context()?;
+
+        let windows = (0..=values.len() - span)
+            .step_by(stride)
+            .map(|start| {
+                let (history, actual) = values[start..start + span].split_at(history_len as usize);
+
+                let input_tensor =
+                    tensor_from_series(history.to_vec(), spec.batch_size(), history_len);
+                let output_tensors = &crate::infer(&graph, || {
+                    ctx.run(
+                        [(spec.input_tensor.as_str(), input_tensor)],
+                        &[spec.output_tensor.as_str()],
+                    )
+                })?;
+                let forecast = predicted_values(
+                    &output_tensors[spec.output_tensor.as_str()],
+                    spec.batch_size(),
+                    prediction_len,
+                )?;
+
+                Ok(WindowResult {
+                    start: data_points[start].0.timestamp,
+                    mae: mae(&forecast, actual),
+                    mape: mape(&forecast, actual),
+                    forecast: forecast.to_vec(),
+                    actual: actual.to_vec(),
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let forecast: Vec<_> = windows.iter().flat_map(|w| w.forecast.clone()).collect();
+        let actual: Vec<_> = windows.iter().flat_map(|w| w.actual.clone()).collect();
+
+        Ok(BacktestReport {
+            mae: mae(&forecast, &actual),
+            mape: mape(&forecast, &actual),
+            windows,
+        })
+    }
+}
+
+pub fn report_to_vec(report: &BacktestReport) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(report)
+        .map_err(|e| Error::internal(format!("Error serializing backtest report: {e}")))
+}
+
+pub fn report_to_csv(report: &BacktestReport) -> String {
+    let mut csv = String::from("window_start,horizon_step,forecast,actual,error\n");
+    for window in &report.windows {
+        let start = window
+            .start
+            .map(|start| start.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+            .unwrap_or_default();
+        for (step, (forecast, actual)) in window.forecast.iter().zip(&window.actual).enumerate() {
+            let error = forecast - actual;
+            // Writing to a String cannot fail
+            let _ = writeln!(csv, "{start},{},{forecast},{actual},{error}", step + 1);
+        }
+    }
+    csv
+}
@@ -0,0 +1,85 @@
+// This module maintains a cheap statistical baseline for each series
+// whose data points are accumulated (see ingest.rs), so that consumers
+// can sanity-check the forecast of the model against a simple reference.
+// Every ingested data point updates the level and trend of the series by
+// Holt's linear exponential smoothing, and `GET /predict` returns the
+// extrapolated trend next to the forecast:
+//
+// { "result": { ... }, "baseline": [21.4, 21.5, 21.6, ...] }
+//
+// The trend is per data point, so the baseline assumes that the series
+// keeps its cadence. The level and trend of each series are kept in the
+// state directory (see state.rs), and a series only gets a baseline
+// once it has two data points.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, state};
+
+const STATE_FILE: &str = "baseline.json";
+
+// The weight of the most recent value in the level
+const ALPHA: f32 = 0.3;
+// The weight of the most recent change of the level in the trend
+const BETA: f32 = 0.1;
+
+#[derive(Serialize, Deserialize)]
+struct Smoothing {
+    level: f32,
+    // `None` until the series has a second data point
+    trend: Option<f32>,
+}
+
+// Updates the baseline of the series with its new values, oldest first.
+// All series share the state, so it is updated under its lock.
+pub fn update(series: &str, values: &[f32]) -> Result<(), Error> {
+    state::update(STATE_FILE, |baselines: &mut BTreeMap<String, Smoothing>| {
+        smooth(baselines, series, values);
+        Ok(())
+    })
+}
+
+fn smooth(baselines: &mut BTreeMap<String, Smoothing>, series: &str, values: &[f32]) {
+    for &value in values {
+        match baselines.get_mut(series) {
+            None => {
+                let smoothing = Smoothing {
+                    level: value,
+                    trend: None,
+                };
+                baselines.insert(series.to_string(), smoothing);
+            }
+            // The trend starts with the first change
+            Some(smoothing @ Smoothing { trend: None, .. }) => {
+                smoothing.trend = Some(value - smoothing.level);
+                smoothing.level = value;
+            }
+            Some(Smoothing {
+                level,
+                trend: Some(trend),
+            }) => {
+                let previous = *level;
+                *level = ALPHA * value + (1.0 - ALPHA) * (previous + *trend);
+                *trend = BETA * (*level - previous) + (1.0 - BETA) * *trend;
+            }
+        }
+    }
+}
+
+// The baseline forecast of the series for the next `horizon` data
+// points, if it has one
+pub fn forecast(series: &str, horizon: u32) -> Result<Option<Vec<f32>>, Error> {
+    let mut baselines: BTreeMap<String, Smoothing> = state::load(STATE_FILE)?;
+    let Some(Smoothing {
+        level,
+        trend: Some(trend),
+    }) = baselines.remove(series)
+    else {
+        return Ok(None);
+    };
+    Ok(Some(
+        (1..=horizon).map(|h| level + h as f32 * trend).collect(),
+    ))
+}
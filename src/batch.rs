@@ -0,0 +1,108 @@
+// This module implements `POST /predict/batch`, which forecasts up to
+// `num_batches` independent series at once (see `Shape` in model.rs).
+// The model always processes that many series per inference (see
+// lib.rs), and a single forecast only uses the first of them. A batch
+// packs each window into one of them instead, so that a gateway with
+// many sensors gets up to 16 forecasts for the compute of one:
+//
+// { "windows": [{ "Input1": { ... }, ... }, { "Input1": { ... }, ... }] }
+//
+// The response contains the result of each window in the same order,
+// just like a single forecast would return it:
+//
+// { "results": [{ ... }, { ... }] }
+
+use serde::{Deserialize, Serialize};
+use wasi_nn_demo_lib::interface;
+
+use crate::{
+    error::Error,
+    forecast_histories, forecast_timestamps,
+    http::JsonBody,
+    inference_result_from_values,
+    model::{self, ModelSpec},
+    series_from_data_window, HttpHandler,
+};
+
+#[derive(Deserialize)]
+pub struct BatchRequest {
+    windows: Vec<interface::DataWindow>,
+}
+
+#[derive(Serialize)]
+pub struct BatchForecast {
+    results: Vec<interface::InferenceResult>,
+}
+
+impl JsonBody for BatchRequest {
+    const NAME: &'static str = "batch request";
+
+    fn check(self) -> Result<Self, Error> {
+        #[cfg(feature = "usage")]
+        crate::usage::record_data_points(self.windows.iter().map(|w| w.data.len()).sum());
+
+        // Larger batches would need more than one inference, which is what
+        // separate requests are for
+        if self.windows.is_empty() {
+            return Err(Error::BadRequest("No windows given".into()));
+        }
+        let num_batches = model::shape()?.num_batches;
+        if self.windows.len() > num_batches as usize {
+            return Err(Error::BadRequest(format!(
+                "At most {num_batches} windows per batch, got {}",
+                self.windows.len()
+            )));
+        }
+        Ok(self)
+    }
+}
+
+// Fails unless every window has exactly as many values as the model
+// takes, like a single forecast in strict mode (see
+// `check_history_len` in lib.rs)
+#[cfg(feature = "strict")]
+pub fn check_history_lens(request: &BatchRequest) -> Result<(), Error> {
+    let fit = crate::fit::Fit::load();
+    let history_len = model::shape()?.history_len;
+    for (i, window) in request.windows.iter().enumerate() {
+        crate::check_history_len(window, &fit, history_len).map_err(|e| match e {
+            Error::WindowLength(window_length) => Error::WindowLength(crate::error::WindowLength {
+                window: Some(i),
+                ..window_length
+            }),
+            e => e,
+        })?;
+    }
+    Ok(())
+}
+
+pub fn forecast_to_vec(forecast: &BatchForecast) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(forecast)
+        .map_err(|e| Error::internal(format!("Error serializing batch forecast: {e}")))
+}
+
+impl HttpHandler {
+    pub fn forecast_batch(
+        &mut self,
+        model: Option<&ModelSpec>,
+        request: BatchRequest,
+    ) -> Result<BatchForecast, Error> {
+        let histories: Vec<_> = request
+            .windows
+            .iter()
+            .map(series_from_data_window)
+            .collect();
+        let forecasts = forecast_histories(model, &histories)?;
+
+        let results = request
+            .windows
+            .iter()
+            .zip(forecasts)
+            .map(|(window, forecast)| {
+                let timestamps = forecast_timestamps(window, forecast.len() as u32);
+                inference_result_from_values(forecast, timestamps)
+            })
+            .collect();
+        Ok(BatchForecast { results })
+    }
+}
@@ -0,0 +1,224 @@
+// The binary encodings of data windows and forecasts, for gateways that
+// are short on bandwidth or CPU. With the `cbor` feature, windows can be
+// posted as CBOR (`Content-Type: application/cbor`), and with the
+// `msgpack` feature as MessagePack (`application/msgpack`). Clients that
+// accept one of them get the forecast in that encoding as well.
+//
+// The documents have the same structure as the JSON ones, so that a
+// client can switch by just changing the encoder. Both encodings are
+// translated from and to the JSON values of serde_json, which already
+// know the structure of the demo library, instead of duplicating it for
+// every encoding (see binary/cbor.rs and binary/msgpack.rs). Timestamps
+// are RFC 3339 strings like in JSON, but the timestamps native to each
+// encoding are accepted as well.
+
+use chrono::{DateTime, SecondsFormat};
+use serde_json::{Number, Value};
+use wasi_nn_demo_lib::interface;
+
+use crate::error::Error;
+
+#[cfg(feature = "cbor")]
+mod cbor;
+#[cfg(feature = "msgpack")]
+mod msgpack;
+
+// Documents are nested only a few levels deep, so anything deeper is
+// rejected instead of risking the stack of the component
+const MAX_DEPTH: usize = 32;
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    #[cfg(feature = "cbor")]
+    Cbor,
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+}
+
+const FORMATS: &[Format] = &[
+    #[cfg(feature = "cbor")]
+    Format::Cbor,
+    #[cfg(feature = "msgpack")]
+    Format::MessagePack,
+];
+
+impl Format {
+    // The media types of the encoding. The first one is registered and
+    // used for responses, the others are still common.
+    fn media_types(self) -> &'static [&'static str] {
+        match self {
+            #[cfg(feature = "cbor")]
+            Format::Cbor => &["application/cbor"],
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => &[
+                "application/msgpack",
+                "application/vnd.msgpack",
+                "application/x-msgpack",
+            ],
+        }
+    }
+
+    pub fn media_type(self) -> &'static str {
+        self.media_types()[0]
+    }
+
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        Self::find(|candidate| candidate.eq_ignore_ascii_case(media_type))
+    }
+
+    // The encoding the client accepts, if any (see `Request::accepts` in
+    // http.rs)
+    pub fn accepted(accepts: impl Fn(&str) -> bool) -> Option<Self> {
+        Self::find(accepts)
+    }
+
+    fn find(matches: impl Fn(&str) -> bool) -> Option<Self> {
+        FORMATS.iter().copied().find(|format| {
+            format
+                .media_types()
+                .iter()
+                .any(|media_type| matches(media_type))
+        })
+    }
+
+    fn decode(self, input: &[u8]) -> Result<Value, String> {
+        match self {
+            #[cfg(feature = "cbor")]
+            Format::Cbor => cbor::decode(input),
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => msgpack::decode(input),
+        }
+    }
+
+    fn encode(self, value: &Value) -> Vec<u8> {
+        match self {
+            #[cfg(feature = "cbor")]
+            Format::Cbor => cbor::encode(value),
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => msgpack::encode(value),
+        }
+    }
+}
+
+pub fn parse_data_window(format: Format, input: &[u8]) -> Result<interface::DataWindow, Error> {
+    let invalid = |e: String| Error::BadRequest(format!("Invalid data window: {e}"));
+    let value = format.decode(input).map_err(invalid)?;
+    let window: interface::DataWindow =
+        serde_json::from_value(value).map_err(|e| invalid(e.to_string()))?;
+
+    #[cfg(feature = "strict")]
+    crate::check_values(&window)?;
+    #[cfg(feature = "usage")]
+    crate::usage::record_data_points(window.data.len());
+    #[cfg(feature = "forward")]
+    crate::forward::record(&window);
+    Ok(window)
+}
+
+// Encodes the JSON body of a response, e.g. a forecast with additional
+// sections (see `with_sections` in http.rs)
+pub fn from_json(format: Format, body: &[u8]) -> Result<Vec<u8>, Error> {
+    let value: Value = serde_json::from_slice(body)
+        .map_err(|e| Error::internal(format!("Invalid response body: {e}")))?;
+    Ok(format.encode(&value))
+}
+
+// A number of the decoded document. JSON has no NaN or infinity, so
+// they become null, like in the JSON of the demo library.
+fn float(value: f64) -> Value {
+    Number::from_f64(value).map_or(Value::Null, Value::Number)
+}
+
+// A single-precision number of the decoded document, with the shortest
+// decimal representation like in JSON (0.1 instead of 0.100000001)
+fn single(bits: u32) -> Value {
+    let value = f32::from_bits(bits);
+    float(value.to_string().parse().unwrap_or(f64::from(value)))
+}
+
+// The value as f32, if that is exact. Most numbers of the documents are
+// f32 values of the demo library, which would otherwise take twice the
+// space.
+fn narrow(value: f64) -> Option<f32> {
+    let narrow = value as f32;
+    (narrow.to_string().parse() == Ok(value)).then_some(narrow)
+}
+
+// A timestamp of an encoding as RFC 3339 string, like in JSON
+fn timestamp(seconds: i64, nanoseconds: u32) -> Result<Value, String> {
+    let timestamp = DateTime::from_timestamp(seconds, nanoseconds)
+        .ok_or_else(|| format!("Invalid timestamp {seconds}.{nanoseconds:09}"))?;
+    Ok(Value::String(
+        timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+    ))
+}
+
+// The bytes of a document that are read one item at a time, shared by
+// both decoders
+struct Input<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Input<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = self.peek().ok_or("Unexpected end of input")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn take(&mut self, len: u64) -> Result<&'a [u8], String> {
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| self.pos.checked_add(len))
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or("Unexpected end of input")?;
+        let taken = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(taken)
+    }
+
+    // Fails unless the rest of the input can hold the given number of
+    // items, each of which takes at least one byte. Lengths are read
+    // from the request, so they are checked before anything is read (or
+    // allocated) for them.
+    fn fits(&self, items: u64) -> Result<(), String> {
+        let remaining = (self.bytes.len() - self.pos) as u64;
+        if items > remaining {
+            return Err(format!(
+                "Length {items} at byte {} exceeds the input",
+                self.pos
+            ));
+        }
+        Ok(())
+    }
+
+    // An unsigned big-endian integer of the given number of bytes
+    fn uint(&mut self, len: u64) -> Result<u64, String> {
+        let bytes = self.take(len)?;
+        Ok(bytes
+            .iter()
+            .fold(0, |value, byte| (value << 8) | u64::from(*byte)))
+    }
+
+    fn string(&mut self, len: u64) -> Result<String, String> {
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|e| format!("Invalid string: {e}"))
+    }
+
+    // Fails unless the whole input was read
+    fn end(&self) -> Result<(), String> {
+        if self.pos < self.bytes.len() {
+            return Err(format!("Unexpected data at byte {}", self.pos));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,444 @@
+// A minimal CBOR (RFC 8949) decoder and encoder for JSON values. Every
+// data item starts with a byte of its major type (the upper 3 bits) and
+// additional information (the lower 5 bits), which is either the
+// argument itself or says in how many of the following bytes it is.
+// Byte strings have no JSON equivalent and are rejected. Tags are
+// ignored, except for epoch timestamps (tag 1), which become RFC 3339
+// strings like standard timestamps (tag 0) already are.
+
+use serde_json::{Map, Value};
+
+use super::{float, narrow, single, timestamp, Input, MAX_DEPTH};
+
+// The end of items of indefinite length
+const BREAK: u8 = 0xff;
+
+pub fn decode(input: &[u8]) -> Result<Value, String> {
+    let mut input = Input::new(input);
+    let value = item(&mut input, 0)?;
+    input.end()?;
+    Ok(value)
+}
+
+fn item(input: &mut Input, depth: usize) -> Result<Value, String> {
+    if depth > MAX_DEPTH {
+        return Err("Nested too deeply".into());
+    }
+    let initial = input.byte()?;
+    let (major, info) = (initial >> 5, initial & 0x1f);
+    if major == 7 {
+        return simple(input, info);
+    }
+    let argument = argument(input, info)?;
+    match (major, argument) {
+        (0, Some(n)) => Ok(Value::from(n)),
+        (1, Some(n)) => Ok(match i64::try_from(n) {
+            Ok(n) => Value::from(-1 - n),
+            Err(_) => float(-1.0 - n as f64),
+        }),
+        (2, _) => Err("Byte strings are not supported".into()),
+        (3, len) => text(input, len).map(Value::String),
+        (4, len) => {
+            let mut array = Vec::new();
+            items(input, len, |input| {
+                array.push(item(input, depth + 1)?);
+                Ok(())
+            })?;
+            Ok(Value::Array(array))
+        }
+        (5, len) => {
+            let mut map = Map::new();
+            items(input, len, |input| {
+                let Value::String(key) = item(input, depth + 1)? else {
+                    return Err("Map keys must be strings".into());
+                };
+                map.insert(key, item(input, depth + 1)?);
+                Ok(())
+            })?;
+            Ok(Value::Object(map))
+        }
+        (6, Some(1)) => match item(input, depth + 1)? {
+            Value::Number(seconds) => {
+                let seconds = seconds.as_f64().unwrap_or_default();
+                // Negative timestamps have a negative fraction, the
+                // nanoseconds count from the second before them
+                let nanoseconds = ((seconds - seconds.floor()) * 1e9).round();
+                timestamp(
+                    seconds.floor() as i64,
+                    nanoseconds.min(999_999_999.0) as u32,
+                )
+            }
+            _ => Err("Epoch timestamps must be numbers".into()),
+        },
+        (6, Some(_)) => item(input, depth + 1),
+        _ => Err(format!("Invalid length of major type {major}")),
+    }
+}
+
+// The argument of a data item, `None` for an indefinite length
+fn argument(input: &mut Input, info: u8) -> Result<Option<u64>, String> {
+    let len = match info {
+        0..=23 => return Ok(Some(u64::from(info))),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        31 => return Ok(None),
+        _ => return Err(format!("Invalid additional information {info}")),
+    };
+    input.uint(len).map(Some)
+}
+
+// Reads the given number of items (or pairs of a map), or up to the
+// break for an indefinite length. A definite length is checked against
+// the rest of the input first (see `Input::fits`).
+fn items(
+    input: &mut Input,
+    len: Option<u64>,
+    mut read: impl FnMut(&mut Input) -> Result<(), String>,
+) -> Result<(), String> {
+    match len {
+        Some(len) => {
+            input.fits(len)?;
+            (0..len).try_for_each(|_| read(input))
+        }
+        None => {
+            while input.peek() != Some(BREAK) {
+                read(input)?;
+            }
+            input.byte().map(|_| ())
+        }
+    }
+}
+
+// A text string. Strings of indefinite length are a series of chunks of
+// definite length.
+fn text(input: &mut Input, len: Option<u64>) -> Result<String, String> {
+    let Some(len) = len else {
+        let mut text = String::new();
+        items(input, None, |input| {
+            let initial = input.byte()?;
+            match argument(input, initial & 0x1f)? {
+                Some(len) if initial >> 5 == 3 => {
+                    text.push_str(&input.string(len)?);
+                    Ok(())
+                }
+                _ => Err("Invalid chunk of a text string".into()),
+            }
+        })?;
+        return Ok(text);
+    };
+    input.string(len)
+}
+
+// The simple values and floats of major type 7
+fn simple(input: &mut Input, info: u8) -> Result<Value, String> {
+    match info {
+        20 => Ok(Value::Bool(false)),
+        21 => Ok(Value::Bool(true)),
+        // Undefined has no JSON equivalent either
+        22 | 23 => Ok(Value::Null),
+        25 => Ok(float(half(input.uint(2)? as u16))),
+        26 => Ok(single(input.uint(4)? as u32)),
+        27 => Ok(float(f64::from_bits(input.uint(8)?))),
+        _ => Err(format!("Unsupported simple value {info}")),
+    }
+}
+
+// A half-precision float, which Rust has no type for
+fn half(bits: u16) -> f64 {
+    let exponent = i32::from((bits >> 10) & 0x1f);
+    let mantissa = f64::from(bits & 0x3ff);
+    let value = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mantissa + 1024.0) * 2f64.powi(exponent - 25),
+    };
+    if bits & 0x8000 != 0 {
+        -value
+    } else {
+        value
+    }
+}
+
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    write(&mut out, value);
+    out
+}
+
+fn write(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => out.push(0xf6),
+        Value::Bool(false) => out.push(0xf4),
+        Value::Bool(true) => out.push(0xf5),
+        Value::Number(number) => {
+            if let Some(n) = number.as_u64() {
+                head(out, 0, n);
+            } else if let Some(n) = number.as_i64() {
+                head(out, 1, (-1 - n) as u64);
+            } else {
+                let value = number.as_f64().unwrap_or_default();
+                match narrow(value) {
+                    Some(value) => {
+                        out.push(0xfa);
+                        out.extend(value.to_be_bytes());
+                    }
+                    None => {
+                        out.push(0xfb);
+                        out.extend(value.to_be_bytes());
+                    }
+                }
+            }
+        }
+        Value::String(string) => {
+            head(out, 3, string.len() as u64);
+            out.extend(string.as_bytes());
+        }
+        Value::Array(array) => {
+            head(out, 4, array.len() as u64);
+            array.iter().for_each(|value| write(out, value));
+        }
+        Value::Object(map) => {
+            head(out, 5, map.len() as u64);
+            for (key, value) in map {
+                head(out, 3, key.len() as u64);
+                out.extend(key.as_bytes());
+                write(out, value);
+            }
+        }
+    }
+}
+
+// The initial byte of a data item and its argument in as few bytes as
+// possible
+fn head(out: &mut Vec<u8>, major: u8, argument: u64) {
+    let major = major << 5;
+    match argument {
+        0..=23 => out.push(major | argument as u8),
+        24..=0xff => out.extend([major | 24, argument as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend((argument as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend((argument as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend(argument.to_be_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn decode_hex(hex: &str) -> Result<Value, String> {
+        decode(&unhex(hex))
+    }
+
+    // The examples of RFC 8949, Appendix A, that have a JSON equivalent
+    #[test]
+    fn decodes_rfc_examples() {
+        let examples = [
+            ("00", json!(0)),
+            ("01", json!(1)),
+            ("0a", json!(10)),
+            ("17", json!(23)),
+            ("1818", json!(24)),
+            ("1819", json!(25)),
+            ("1864", json!(100)),
+            ("1903e8", json!(1000)),
+            ("1a000f4240", json!(1000000)),
+            ("1b000000e8d4a51000", json!(1000000000000u64)),
+            ("1bffffffffffffffff", json!(18446744073709551615u64)),
+            ("3bffffffffffffffff", json!(-18446744073709551616.0)),
+            ("20", json!(-1)),
+            ("29", json!(-10)),
+            ("3863", json!(-100)),
+            ("3903e7", json!(-1000)),
+            ("f90000", json!(0.0)),
+            ("f98000", json!(-0.0)),
+            ("f93c00", json!(1.0)),
+            ("fb3ff199999999999a", json!(1.1)),
+            ("f93e00", json!(1.5)),
+            ("f97bff", json!(65504.0)),
+            ("fa47c35000", json!(100000.0)),
+            // The shortest representation of the f32, which JSON would
+            // have as well, not 3.4028234663852886e+38
+            ("fa7f7fffff", json!(3.4028235e38)),
+            ("fb7e37e43c8800759c", json!(1.0e300)),
+            ("f90001", json!(5.960464477539063e-8)),
+            ("f90400", json!(0.00006103515625)),
+            ("f9c400", json!(-4.0)),
+            ("fbc010666666666666", json!(-4.1)),
+            // JSON has no infinity or NaN
+            ("f97c00", json!(null)),
+            ("f97e00", json!(null)),
+            ("f9fc00", json!(null)),
+            ("fa7f800000", json!(null)),
+            ("fb7ff8000000000000", json!(null)),
+            ("f4", json!(false)),
+            ("f5", json!(true)),
+            ("f6", json!(null)),
+            ("f7", json!(null)),
+            (
+                "c074323031332d30332d32315432303a30343a30305a",
+                json!("2013-03-21T20:04:00Z"),
+            ),
+            ("c11a514b67b0", json!("2013-03-21T20:04:00Z")),
+            ("c1fb41d452d9ec200000", json!("2013-03-21T20:04:00.500Z")),
+            ("60", json!("")),
+            ("6161", json!("a")),
+            ("6449455446", json!("IETF")),
+            ("62225c", json!("\"\\")),
+            ("62c3bc", json!("\u{fc}")),
+            ("63e6b0b4", json!("\u{6c34}")),
+            ("64f0908591", json!("\u{10151}")),
+            ("80", json!([])),
+            ("83010203", json!([1, 2, 3])),
+            ("8301820203820405", json!([1, [2, 3], [4, 5]])),
+            (
+                "98190102030405060708090a0b0c0d0e0f101112131415161718181819",
+                json!((1..=25).collect::<Vec<_>>()),
+            ),
+            ("a0", json!({})),
+            ("a26161016162820203", json!({"a": 1, "b": [2, 3]})),
+            ("826161a161626163", json!(["a", {"b": "c"}])),
+            (
+                "a56161614161626142616361436164614461656145",
+                json!({"a": "A", "b": "B", "c": "C", "d": "D", "e": "E"}),
+            ),
+            ("7f657374726561646d696e67ff", json!("streaming")),
+            ("9fff", json!([])),
+            ("9f018202039f0405ffff", json!([1, [2, 3], [4, 5]])),
+            ("9f01820203820405ff", json!([1, [2, 3], [4, 5]])),
+            ("83018202039f0405ff", json!([1, [2, 3], [4, 5]])),
+            ("83019f0203ff820405", json!([1, [2, 3], [4, 5]])),
+            (
+                "9f0102030405060708090a0b0c0d0e0f101112131415161718181819ff",
+                json!((1..=25).collect::<Vec<_>>()),
+            ),
+            ("bf61610161629f0203ffff", json!({"a": 1, "b": [2, 3]})),
+            ("826161bf61626163ff", json!(["a", {"b": "c"}])),
+            ("bf6346756ef563416d7421ff", json!({"Fun": true, "Amt": -2})),
+        ];
+        for (hex, expected) in examples {
+            assert_eq!(decode_hex(hex), Ok(expected), "{hex}");
+        }
+    }
+
+    // The examples without a JSON equivalent
+    #[test]
+    fn rejects_unsupported_items() {
+        for hex in [
+            "40",
+            "4401020304",
+            "d74401020304",
+            "a201020304",
+            "f0",
+            "f8ff",
+        ] {
+            assert!(decode_hex(hex).is_err(), "{hex}");
+        }
+    }
+
+    #[test]
+    fn encodes_rfc_examples() {
+        let examples = [
+            (json!(0), "00"),
+            (json!(24), "1818"),
+            (json!(1000000), "1a000f4240"),
+            (json!(18446744073709551615u64), "1bffffffffffffffff"),
+            (json!(-1000), "3903e7"),
+            (json!(100000.0), "fa47c35000"),
+            (json!(1.0e300), "fb7e37e43c8800759c"),
+            // Unlike in the RFC, floats are written as f32 if that reads
+            // back as the same number, since most values are f32
+            (json!(1.1), "fa3f8ccccd"),
+            (json!(null), "f6"),
+            (json!("IETF"), "6449455446"),
+            (json!([1, [2, 3], [4, 5]]), "8301820203820405"),
+            (json!({"a": 1, "b": [2, 3]}), "a26161016162820203"),
+        ];
+        for (value, hex) in examples {
+            assert_eq!(encode(&value), unhex(hex), "{value}");
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let values = [
+            json!({
+                "Input1": { "dataType": "Number", "value": 21.5, "quality": 192,
+                            "timestamp": "2024-05-01T12:00:00Z" },
+                "Input2": { "dataType": "String", "value": "n/a", "quality": null }
+            }),
+            json!([i64::MIN, -129, -33, -24, 0, 255, 65536, u64::MAX]),
+            json!([0.1, -2.5e-8, 1.0e300, f64::MAX]),
+            json!("x".repeat(300)),
+            json!((0..300).collect::<Vec<_>>()),
+        ];
+        for value in values {
+            assert_eq!(decode(&encode(&value)), Ok(value.clone()), "{value}");
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        for hex in [
+            "", "18", "1903", "830102", "a16161", "64494554", "fb3ff1", "9f01", "c1",
+        ] {
+            assert!(decode_hex(hex).is_err(), "{hex}");
+        }
+        // Trailing data is not part of the item
+        assert!(decode_hex("0102").is_err());
+    }
+
+    #[test]
+    fn rejects_huge_lengths() {
+        for hex in [
+            "9bffffffffffffffff",
+            "bbffffffffffffffff",
+            "7bffffffffffffffff",
+            "9a7fffffff00",
+            "7a7fffffff61",
+        ] {
+            assert!(decode_hex(hex).is_err(), "{hex}");
+        }
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let nested = |depth| "81".repeat(depth) + "01";
+        assert!(decode_hex(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(
+            decode_hex(&nested(MAX_DEPTH + 1)),
+            Err("Nested too deeply".into())
+        );
+        assert!(decode_hex(&"9f".repeat(100_000)).is_err());
+        assert!(decode_hex(&"c1".repeat(100_000)).is_err());
+    }
+
+    #[test]
+    fn decodes_negative_epoch_timestamps() {
+        // 1(-1.5), half a second before the second before the epoch
+        assert_eq!(
+            decode_hex("c1f9be00"),
+            Ok(json!("1969-12-31T23:59:58.500Z"))
+        );
+    }
+}
@@ -0,0 +1,383 @@
+// A minimal MessagePack decoder and encoder for JSON values. Every
+// value starts with a marker byte of its type, which for small integers,
+// strings, arrays and maps also holds the value or length itself (the
+// "fix" types). Binary data has no JSON equivalent and is rejected, as
+// are extension types other than timestamps (-1), which become RFC 3339
+// strings.
+
+use serde_json::{Map, Value};
+
+use super::{float, narrow, single, timestamp, Input, MAX_DEPTH};
+
+const TIMESTAMP_EXTENSION: i8 = -1;
+
+pub fn decode(input: &[u8]) -> Result<Value, String> {
+    let mut input = Input::new(input);
+    let value = value(&mut input, 0)?;
+    input.end()?;
+    Ok(value)
+}
+
+fn value(input: &mut Input, depth: usize) -> Result<Value, String> {
+    if depth > MAX_DEPTH {
+        return Err("Nested too deeply".into());
+    }
+    let marker = input.byte()?;
+    match marker {
+        0x00..=0x7f => Ok(Value::from(marker)),
+        0x80..=0x8f => map(input, u64::from(marker & 0x0f), depth),
+        0x90..=0x9f => array(input, u64::from(marker & 0x0f), depth),
+        0xa0..=0xbf => input.string(u64::from(marker & 0x1f)).map(Value::String),
+        0xc0 => Ok(Value::Null),
+        0xc2 => Ok(Value::Bool(false)),
+        0xc3 => Ok(Value::Bool(true)),
+        0xc4..=0xc6 => Err("Binary data is not supported".into()),
+        0xc7..=0xc9 => {
+            let len = input.uint(1 << (marker - 0xc7))?;
+            extension(input, len)
+        }
+        0xca => Ok(single(input.uint(4)? as u32)),
+        0xcb => Ok(float(f64::from_bits(input.uint(8)?))),
+        0xcc..=0xcf => input.uint(1 << (marker - 0xcc)).map(Value::from),
+        0xd0..=0xd3 => {
+            let len = 1 << (marker - 0xd0);
+            // The integer is sign-extended from its length
+            let shift = 64 - 8 * len;
+            let n = ((input.uint(len)? << shift) as i64) >> shift;
+            Ok(Value::from(n))
+        }
+        0xd4..=0xd8 => extension(input, 1 << (marker - 0xd4)),
+        0xd9..=0xdb => {
+            let len = input.uint(1 << (marker - 0xd9))?;
+            input.string(len).map(Value::String)
+        }
+        0xdc | 0xdd => {
+            let len = input.uint(2 << (marker - 0xdc))?;
+            array(input, len, depth)
+        }
+        0xde | 0xdf => {
+            let len = input.uint(2 << (marker - 0xde))?;
+            map(input, len, depth)
+        }
+        0xe0..=0xff => Ok(Value::from(marker as i8)),
+        0xc1 => Err("Invalid marker 0xc1".into()),
+    }
+}
+
+// The elements of an array. The length is read from the request, so it
+// is checked against the input and not trusted to reserve memory.
+fn array(input: &mut Input, len: u64, depth: usize) -> Result<Value, String> {
+    input.fits(len)?;
+    let mut array = Vec::new();
+    for _ in 0..len {
+        array.push(value(input, depth + 1)?);
+    }
+    Ok(Value::Array(array))
+}
+
+fn map(input: &mut Input, len: u64, depth: usize) -> Result<Value, String> {
+    input.fits(len)?;
+    let mut map = Map::new();
+    for _ in 0..len {
+        let Value::String(key) = value(input, depth + 1)? else {
+            return Err("Map keys must be strings".into());
+        };
+        map.insert(key, value(input, depth + 1)?);
+    }
+    Ok(Value::Object(map))
+}
+
+// An extension with data of the given length, after its type. Timestamps
+// have 32 bits of seconds, 30 bits of nanoseconds and 34 bits of
+// seconds, or 32 bits of nanoseconds and 64 bits of seconds.
+fn extension(input: &mut Input, len: u64) -> Result<Value, String> {
+    let extension_type = input.byte()? as i8;
+    if extension_type != TIMESTAMP_EXTENSION {
+        return Err(format!("Extension type {extension_type} is not supported"));
+    }
+    match len {
+        4 => timestamp(input.uint(4)? as i64, 0),
+        8 => {
+            let data = input.uint(8)?;
+            timestamp((data & 0x3_ffff_ffff) as i64, (data >> 34) as u32)
+        }
+        12 => {
+            let nanoseconds = input.uint(4)? as u32;
+            timestamp(input.uint(8)? as i64, nanoseconds)
+        }
+        _ => Err(format!("Invalid timestamp of {len} bytes")),
+    }
+}
+
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    write(&mut out, value);
+    out
+}
+
+fn write(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => out.push(0xc0),
+        Value::Bool(false) => out.push(0xc2),
+        Value::Bool(true) => out.push(0xc3),
+        Value::Number(number) => {
+            if let Some(n) = number.as_u64() {
+                write_uint(out, n);
+            } else if let Some(n) = number.as_i64() {
+                write_int(out, n);
+            } else {
+                let value = number.as_f64().unwrap_or_default();
+                match narrow(value) {
+                    Some(value) => {
+                        out.push(0xca);
+                        out.extend(value.to_be_bytes());
+                    }
+                    None => {
+                        out.push(0xcb);
+                        out.extend(value.to_be_bytes());
+                    }
+                }
+            }
+        }
+        Value::String(string) => write_string(out, string),
+        Value::Array(array) => {
+            write_len(out, array.len(), 0x90, 0xdc);
+            array.iter().for_each(|value| write(out, value));
+        }
+        Value::Object(map) => {
+            write_len(out, map.len(), 0x80, 0xde);
+            for (key, value) in map {
+                write_string(out, key);
+                write(out, value);
+            }
+        }
+    }
+}
+
+fn write_uint(out: &mut Vec<u8>, n: u64) {
+    match n {
+        0..=0x7f => out.push(n as u8),
+        0x80..=0xff => out.extend([0xcc, n as u8]),
+        0x100..=0xffff => {
+            out.push(0xcd);
+            out.extend((n as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(0xce);
+            out.extend((n as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(0xcf);
+            out.extend(n.to_be_bytes());
+        }
+    }
+}
+
+// Writes a negative integer
+fn write_int(out: &mut Vec<u8>, n: i64) {
+    if n >= -32 {
+        out.push(n as u8);
+    } else if n >= i64::from(i8::MIN) {
+        out.extend([0xd0, n as u8]);
+    } else if n >= i64::from(i16::MIN) {
+        out.push(0xd1);
+        out.extend((n as i16).to_be_bytes());
+    } else if n >= i64::from(i32::MIN) {
+        out.push(0xd2);
+        out.extend((n as i32).to_be_bytes());
+    } else {
+        out.push(0xd3);
+        out.extend(n.to_be_bytes());
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, string: &str) {
+    let len = string.len();
+    match len {
+        0..=31 => out.push(0xa0 | len as u8),
+        32..=0xff => out.extend([0xd9, len as u8]),
+        0x100..=0xffff => {
+            out.push(0xda);
+            out.extend((len as u16).to_be_bytes());
+        }
+        _ => {
+            out.push(0xdb);
+            out.extend((len as u32).to_be_bytes());
+        }
+    }
+    out.extend(string.as_bytes());
+}
+
+// Writes the marker and length of an array or map, with the fix marker
+// for up to 15 elements, else with a 16 or 32 bit length
+fn write_len(out: &mut Vec<u8>, len: usize, fix: u8, marker16: u8) {
+    match len {
+        0..=15 => out.push(fix | len as u8),
+        16..=0xffff => {
+            out.push(marker16);
+            out.extend((len as u16).to_be_bytes());
+        }
+        _ => {
+            out.push(marker16 + 1);
+            out.extend((len as u32).to_be_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn decode_hex(hex: &str) -> Result<Value, String> {
+        decode(&unhex(hex))
+    }
+
+    // Every format of the MessagePack specification that has a JSON
+    // equivalent
+    #[test]
+    fn decodes_formats() {
+        let examples = [
+            ("00", json!(0)),
+            ("7f", json!(127)),
+            ("e0", json!(-32)),
+            ("ff", json!(-1)),
+            ("ccff", json!(255)),
+            ("cd0100", json!(256)),
+            ("ce00010000", json!(65536)),
+            ("cfffffffffffffffff", json!(u64::MAX)),
+            ("d080", json!(-128)),
+            ("d1ff7f", json!(-129)),
+            ("d2ffff7fff", json!(-32769)),
+            ("d38000000000000000", json!(i64::MIN)),
+            ("ca3fc00000", json!(1.5)),
+            ("ca3dcccccd", json!(0.1)),
+            ("cb3ff199999999999a", json!(1.1)),
+            ("cb7ff0000000000000", json!(null)),
+            ("c0", json!(null)),
+            ("c2", json!(false)),
+            ("c3", json!(true)),
+            ("a0", json!("")),
+            ("a449455446", json!("IETF")),
+            ("d90449455446", json!("IETF")),
+            ("da000449455446", json!("IETF")),
+            ("db0000000449455446", json!("IETF")),
+            ("90", json!([])),
+            ("93010203", json!([1, 2, 3])),
+            ("dc0003010203", json!([1, 2, 3])),
+            ("dd00000003010203", json!([1, 2, 3])),
+            ("80", json!({})),
+            ("82a16101a162920203", json!({"a": 1, "b": [2, 3]})),
+            ("de0001a161a162", json!({"a": "b"})),
+            ("df00000001a161a162", json!({"a": "b"})),
+            // The timestamp 1363896240 (.5) in 32, 64 and 96 bits
+            ("d6ff514b67b0", json!("2013-03-21T20:04:00Z")),
+            ("d7ff77359400514b67b0", json!("2013-03-21T20:04:00.500Z")),
+            (
+                "c70cff1dcd650000000000514b67b0",
+                json!("2013-03-21T20:04:00.500Z"),
+            ),
+            (
+                "c70cff00000000ffffffffffffffff",
+                json!("1969-12-31T23:59:59Z"),
+            ),
+        ];
+        for (hex, expected) in examples {
+            assert_eq!(decode_hex(hex), Ok(expected), "{hex}");
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_values() {
+        // Binary data, an extension other than timestamps, a timestamp
+        // of the wrong size, a map key that is not a string and 0xc1
+        for hex in ["c40100", "d40100", "d5ff0000", "810102", "c1"] {
+            assert!(decode_hex(hex).is_err(), "{hex}");
+        }
+    }
+
+    #[test]
+    fn encodes_smallest_formats() {
+        let examples = [
+            (json!(127), "7f"),
+            (json!(-32), "e0"),
+            (json!(-33), "d0df"),
+            (json!(256), "cd0100"),
+            (json!(i64::MIN), "d38000000000000000"),
+            (json!(1.5), "ca3fc00000"),
+            (json!(1.0e300), "cb7e37e43c8800759c"),
+            (json!("IETF"), "a449455446"),
+            (json!("x".repeat(32)), &format!("d920{}", "78".repeat(32))),
+            (
+                json!((0..16).collect::<Vec<_>>()),
+                "dc0010000102030405060708090a0b0c0d0e0f",
+            ),
+            (json!({"a": 1, "b": [2, 3]}), "82a16101a162920203"),
+        ];
+        for (value, hex) in examples {
+            assert_eq!(encode(&value), unhex(hex), "{value}");
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let values = [
+            json!({
+                "Input1": { "dataType": "Number", "value": 21.5, "quality": 192,
+                            "timestamp": "2024-05-01T12:00:00Z" },
+                "Input2": { "dataType": "String", "value": "n/a", "quality": null }
+            }),
+            json!([i64::MIN, -32769, -129, -33, -32, 0, 255, 65536, u64::MAX]),
+            json!([0.1, -2.5e-8, 1.0e300, f64::MAX]),
+            json!("x".repeat(70000)),
+            json!((0..70000).collect::<Vec<_>>()),
+            Value::Object((0..20).map(|i| (i.to_string(), json!(i))).collect()),
+        ];
+        for value in values {
+            assert_eq!(decode(&encode(&value)), Ok(value.clone()));
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        for hex in [
+            "", "cc", "cd01", "cb3ff1", "9201", "a36162", "81a161", "d6ff51", "dc00",
+        ] {
+            assert!(decode_hex(hex).is_err(), "{hex}");
+        }
+        // Trailing data is not part of the value
+        assert!(decode_hex("0102").is_err());
+    }
+
+    #[test]
+    fn rejects_huge_lengths() {
+        for hex in [
+            "ddffffffff",
+            "dfffffffff",
+            "dbffffffff",
+            "c9ffffffffff",
+            "dcffff01",
+        ] {
+            assert!(decode_hex(hex).is_err(), "{hex}");
+        }
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let nested = |depth| "91".repeat(depth) + "01";
+        assert!(decode_hex(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(
+            decode_hex(&nested(MAX_DEPTH + 1)),
+            Err("Nested too deeply".into())
+        );
+        assert!(decode_hex(&"81a0".repeat(100_000)).is_err());
+    }
+}
@@ -0,0 +1,53 @@
+// This module caps the size of request bodies, so that a client cannot
+// exhaust the memory of a small device with a huge upload. The body is
+// read in chunks (see `read_body` in http.rs), and reading stops with
+// 413 (Content Too Large) as soon as it exceeds the limit, or before it
+// starts if the Content-Length announces a larger body. The limit is set
+// in the optional `body-limit` config (see config.rs):
+//
+// { "max_bytes": 262144 }
+//
+// Without the config, bodies are limited to DEFAULT_MAX_BYTES, which is
+// plenty for a data window, a batch or an image of a vision model.
+
+use serde::Deserialize;
+
+use crate::{config, error::Error};
+
+const CONFIG: &str = "body-limit";
+
+const DEFAULT_MAX_BYTES: u64 = 1024 * 1024;
+
+#[derive(Deserialize)]
+struct BodyLimit {
+    max_bytes: u64,
+}
+
+// The maximum number of bytes of a request body
+pub fn max_bytes() -> Result<u64, Error> {
+    let limit: Option<BodyLimit> = config::load(CONFIG)?;
+    Ok(limit.map_or(DEFAULT_MAX_BYTES, |limit| limit.max_bytes))
+}
+
+// Fails if the Content-Length header announces a body that exceeds the
+// limit. Bodies without the header are checked while they are read.
+pub fn check_announced(headers: &[(String, Vec<u8>)], max_bytes: u64) -> Result<(), Error> {
+    let content_length = headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| std::str::from_utf8(value).ok()?.trim().parse().ok());
+    match content_length {
+        Some(len) => check(len, max_bytes),
+        None => Ok(()),
+    }
+}
+
+// Fails if a body of the given size exceeds the limit
+pub fn check(len: u64, max_bytes: u64) -> Result<(), Error> {
+    if len > max_bytes {
+        return Err(Error::ContentTooLarge(format!(
+            "Request body exceeds the limit of {max_bytes} bytes"
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,160 @@
+// This module is a circuit breaker around the model: If loading or
+// running the model fails several times in a row (e.g. because the
+// accelerator is wedged), the circuit opens and the model is not tried
+// again for a cool-down period. In the meantime, forecasts fail at once
+// (or use the statistical fallback, see fallback.rs) instead of every
+// request waiting for the backend to fail again. After the cool-down,
+// the next request tries the model again, and the circuit closes if it
+// succeeds.
+//
+// Since the component does not keep state across requests, the state
+// of the circuit is stored in the state directory (see state.rs). The
+// defaults can be changed in the optional `breaker` config (see
+// config.rs):
+//
+// { "failures": 3, "cool_down_seconds": 60 }
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use wasi::http::types::ErrorCode;
+
+use crate::{
+    clock::{self, Clock},
+    config,
+    error::Error,
+    state,
+};
+
+const CONFIG: &str = "breaker";
+const STATE_FILE: &str = "breaker.json";
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct Breaker {
+    // The number of consecutive failures that opens the circuit
+    failures: u32,
+    cool_down_seconds: u32,
+}
+
+impl Default for Breaker {
+    fn default() -> Self {
+        Self {
+            failures: 3,
+            cool_down_seconds: 60,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Circuit {
+    consecutive_failures: u32,
+    // The circuit is open until this time
+    open_until: Option<DateTime<Utc>>,
+}
+
+impl Circuit {
+    fn is_open(&self, now: DateTime<Utc>) -> bool {
+        self.open_until.is_some_and(|open_until| now < open_until)
+    }
+
+    // The seconds until the circuit closes, if it is open. Rounded up,
+    // so that clients do not retry too early.
+    #[cfg_attr(not(any(test, feature = "http")), allow(dead_code))]
+    fn retry_after(&self, clock: &dyn Clock) -> Option<u64> {
+        let now = clock.now();
+        self.open_until
+            .filter(|_| self.is_open(now))
+            .map(|open_until| (open_until - now).num_seconds() as u64 + 1)
+    }
+}
+
+// Runs the model using `forecast`, unless the circuit is open, and
+// records whether it failed
+pub fn call<T>(forecast: impl FnOnce() -> Result<T, ErrorCode>) -> Result<T, ErrorCode> {
+    let internal = |e: Error| ErrorCode::InternalError(Some(e.to_string()));
+    let breaker: Breaker = config::load(CONFIG).map_err(internal)?.unwrap_or_default();
+    let mut circuit: Circuit = state::load(STATE_FILE).map_err(internal)?;
+
+    let now = clock::now();
+    if circuit.is_open(now) {
+        return Err(ErrorCode::InternalError(Some(format!(
+            "The model failed {} times in a row and is not tried again until {}",
+            circuit.consecutive_failures,
+            circuit.open_until.unwrap_or(now).to_rfc3339()
+        ))));
+    }
+
+    let result = forecast();
+    match &result {
+        Ok(_) if circuit.consecutive_failures == 0 => return result,
+        Ok(_) => circuit = Circuit::default(),
+        // A disabled model has not failed (see admin.rs)
+        #[cfg(feature = "admin")]
+        Err(e) if crate::admin::is_unavailable_error(e) => return result,
+        Err(e) => {
+            circuit.consecutive_failures += 1;
+            if circuit.consecutive_failures >= breaker.failures {
+                let cool_down = TimeDelta::seconds(breaker.cool_down_seconds.into());
+                circuit.open_until = Some(now + cool_down);
+                eprintln!(
+                    "Model failed {} times in a row, opening the circuit for {cool_down}: {e:?}",
+                    circuit.consecutive_failures
+                );
+            }
+        }
+    }
+    state::save(STATE_FILE, &circuit).map_err(internal)?;
+    result
+}
+
+// Turns internal errors into 503 responses while the circuit is open,
+// telling the client when to try again
+#[cfg(feature = "http")]
+pub fn unavailable(error: Error) -> Error {
+    let Error::Internal(_) = error else {
+        return error;
+    };
+    let Ok(circuit) = state::load::<Circuit>(STATE_FILE) else {
+        return error;
+    };
+    match circuit.retry_after(clock::clock()) {
+        Some(retry_after) => Error::Unavailable {
+            message: error.to_string(),
+            retry_after,
+        },
+        None => error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+
+    fn at(time: &str) -> FixedClock {
+        FixedClock(
+            DateTime::parse_from_rfc3339(time)
+                .unwrap()
+                .with_timezone(&Utc),
+        )
+    }
+
+    #[test]
+    fn tells_when_to_retry_while_open() {
+        let circuit = Circuit {
+            consecutive_failures: 3,
+            open_until: Some(at("2024-05-01T12:01:00Z").0),
+        };
+        assert_eq!(circuit.retry_after(&at("2024-05-01T12:00:00Z")), Some(61));
+        assert_eq!(circuit.retry_after(&at("2024-05-01T12:00:59Z")), Some(2));
+        assert_eq!(circuit.retry_after(&at("2024-05-01T12:01:00Z")), None);
+    }
+
+    #[test]
+    fn closed_circuits_need_no_retry() {
+        assert_eq!(
+            Circuit::default().retry_after(&at("2024-05-01T12:00:00Z")),
+            None
+        );
+    }
+}
@@ -0,0 +1,95 @@
+// This module keeps the compute load of forecasts below a budget, so
+// that the latency stays predictable when the device is shared with
+// other workloads. The cost of a forecast is estimated as the number of
+// data points in the window times the horizon, times the weight of the
+// series (e.g. for series that are routed to a larger model, see
+// routing.rs). The budget is a token bucket that is refilled with the
+// configured cost per second and holds at most one second worth of it.
+// A forecast that does not fit waits until it does, or is rejected with
+// 503 and a `Retry-After` header if that would take too long.
+//
+// The budget is configured in the `budget` config (see config.rs):
+//
+// { "per_second": 50000, "max_wait_seconds": 2, "weights": { "energy-1": 4 } }
+//
+// Since the component does not keep state across requests, the bucket
+// is stored in the state directory (see state.rs), and locked while a
+// forecast takes its cost from it.
+
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use wasi::clocks::monotonic_clock::subscribe_duration;
+
+use crate::{clock, config, error::Error, state};
+
+const CONFIG: &str = "budget";
+const STATE_FILE: &str = "budget.json";
+
+#[derive(Deserialize)]
+struct Budget {
+    per_second: f64,
+    #[serde(default)]
+    max_wait_seconds: f64,
+    // The weights of series that are more expensive than others,
+    // series without weight have the weight 1
+    #[serde(default)]
+    weights: HashMap<String, f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Bucket {
+    // Negative while forecasts wait for a reservation
+    available: f64,
+    updated: DateTime<Utc>,
+}
+
+// Takes the estimated cost of a forecast from the budget, waiting for
+// it if necessary
+pub fn charge(points: usize, horizon: u32, series: Option<&str>) -> Result<(), Error> {
+    let budget: Budget = config::require(CONFIG)?;
+    if budget.per_second <= 0.0 {
+        return Err(Error::internal("The budget per second must be positive"));
+    }
+    let weight = series
+        .and_then(|series| budget.weights.get(series))
+        .copied()
+        .unwrap_or(1.0);
+    // A forecast that costs more than the bucket holds waits for a full
+    // bucket instead of being rejected forever
+    let cost = (points as f64 * f64::from(horizon) * weight).min(budget.per_second);
+
+    // The bucket is locked while the cost is reserved, so that
+    // concurrent forecasts cannot all reserve against the same
+    // available budget (see state.rs)
+    let wait = state::update(STATE_FILE, |bucket: &mut Option<Bucket>| {
+        let now = clock::now();
+        let Bucket { available, updated } = bucket.take().unwrap_or(Bucket {
+            available: budget.per_second,
+            updated: now,
+        });
+        let elapsed = ((now - updated).num_milliseconds() as f64 / 1000.0).max(0.0);
+        let available = (available + elapsed * budget.per_second).min(budget.per_second);
+
+        let wait = (cost - available).max(0.0) / budget.per_second;
+        if wait > budget.max_wait_seconds {
+            return Err(Error::Unavailable {
+                message: "The compute budget is exhausted".to_string(),
+                retry_after: wait.ceil() as u64,
+            });
+        }
+
+        // The cost is reserved before waiting, so that later forecasts
+        // queue up behind this one
+        *bucket = Some(Bucket {
+            available: available - cost,
+            updated: now,
+        });
+        Ok(wait)
+    })?;
+    if wait > 0.0 {
+        subscribe_duration(Duration::from_secs_f64(wait).as_nanos() as u64).block();
+    }
+    Ok(())
+}
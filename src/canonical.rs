@@ -0,0 +1,70 @@
+// A canonical serialization of data windows, so that windows that only
+// differ in the order of their data points, the formatting of their
+// numbers or the time zone of their timestamps are recognized as the
+// same window. Everything that needs the identity of a window (e.g. the
+// ETag of a forecast) should use `hash`, so that they all agree on it.
+//
+// The canonical form is JSON with the data points sorted by key, the
+// members of a data point in a fixed order, numbers in their shortest
+// representation and timestamps in UTC:
+//
+// {"Input1":{"dataType":"Number","quality":1024,"timestamp":"2024-12-03T15:35:18.372Z","value":43.098183}}
+
+use std::collections::BTreeMap;
+
+use chrono::SecondsFormat;
+use wasi_nn_demo_lib::interface;
+
+use crate::{json, sha256};
+
+pub fn canonicalize(input: &interface::DataWindow) -> String {
+    let data_points: BTreeMap<_, _> = input.data.iter().collect();
+
+    let mut out = String::from("{");
+    for (i, (key, data_point)) in data_points.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        json::write_string(&mut out, key);
+        out.push_str(r#":{"dataType":"#);
+        match &data_point.value {
+            interface::Value::Number(_) => out.push_str(r#""Number""#),
+            interface::Value::String(_) => out.push_str(r#""String""#),
+        }
+        out.push_str(r#","quality":"#);
+        match data_point.quality {
+            Some(quality) => out.push_str(&quality.to_string()),
+            None => out.push_str("null"),
+        }
+        out.push_str(r#","timestamp":"#);
+        match data_point.timestamp {
+            Some(timestamp) => json::write_string(
+                &mut out,
+                &timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            ),
+            None => out.push_str("null"),
+        }
+        out.push_str(r#","value":"#);
+        match &data_point.value {
+            // JSON has no representation for NaN or infinity
+            interface::Value::Number(num) if num.is_finite() => out.push_str(&num.to_string()),
+            interface::Value::Number(_) => out.push_str("null"),
+            interface::Value::String(string) => json::write_string(&mut out, string),
+        }
+        out.push('}');
+    }
+    out.push('}');
+    out
+}
+
+// The SHA-256 hash (in hex) of the canonical window, followed by the
+// parameters that change the result for the window (e.g. the horizon),
+// each on its own line
+pub fn hash(input: &interface::DataWindow, params: &[&str]) -> String {
+    let mut canonical = canonicalize(input);
+    for param in params {
+        canonical.push('\n');
+        canonical.push_str(param);
+    }
+    sha256::hex(&sha256::sha256(canonical.as_bytes()))
+}
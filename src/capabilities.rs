@@ -0,0 +1,128 @@
+// This module probes which of the optional interfaces the host actually
+// provides. A component that imports an interface the host lacks does
+// not start at all, but a host can provide an interface without the
+// resource behind it, e.g. wasmCloud without a link to a key-value
+// provider, or a broker connection that is not configured. Without
+// probing, features that depend on them fail at first use, with an
+// error for every request.
+//
+// The probes run once per instance of the component, before its first
+// request is handled (see http.rs), and the features that depend on an
+// interface that is not available are disabled:
+//
+// - `config_store`: The config is read from files instead (see
+//   config.rs)
+// - `keyvalue`: The state is stored in the state directory instead
+//   (see state.rs)
+// - `messaging`: Forecasts are not published (see publish.rs)
+//
+// Only interfaces the component uses are probed. The capability matrix
+// is returned with `GET /version`, which also probes wasi-nn by loading
+// the forecasting model, since that is too expensive to do for every
+// instance:
+//
+// { "version": "0.1.0", "capabilities":
+//   { "config_store": true, "keyvalue": false, "messaging": true, "wasi_nn": true } }
+
+use std::{collections::BTreeMap, sync::OnceLock};
+
+use serde::Serialize;
+
+use crate::error::Error;
+
+// The capabilities of the host by the names of the interfaces, once
+// probed
+static CAPABILITIES: OnceLock<BTreeMap<&str, bool>> = OnceLock::new();
+
+#[derive(Serialize)]
+pub struct Version {
+    version: &'static str,
+    capabilities: BTreeMap<&'static str, bool>,
+}
+
+// Probes the host, unless this instance has already done so. Each
+// interface is probed by the module that uses it, which also disables
+// what depends on it.
+pub fn probe() -> &'static BTreeMap<&'static str, bool> {
+    CAPABILITIES.get_or_init(|| {
+        BTreeMap::from_iter([
+            // The config and the state are only read by some features
+            // (see lib.rs)
+            #[cfg(all(
+                feature = "config-store",
+                any(
+                    feature = "alerts",
+                    feature = "auth",
+                    feature = "body-limit",
+                    feature = "breaker",
+                    feature = "budget",
+                    feature = "calendar",
+                    feature = "cors",
+                    feature = "csv",
+                    feature = "encryption",
+                    feature = "fit-config",
+                    feature = "forward",
+                    feature = "homeassistant",
+                    feature = "integrity",
+                    feature = "lenient",
+                    feature = "model-config",
+                    feature = "model-limits",
+                    feature = "named-models",
+                    feature = "pipeline",
+                    feature = "pushgateway",
+                    feature = "queue",
+                    feature = "registry",
+                    feature = "resample",
+                    feature = "retention",
+                    feature = "routing",
+                    feature = "s3",
+                    feature = "schedule",
+                    feature = "sign",
+                    feature = "signature",
+                    feature = "trigger"
+                )
+            ))]
+            ("config_store", crate::config::probe()),
+            #[cfg(all(
+                feature = "keyvalue",
+                any(
+                    feature = "accuracy",
+                    feature = "admin",
+                    feature = "breaker",
+                    feature = "budget",
+                    feature = "ingest",
+                    feature = "latest",
+                    feature = "previous",
+                    feature = "prune",
+                    feature = "replay",
+                    feature = "retention",
+                    feature = "search",
+                    feature = "stale",
+                    feature = "stitch",
+                    feature = "trace",
+                    feature = "trigger",
+                    feature = "usage"
+                )
+            ))]
+            ("keyvalue", crate::state::probe()),
+            #[cfg(feature = "messaging")]
+            ("messaging", crate::messaging::probe()),
+        ])
+    })
+}
+
+// The version of the component with the capability matrix, including
+// wasi-nn
+pub fn version() -> Version {
+    let mut capabilities = probe().clone();
+    capabilities.insert("wasi_nn", crate::load_graph().is_ok());
+    Version {
+        version: env!("CARGO_PKG_VERSION"),
+        capabilities,
+    }
+}
+
+pub fn version_to_vec(version: &Version) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(version)
+        .map_err(|e| Error::internal(format!("Error serializing version: {e}")))
+}
@@ -0,0 +1,98 @@
+// This module detects a change point in the data window, i.e. a point
+// where the level of the series shifts (e.g. after maintenance or a
+// change of the operating mode). The model assumes that the history
+// continues, so a forecast for a window with a recent regime change
+// may be unreliable. The forecast is still returned, but annotated
+// with the change point (see http.rs and cli.rs).
+//
+// The detector is a CUSUM test for a single shift of the mean: For
+// every possible split of the window, the difference of the means
+// before and after the split is compared to the noise level of the
+// series. It assumes that the values fluctuate around a constant
+// level, so strongly seasonal series may need a higher threshold.
+
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use wasi_nn_demo_lib::interface;
+
+use crate::numeric_data_points;
+
+// A shift is reported if the difference of the means is larger than
+// this many standard errors
+const THRESHOLD: f32 = 5.0;
+// Both sides of a change point must contain at least this many values
+const MIN_SEGMENT_LEN: usize = 8;
+
+pub struct ChangePoint {
+    // The index of the first value after the change point within the
+    // (chronologically sorted) window and its timestamp
+    pub index: usize,
+    pub timestamp: Option<DateTime<Utc>>,
+    // The mean after the change point minus the mean before it
+    pub shift: f32,
+}
+
+// Returns the most significant change point of the window, if any
+pub fn detect(input: &interface::DataWindow) -> Option<ChangePoint> {
+    let data_points = numeric_data_points(input);
+    let values: Vec<_> = data_points.iter().map(|(_, value)| *value).collect();
+    let n = values.len();
+    if n < 2 * MIN_SEGMENT_LEN {
+        return None;
+    }
+
+    let noise = noise_level(&values)?;
+    let mean = values.iter().sum::<f32>() / n as f32;
+
+    // The cumulative sum of the deviations from the mean is
+    // proportional to the difference of the means before and after
+    // each index
+    let mut cusum = 0.0;
+    let mut best: Option<(usize, f32, f32)> = None;
+    for k in 1..n {
+        cusum += values[k - 1] - mean;
+        if k < MIN_SEGMENT_LEN || n - k < MIN_SEGMENT_LEN {
+            continue;
+        }
+        let scale = (k * (n - k)) as f32 / n as f32;
+        let statistic = cusum.abs() / (noise * scale.sqrt());
+        if best.is_none_or(|(_, best, _)| statistic > best) {
+            best = Some((k, statistic, -cusum / scale));
+        }
+    }
+
+    best.filter(|(_, statistic, _)| *statistic > THRESHOLD)
+        .map(|(index, _, shift)| ChangePoint {
+            index,
+            timestamp: data_points[index].0.timestamp,
+            shift,
+        })
+}
+
+// Estimates the standard deviation of the noise from the differences
+// of consecutive values. Using the median makes the estimate robust
+// against the shift we are looking for. Returns `None` for a constant
+// series, which has no change point.
+fn noise_level(values: &[f32]) -> Option<f32> {
+    let mut differences: Vec<_> = values.windows(2).map(|w| (w[1] - w[0]).abs()).collect();
+    differences.sort_by(f32::total_cmp);
+    let median = differences[differences.len() / 2];
+
+    // For normally distributed noise, the median absolute difference
+    // is 0.6745 * sqrt(2) standard deviations
+    let noise = median / (0.6745 * std::f32::consts::SQRT_2);
+    (noise > 0.0).then_some(noise)
+}
+
+// The change point in the format of the `change-point` response header,
+// e.g. `index=97; shift=-3.25; timestamp=2024-05-01T12:00:00Z`
+impl fmt::Display for ChangePoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "index={}; shift={}", self.index, self.shift)?;
+        if let Some(timestamp) = self.timestamp {
+            write!(f, "; timestamp={}", timestamp.to_rfc3339())?;
+        }
+        Ok(())
+    }
+}
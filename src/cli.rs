@@ -0,0 +1,74 @@
+// This module exports the wasi:cli/command world, which allows running
+// the component in batch mode using `wasmtime run`. It reads a single
+// JSON data window from stdin, runs the inference on it and writes
+// the result as JSON to stdout.
+// With the `--scheduled` argument, the data window is pulled from a
+// data source and the forecast is pushed to a sink instead (see
+// schedule.rs). With `--listen <address>:<port>`, forecasts are served
+// over TCP (see tcp.rs), and `--sync-models` downloads new model files
+// (see s3.rs).
+
+use std::io::{self, Read, Write};
+
+use wasi::{cli::command::export, exports::cli::run::Guest};
+
+use wasi_nn_demo_lib::http::RequestHandler;
+
+use crate::{error::Error, json, with_handler, Component};
+
+export!(Component);
+
+impl Guest for Component {
+    fn run() -> Result<(), ()> {
+        // The exit code of a wasi:cli command is just success or
+        // failure, so we report the actual error on stderr.
+        #[cfg(feature = "schedule")]
+        if std::env::args().any(|arg| arg == "--scheduled") {
+            return crate::schedule::run().map_err(|e| eprintln!("Error: {e}"));
+        }
+
+        #[cfg(feature = "s3")]
+        if std::env::args().any(|arg| arg == "--sync-models") {
+            return crate::s3::sync_models()
+                .and_then(|report| crate::s3::report_to_vec(&report))
+                .map(|report| println!("{}", String::from_utf8_lossy(&report)))
+                .map_err(|e| eprintln!("Error: {e}"));
+        }
+
+        #[cfg(feature = "tcp")]
+        if let Some(address) = std::env::args().skip_while(|arg| arg != "--listen").nth(1) {
+            return crate::tcp::listen(&address).map_err(|e| eprintln!("Error: {e}"));
+        }
+
+        run_batch().map_err(|e| eprintln!("Error: {e}"))
+    }
+}
+
+fn run_batch() -> Result<(), String> {
+    let mut input = Vec::new();
+    io::stdin()
+        .read_to_end(&mut input)
+        .map_err(|e| format!("Error reading stdin: {e}"))?;
+
+    // The input has the same format as the body of a HTTP request
+    let input = json::parse_data_window(&input).map_err(|e| e.to_string())?;
+
+    // There are no headers in batch mode, so a regime change in the
+    // window (see changepoint.rs) is reported on stderr
+    #[cfg(feature = "changepoint")]
+    if let Some(change_point) = crate::changepoint::detect(&input) {
+        eprintln!(
+            "Warning: Change point in the input, the forecast may be unreliable ({change_point})"
+        );
+    }
+
+    let output = with_handler(|handler| Ok(handler.handle_data(input)?))
+        .and_then(|result| json::inference_result_to_vec(&result))
+        .map_err(|e: Error| e.to_string())?;
+
+    let mut stdout = io::stdout();
+    stdout
+        .write_all(&output)
+        .and_then(|_| stdout.flush())
+        .map_err(|e| format!("Error writing stdout: {e}"))
+}
@@ -0,0 +1,93 @@
+// Everything that depends on the current time (TTLs like the cool-down
+// of the circuit breaker, the compute budget, replay protection, ...)
+// asks the clock returned by `clock` for it, so that time-dependent
+// behavior is consistent and can be reproduced. Normally, this is the
+// wall clock of the host (wasi:clocks). In builds with the `fixed-clock`
+// feature (and in tests), the `FIXED_TIME` environment variable can be
+// set to an RFC 3339 timestamp (`--env FIXED_TIME=2024-05-01T12:00:00Z`)
+// to let the time stand still at that point instead, e.g. to replay a
+// situation from a bug report. Other builds ignore the variable, since
+// a stray variable would otherwise stop token expiry, replay protection
+// and the like without any error.
+//
+// The logic that depends on the time takes the clock as a `&dyn Clock`
+// where it is worth testing (e.g. the replay window in replay.rs or the
+// retention of traces in trace.rs), so that tests can pass a
+// FixedClock. Only the code around it calls `clock`.
+
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Utc};
+use wasi::clocks::wall_clock;
+
+#[cfg(any(test, feature = "fixed-clock"))]
+const FIXED_TIME_VARIABLE: &str = "FIXED_TIME";
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+// The wall clock of the host
+pub struct WallClock;
+
+impl Clock for WallClock {
+    fn now(&self) -> DateTime<Utc> {
+        let now = wall_clock::now();
+        DateTime::from_timestamp(now.seconds as i64, now.nanoseconds).unwrap_or_default()
+    }
+}
+
+// A clock that always returns the same time
+#[cfg(any(test, feature = "fixed-clock"))]
+pub struct FixedClock(pub DateTime<Utc>);
+
+#[cfg(any(test, feature = "fixed-clock"))]
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+// The clock, which is chosen once per instance
+pub fn clock() -> &'static dyn Clock {
+    static CLOCK: OnceLock<Box<dyn Clock>> = OnceLock::new();
+
+    CLOCK
+        .get_or_init(|| {
+            #[cfg(any(test, feature = "fixed-clock"))]
+            if let Ok(fixed_time) = std::env::var(FIXED_TIME_VARIABLE) {
+                match DateTime::parse_from_rfc3339(&fixed_time) {
+                    Ok(fixed_time) => {
+                        eprintln!("The time is fixed at {fixed_time}");
+                        return Box::new(FixedClock(fixed_time.with_timezone(&Utc)));
+                    }
+                    Err(e) => eprintln!("Ignoring invalid {FIXED_TIME_VARIABLE} {fixed_time}: {e}"),
+                }
+            }
+            Box::new(WallClock)
+        })
+        .as_ref()
+}
+
+// The current time according to the clock
+#[cfg_attr(
+    not(any(
+        feature = "admin",
+        feature = "auth",
+        feature = "breaker",
+        feature = "budget",
+        feature = "ingest",
+        feature = "latest",
+        feature = "previous",
+        feature = "prune",
+        feature = "queue",
+        feature = "retention",
+        feature = "stale",
+        feature = "stitch",
+        feature = "usage"
+    )),
+    allow(dead_code)
+)]
+pub fn now() -> DateTime<Utc> {
+    clock().now()
+}
@@ -0,0 +1,156 @@
+// This module implements `POST /cluster`, which groups similar series,
+// e.g. sensors across a plant that behave alike. Each series is turned
+// into a vector by the embedding model (see embedding.rs), and the
+// vectors are clustered using k-means.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use wasi_nn_demo_lib::interface;
+
+use crate::{error::Error, http::JsonBody, HttpHandler};
+
+// The number of clusters if the client does not request one
+const DEFAULT_K: usize = 3;
+// k-means usually converges after a few iterations, this is only a
+// safeguard
+const MAX_ITERATIONS: usize = 100;
+
+#[derive(Deserialize)]
+pub struct ClusterRequest {
+    series: BTreeMap<String, interface::DataWindow>,
+    k: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct ClusterResult {
+    // The cluster (index) of each series, by name
+    assignments: BTreeMap<String, usize>,
+    // The names of the series in each cluster
+    clusters: Vec<Vec<String>>,
+    // The sum of the squared distances of the vectors to the center of
+    // their cluster, lower is tighter
+    inertia: f32,
+}
+
+impl JsonBody for ClusterRequest {
+    const NAME: &'static str = "cluster request";
+
+    fn check(self) -> Result<Self, Error> {
+        #[cfg(feature = "usage")]
+        crate::usage::record_data_points(
+            self.series.values().map(|window| window.data.len()).sum(),
+        );
+
+        let k = self.k.unwrap_or(DEFAULT_K);
+        if k == 0 || k > self.series.len() {
+            return Err(Error::BadRequest(format!(
+                "k must be between 1 and the number of series ({})",
+                self.series.len()
+            )));
+        }
+        Ok(self)
+    }
+}
+
+pub fn result_to_vec(result: &ClusterResult) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(result)
+        .map_err(|e| Error::internal(format!("Error serializing clusters: {e}")))
+}
+
+impl HttpHandler {
+    pub fn cluster(&mut self, request: ClusterRequest) -> Result<ClusterResult, Error> {
+        let k = request.k.unwrap_or(DEFAULT_K);
+        let windows: Vec<_> = request.series.values().collect();
+        let vectors = self.embed_windows(&windows)?;
+
+        let (assignments, inertia) = k_means(&vectors, k);
+
+        let mut clusters = vec![Vec::new(); k];
+        for (name, cluster) in request.series.keys().zip(&assignments) {
+            clusters[*cluster].push(name.clone());
+        }
+        Ok(ClusterResult {
+            assignments: request.series.into_keys().zip(assignments).collect(),
+            clusters,
+            inertia,
+        })
+    }
+}
+
+// Clusters the vectors into k clusters using Lloyd's algorithm and
+// returns the cluster of each vector and the inertia. There must be at
+// least k vectors.
+fn k_means(vectors: &[Vec<f32>], k: usize) -> (Vec<usize>, f32) {
+    let mut centroids = initial_centroids(vectors, k);
+    let mut assignments = vec![0; vectors.len()];
+
+    for iteration in 0..MAX_ITERATIONS {
+        // Assign each vector to the nearest centroid ...
+        let mut changed = false;
+        for (vector, assignment) in vectors.iter().zip(&mut assignments) {
+            let nearest = nearest(vector, &centroids).0;
+            changed |= nearest != *assignment;
+            *assignment = nearest;
+        }
+        if iteration > 0 && !changed {
+            break;
+        }
+
+        // ... and move each centroid to the mean of its vectors. A
+        // cluster that lost all of its vectors keeps its centroid.
+        for (cluster, centroid) in centroids.iter_mut().enumerate() {
+            let members: Vec<_> = vectors
+                .iter()
+                .zip(&assignments)
+                .filter(|(_, assignment)| **assignment == cluster)
+                .map(|(vector, _)| vector)
+                .collect();
+            if members.is_empty() {
+                continue;
+            }
+            for (d, value) in centroid.iter_mut().enumerate() {
+                *value = members.iter().map(|m| m[d]).sum::<f32>() / members.len() as f32;
+            }
+        }
+    }
+
+    let inertia = vectors.iter().map(|v| nearest(v, &centroids).1).sum();
+    (assignments, inertia)
+}
+
+// Chooses the initial centroids deterministically, so that the same
+// request always gives the same clusters: The first vector, and then
+// repeatedly the vector that is farthest from all chosen centroids.
+fn initial_centroids(vectors: &[Vec<f32>], k: usize) -> Vec<Vec<f32>> {
+    let mut centroids = vec![vectors[0].clone()];
+    while centroids.len() < k {
+        let farthest = vectors
+            .iter()
+            .max_by(|a, b| {
+                nearest(a, &centroids)
+                    .1
+                    .total_cmp(&nearest(b, &centroids).1)
+            })
+            .unwrap_or(&vectors[0]);
+        centroids.push(farthest.clone());
+    }
+    centroids
+}
+
+// The index of the centroid nearest to the vector and the squared
+// distance to it
+fn nearest(vector: &[f32], centroids: &[Vec<f32>]) -> (usize, f32) {
+    centroids
+        .iter()
+        .map(|centroid| {
+            vector
+                .iter()
+                .zip(centroid)
+                .map(|(a, b)| (a - b).powi(2))
+                .sum::<f32>()
+        })
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .unwrap_or((0, 0.0))
+}
@@ -0,0 +1,111 @@
+// This module implements `POST /compare`, which helps to choose between
+// the full precision (fp32) version of the forecasting model and a
+// quantized (int8) version of it for a device class: The same data
+// window is run through both models, and the response reports how much
+// the forecasts differ and how long loading and inference took on this
+// device.
+
+use std::{path::Path, time::Instant};
+
+use serde::Serialize;
+use wasi_nn_demo_lib::interface;
+
+use crate::{
+    error::Error,
+    model::{self, ModelSpec},
+    predicted_values, series_from_data_window, tensor_from_series, HttpHandler, MODEL_FORMAT,
+};
+
+// The quantized version of the configured model (see model.rs). It must have the
+// same inputs and outputs. No such model is included in this
+// repository, place your own model in the models directory.
+const QUANTIZED_MODEL_FILES: [&str; 1] = ["models/model-int8.onnx"];
+// The inference is run this many times, the latency is the average
+const RUNS: u32 = 5;
+
+#[derive(Serialize)]
+pub struct Comparison {
+    full: ModelResult,
+    quantized: ModelResult,
+    // The differences of the quantized forecast to the full forecast
+    delta: Vec<f32>,
+    max_abs_delta: f32,
+    mean_abs_delta: f32,
+}
+
+#[derive(Serialize)]
+struct ModelResult {
+    forecast: Vec<f32>,
+    // In milliseconds
+    load_latency: f64,
+    inference_latency: f64,
+}
+
+pub fn comparison_to_vec(comparison: &Comparison) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(comparison)
+        .map_err(|e| Error::internal(format!("Error serializing comparison: {e}")))
+}
+
+impl HttpHandler {
+    pub fn compare(&mut self, input: interface::DataWindow) -> Result<Comparison, Error> {
+        let history = series_from_data_window(&input);
+        let spec = model::spec()?;
+        let full = run_model(&spec, &spec.files, &history)?;
+        let quantized = run_model(&spec, &QUANTIZED_MODEL_FILES, &history)?;
+
+        let delta: Vec<_> = quantized
+            .forecast
+            .iter()
+            .zip(&full.forecast)
+            .map(|(q, f)| q - f)
+            .collect();
+        let max_abs_delta = delta.iter().fold(0.0, |max: f32, d| max.max(d.abs()));
+        let mean_abs_delta = delta.iter().map(|d| d.abs()).sum::<f32>() / delta.len() as f32;
+
+        Ok(Comparison {
+            full,
+            quantized,
+            delta,
+            max_abs_delta,
+            mean_abs_delta,
+        })
+    }
+}
+
+// Loads the model from the files and forecasts the history, measuring
+// the time it takes
+fn run_model<P: AsRef<Path>>(
+    spec: &ModelSpec,
+    files: &[P],
+    history: &[f32],
+) -> Result<ModelResult, Error> {
+    let start = Instant::now();
+    let graph = crate::load_model(MODEL_FORMAT, files)?;
+    let ctx = graph.init_execution_context()?;
+    let load_latency = start.elapsed().as_secs_f64() * 1000.0;
+
+    let start = Instant::now();
+    let mut forecast = Vec::new();
+    for _ in 0..RUNS {
+        let input_tensor =
+            tensor_from_series(history.to_vec(), spec.batch_size(), spec.shape.history_len);
+        let output_tensors = &crate::infer(&graph, || {
+            ctx.run(
+                [(spec.input_tensor.as_str(), input_tensor)],
+                &[spec.output_tensor.as_str()],
+            )
+        })?;
+        forecast = predicted_values(
+            &output_tensors[spec.output_tensor.as_str()],
+            spec.batch_size(),
+            spec.shape.prediction_len,
+        )?;
+    }
+    let inference_latency = start.elapsed().as_secs_f64() * 1000.0 / f64::from(RUNS);
+
+    Ok(ModelResult {
+        forecast,
+        load_latency,
+        inference_latency,
+    })
+}
@@ -0,0 +1,94 @@
+// Features that need configuration (e.g. alert rules) read it as JSON
+// documents with a name like `alerts`. By default, these are the files
+// in the config directory (e.g. `config/alerts.json`), which must be
+// preopened for the component (`--dir config::config`). With the
+// `config-store` feature, they are the values of the keys with the same
+// name in the wasi:config store of the host instead, which is how
+// configuration is provided in Spin (as variables) and wasmCloud. If
+// the host has no config store after all (see capabilities.rs), the
+// files are read instead.
+
+use serde::de::DeserializeOwned;
+
+use crate::error::Error;
+
+// Reads the config with the given name. Returns `None` if there is no
+// such config.
+pub fn load<T: DeserializeOwned>(name: &str) -> Result<Option<T>, Error> {
+    read(name)?
+        .map(|contents| {
+            serde_json::from_slice(&contents)
+                .map_err(|e| Error::internal(format!("Invalid config {name}: {e}")))
+        })
+        .transpose()
+}
+
+// Like `load`, for features that do not work without their config
+#[cfg_attr(
+    not(any(
+        feature = "calendar",
+        feature = "s3",
+        feature = "schedule",
+        feature = "sign",
+        feature = "signature"
+    )),
+    allow(dead_code)
+)]
+pub fn require<T: DeserializeOwned>(name: &str) -> Result<T, Error> {
+    load(name)?.ok_or_else(|| Error::internal(format!("Missing config {name}")))
+}
+
+#[cfg(not(feature = "config-store"))]
+fn read(name: &str) -> Result<Option<Vec<u8>>, Error> {
+    read_file(name)
+}
+
+#[cfg(any(not(feature = "config-store"), feature = "capabilities"))]
+fn read_file(name: &str) -> Result<Option<Vec<u8>>, Error> {
+    let path = format!("config/{name}.json");
+    match std::fs::read(&path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::internal(format!("Error reading {path}: {e}"))),
+    }
+}
+
+#[cfg(feature = "config-store")]
+mod bindings {
+    wit_bindgen::generate!({
+        path: "wit",
+        world: "config-store",
+        generate_all,
+    });
+}
+
+#[cfg(feature = "config-store")]
+fn read(name: &str) -> Result<Option<Vec<u8>>, Error> {
+    use bindings::wasi::config::store;
+
+    #[cfg(feature = "capabilities")]
+    if !probe() {
+        return read_file(name);
+    }
+    store::get(name)
+        .map(|value| value.map(String::into_bytes))
+        .map_err(|e| {
+            let message = match e {
+                store::Error::Upstream(message) | store::Error::Io(message) => message,
+            };
+            Error::internal(format!("Error reading config {name}: {message}"))
+        })
+}
+
+// Whether the host has a config store that can be read, probed once per
+// instance (see capabilities.rs)
+#[cfg(all(feature = "config-store", feature = "capabilities"))]
+pub fn probe() -> bool {
+    static AVAILABLE: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+
+    *AVAILABLE.get_or_init(|| {
+        bindings::wasi::config::store::get_all()
+            .inspect_err(|e| eprintln!("Config store not available, reading files instead: {e:?}"))
+            .is_ok()
+    })
+}
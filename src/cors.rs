@@ -0,0 +1,103 @@
+// This module lets browsers call the component from other origins, e.g.
+// a dashboard served by another host that fetches forecasts. Browsers
+// first send a preflight request (OPTIONS) for such calls, and only make
+// them if the response allows the origin. The origins (`*` for any),
+// methods, request headers and how long browsers may cache the
+// preflight are set in the optional `cors` config (see config.rs):
+//
+// { "origins": ["https://dashboard.local"], "methods": ["GET", "POST"],
+//   "headers": ["content-type", "authorization"], "expose_headers":
+//   ["history-adjustment"], "max_age_seconds": 600 }
+//
+// Preflight requests are answered before they are authenticated, since
+// browsers send them without credentials (see http.rs). Every response
+// to an allowed origin has Access-Control-Allow-Origin with that
+// origin, and the response headers in `expose_headers` are made
+// readable by the page. Without the config, no origin is allowed.
+
+use serde::Deserialize;
+
+use crate::config;
+
+const CONFIG: &str = "cors";
+
+#[derive(Deserialize)]
+struct Cors {
+    origins: Vec<String>,
+    #[serde(default = "default_methods")]
+    methods: Vec<String>,
+    #[serde(default = "default_headers")]
+    headers: Vec<String>,
+    #[serde(default)]
+    expose_headers: Vec<String>,
+    #[serde(default = "default_max_age_seconds")]
+    max_age_seconds: u32,
+}
+
+fn default_methods() -> Vec<String> {
+    vec!["GET".into(), "POST".into()]
+}
+
+fn default_headers() -> Vec<String> {
+    vec!["content-type".into()]
+}
+
+fn default_max_age_seconds() -> u32 {
+    600
+}
+
+// The config, if the origin is allowed by it
+fn allowing(origin: Option<&str>) -> Option<Cors> {
+    let cors: Cors = config::load(CONFIG)
+        .inspect_err(|e| eprintln!("Not allowing other origins: {e}"))
+        .ok()??;
+    let origin = origin?;
+    cors.origins
+        .iter()
+        .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+        .then_some(cors)
+}
+
+// The headers of the response to a request from the origin
+pub fn headers(origin: Option<&str>) -> Vec<(&'static str, String)> {
+    let Some(cors) = allowing(origin) else {
+        return Vec::new();
+    };
+    let mut headers = vec![
+        (
+            "access-control-allow-origin",
+            origin.unwrap_or_default().into(),
+        ),
+        // The response depends on the origin, so caches must tell them
+        // apart
+        ("vary", "Origin".into()),
+    ];
+    if !cors.expose_headers.is_empty() {
+        headers.push((
+            "access-control-expose-headers",
+            cors.expose_headers.join(", "),
+        ));
+    }
+    headers
+}
+
+// The headers of the response to a preflight request, which allow the
+// method if the origin is allowed. The origin itself is allowed like
+// for every other request (see `headers`).
+pub fn preflight(origin: Option<&str>, method: &str) -> Vec<(&'static str, String)> {
+    let Some(cors) = allowing(origin) else {
+        return Vec::new();
+    };
+    if !cors
+        .methods
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(method))
+    {
+        return Vec::new();
+    }
+    vec![
+        ("access-control-allow-methods", cors.methods.join(", ")),
+        ("access-control-allow-headers", cors.headers.join(", ")),
+        ("access-control-max-age", cors.max_age_seconds.to_string()),
+    ]
+}
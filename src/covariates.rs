@@ -0,0 +1,153 @@
+// This module adds a multivariate forecasting model to the component,
+// served by `POST /forecast/covariates`. In addition to the history of
+// the forecast series, such a model uses covariates: Other series that
+// influence it and whose future values are already known, like a
+// weather forecast or a planned production schedule. The client sends
+// the past values of each covariate (aligned with the history) and
+// its known future values (aligned with the forecast). Holiday
+// indicators can be computed from a calendar instead (see
+// calendar.rs).
+
+use std::collections::HashMap;
+
+#[cfg(feature = "calendar")]
+use chrono::NaiveDate;
+
+use serde::Deserialize;
+use wasi_nn_demo_lib::interface;
+
+use crate::{
+    error::Error,
+    fit_to_history_len, forecast_timestamps,
+    http::JsonBody,
+    inference_result_from_values,
+    nn::{GraphEncoding, Tensor},
+    series_from_data_window, HttpHandler, HISTORY_LEN, PREDICTION_LEN,
+};
+
+// These constants are the parameters that are specific to the
+// covariate model. Its shape is that of the included forecasting
+// model, not the configured one (see `Shape` in model.rs). No such
+// model is included in this repository, place your own model in the
+// models directory.
+const MODEL_FORMAT: GraphEncoding = GraphEncoding::Onnx;
+const MODEL_FILES: [&str; 1] = ["models/covariates.onnx"];
+// The model has two inputs: The history of the series together with
+// the past values of the covariates (1 x HISTORY_LEN x 1 + number of
+// covariates), and the future values of the covariates (1 x
+// PREDICTION_LEN x number of covariates). It returns the forecast (1
+// x PREDICTION_LEN x 1).
+const PAST_TENSOR_NAME: &str = "past_values";
+const FUTURE_TENSOR_NAME: &str = "future_covariates";
+const OUTPUT_TENSOR_NAME: &str = "forecast";
+// The covariates the model was trained with, in the order the model
+// expects them
+const COVARIATES: [&str; 3] = ["temperature_forecast", "production_schedule", "holiday"];
+
+#[cfg(feature = "calendar")]
+mod calendar;
+
+#[derive(Deserialize)]
+pub struct CovariateRequest {
+    window: interface::DataWindow,
+    covariates: HashMap<String, Covariate>,
+    // The holidays within the forecast, if the holiday covariate was
+    // computed from the calendar
+    #[cfg(feature = "calendar")]
+    #[serde(skip)]
+    pub holidays: Vec<NaiveDate>,
+}
+
+#[derive(Deserialize)]
+struct Covariate {
+    // Exactly HISTORY_LEN values, aligned with the (sorted) history
+    past: Vec<f32>,
+    // Exactly PREDICTION_LEN values, aligned with the forecast
+    future: Vec<f32>,
+}
+
+impl JsonBody for CovariateRequest {
+    const NAME: &'static str = "covariate request";
+
+    fn check(self) -> Result<Self, Error> {
+        #[cfg(feature = "usage")]
+        crate::usage::record_data_points(self.window.data.len());
+        #[cfg(feature = "calendar")]
+        let request = calendar::add_holiday_covariate(self)?;
+        #[cfg(not(feature = "calendar"))]
+        let request = self;
+
+        for name in COVARIATES {
+            let covariate = request
+                .covariates
+                .get(name)
+                .ok_or_else(|| Error::BadRequest(format!("Missing covariate {name}")))?;
+            if covariate.past.len() != HISTORY_LEN as usize
+                || covariate.future.len() != PREDICTION_LEN as usize
+            {
+                return Err(Error::BadRequest(format!(
+                    "Covariate {name} must have {HISTORY_LEN} past and {PREDICTION_LEN} future values"
+                )));
+            }
+        }
+        Ok(request)
+    }
+}
+
+impl HttpHandler {
+    // This is the equivalent of `handle_data` for the covariate model
+    pub fn forecast_with_covariates(
+        &mut self,
+        request: CovariateRequest,
+    ) -> Result<interface::InferenceResult, Error> {
+        let graph = crate::load_model(MODEL_FORMAT, &MODEL_FILES)?;
+        let ctx = graph.init_execution_context()?;
+
+        let timestamps = forecast_timestamps(&request.window, PREDICTION_LEN);
+        let (past, future) = tensors_from_request(request);
+        let output_tensors = &crate::infer(&graph, || {
+            ctx.run(
+                [(PAST_TENSOR_NAME, past), (FUTURE_TENSOR_NAME, future)],
+                &[OUTPUT_TENSOR_NAME],
+            )
+        })?;
+        let forecast: &[[f32; PREDICTION_LEN as usize]; 1] =
+            crate::reshape_output(&output_tensors[OUTPUT_TENSOR_NAME])?;
+
+        Ok(inference_result_from_values(
+            forecast[0].to_vec(),
+            timestamps,
+        ))
+    }
+}
+
+// This function assembles the two input tensors. For each time step,
+// the values of the series and the covariates are next to each other.
+fn tensors_from_request(request: CovariateRequest) -> (Tensor<f32>, Tensor<f32>) {
+    let mut history = series_from_data_window(&request.window);
+    fit_to_history_len(&mut history, HISTORY_LEN);
+
+    // The covariates are in the order expected by the model, they have
+    // all been checked to exist by `check`
+    let covariates: Vec<_> = COVARIATES
+        .iter()
+        .filter_map(|name| request.covariates.get(*name))
+        .collect();
+
+    let past = history
+        .iter()
+        .enumerate()
+        .flat_map(|(t, value)| {
+            std::iter::once(*value).chain(covariates.iter().map(move |covariate| covariate.past[t]))
+        })
+        .collect();
+    let future = (0..PREDICTION_LEN as usize)
+        .flat_map(|t| covariates.iter().map(move |covariate| covariate.future[t]))
+        .collect();
+
+    let num_covariates = COVARIATES.len() as u32;
+    (
+        Tensor::new(past, vec![1, HISTORY_LEN, 1 + num_covariates]),
+        Tensor::new(future, vec![1, PREDICTION_LEN, num_covariates]),
+    )
+}
@@ -0,0 +1,77 @@
+// With a holiday calendar, the component computes the `holiday`
+// covariate itself, so that clients of a model trained with holiday
+// indicators (e.g. for demand-like series, which behave differently on
+// holidays) do not have to send it. The calendar is read from the
+// `holidays` config (see config.rs), which maps dates to the names of
+// the holidays:
+//
+// { "2024-12-25": "Christmas Day", "2024-12-26": "Boxing Day" }
+//
+// Timestamps are matched to dates in UTC.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, NaiveDate, Utc};
+
+use super::{Covariate, CovariateRequest};
+use crate::{
+    config, error::Error, fit_to_history_len, forecast_timestamps, numeric_data_points,
+    HISTORY_LEN, PREDICTION_LEN,
+};
+
+const CALENDAR_CONFIG: &str = "holidays";
+// The name of the covariate the model expects the indicators in
+const HOLIDAY_COVARIATE: &str = "holiday";
+
+type Calendar = BTreeMap<NaiveDate, String>;
+
+// Adds the holiday covariate to the request, unless the client already
+// sent it, and notes the holidays within the forecast
+pub fn add_holiday_covariate(mut request: CovariateRequest) -> Result<CovariateRequest, Error> {
+    if request.covariates.contains_key(HOLIDAY_COVARIATE) {
+        return Ok(request);
+    }
+    let calendar = load_calendar()?;
+
+    // The past values are aligned with the history the model sees (see
+    // `fit_to_history_len`)
+    let past_timestamps: Vec<_> = numeric_data_points(&request.window)
+        .into_iter()
+        .map(|(data_point, _)| data_point.timestamp)
+        .collect::<Option<_>>()
+        .ok_or_else(|| {
+            Error::BadRequest("The holiday covariate requires timestamps for all values".into())
+        })?;
+    let future_timestamps =
+        forecast_timestamps(&request.window, PREDICTION_LEN).ok_or_else(|| {
+            Error::BadRequest("The holiday covariate requires increasing timestamps".into())
+        })?;
+
+    let mut past = indicators(&calendar, &past_timestamps);
+    fit_to_history_len(&mut past, HISTORY_LEN);
+    let future = indicators(&calendar, &future_timestamps);
+
+    request.holidays = future_timestamps
+        .iter()
+        .map(|timestamp| timestamp.date_naive())
+        .filter(|date| calendar.contains_key(date))
+        .collect();
+    request.holidays.dedup();
+
+    request
+        .covariates
+        .insert(HOLIDAY_COVARIATE.to_string(), Covariate { past, future });
+    Ok(request)
+}
+
+// 1 for timestamps on a holiday, 0 otherwise
+fn indicators(calendar: &Calendar, timestamps: &[DateTime<Utc>]) -> Vec<f32> {
+    timestamps
+        .iter()
+        .map(|timestamp| f32::from(u8::from(calendar.contains_key(&timestamp.date_naive()))))
+        .collect()
+}
+
+fn load_calendar() -> Result<Calendar, Error> {
+    config::require(CALENDAR_CONFIG)
+}
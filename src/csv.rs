@@ -0,0 +1,337 @@
+// This module reads data windows from CSV, as exported by most
+// historians, so that the exports can be posted as they are (with
+// `Content-Type: text/csv`). Which column holds what is configured in
+// the optional `columns` config (see config.rs), the defaults are:
+//
+// { "timestamp": "timestamp", "value": "value", "quality": "quality",
+//   "series": "series", "delimiter": ",", "header": true }
+//
+// The first line must contain the column names, unless `header` is
+// false. Exports without a header have the columns
+// `timestamp,value[,quality]` in this order instead, and the names are
+// not used. Only the timestamp and value columns are required. If there
+// is a series column, the rows of
+// the series given in the `series` parameter are used, or all rows if
+// they belong to the same series. Timestamps are RFC 3339 or
+// `YYYY-MM-DD HH:MM:SS` in UTC. With the `lenient` feature, values are
+// parsed in the configured number format (see lenient.rs), e.g. for
+// exports with `;` as delimiter and `,` as decimal separator.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+use wasi_nn_demo_lib::interface;
+
+use crate::{config, error::Error};
+
+const CONFIG: &str = "columns";
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct Columns {
+    timestamp: String,
+    value: String,
+    quality: String,
+    series: String,
+    delimiter: char,
+    header: bool,
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        Self {
+            timestamp: "timestamp".into(),
+            value: "value".into(),
+            quality: "quality".into(),
+            series: "series".into(),
+            delimiter: ',',
+            header: true,
+        }
+    }
+}
+
+pub fn parse_data_window(
+    input: &[u8],
+    series: Option<&str>,
+) -> Result<interface::DataWindow, Error> {
+    let columns: Columns = config::load(CONFIG)?.unwrap_or_default();
+    #[cfg(feature = "lenient")]
+    let number_format = crate::lenient::NumberFormat::load();
+    #[cfg(feature = "lenient")]
+    let parse_value = |value: &str| number_format.parse(value);
+    #[cfg(not(feature = "lenient"))]
+    let parse_value = |value: &str| value.parse().ok();
+
+    let input = std::str::from_utf8(input)
+        .map_err(|e| Error::BadRequest(format!("CSV is not valid UTF-8: {e}")))?;
+    let data = parse_rows(input, series, &columns, parse_value)?;
+
+    #[cfg(feature = "usage")]
+    crate::usage::record_data_points(data.len());
+    let window = interface::DataWindow { data };
+    #[cfg(feature = "forward")]
+    crate::forward::record(&window);
+    Ok(window)
+}
+
+// Parses the rows of the series into data points by their line numbers,
+// with `parse_value` parsing the values
+fn parse_rows(
+    input: &str,
+    series: Option<&str>,
+    columns: &Columns,
+    parse_value: impl Fn(&str) -> Option<f32>,
+) -> Result<HashMap<String, interface::DataPoint>, Error> {
+    let mut lines = input.lines().filter(|line| !line.trim().is_empty());
+    let (timestamp_index, value_index, quality_index, series_index) = if columns.header {
+        let header = split_line(lines.next().unwrap_or_default(), columns.delimiter);
+        let index = |name: &str| header.iter().position(|column| column.trim() == name);
+        let required = |name: &str| {
+            index(name).ok_or_else(|| Error::BadRequest(format!("CSV has no column {name}")))
+        };
+        (
+            required(&columns.timestamp)?,
+            required(&columns.value)?,
+            index(&columns.quality),
+            index(&columns.series),
+        )
+    } else {
+        // Rows without a quality simply have no third field
+        (0, 1, Some(2), None)
+    };
+    // Line numbers start at 1, after the header if there is one
+    let first_line_number = if columns.header { 2 } else { 1 };
+
+    let mut data = HashMap::new();
+    let mut series_seen: Option<String> = None;
+    for (i, line) in lines.enumerate() {
+        let line_number = i + first_line_number;
+        let fields = split_line(line, columns.delimiter);
+        let field = |index: usize| fields.get(index).map(|field| field.trim()).unwrap_or("");
+        let invalid = |what: &str, value: &str| {
+            Error::BadRequest(format!("Invalid {what} {value} in line {line_number}"))
+        };
+
+        if let Some(series_index) = series_index {
+            let row_series = field(series_index);
+            match (series, &series_seen) {
+                (Some(series), _) if row_series != series => continue,
+                (None, Some(seen)) if seen != row_series => {
+                    return Err(Error::BadRequest(
+                        "CSV contains several series, select one with the series parameter".into(),
+                    ))
+                }
+                _ => series_seen = Some(row_series.to_string()),
+            }
+        }
+
+        let value = field(value_index);
+        if value.is_empty() {
+            continue;
+        }
+        let value = parse_value(value).ok_or_else(|| invalid("value", value))?;
+
+        let timestamp = field(timestamp_index);
+        let timestamp =
+            parse_timestamp(timestamp).ok_or_else(|| invalid("timestamp", timestamp))?;
+        let quality = match quality_index.map(field) {
+            None | Some("") => None,
+            Some(quality) => Some(quality.parse().map_err(|_| invalid("quality", quality))?),
+        };
+
+        let data_point = interface::DataPoint {
+            quality,
+            value: interface::Value::Number(value),
+            timestamp: Some(timestamp),
+        };
+        data.insert(line_number.to_string(), data_point);
+    }
+    Ok(data)
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S%.f")
+                .map(|timestamp| timestamp.and_utc())
+        })
+        .ok()
+}
+
+// Splits a line into its fields. Fields can be quoted to contain the
+// delimiter, and quotes in quoted fields are doubled.
+fn split_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str, series: Option<&str>, columns: &Columns) -> Result<Vec<Row>, Error> {
+        let data = parse_rows(input, series, columns, |value| value.parse().ok())?;
+        let mut rows: Vec<_> = data
+            .into_iter()
+            .map(|(line, point)| {
+                let interface::Value::Number(value) = point.value else {
+                    panic!("Value of line {line} is not a number");
+                };
+                Row {
+                    line: line.parse().unwrap(),
+                    timestamp: point.timestamp.unwrap().to_rfc3339(),
+                    value,
+                    quality: point.quality,
+                }
+            })
+            .collect();
+        rows.sort_by_key(|row| row.line);
+        Ok(rows)
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Row {
+        line: usize,
+        timestamp: String,
+        value: f32,
+        quality: Option<u32>,
+    }
+
+    fn row(line: usize, timestamp: &str, value: f32, quality: Option<u32>) -> Row {
+        Row {
+            line,
+            timestamp: timestamp.into(),
+            value,
+            quality,
+        }
+    }
+
+    fn reason(result: Result<Vec<Row>, Error>) -> String {
+        match result {
+            Err(Error::BadRequest(reason)) => reason,
+            other => panic!("Expected a bad request, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_columns_by_their_names() {
+        let input = "quality,value,timestamp\n\
+                     192,1.5,2024-05-01T12:00:00Z\n\
+                     \n\
+                     ,2.5,2024-05-01 12:01:00\n\
+                     0,,2024-05-01T12:02:00Z\n";
+        assert_eq!(
+            parse(input, None, &Columns::default()).unwrap(),
+            [
+                row(2, "2024-05-01T12:00:00+00:00", 1.5, Some(192)),
+                row(3, "2024-05-01T12:01:00+00:00", 2.5, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_configured_columns() {
+        let columns = Columns {
+            timestamp: "Time".into(),
+            value: "Wert".into(),
+            delimiter: ';',
+            ..Columns::default()
+        };
+        let input = "Time;Tag;Wert\n2024-05-01T12:00:00Z;\"Boiler; 1\";3\n";
+        assert_eq!(
+            parse(input, None, &columns).unwrap(),
+            [row(2, "2024-05-01T12:00:00+00:00", 3.0, None)]
+        );
+    }
+
+    #[test]
+    fn parses_exports_without_a_header() {
+        let columns = Columns {
+            header: false,
+            ..Columns::default()
+        };
+        let input = "2024-05-01T12:00:00Z,1\n2024-05-01T12:01:00Z,2,192\n";
+        assert_eq!(
+            parse(input, None, &columns).unwrap(),
+            [
+                row(1, "2024-05-01T12:00:00+00:00", 1.0, None),
+                row(2, "2024-05-01T12:01:00+00:00", 2.0, Some(192)),
+            ]
+        );
+    }
+
+    #[test]
+    fn selects_the_series() {
+        let input = "series,timestamp,value\n\
+                     a,2024-05-01T12:00:00Z,1\n\
+                     b,2024-05-01T12:00:00Z,2\n";
+        assert_eq!(
+            parse(input, Some("b"), &Columns::default()).unwrap(),
+            [row(3, "2024-05-01T12:00:00+00:00", 2.0, None)]
+        );
+        assert!(reason(parse(input, None, &Columns::default())).contains("several series"));
+        assert_eq!(parse(input, Some("c"), &Columns::default()).unwrap(), []);
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let columns = Columns::default();
+        assert_eq!(
+            reason(parse("time,value\n", None, &columns)),
+            "CSV has no column timestamp"
+        );
+        assert_eq!(
+            reason(parse("", None, &columns)),
+            "CSV has no column timestamp"
+        );
+        assert_eq!(
+            reason(parse(
+                "timestamp,value\n2024-05-01T12:00:00Z,one\n",
+                None,
+                &columns
+            )),
+            "Invalid value one in line 2"
+        );
+        assert_eq!(
+            reason(parse("timestamp,value\nyesterday,1\n", None, &columns)),
+            "Invalid timestamp yesterday in line 2"
+        );
+        assert_eq!(
+            reason(parse(
+                "timestamp,value,quality\n2024-05-01T12:00:00Z,1,good\n",
+                None,
+                &columns
+            )),
+            "Invalid quality good in line 2"
+        );
+    }
+
+    #[test]
+    fn splits_quoted_fields() {
+        assert_eq!(split_line("a,b,,c", ','), ["a", "b", "", "c"]);
+        assert_eq!(
+            split_line("\"a,b\",\"say \"\"hi\"\"\"", ','),
+            ["a,b", "say \"hi\""]
+        );
+        assert_eq!(split_line("", ','), [""]);
+        // An unterminated quote runs to the end of the line
+        assert_eq!(split_line("\"a,b", ','), ["a,b"]);
+    }
+}
@@ -0,0 +1,192 @@
+// This module describes the component in a machine-readable document
+// with `GET /.well-known/wasi-nn-demo`, so that orchestrators can
+// configure clients without knowing how it was built and configured:
+//
+// { "name": "wasi-nn-demo", "version": "0.1.0",
+//   "formats": ["application/json", "text/csv"], "auth": ["api-key"],
+//   "limits": { "history_len": 128, "prediction_len": 24, "max_horizon": 96 },
+//   "models": { "default": ["models/model.onnx"], "named": ["pump"] },
+//   "features": ["http", "strict", "auth", "csv", "registry"] }
+//
+// - `formats`: The content types of the data accepted by `POST /`
+// - `auth`: The authentication schemes a client can use (see
+//   http/auth.rs), none if requests are not authenticated
+// - `limits`: The length of the window the model takes, of a forecast,
+//   and the longest horizon a client can ask for, and the largest
+//   request body with the `body-limit` feature (see body_limit.rs)
+// - `models`: The files of the default model and the names of the
+//   models in the registry (see registry.rs)
+// - `features`: The features the component was built with (see
+//   Cargo.toml)
+//
+// Like the health probes, the document is returned without
+// authentication, since clients need it to know how to authenticate.
+
+use serde::Serialize;
+
+use crate::{error::Error, model};
+
+// Each of the features, if it is enabled
+macro_rules! features {
+    ($($feature:literal,)*) => {
+        [$(if cfg!(feature = $feature) { Some($feature) } else { None },)*]
+    };
+}
+
+// The features, in the order of Cargo.toml, without those that only
+// group others or are needed by all
+const FEATURES: &[Option<&str>] = &features![
+    "http",
+    "cli",
+    "messaging",
+    "forecaster",
+    "config-store",
+    "keyvalue",
+    "logging",
+    "cors",
+    "body-limit",
+    "health",
+    "capabilities",
+    "discovery",
+    "spin",
+    "wasmcloud",
+    "strict",
+    "anomaly",
+    "audio",
+    "simulate",
+    "text",
+    "backtest",
+    "admin",
+    "prune",
+    "model-limits",
+    "accuracy",
+    "pushgateway",
+    "grafana",
+    "homeassistant",
+    "alerts",
+    "embedding",
+    "search",
+    "cluster",
+    "compare",
+    "covariates",
+    "calendar",
+    "aggregate",
+    "ingest",
+    "baseline",
+    "batch",
+    "hierarchy",
+    "quality",
+    "iothub",
+    "publish",
+    "s3",
+    "pipeline",
+    "routing",
+    "registry",
+    "queue",
+    "budget",
+    "auth",
+    "usage",
+    "introspect",
+    "variance",
+    "forward",
+    "stale",
+    "retention",
+    "replay",
+    "sign",
+    "signature",
+    "resolutions",
+    "previous",
+    "timing",
+    "latest",
+    "stitch",
+    "trigger",
+    "trace",
+    "tracecontext",
+    "csv",
+    "cbor",
+    "msgpack",
+    "ndjson",
+    "etag",
+    "fixed-clock",
+    "breaker",
+    "integrity",
+    "encryption",
+    "lenient",
+    "named-models",
+    "model-config",
+    "fit-config",
+    "resample",
+    "fallback",
+    "changepoint",
+    "schedule",
+    "tcp",
+    "generate",
+    "vision",
+];
+
+#[derive(Serialize)]
+pub struct Document {
+    name: &'static str,
+    version: &'static str,
+    formats: Vec<&'static str>,
+    auth: Vec<&'static str>,
+    limits: Limits,
+    models: Models,
+    features: Vec<&'static str>,
+}
+
+#[derive(Serialize)]
+struct Limits {
+    history_len: u32,
+    prediction_len: u32,
+    max_horizon: u32,
+    #[cfg(feature = "body-limit")]
+    max_body_bytes: u64,
+}
+
+#[derive(Serialize)]
+struct Models {
+    default: Vec<String>,
+    named: Vec<String>,
+}
+
+// The document, with the authentication schemes that are configured
+pub fn document(auth: Vec<&'static str>) -> Result<Document, Error> {
+    let formats = [
+        Some("application/json"),
+        cfg!(feature = "csv").then_some("text/csv"),
+        cfg!(feature = "cbor").then_some("application/cbor"),
+        cfg!(feature = "msgpack").then_some("application/msgpack"),
+        cfg!(feature = "ndjson").then_some("application/x-ndjson"),
+        cfg!(feature = "vision").then_some("image/jpeg"),
+        cfg!(feature = "vision").then_some("image/png"),
+        cfg!(feature = "audio").then_some("audio/wav"),
+    ];
+    let spec = model::spec()?;
+    Ok(Document {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        formats: formats.into_iter().flatten().collect(),
+        auth,
+        limits: Limits {
+            history_len: spec.shape.history_len,
+            prediction_len: spec.shape.prediction_len,
+            max_horizon: spec.shape.max_horizon(),
+            #[cfg(feature = "body-limit")]
+            max_body_bytes: crate::body_limit::max_bytes()?,
+        },
+        models: Models {
+            default: spec.files,
+            #[cfg(feature = "registry")]
+            named: crate::registry::names()?,
+            #[cfg(not(feature = "registry"))]
+            named: Vec::new(),
+        },
+        features: FEATURES.iter().flatten().copied().collect(),
+    })
+}
+
+pub fn document_to_vec(document: &Document) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(document)
+        .map_err(|e| Error::internal(format!("Error serializing document: {e}")))
+}
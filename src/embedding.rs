@@ -0,0 +1,96 @@
+// This module adds an embedding model to the component, served by
+// `POST /embed`. Instead of a forecast, such a model computes a
+// feature vector for the data window (e.g. a vibration signature),
+// which can be compared to the vectors of other windows in a
+// downstream similarity search.
+//
+// The `interface::InferenceResult` of the demo library only knows
+// predicted values, so the result is our own type here. It is
+// serialized the same way the library serializes its results, i.e.
+// as `{"Embedding": [...]}`.
+
+use serde::Serialize;
+use wasi_nn_demo_lib::interface;
+
+use crate::{
+    error::Error,
+    fit_to_history_len,
+    nn::{GraphEncoding, Tensor},
+    series_from_data_window, HttpHandler, HISTORY_LEN, NUM_BATCHES,
+};
+
+// These constants are the parameters that are specific to the
+// embedding model. No such model is included in this repository,
+// place your own model in the models directory.
+const MODEL_FORMAT: GraphEncoding = GraphEncoding::Onnx;
+const MODEL_FILES: [&str; 1] = ["models/embedding.onnx"];
+// The labels of the input and output tensors in the model
+const INPUT_TENSOR_NAME: &str = "input";
+const OUTPUT_TENSOR_NAME: &str = "embedding";
+// The model takes the same input as the included forecasting model (16
+// x 128 x 1), also if another shape is configured for the forecasts
+// (see `Shape` in model.rs), and returns a vector of this length for
+// each batch (16 x EMBEDDING_DIM)
+pub const EMBEDDING_DIM: usize = 64;
+
+#[derive(Serialize)]
+pub enum EmbeddingResult {
+    Embedding(Vec<f32>),
+}
+
+impl HttpHandler {
+    // This is the equivalent of `handle_data` for the embedding model
+    pub fn embed(&mut self, input: interface::DataWindow) -> Result<EmbeddingResult, Error> {
+        let mut embeddings = self.embed_windows(&[&input])?;
+        Ok(EmbeddingResult::Embedding(embeddings.remove(0)))
+    }
+
+    // Computes the normalized vectors of any number of windows. Since
+    // the model processes 16 batches at once, up to 16 windows are
+    // embedded per inference.
+    pub fn embed_windows(
+        &mut self,
+        windows: &[&interface::DataWindow],
+    ) -> Result<Vec<Vec<f32>>, Error> {
+        let graph = crate::load_model(MODEL_FORMAT, &MODEL_FILES)?;
+        let ctx = graph.init_execution_context()?;
+
+        let mut vectors = Vec::with_capacity(windows.len());
+        for chunk in windows.chunks(NUM_BATCHES as usize) {
+            // Unused batches are filled with zeros
+            let mut data = Vec::with_capacity((NUM_BATCHES * HISTORY_LEN) as usize);
+            for window in chunk {
+                let mut series = series_from_data_window(window);
+                fit_to_history_len(&mut series, HISTORY_LEN);
+                data.extend(series);
+            }
+            data.resize((NUM_BATCHES * HISTORY_LEN) as usize, 0.0);
+            let input_tensor = Tensor::new(data, vec![NUM_BATCHES, HISTORY_LEN, 1]);
+
+            let output_tensors = &crate::infer(&graph, || {
+                ctx.run([(INPUT_TENSOR_NAME, input_tensor)], &[OUTPUT_TENSOR_NAME])
+            })?;
+            let embeddings: &[[f32; EMBEDDING_DIM]; NUM_BATCHES as usize] =
+                crate::reshape_output(&output_tensors[OUTPUT_TENSOR_NAME])?;
+
+            // The vectors are normalized to unit length, so that the
+            // similarity of two vectors is simply their dot product.
+            vectors.extend(embeddings[..chunk.len()].iter().map(|e| normalize(e)));
+        }
+        Ok(vectors)
+    }
+}
+
+pub fn normalize(vector: &[f32]) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter().map(|x| x / norm).collect()
+    } else {
+        vector.to_vec()
+    }
+}
+
+pub fn result_to_vec(result: &EmbeddingResult) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(result)
+        .map_err(|e| Error::internal(format!("Error serializing embedding: {e}")))
+}
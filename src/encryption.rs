@@ -0,0 +1,66 @@
+// This module decrypts model files that are stored encrypted with
+// AES-256-GCM, for deployments where the model must not be readable by
+// anyone with access to the flash storage of the device. The files are
+// only decrypted in memory when they are loaded. An encrypted file
+// consists of the 12-byte nonce, the ciphertext and the 16-byte tag.
+//
+// The 256-bit key is given hex encoded in the `MODEL_KEY` environment
+// variable (`wasmtime serve --env MODEL_KEY`), or in the `encryption`
+// config (see config.rs), e.g. as a secret in Spin:
+//
+// { "key": "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f" }
+
+use std::{env, fs, path::Path};
+
+use serde::Deserialize;
+use wasi::http::types::ErrorCode;
+
+use crate::{aes_gcm, config};
+
+const CONFIG: &str = "encryption";
+const KEY_VARIABLE: &str = "MODEL_KEY";
+const NONCE_LEN: usize = 12;
+
+#[derive(Deserialize)]
+struct Encryption {
+    key: String,
+}
+
+// Reads and decrypts the file
+pub fn read(path: impl AsRef<Path>) -> Result<Vec<u8>, ErrorCode> {
+    let error = |message: String| ErrorCode::InternalError(Some(message));
+    let path = path.as_ref().display();
+
+    let key = key().map_err(error)?;
+    let contents =
+        fs::read(path.to_string()).map_err(|e| error(format!("Error reading {path}: {e}")))?;
+    let (nonce, data) = contents
+        .split_first_chunk::<NONCE_LEN>()
+        .ok_or_else(|| error(format!("Encrypted model {path} is too short")))?;
+    aes_gcm::decrypt(&key, nonce, data).ok_or_else(|| {
+        error(format!(
+            "Cannot decrypt model {path}, the key is wrong or the file was modified"
+        ))
+    })
+}
+
+fn key() -> Result<[u8; 32], String> {
+    let key = match env::var(KEY_VARIABLE) {
+        Ok(key) => key,
+        Err(_) => {
+            let config: Encryption = config::require(CONFIG).map_err(|e| e.to_string())?;
+            config.key
+        }
+    };
+    let key = key.trim();
+    let invalid = || "The model key must be 64 hex digits".to_string();
+    if key.len() != 64 {
+        return Err(invalid());
+    }
+    let mut bytes = [0; 32];
+    for (byte, digits) in bytes.iter_mut().zip(key.as_bytes().chunks(2)) {
+        let digits = std::str::from_utf8(digits).map_err(|_| invalid())?;
+        *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+    }
+    Ok(bytes)
+}
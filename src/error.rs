@@ -0,0 +1,220 @@
+// Errors that can occur while handling a request. In contrast to the
+// `ErrorCode` of wasi-http, which the host turns into an opaque 500
+// response, these carry the HTTP status code and a message that we
+// report back to the client.
+
+use std::fmt;
+
+#[cfg(feature = "strict")]
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use wasi::http::types::ErrorCode;
+
+#[derive(Debug)]
+pub enum Error {
+    // The request could not be understood, e.g. because the body is
+    // not a valid data window (400)
+    BadRequest(String),
+    // The data window contains values that are not numbers, which
+    // would be dropped from the series otherwise (400)
+    #[cfg(feature = "strict")]
+    InvalidValues(Vec<InvalidValue>),
+    // The series does not have the length the model takes, and the
+    // client did not accept that it is fitted (422)
+    #[cfg(feature = "strict")]
+    WindowLength(WindowLength),
+    // The request could not be authenticated, e.g. because it is a
+    // replay of an earlier request (401)
+    #[cfg_attr(not(any(feature = "replay", feature = "signature")), allow(dead_code))]
+    Unauthorized(String),
+    // The client is authenticated, but must not do what it requested,
+    // e.g. use the admin endpoints without being an admin (403)
+    #[cfg_attr(not(feature = "auth"), allow(dead_code))]
+    Forbidden(String),
+    // The requested resource does not exist (404)
+    #[cfg_attr(not(feature = "trace"), allow(dead_code))]
+    NotFound(String),
+    // The request conflicts with the stored data, e.g. a data point
+    // that is older than the last one of its series (409)
+    #[cfg_attr(not(feature = "ingest"), allow(dead_code))]
+    Conflict(String),
+    // The request cannot be handled yet, e.g. because a series does not
+    // have enough data points for a forecast (425)
+    #[cfg_attr(not(feature = "ingest"), allow(dead_code))]
+    TooEarly(String),
+    // The request body exceeds the configured limit (413)
+    #[cfg_attr(not(feature = "body-limit"), allow(dead_code))]
+    ContentTooLarge(String),
+    // The model is temporarily unavailable, the client should retry
+    // after the given number of seconds (503)
+    #[cfg_attr(
+        not(any(
+            feature = "admin",
+            all(feature = "breaker", feature = "http"),
+            feature = "budget",
+            feature = "health",
+            feature = "queue"
+        )),
+        allow(dead_code)
+    )]
+    Unavailable {
+        message: String,
+        retry_after: u64,
+    },
+    // Errors reported by the wasi bindings or the demo library (500)
+    Internal(ErrorCode),
+}
+
+// A data point of the window whose value is not a number
+#[cfg(feature = "strict")]
+#[derive(Debug)]
+pub struct InvalidValue {
+    pub key: String,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub value: String,
+}
+
+// A series of the wrong length, with what the model requires
+#[cfg(feature = "strict")]
+#[derive(Debug)]
+pub struct WindowLength {
+    // The index of the window in a batch
+    pub window: Option<usize>,
+    pub len: usize,
+    // The number of values the model takes
+    pub required: u32,
+    // How the series would be fitted (see fit.rs)
+    pub adjustment: String,
+    // The interval between the data points of the window, if it has
+    // timestamps
+    pub cadence: Option<TimeDelta>,
+}
+
+// Batch mode only reports errors as text, so without the HTTP world
+// some of these are unused
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+impl Error {
+    // An internal error with the given message
+    pub fn internal(message: impl Into<String>) -> Self {
+        Error::Internal(ErrorCode::InternalError(Some(message.into())))
+    }
+
+    pub fn status(&self) -> u16 {
+        match self {
+            Error::BadRequest(_) => 400,
+            #[cfg(feature = "strict")]
+            Error::InvalidValues(_) => 400,
+            #[cfg(feature = "strict")]
+            Error::WindowLength(_) => 422,
+            Error::Unauthorized(_) => 401,
+            Error::Forbidden(_) => 403,
+            Error::NotFound(_) => 404,
+            Error::Conflict(_) => 409,
+            Error::TooEarly(_) => 425,
+            Error::ContentTooLarge(_) => 413,
+            Error::Unavailable { .. } => 503,
+            Error::Internal(ErrorCode::InternalError(Some(message)))
+                if message.starts_with(crate::OUTPUT_SHAPE_ERROR) =>
+            {
+                502
+            }
+            Error::Internal(_) => 500,
+        }
+    }
+
+    // The error as JSON object, which is used as the response body.
+    // This is written by hand so that it does not depend on serde.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from(r#"{"error":"#);
+        crate::json::write_string(&mut json, &self.to_string());
+        // The invalid values are listed, so that the client can tell
+        // which data points to fix
+        #[cfg(feature = "strict")]
+        if let Error::InvalidValues(invalid) = self {
+            json.push_str(r#","invalid_values":["#);
+            for (i, point) in invalid.iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+                json.push_str(r#"{"key":"#);
+                crate::json::write_string(&mut json, &point.key);
+                json.push_str(r#","timestamp":"#);
+                match point.timestamp {
+                    Some(timestamp) => crate::json::write_string(
+                        &mut json,
+                        &timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+                    ),
+                    None => json.push_str("null"),
+                }
+                json.push_str(r#","value":"#);
+                crate::json::write_string(&mut json, &point.value);
+                json.push('}');
+            }
+            json.push(']');
+        }
+        // The client learns what to send instead: As many data points
+        // as the model takes, at the cadence of the window
+        #[cfg(feature = "strict")]
+        if let Error::WindowLength(window_length) = self {
+            let data_points = window_length.required;
+            json.push_str(&format!(r#","required":{{"data_points":{data_points}"#));
+            if let Some(cadence) = window_length.cadence {
+                let seconds = cadence.num_milliseconds() as f64 / 1000.0;
+                let duration = seconds * f64::from(data_points);
+                json.push_str(&format!(
+                    r#","cadence_seconds":{seconds},"duration_seconds":{duration}"#
+                ));
+            }
+            json.push('}');
+        }
+        json.push('}');
+        json
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadRequest(message)
+            | Error::Unauthorized(message)
+            | Error::Forbidden(message)
+            | Error::NotFound(message)
+            | Error::Conflict(message)
+            | Error::TooEarly(message)
+            | Error::ContentTooLarge(message)
+            | Error::Unavailable { message, .. } => write!(f, "{message}"),
+            #[cfg(feature = "strict")]
+            Error::InvalidValues(invalid) => {
+                write!(f, "The window contains values that are not numbers at ")?;
+                for (i, point) in invalid.iter().enumerate() {
+                    let at = match point.timestamp {
+                        Some(timestamp) => timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+                        None => point.key.clone(),
+                    };
+                    write!(f, "{}{at}", if i > 0 { ", " } else { "" })?;
+                }
+                Ok(())
+            }
+            #[cfg(feature = "strict")]
+            Error::WindowLength(window_length) => {
+                if let Some(i) = window_length.window {
+                    write!(f, "Window {i}: ")?;
+                }
+                write!(
+                    f,
+                    "Expected {} data points, got {}. With fit=auto, the series is {}.",
+                    window_length.required, window_length.len, window_length.adjustment
+                )
+            }
+            Error::Internal(ErrorCode::InternalError(Some(message))) => write!(f, "{message}"),
+            Error::Internal(code) => write!(f, "{code:?}"),
+        }
+    }
+}
+
+// This allows using the `?` operator on all the functions from the
+// demo library and the wasi bindings.
+impl From<ErrorCode> for Error {
+    fn from(code: ErrorCode) -> Self {
+        Error::Internal(code)
+    }
+}
@@ -0,0 +1,45 @@
+// This module provides a statistical forecast that is used instead of
+// the model if loading or running the model fails (e.g. because the
+// model file is missing or the wasi-nn backend is unavailable), so that
+// clients get a rough forecast instead of an error.
+//
+// If the history covers at least one season, the forecast is seasonal
+// naive, i.e. it repeats the last season. Otherwise, it is the level of
+// the series estimated by simple exponential smoothing.
+
+use wasi::http::types::ErrorCode;
+
+// The number of values in one season, e.g. a day of hourly values
+const SEASON_LEN: usize = 24;
+// The weight of the most recent value in exponential smoothing
+const ALPHA: f32 = 0.5;
+
+// Returns the fallback forecast for the history, or the original error
+// of the model if the history is empty
+pub fn forecast(history: &[f32], horizon: u32, error: ErrorCode) -> Result<Vec<f32>, ErrorCode> {
+    if history.is_empty() {
+        return Err(error);
+    }
+    eprintln!("Forecast failed, falling back to a statistical forecast: {error:?}");
+
+    #[cfg(feature = "trace")]
+    crate::trace::record(crate::trace::Decision::Fallback {
+        reason: format!("{error:?}"),
+        method: if history.len() >= SEASON_LEN {
+            "seasonal naive".into()
+        } else {
+            "exponential smoothing".into()
+        },
+    });
+
+    let horizon = horizon as usize;
+    if history.len() >= SEASON_LEN {
+        let last_season = &history[history.len() - SEASON_LEN..];
+        Ok(last_season.iter().copied().cycle().take(horizon).collect())
+    } else {
+        let level = history[1..].iter().fold(history[0], |level, value| {
+            ALPHA * value + (1.0 - ALPHA) * level
+        });
+        Ok(vec![level; horizon])
+    }
+}
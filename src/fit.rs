@@ -0,0 +1,277 @@
+// The model takes exactly `history_len` values (see `Shape` in
+// model.rs). This module decides how a series of another length is
+// fitted to it: Long series are truncated by dropping their oldest
+// (`drop-oldest`) or most recent (`drop-newest`) values. Short series
+// are padded in front of the oldest value with zeros (`pad-with-zeros`)
+// or copies of the oldest value (`pad-with-first-value`), or after the
+// most recent value with copies of it (`repeat-last-value`).
+//
+// By default, the oldest values are dropped and short series are padded
+// with their first value, so that the most recent values, which matter
+// most for the forecast, stay at the end where the model expects them.
+// Forecasts over HTTP can choose other strategies with the `truncate`
+// and `pad` parameters. With the `fit-config` feature, the defaults are
+// read from the optional `fit` config (see config.rs):
+//
+// { "truncate": "drop-newest", "pad": "repeat-last-value" }
+
+use std::{cmp::Ordering, str::FromStr};
+
+#[cfg(feature = "fit-config")]
+use serde::Deserialize;
+
+use crate::error::Error;
+
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(
+    feature = "fit-config",
+    derive(Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum Truncate {
+    #[default]
+    DropOldest,
+    DropNewest,
+}
+
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "fit-config", derive(Deserialize))]
+pub enum Pad {
+    #[cfg_attr(feature = "fit-config", serde(rename = "pad-with-zeros"))]
+    Zeros,
+    #[default]
+    #[cfg_attr(feature = "fit-config", serde(rename = "pad-with-first-value"))]
+    FirstValue,
+    #[cfg_attr(feature = "fit-config", serde(rename = "repeat-last-value"))]
+    LastValue,
+}
+
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "fit-config", derive(Deserialize), serde(default))]
+pub struct Fit {
+    pub truncate: Truncate,
+    pub pad: Pad,
+}
+
+impl FromStr for Truncate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(Truncate::DropOldest),
+            "drop-newest" => Ok(Truncate::DropNewest),
+            _ => Err(Error::BadRequest(format!(
+                "Invalid truncate {s}, must be drop-oldest or drop-newest"
+            ))),
+        }
+    }
+}
+
+impl FromStr for Pad {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pad-with-zeros" => Ok(Pad::Zeros),
+            "pad-with-first-value" => Ok(Pad::FirstValue),
+            "repeat-last-value" => Ok(Pad::LastValue),
+            _ => Err(Error::BadRequest(format!(
+                "Invalid pad {s}, must be pad-with-zeros, pad-with-first-value or \
+                 repeat-last-value"
+            ))),
+        }
+    }
+}
+
+impl Fit {
+    // The default strategies
+    #[cfg(not(feature = "fit-config"))]
+    pub fn load() -> Self {
+        Self::default()
+    }
+
+    // Loads the strategies from the config. An invalid config is
+    // reported and the default is used instead, since the callers
+    // cannot report errors.
+    #[cfg(feature = "fit-config")]
+    pub fn load() -> Self {
+        crate::config::load("fit")
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                None
+            })
+            .unwrap_or_default()
+    }
+
+    // The loaded strategies, overridden by those given by the client
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub fn with_params(truncate: Option<&str>, pad: Option<&str>) -> Result<Self, Error> {
+        let mut fit = Self::load();
+        if let Some(truncate) = truncate {
+            fit.truncate = truncate.parse()?;
+        }
+        if let Some(pad) = pad {
+            fit.pad = pad.parse()?;
+        }
+        Ok(fit)
+    }
+
+    // Forces the length of the series to `history_len`
+    pub fn apply(&self, series: &mut Vec<f32>, history_len: u32) {
+        let history_len = history_len as usize;
+        match series.len().cmp(&history_len) {
+            Ordering::Equal => {}
+            Ordering::Greater => match self.truncate {
+                Truncate::DropOldest => {
+                    series.drain(..series.len() - history_len);
+                }
+                Truncate::DropNewest => series.truncate(history_len),
+            },
+            Ordering::Less => {
+                let missing = history_len - series.len();
+                match self.pad {
+                    Pad::Zeros => {
+                        series.splice(0..0, std::iter::repeat_n(0.0, missing));
+                    }
+                    Pad::FirstValue => {
+                        let first = series.first().copied().unwrap_or_default();
+                        series.splice(0..0, std::iter::repeat_n(first, missing));
+                    }
+                    Pad::LastValue => {
+                        let last = series.last().copied().unwrap_or_default();
+                        series.resize(history_len, last);
+                    }
+                }
+            }
+        }
+    }
+
+    // Describes how `apply` changes a series of the given length, if it
+    // does
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub fn adjustment(&self, len: usize, history_len: u32) -> Option<String> {
+        let history_len = history_len as usize;
+        match len.cmp(&history_len) {
+            Ordering::Equal => None,
+            Ordering::Greater => {
+                let dropped = len - history_len;
+                Some(match self.truncate {
+                    Truncate::DropOldest => {
+                        format!("truncated, dropping the {dropped} oldest values")
+                    }
+                    Truncate::DropNewest => {
+                        format!("truncated, dropping the {dropped} most recent values")
+                    }
+                })
+            }
+            Ordering::Less => {
+                let missing = history_len - len;
+                Some(match self.pad {
+                    Pad::Zeros => {
+                        format!("padded with {missing} zeros before the oldest value")
+                    }
+                    Pad::FirstValue => {
+                        format!("padded with {missing} copies of the oldest value")
+                    }
+                    Pad::LastValue => {
+                        format!("padded with {missing} copies of the most recent value")
+                    }
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fitted(truncate: Truncate, pad: Pad, mut series: Vec<f32>, history_len: u32) -> Vec<f32> {
+        Fit { truncate, pad }.apply(&mut series, history_len);
+        series
+    }
+
+    #[test]
+    fn keeps_series_of_the_right_length() {
+        let fit = Fit::default();
+        assert_eq!(fitted(fit.truncate, fit.pad, vec![1.0, 2.0], 2), [1.0, 2.0]);
+        assert_eq!(fit.adjustment(2, 2), None);
+    }
+
+    #[test]
+    fn truncates_long_series() {
+        let series = vec![1.0, 2.0, 3.0, 4.0];
+        assert_eq!(
+            fitted(Truncate::DropOldest, Pad::Zeros, series.clone(), 3),
+            [2.0, 3.0, 4.0]
+        );
+        assert_eq!(
+            fitted(Truncate::DropNewest, Pad::Zeros, series, 3),
+            [1.0, 2.0, 3.0]
+        );
+        let fit = |truncate| Fit {
+            truncate,
+            pad: Pad::default(),
+        };
+        assert_eq!(
+            fit(Truncate::DropOldest).adjustment(4, 3).unwrap(),
+            "truncated, dropping the 1 oldest values"
+        );
+        assert_eq!(
+            fit(Truncate::DropNewest).adjustment(4, 3).unwrap(),
+            "truncated, dropping the 1 most recent values"
+        );
+    }
+
+    #[test]
+    fn pads_short_series() {
+        let series = vec![1.0, 2.0];
+        assert_eq!(
+            fitted(Truncate::default(), Pad::Zeros, series.clone(), 4),
+            [0.0, 0.0, 1.0, 2.0]
+        );
+        assert_eq!(
+            fitted(Truncate::default(), Pad::FirstValue, series.clone(), 4),
+            [1.0, 1.0, 1.0, 2.0]
+        );
+        assert_eq!(
+            fitted(Truncate::default(), Pad::LastValue, series, 4),
+            [1.0, 2.0, 2.0, 2.0]
+        );
+        let fit = |pad| Fit {
+            truncate: Truncate::default(),
+            pad,
+        };
+        assert_eq!(
+            fit(Pad::Zeros).adjustment(2, 4).unwrap(),
+            "padded with 2 zeros before the oldest value"
+        );
+        assert_eq!(
+            fit(Pad::FirstValue).adjustment(2, 4).unwrap(),
+            "padded with 2 copies of the oldest value"
+        );
+        assert_eq!(
+            fit(Pad::LastValue).adjustment(2, 4).unwrap(),
+            "padded with 2 copies of the most recent value"
+        );
+    }
+
+    // Without values to copy, the series is padded with zeros
+    #[test]
+    fn pads_empty_series_with_zeros() {
+        for pad in [Pad::Zeros, Pad::FirstValue, Pad::LastValue] {
+            assert_eq!(fitted(Truncate::default(), pad, Vec::new(), 2), [0.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn parses_the_strategies() {
+        assert!(matches!("drop-newest".parse(), Ok(Truncate::DropNewest)));
+        assert!(matches!("repeat-last-value".parse(), Ok(Pad::LastValue)));
+        assert!(matches!(
+            "oldest".parse::<Truncate>(),
+            Err(Error::BadRequest(_))
+        ));
+        assert!(matches!("zeros".parse::<Pad>(), Err(Error::BadRequest(_))));
+    }
+}
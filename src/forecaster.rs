@@ -0,0 +1,93 @@
+// This module exports the forecast as a typed interface (the
+// `forecaster` world in wit/world.wit), so that other components can be
+// composed with this one (e.g. with `wac plug`) and call `predict`
+// directly, without a HTTP request and the JSON round-trip. The data
+// window and inference result are WIT records that mirror the JSON
+// format, and the forecast is the same as that of a HTTP request
+// without parameters. With the strict feature, that includes rejecting
+// windows with values that are not numbers or with a series of another
+// length than the model takes (see `check_values` and
+// `check_history_len` in lib.rs).
+//
+// The interface is exported in addition to the other worlds, so the
+// same component can still be served over HTTP.
+
+use chrono::{DateTime, Utc};
+use wasi_nn_demo_lib::{http::RequestHandler, interface};
+
+use crate::{with_handler, Component};
+
+mod bindings {
+    wit_bindgen::generate!({
+        path: "wit",
+        world: "forecaster",
+        generate_all,
+    });
+}
+
+use bindings::exports::joshuabach::wasi_nn_demo::forecast::{
+    DataPoint, DataWindow, Datetime, Error, Guest, InferenceResult, Value,
+};
+
+bindings::export!(Component with_types_in bindings);
+
+impl Guest for Component {
+    fn predict(window: DataWindow) -> Result<InferenceResult, Error> {
+        let input = data_window_from_wit(window)?;
+        #[cfg(feature = "strict")]
+        crate::check_values(&input)
+            .and_then(|()| Ok(crate::model::shape()?.history_len))
+            .and_then(|history_len| {
+                crate::check_history_len(&input, &crate::fit::Fit::load(), history_len)
+            })
+            .map_err(|e| Error::InvalidWindow(e.to_string()))?;
+        let result =
+            with_handler(|handler| Ok::<_, crate::error::Error>(handler.handle_data(input)?))
+                .map_err(|e| Error::Inference(e.to_string()))?;
+        let interface::InferenceResult::PredictedValues(data_points) = result;
+        Ok(InferenceResult::PredictedValues(
+            data_points.into_iter().map(data_point_to_wit).collect(),
+        ))
+    }
+}
+
+fn data_window_from_wit(window: DataWindow) -> Result<interface::DataWindow, Error> {
+    let data = window
+        .data
+        .into_iter()
+        .map(|(key, data_point)| {
+            let timestamp = data_point
+                .timestamp
+                .map(|timestamp| {
+                    DateTime::<Utc>::from_timestamp(timestamp.seconds, timestamp.nanoseconds)
+                        .ok_or_else(|| Error::InvalidWindow(format!("Invalid timestamp of {key}")))
+                })
+                .transpose()?;
+            let value = match data_point.value {
+                Value::Number(num) => interface::Value::Number(num),
+                Value::String(string) => interface::Value::String(string),
+            };
+            let data_point = interface::DataPoint {
+                quality: data_point.quality,
+                value,
+                timestamp,
+            };
+            Ok((key, data_point))
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(interface::DataWindow { data })
+}
+
+fn data_point_to_wit(data_point: interface::DataPoint) -> DataPoint {
+    DataPoint {
+        quality: data_point.quality,
+        value: match data_point.value {
+            interface::Value::Number(num) => Value::Number(num),
+            interface::Value::String(string) => Value::String(string),
+        },
+        timestamp: data_point.timestamp.map(|timestamp| Datetime {
+            seconds: timestamp.timestamp(),
+            nanoseconds: timestamp.timestamp_subsec_nanos(),
+        }),
+    }
+}
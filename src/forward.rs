@@ -0,0 +1,116 @@
+// This module forwards the data windows received over HTTP to an
+// upstream collector, so that the component can double as the relay of
+// the telemetry it forecasts on. The collector is set in the `forward`
+// config (see config.rs):
+//
+// { "url": "http://collector.local/ingest", "attempts": 3, "backoff_ms": 100 }
+//
+// Every window that is parsed while a request is handled (see json.rs
+// and csv.rs) is recorded, and once the response has been sent, each
+// of them is posted to the collector as JSON. This is fire-and-forget:
+// The client does not wait for it, and failures are only logged. A
+// window is sent up to `attempts` times if the collector cannot be
+// reached or responds with 429 or 5xx, waiting `backoff_ms` before the
+// first retry and twice as long before each further one. Windows that
+// still fail are dropped, since the next request must not wait for
+// them.
+
+use std::{sync::Mutex, time::Duration};
+
+use serde::Deserialize;
+use wasi::clocks::monotonic_clock::subscribe_duration;
+use wasi_nn_demo_lib::interface;
+
+use crate::{config, error::Error, outgoing};
+
+const CONFIG: &str = "forward";
+
+// The windows received by the request being handled, if any
+static WINDOWS: Mutex<Option<Vec<Vec<u8>>>> = Mutex::new(None);
+
+#[derive(Deserialize)]
+struct Forward {
+    url: String,
+    #[serde(default = "default_attempts")]
+    attempts: u32,
+    #[serde(default = "default_backoff_ms")]
+    backoff_ms: u64,
+}
+
+fn default_attempts() -> u32 {
+    3
+}
+
+fn default_backoff_ms() -> u64 {
+    100
+}
+
+// Starts recording the windows of a request
+pub fn start() {
+    if let Ok(mut windows) = WINDOWS.lock() {
+        *windows = Some(Vec::new());
+    }
+}
+
+// Records the window if a request is being handled
+pub fn record(window: &interface::DataWindow) {
+    let Ok(mut windows) = WINDOWS.lock() else {
+        return;
+    };
+    let Some(windows) = windows.as_mut() else {
+        return;
+    };
+    match serde_json::to_vec(window) {
+        Ok(body) => windows.push(body),
+        Err(e) => eprintln!("Error serializing window for forwarding: {e}"),
+    }
+}
+
+// Stops recording and sends the recorded windows to the collector. The
+// response has been sent at this point, so errors are only logged.
+pub fn finish() {
+    let Some(windows) = WINDOWS.lock().ok().and_then(|mut windows| windows.take()) else {
+        return;
+    };
+    if windows.is_empty() {
+        return;
+    }
+    let forward = match config::load::<Forward>(CONFIG) {
+        Ok(Some(forward)) => forward,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("{e}");
+            return;
+        }
+    };
+    for window in windows {
+        if let Err(e) = forward.send(&window) {
+            eprintln!("Error forwarding window to {}: {e}", forward.url);
+        }
+    }
+}
+
+impl Forward {
+    fn send(&self, window: &[u8]) -> Result<(), Error> {
+        let mut backoff = Duration::from_millis(self.backoff_ms);
+        let mut attempt = 1;
+        loop {
+            let result = outgoing::post(&self.url, "application/json", window);
+            let retry = match &result {
+                Ok(status) if (200..300).contains(status) => return Ok(()),
+                Ok(status) => *status == 429 || *status >= 500,
+                Err(_) => true,
+            };
+            if !retry || attempt >= self.attempts {
+                return result.and_then(|status| {
+                    Err(Error::internal(format!(
+                        "Collector responded with {status} after {attempt} attempts"
+                    )))
+                });
+            }
+            subscribe_duration(backoff.as_nanos() as u64).block();
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+}
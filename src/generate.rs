@@ -0,0 +1,263 @@
+// This module adds a text generation model to the component (e.g.
+// GPT-2 exported to ONNX), which is served by `POST /generate`. The
+// text is generated one token at a time: The model predicts the next
+// token based on the prompt and all tokens generated so far. Each
+// token is reported as soon as it is known, so that clients can
+// display the text while it is being generated.
+
+use serde::Deserialize;
+use serde_json::json;
+use wasi::random::random::get_random_u64;
+
+use crate::{
+    error::Error,
+    http::JsonBody,
+    nn::{ExecutionContext, Graph, GraphEncoding, Tensor},
+    tokenizer::Tokenizer,
+    HttpHandler,
+};
+
+// These constants are the parameters that are specific to the
+// generation model. No such model is included in this repository,
+// place your own model and the files of its tokenizer in the models
+// directory.
+const MODEL_FORMAT: GraphEncoding = GraphEncoding::Onnx;
+const MODEL_FILES: [&str; 1] = ["models/generate.onnx"];
+const VOCAB_FILE: &str = "models/generate-vocab.json";
+const MERGES_FILE: &str = "models/generate-merges.txt";
+// The labels of the input and output tensors in the model
+const INPUT_IDS_TENSOR_NAME: &str = "input_ids";
+const ATTENTION_MASK_TENSOR_NAME: &str = "attention_mask";
+const OUTPUT_TENSOR_NAME: &str = "logits";
+// The token that ends the text. It is also used for padding.
+const EOS_TOKEN: &str = "<|endoftext|>";
+// The model sees at most this many tokens (1 x CONTEXT_LEN) and
+// returns scores for each token of the vocabulary at each position
+// (1 x CONTEXT_LEN x VOCAB_SIZE). Older tokens are dropped.
+const CONTEXT_LEN: usize = 128;
+const VOCAB_SIZE: usize = 50257;
+// The limit and default for the number of generated tokens
+const MAX_TOKENS: usize = 256;
+const DEFAULT_MAX_TOKENS: usize = 32;
+
+// The parameters of a request, which are sent as JSON body
+#[derive(Deserialize)]
+pub struct GenerateParams {
+    prompt: String,
+    #[serde(default = "default_max_tokens")]
+    max_tokens: usize,
+    // 0 always picks the most likely token, higher values make the
+    // text more random
+    #[serde(default = "default_temperature")]
+    temperature: f32,
+    // The generation stops as soon as one of these is generated. The
+    // stop sequence itself is not returned.
+    #[serde(default)]
+    stop: Vec<String>,
+}
+
+fn default_max_tokens() -> usize {
+    DEFAULT_MAX_TOKENS
+}
+
+fn default_temperature() -> f32 {
+    1.0
+}
+
+impl JsonBody for GenerateParams {
+    const NAME: &'static str = "parameters";
+
+    fn check(mut self) -> Result<Self, Error> {
+        if self.max_tokens > MAX_TOKENS {
+            return Err(Error::BadRequest(format!(
+                "max_tokens must not exceed {MAX_TOKENS}"
+            )));
+        }
+        if self.temperature < 0.0 {
+            return Err(Error::BadRequest("temperature must not be negative".into()));
+        }
+        self.stop.retain(|stop| !stop.is_empty());
+
+        Ok(self)
+    }
+}
+
+// The events that are reported during the generation
+pub enum Event<'a> {
+    // The next piece of the generated text
+    Token(&'a str),
+    // The generation has finished, either because a stop sequence (or
+    // the end of text) was generated ("stop"), or because max_tokens
+    // was reached ("length")
+    Done(&'static str),
+    // The generation has failed
+    Error(&'a str),
+}
+
+impl Event<'_> {
+    // The event in the format of server-sent events
+    pub fn to_sse(&self) -> Vec<u8> {
+        match self {
+            Event::Token(token) => format!("data: {}\n\n", json!({ "token": token })),
+            Event::Done(reason) => {
+                format!(
+                    "event: done\ndata: {}\n\n",
+                    json!({ "finish_reason": reason })
+                )
+            }
+            Event::Error(error) => format!("event: error\ndata: {}\n\n", json!({ "error": error })),
+        }
+        .into_bytes()
+    }
+}
+
+impl HttpHandler {
+    // Generates text for the prompt and calls `emit` for every piece
+    // of text as soon as it is generated
+    pub fn generate(
+        &mut self,
+        params: &GenerateParams,
+        mut emit: impl FnMut(Event) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let tokenizer = Tokenizer::from_files(VOCAB_FILE, MERGES_FILE)?;
+        let eos = tokenizer
+            .token_id(EOS_TOKEN)
+            .ok_or_else(|| Error::internal(format!("Token {EOS_TOKEN} missing in {VOCAB_FILE}")))?;
+
+        let graph = crate::load_model(MODEL_FORMAT, &MODEL_FILES)?;
+        let ctx = graph.init_execution_context()?;
+
+        // Without a prompt, the model starts with a new text
+        let mut ids = tokenizer.encode(&params.prompt);
+        if ids.is_empty() {
+            ids.push(eos);
+        }
+
+        // The generated text. Tokens are not necessarily complete
+        // UTF-8 characters, so bytes are collected in `pending` until
+        // they can be decoded. Text after `emitted` has not been sent
+        // yet, because it could be the beginning of a stop sequence.
+        let mut text = String::new();
+        let mut pending = Vec::new();
+        let mut emitted = 0;
+
+        for _ in 0..params.max_tokens {
+            let next = next_token(&graph, &ctx, &ids, eos, params.temperature)?;
+            if next == eos {
+                emit(Event::Token(&text[emitted..]))?;
+                return emit(Event::Done("stop"));
+            }
+            ids.push(next);
+
+            pending.extend(tokenizer.decode(next));
+            let valid = match std::str::from_utf8(&pending) {
+                Ok(_) => pending.len(),
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                // Invalid bytes are replaced instead of kept forever
+                Err(_) => pending.len(),
+            };
+            text.push_str(&String::from_utf8_lossy(&pending[..valid]));
+            pending.drain(..valid);
+
+            let stop = params
+                .stop
+                .iter()
+                .filter_map(|stop| text[emitted..].find(stop.as_str()))
+                .min();
+            if let Some(position) = stop {
+                emit(Event::Token(&text[emitted..emitted + position]))?;
+                return emit(Event::Done("stop"));
+            }
+
+            let end = text.len() - stop_prefix_len(&text, &params.stop);
+            if end > emitted {
+                emit(Event::Token(&text[emitted..end]))?;
+                emitted = end;
+            }
+        }
+
+        emit(Event::Token(&text[emitted..]))?;
+        emit(Event::Done("length"))
+    }
+}
+
+// Runs the model on (the most recent part of) the tokens and picks
+// the next token from its prediction
+fn next_token(
+    graph: &Graph,
+    ctx: &ExecutionContext,
+    ids: &[i64],
+    pad: i64,
+    temperature: f32,
+) -> Result<i64, Error> {
+    let context = &ids[ids.len().saturating_sub(CONTEXT_LEN)..];
+
+    // The input is padded at the end, which the model ignores because
+    // of the attention mask (and because it never looks ahead)
+    let mut input_ids = context.to_vec();
+    input_ids.resize(CONTEXT_LEN, pad);
+    let mut attention_mask = vec![1i64; context.len()];
+    attention_mask.resize(CONTEXT_LEN, 0);
+
+    let dims = vec![1, CONTEXT_LEN as u32];
+    let output_tensors = &crate::infer(graph, || {
+        ctx.run(
+            [
+                (INPUT_IDS_TENSOR_NAME, Tensor::new(input_ids, dims.clone())),
+                (
+                    ATTENTION_MASK_TENSOR_NAME,
+                    Tensor::new(attention_mask, dims),
+                ),
+            ],
+            &[OUTPUT_TENSOR_NAME],
+        )
+    })?;
+    // We drop the batch dimension of size one
+    let logits: &[[f32; VOCAB_SIZE]; CONTEXT_LEN] =
+        crate::reshape_output(&output_tensors[OUTPUT_TENSOR_NAME])?;
+
+    // The prediction for the next token is at the last real position
+    Ok(sample(&logits[context.len() - 1], temperature) as i64)
+}
+
+// Picks a token randomly, weighted by the probabilities predicted by
+// the model (softmax of the scores scaled by the temperature)
+fn sample(logits: &[f32], temperature: f32) -> usize {
+    let most_likely = logits
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or(0, |(token, _)| token);
+    if temperature == 0.0 {
+        return most_likely;
+    }
+
+    let max = logits[most_likely];
+    let weights: Vec<_> = logits
+        .iter()
+        .map(|logit| ((logit - max) / temperature).exp())
+        .collect();
+    let sum: f32 = weights.iter().sum();
+
+    // A random number in [0, sum), using 24 random bits
+    let mut threshold = (get_random_u64() >> 40) as f32 / (1 << 24) as f32 * sum;
+    for (token, weight) in weights.iter().enumerate() {
+        if threshold < *weight {
+            return token;
+        }
+        threshold -= weight;
+    }
+    most_likely
+}
+
+// The length of the longest end of the text that is the beginning of
+// one of the stop sequences
+fn stop_prefix_len(text: &str, stop: &[String]) -> usize {
+    stop.iter()
+        .flat_map(|stop| {
+            (1..stop.len())
+                .filter(|&len| stop.is_char_boundary(len) && text.ends_with(&stop[..len]))
+        })
+        .max()
+        .unwrap_or(0)
+}
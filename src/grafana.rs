@@ -0,0 +1,149 @@
+// This module implements the API of the Grafana Simple JSON datasource
+// (https://github.com/grafana/simple-json-datasource), so that the
+// forecasts can be plotted next to the actual values directly from the
+// component. The datasource URL is the `/grafana` prefix of the
+// component, e.g. `http://device.local:8080/grafana`. The values come
+// from the accuracy tracking (see accuracy.rs): For every series, there
+// is a target with its actual values and one with its forecasts.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{accuracy::RecordedValues, error::Error, with_handler};
+
+const FORECAST_SUFFIX: &str = " forecast";
+
+#[derive(Deserialize)]
+struct SearchRequest {
+    #[serde(default)]
+    target: String,
+}
+
+#[derive(Deserialize)]
+struct Range {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct QueryRequest {
+    range: Range,
+    targets: Vec<Target>,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    target: String,
+}
+
+#[derive(Serialize)]
+struct TimeSeries<'a> {
+    target: &'a str,
+    // Pairs of value and milliseconds since the epoch
+    datapoints: Vec<(f32, i64)>,
+}
+
+#[derive(Deserialize)]
+struct AnnotationRequest {
+    range: Range,
+    // Grafana expects the annotation to be sent back as is
+    annotation: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct Annotation<'a> {
+    annotation: &'a serde_json::Value,
+    time: i64,
+    title: String,
+    text: String,
+}
+
+// `POST /grafana/search`: The names of the targets that contain the
+// search term
+pub fn search(body: &[u8]) -> Result<Vec<u8>, Error> {
+    let request: SearchRequest = parse(body)?;
+    let recorded = with_handler(|handler| handler.recorded_values())?;
+
+    let targets: Vec<String> = recorded
+        .keys()
+        .flat_map(|series| [series.clone(), format!("{series}{FORECAST_SUFFIX}")])
+        .filter(|target| target.contains(&request.target))
+        .collect();
+    to_vec(&targets)
+}
+
+// `POST /grafana/query`: The values of the targets within the time
+// range
+pub fn query(body: &[u8]) -> Result<Vec<u8>, Error> {
+    let request: QueryRequest = parse(body)?;
+    let recorded = with_handler(|handler| handler.recorded_values())?;
+
+    let time_series: Vec<_> = request
+        .targets
+        .iter()
+        .map(|Target { target }| {
+            let values = match target.strip_suffix(FORECAST_SUFFIX) {
+                Some(series) => recorded.get(series).map(|values| &values.forecasts),
+                None => recorded.get(target).map(|values| &values.actuals),
+            };
+            let datapoints = values
+                .into_iter()
+                .flat_map(|values| values.range(request.range.from..=request.range.to))
+                .map(|(timestamp, &value)| (value, timestamp.timestamp_millis()))
+                .collect();
+            TimeSeries { target, datapoints }
+        })
+        .collect();
+    to_vec(&time_series)
+}
+
+// `POST /grafana/annotations`: Marks where the pending forecast of each
+// series starts, i.e. the first forecast value without an actual value
+// yet. The query of the annotation selects the series, all series are
+// annotated if it is empty.
+pub fn annotations(body: &[u8]) -> Result<Vec<u8>, Error> {
+    let request: AnnotationRequest = parse(body)?;
+    let recorded = with_handler(|handler| handler.recorded_values())?;
+
+    let query = request
+        .annotation
+        .get("query")
+        .and_then(|query| query.as_str())
+        .unwrap_or_default();
+    let annotations: Vec<_> = recorded
+        .iter()
+        .filter(|(series, _)| query.is_empty() || series.as_str() == query)
+        .filter_map(|(series, values)| {
+            let start = forecast_start(values)?;
+            (request.range.from..=request.range.to)
+                .contains(&start)
+                .then(|| Annotation {
+                    annotation: &request.annotation,
+                    time: start.timestamp_millis(),
+                    title: format!("Forecast of {series}"),
+                    text: format!("Start of the pending forecast of {series}"),
+                })
+        })
+        .collect();
+    to_vec(&annotations)
+}
+
+// The first forecast value after the latest actual value
+fn forecast_start(values: &RecordedValues) -> Option<DateTime<Utc>> {
+    let latest_actual = values.actuals.keys().next_back();
+    values
+        .forecasts
+        .keys()
+        .find(|&timestamp| Some(timestamp) > latest_actual)
+        .copied()
+}
+
+fn parse<'a, T: Deserialize<'a>>(body: &'a [u8]) -> Result<T, Error> {
+    serde_json::from_slice(body)
+        .map_err(|e| Error::BadRequest(format!("Invalid Grafana request: {e}")))
+}
+
+fn to_vec(value: &impl Serialize) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(value)
+        .map_err(|e| Error::internal(format!("Error serializing Grafana response: {e}")))
+}
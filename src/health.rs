@@ -0,0 +1,88 @@
+// This module answers the probes of orchestrators like Kubernetes, so
+// that they only send traffic to instances that can forecast:
+//
+// - `GET /healthz`: The component runs. Always 200.
+// - `GET /readyz`: The forecasting model has been loaded and a warm-up
+//   inference on a window of zeros succeeded. 503 with Retry-After
+//   otherwise.
+//
+// The probes are answered before the request is authenticated (see
+// http.rs), since orchestrators send no credentials and a busy instance
+// is still alive. The warm-up runs the model, so with the `queue`
+// feature it waits for a slot like a forecast, with low priority, so
+// that probes never hold up clients (see queue.rs). The result is kept
+// by the instance: One that passed the warm-up stays ready, and one
+// that failed it answers with the same error until RETRY_AFTER has
+// passed, so instances that live longer than a request (see the comment
+// on `MODELS` in lib.rs) do not run the model for every probe.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use wasi::http::types::ErrorCode;
+
+use crate::{error::Error, load_graph, model, model_forecast};
+
+// How long orchestrators should wait before probing again
+const RETRY_AFTER: u64 = 5;
+
+// Whether this instance passed the warm-up
+static WARMED_UP: AtomicBool = AtomicBool::new(false);
+// When this instance last failed the warm-up, and why
+static FAILED: Mutex<Option<(Instant, String)>> = Mutex::new(None);
+
+// The response bodies of the probes
+pub const HEALTHY: &[u8] = br#"{"status":"ok"}"#;
+pub const READY: &[u8] = br#"{"status":"ready"}"#;
+
+// Fails unless the model is loaded and has made a forecast
+pub fn ready() -> Result<(), Error> {
+    if WARMED_UP.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let not_ready = |message: String| Error::Unavailable {
+        message: format!("Not ready: {message}"),
+        retry_after: RETRY_AFTER,
+    };
+    if let Some(message) = recent_failure() {
+        return Err(not_ready(message));
+    }
+
+    #[cfg(feature = "queue")]
+    let _permit = crate::queue::admit(crate::queue::Priority::Low)?;
+    if let Err(e) = warm_up() {
+        let message = Error::from(e).to_string();
+        if let Ok(mut failed) = FAILED.lock() {
+            *failed = Some((Instant::now(), message.clone()));
+        }
+        return Err(not_ready(message));
+    }
+    WARMED_UP.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+// The error of the last warm-up, unless it is older than RETRY_AFTER
+fn recent_failure() -> Option<String> {
+    let failed = FAILED.lock().ok()?;
+    let (at, message) = failed.as_ref()?;
+    (at.elapsed() < Duration::from_secs(RETRY_AFTER)).then(|| message.clone())
+}
+
+fn warm_up() -> Result<(), ErrorCode> {
+    let graph = load_graph()?;
+    let spec = model::spec()?;
+    let history = vec![0.0; spec.shape.history_len as usize];
+    model_forecast(
+        &graph,
+        &spec,
+        &history,
+        spec.shape.prediction_len,
+        &mut |_| Ok(()),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,154 @@
+// This module implements `POST /forecast/hierarchy`, which forecasts a
+// hierarchy of series, e.g. the power consumption per machine, per
+// production line and for the whole plant. Only the series at the
+// bottom of the hierarchy (the machines) are forecast by the model.
+// The forecasts of the aggregates (the lines and the plant) are the
+// sums of the forecasts of their children, so that the forecasts are
+// consistent at all levels (this is called bottom-up reconciliation).
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use wasi_nn_demo_lib::interface;
+
+use crate::{
+    error::Error,
+    forecast_histories,
+    http::JsonBody,
+    model::{self, ModelSpec},
+    series_from_data_window, HttpHandler,
+};
+
+#[derive(Deserialize)]
+pub struct HierarchyRequest {
+    // The data windows of the series at the bottom of the hierarchy,
+    // by name
+    series: BTreeMap<String, interface::DataWindow>,
+    // The names of the children of each aggregate, which are either
+    // series or other aggregates
+    aggregates: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Serialize)]
+pub struct HierarchyForecast {
+    series: BTreeMap<String, Vec<f32>>,
+    aggregates: BTreeMap<String, Vec<f32>>,
+}
+
+impl JsonBody for HierarchyRequest {
+    const NAME: &'static str = "hierarchy request";
+
+    fn check(self) -> Result<Self, Error> {
+        #[cfg(feature = "usage")]
+        crate::usage::record_data_points(
+            self.series.values().map(|window| window.data.len()).sum(),
+        );
+
+        for (name, children) in &self.aggregates {
+            if self.series.contains_key(name) {
+                return Err(Error::BadRequest(format!(
+                    "{name} is both a series and an aggregate"
+                )));
+            }
+            if children.is_empty() {
+                return Err(Error::BadRequest(format!(
+                    "Aggregate {name} has no children"
+                )));
+            }
+            // Counting a child twice would make the aggregate inconsistent
+            if let Some((_, child)) = children
+                .iter()
+                .enumerate()
+                .find(|(i, child)| children[..*i].contains(child))
+            {
+                return Err(Error::BadRequest(format!(
+                    "Aggregate {name} contains {child} more than once"
+                )));
+            }
+        }
+        Ok(self)
+    }
+}
+
+pub fn forecast_to_vec(forecast: &HierarchyForecast) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(forecast)
+        .map_err(|e| Error::internal(format!("Error serializing hierarchy forecast: {e}")))
+}
+
+impl HttpHandler {
+    pub fn forecast_hierarchy(
+        &mut self,
+        model: Option<&ModelSpec>,
+        request: &HierarchyRequest,
+    ) -> Result<HierarchyForecast, Error> {
+        // Check the hierarchy before running any inference
+        let order = aggregation_order(request)?;
+
+        let histories: Vec<_> = request
+            .series
+            .values()
+            .map(series_from_data_window)
+            .collect();
+        let forecasts = forecast_histories(model, &histories)?;
+        let prediction_len = model::shape_of(model)?.prediction_len;
+        let series: BTreeMap<_, _> = request.series.keys().cloned().zip(forecasts).collect();
+
+        // The aggregates are summed up in an order where all children
+        // come before their parents
+        let mut aggregates: BTreeMap<String, Vec<f32>> = BTreeMap::new();
+        for name in order {
+            let mut sum = vec![0.0; prediction_len as usize];
+            for child in &request.aggregates[name] {
+                let forecast = series.get(child).or_else(|| aggregates.get(child));
+                for (total, value) in sum.iter_mut().zip(forecast.into_iter().flatten()) {
+                    *total += value;
+                }
+            }
+            aggregates.insert(name.to_string(), sum);
+        }
+
+        Ok(HierarchyForecast { series, aggregates })
+    }
+}
+
+// Returns the names of the aggregates, ordered so that every aggregate
+// comes after all of its children. Fails if a child does not exist or
+// the aggregates contain a cycle.
+fn aggregation_order(request: &HierarchyRequest) -> Result<Vec<&str>, Error> {
+    let mut order = Vec::with_capacity(request.aggregates.len());
+    let mut path = Vec::new();
+    for name in request.aggregates.keys() {
+        visit(name, request, &mut path, &mut order)?;
+    }
+    Ok(order)
+}
+
+// Depth-first search through the aggregates, `path` are the aggregates
+// that are currently being visited
+fn visit<'a>(
+    name: &'a str,
+    request: &'a HierarchyRequest,
+    path: &mut Vec<&'a str>,
+    order: &mut Vec<&'a str>,
+) -> Result<(), Error> {
+    if request.series.contains_key(name) || order.contains(&name) {
+        return Ok(());
+    }
+    if path.contains(&name) {
+        return Err(Error::BadRequest(format!(
+            "Aggregate {name} contains itself"
+        )));
+    }
+    let children = request
+        .aggregates
+        .get(name)
+        .ok_or_else(|| Error::BadRequest(format!("Unknown series or aggregate {name}")))?;
+
+    path.push(name);
+    for child in children {
+        visit(child, request, path, order)?;
+    }
+    path.pop();
+    order.push(name);
+    Ok(())
+}
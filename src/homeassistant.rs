@@ -0,0 +1,146 @@
+// This module formats forecasts as Home Assistant sensor states, for
+// smart-home deployments. With `?format=homeassistant`, the forecast
+// is returned as a state object that a REST sensor can poll:
+//
+// sensor:
+//   - platform: rest
+//     resource: http://device.local:8080/?series=boiler&format=homeassistant
+//     method: POST
+//     value_template: "{{ value_json.state }}"
+//     json_attributes: [forecast]
+//
+// Alternatively, forecasts for a series are pushed to the REST API of
+// Home Assistant as the state of the entity `sensor.<series>_forecast`
+// if it is configured in the `homeassistant` config (see config.rs).
+// The long-lived access token is read from the environment
+// (`--env HOMEASSISTANT_TOKEN`):
+//
+// { "url": "http://homeassistant.local:8123", "unit_of_measurement": "°C" }
+
+use std::env;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use wasi::http::types::Method;
+
+use crate::{config, error::Error, outgoing};
+
+const CONFIG: &str = "homeassistant";
+const TOKEN_VARIABLE: &str = "HOMEASSISTANT_TOKEN";
+
+#[derive(Deserialize)]
+struct Config {
+    url: String,
+    unit_of_measurement: Option<String>,
+}
+
+// The body of `POST /api/states/<entity_id>`, which is also what a REST
+// sensor expects
+#[derive(Serialize)]
+pub struct Sensor {
+    // The first forecast value
+    state: f32,
+    attributes: Attributes,
+}
+
+#[derive(Serialize)]
+struct Attributes {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    friendly_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit_of_measurement: Option<String>,
+    forecast: Vec<ForecastValue>,
+}
+
+// The same shape as the forecasts of Home Assistant weather entities
+#[derive(Serialize)]
+struct ForecastValue {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    datetime: Option<DateTime<Utc>>,
+    value: f32,
+}
+
+// The forecast as sensor state. The timestamps of the forecast values
+// are only known if the window has timestamps.
+pub fn sensor(
+    series: Option<&str>,
+    values: &[f32],
+    timestamps: Option<Vec<DateTime<Utc>>>,
+) -> Sensor {
+    let timestamps = timestamps.map_or_else(
+        || vec![None; values.len()],
+        |timestamps| timestamps.into_iter().map(Some).collect(),
+    );
+    let forecast = values
+        .iter()
+        .zip(timestamps)
+        .map(|(&value, datetime)| ForecastValue { datetime, value })
+        .collect();
+
+    Sensor {
+        // The horizon is at least 1, so there always is a first value
+        state: values.first().copied().unwrap_or(f32::NAN),
+        attributes: Attributes {
+            friendly_name: series.map(|series| format!("{series} forecast")),
+            unit_of_measurement: None,
+            forecast,
+        },
+    }
+}
+
+pub fn sensor_to_vec(sensor: &Sensor) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(sensor)
+        .map_err(|e| Error::internal(format!("Error serializing sensor state: {e}")))
+}
+
+// Pushes the sensor state of the series to Home Assistant if it is
+// configured. Like alert notifications, failed pushes are only
+// reported on stderr and do not fail the request.
+pub fn push(series: &str, sensor: Sensor) {
+    if let Err(e) = try_push(series, sensor) {
+        eprintln!("Error pushing forecast to Home Assistant: {e}");
+    }
+}
+
+fn try_push(series: &str, mut sensor: Sensor) -> Result<(), Error> {
+    let Some(config) = config::load::<Config>(CONFIG)? else {
+        return Ok(());
+    };
+    let token = env::var(TOKEN_VARIABLE)
+        .map_err(|_| Error::internal(format!("Missing environment variable {TOKEN_VARIABLE}")))?;
+
+    sensor.attributes.unit_of_measurement = config.unit_of_measurement;
+    let url = format!(
+        "{}/api/states/sensor.{}_forecast",
+        config.url.trim_end_matches('/'),
+        object_id(series)
+    );
+    let headers = [
+        ("content-type".to_string(), b"application/json".to_vec()),
+        (
+            "authorization".to_string(),
+            format!("Bearer {token}").into_bytes(),
+        ),
+    ];
+    let response = outgoing::request(Method::Post, &url, &headers, &sensor_to_vec(&sensor)?)?;
+    if !(200..300).contains(&response.status) {
+        return Err(Error::internal(format!(
+            "Home Assistant responded with {}",
+            response.status
+        )));
+    }
+    Ok(())
+}
+
+// Entity ids may only contain lowercase letters, digits and
+// underscores
+fn object_id(series: &str) -> String {
+    series
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' => c,
+            'A'..='Z' => c.to_ascii_lowercase(),
+            _ => '_',
+        })
+        .collect()
+}
@@ -0,0 +1,1619 @@
+// This module exports the wasi:http/proxy world, which makes the
+// component a HTTP service that can be run using `wasmtime serve`.
+
+use wasi::{
+    exports::http::incoming_handler::{Guest, IncomingRequest, ResponseOutparam},
+    http::{
+        proxy::export,
+        types::{ErrorCode, Fields, IncomingBody, Method, OutgoingBody, OutgoingResponse},
+    },
+    io::streams::{OutputStream, StreamError},
+};
+use wasi_nn_demo_lib::interface;
+
+use crate::{error::Error, inference_result_from_values, json, with_handler, Component};
+
+#[cfg(feature = "auth")]
+mod auth;
+mod router;
+
+use router::Route;
+
+// The maximum number of bytes read from or written to a stream at
+// once (wasi-io does not allow writing more than 4096 bytes at once)
+const CHUNK_SIZE: u64 = 4096;
+
+// To create a HTTP service in WASM, we need to implement the `Guest`
+// trait of the incoming handler for our component. The `handle`
+// function will be invoked by the WASM runtime for every request.
+export!(Component);
+
+impl Guest for Component {
+    fn handle(request: IncomingRequest, response_outparam: ResponseOutparam) {
+        // The demo library could also read the request and write the
+        // response for us (`RequestHandler::handle_request`), but we
+        // do this ourselves, so that we control how the body is
+        // parsed (see json.rs) and can offer more than one kind of
+        // input.
+        // Features whose interfaces the host does not provide are
+        // disabled before they are used (see capabilities.rs)
+        #[cfg(feature = "capabilities")]
+        crate::capabilities::probe();
+        #[cfg(feature = "forward")]
+        crate::forward::start();
+        // The request is a span in the trace of the caller (see
+        // tracecontext.rs)
+        #[cfg(feature = "tracecontext")]
+        crate::tracecontext::start(&request.headers().entries());
+        // The request is logged before it is read, so that requests
+        // whose body cannot be read are logged as well (see logging.rs)
+        #[cfg(feature = "logging")]
+        let (method, path, start) = {
+            let method = method_name(&request.method()).to_string();
+            let path_with_query = request.path_with_query().unwrap_or_default();
+            let path = match path_with_query.split_once('?') {
+                Some((path, _)) => path.to_string(),
+                None => path_with_query,
+            };
+            crate::logging::request_start(&method, &path);
+            (method, path, std::time::Instant::now())
+        };
+        let response = Request::read(&request)
+            .and_then(dispatch)
+            .unwrap_or_else(error_response);
+        // Browsers may read the response if its origin is allowed (see
+        // cors.rs)
+        #[cfg(feature = "cors")]
+        let response = {
+            let headers = request.headers().entries();
+            let origin = headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case("origin"))
+                .and_then(|(_, value)| std::str::from_utf8(value).ok());
+            crate::cors::headers(origin)
+                .into_iter()
+                .fold(response, |response, (name, value)| {
+                    response.with_header(name, value)
+                })
+        };
+        #[cfg(feature = "tracecontext")]
+        let response = match crate::tracecontext::traceparent() {
+            Some(traceparent) => response.with_header("traceparent", traceparent),
+            None => response,
+        };
+        #[cfg(feature = "sign")]
+        let response = response.signed();
+
+        #[cfg(feature = "logging")]
+        let status = response.status;
+        response.send(response_outparam);
+        #[cfg(feature = "logging")]
+        crate::logging::request_end(&method, &path, status, start.elapsed());
+        // The request is accounted to the client once the response has
+        // been sent, which includes streamed responses (see usage.rs)
+        #[cfg(feature = "usage")]
+        crate::usage::finish();
+        // Expired data is deleted after the response, so that this
+        // does not delay it (see retention.rs)
+        #[cfg(feature = "retention")]
+        crate::retention::cleanup();
+        #[cfg(feature = "forward")]
+        crate::forward::finish();
+        #[cfg(feature = "tracecontext")]
+        crate::tracecontext::finish();
+    }
+}
+
+fn error_response(error: Error) -> Response {
+    // While the circuit breaker is open, errors of the model are
+    // reported as temporary (see breaker.rs)
+    #[cfg(feature = "breaker")]
+    let error = crate::breaker::unavailable(error);
+    // The same goes for disabled models and models that used up their
+    // inference time (see admin.rs)
+    #[cfg(feature = "admin")]
+    let error = crate::admin::unavailable(error);
+    #[cfg(feature = "logging")]
+    crate::logging::request_error(&error);
+
+    let response = Response::json(error.status(), error.to_json().into_bytes());
+    match error {
+        Error::Unavailable { retry_after, .. } => {
+            response.with_header("retry-after", retry_after.to_string())
+        }
+        _ => response,
+    }
+}
+
+// Answers the probes of orchestrators without authentication, since
+// they have no credentials (see health.rs). The same goes for the
+// preflight requests of browsers (see cors.rs) and the document that
+// describes the component (see discovery.rs). All other requests are
+// authenticated and admitted before they are routed.
+fn dispatch(request: Request) -> Result<Response, Error> {
+    #[cfg(feature = "cors")]
+    if let (Method::Options, Some(method)) = (
+        &request.method,
+        request.header("access-control-request-method"),
+    ) {
+        return Ok(crate::cors::preflight(request.header("origin"), method)
+            .into_iter()
+            .fold(
+                Response::new(204, "text/plain", Vec::new()),
+                |response, (name, value)| response.with_header(name, value),
+            ));
+    }
+    #[cfg(feature = "health")]
+    match (&request.method, request.path.as_str()) {
+        (Method::Get, "/healthz") => {
+            return Ok(Response::json(200, crate::health::HEALTHY.to_vec()))
+        }
+        (Method::Get, "/readyz") => {
+            crate::health::ready()?;
+            return Ok(Response::json(200, crate::health::READY.to_vec()));
+        }
+        _ => {}
+    }
+    // Clients need the document to know how to authenticate (see
+    // discovery.rs)
+    #[cfg(feature = "discovery")]
+    if let (Method::Get, "/.well-known/wasi-nn-demo") = (&request.method, request.path.as_str()) {
+        #[cfg(feature = "auth")]
+        let schemes = auth::schemes()?;
+        #[cfg(not(feature = "auth"))]
+        let schemes = Vec::new();
+        let document = crate::discovery::document(schemes)?;
+        return Ok(Response::json(
+            200,
+            crate::discovery::document_to_vec(&document)?,
+        ));
+    }
+    authenticate(request).and_then(admit)
+}
+
+// Rejects requests that must not be handled, before they are routed
+fn authenticate(request: Request) -> Result<Request, Error> {
+    // The client must identify itself with one of the configured
+    // schemes (see http/auth.rs)
+    #[cfg(feature = "auth")]
+    let request = Request {
+        identity: Some(auth::authenticate(&request)?),
+        ..request
+    };
+    // Only admins may use the admin endpoints, e.g. to disable a model
+    // or delete state
+    #[cfg(feature = "auth")]
+    if request.path.starts_with("/admin/")
+        && !request
+            .identity
+            .as_ref()
+            .is_some_and(|identity| identity.admin)
+    {
+        return Err(Error::Forbidden(format!(
+            "{} requires an admin",
+            request.path
+        )));
+    }
+    // From now on, the request is accounted to the client (see
+    // usage.rs)
+    #[cfg(feature = "usage")]
+    if let Some(identity) = &request.identity {
+        crate::usage::start(&identity.subject);
+    }
+    // The signature is verified before the nonce is recorded, so that
+    // requests that are not signed cannot use up nonces. With replay
+    // protection, the timestamp and nonce are signed along with the
+    // body (see signature.rs).
+    #[cfg(all(feature = "signature", feature = "replay"))]
+    crate::signature::verify(
+        request.header("x-signature"),
+        &[
+            request
+                .header("x-request-timestamp")
+                .unwrap_or_default()
+                .as_bytes(),
+            request
+                .header("x-request-nonce")
+                .unwrap_or_default()
+                .as_bytes(),
+            &request.body,
+        ],
+    )?;
+    #[cfg(all(feature = "signature", not(feature = "replay")))]
+    crate::signature::verify(request.header("x-signature"), &[&request.body])?;
+    #[cfg(feature = "replay")]
+    crate::replay::check(
+        request.header("x-request-timestamp"),
+        request.header("x-request-nonce"),
+    )?;
+
+    Ok(request)
+}
+
+// Routes the request once it may run. Only POST requests are queued,
+// the others do not run the model and should be answered even when the
+// device is busy (e.g. GET /metrics). The same goes for the admin
+// endpoints, so that a bad model can be disabled right away.
+fn admit(request: Request) -> Result<Response, Error> {
+    #[cfg(feature = "queue")]
+    let _permit = match request.method {
+        Method::Post if !request.path.starts_with("/admin/") => {
+            let priority = crate::queue::Priority::parse(request.header("x-priority"))?;
+            Some(crate::queue::admit(priority)?)
+        }
+        _ => None,
+    };
+    // Data that may have reached its hard retention is deleted before
+    // the request can read it (see retention.rs)
+    #[cfg(feature = "retention")]
+    crate::retention::enforce()?;
+
+    let response = route(request)?;
+    #[cfg(feature = "queue")]
+    let response = response.holding(_permit);
+    Ok(response)
+}
+
+// Decides how to handle the request based on its method and path (see
+// http/router.rs). Requests without an endpoint of their own are
+// forecasts or handled by their content type.
+fn route(request: Request) -> Result<Response, Error> {
+    match router::find(ROUTES, &request.method, &request.path) {
+        Some(route) => (route.handler)(request),
+        None => route_by_content_type(request),
+    }
+}
+
+// The endpoints, which are tried in order
+static ROUTES: &[Route] = &[
+    #[cfg(feature = "accuracy")]
+    Route {
+        method: "POST",
+        path: "/actuals",
+        handler: |request| {
+            let series = required_series(&request)?;
+            let input = json::parse_data_window(&request.body)?;
+            let report = with_handler(|handler| handler.record_actuals(series, input))?;
+            #[cfg(feature = "pushgateway")]
+            crate::pushgateway::push_metrics();
+            Ok(Response::json(
+                200,
+                crate::accuracy::actuals_report_to_vec(&report)?,
+            ))
+        },
+    },
+    #[cfg(feature = "admin")]
+    Route {
+        method: "GET",
+        path: "/admin/models",
+        handler: |_| {
+            let models = crate::admin::models()?;
+            Ok(Response::json(200, crate::admin::models_to_vec(&models)?))
+        },
+    },
+    #[cfg(feature = "admin")]
+    Route {
+        method: "POST",
+        path: "/admin/models/**",
+        handler: |request| {
+            let status = crate::admin::switch(&request.path)?;
+            Ok(Response::json(200, crate::admin::status_to_vec(&status)?))
+        },
+    },
+    #[cfg(feature = "prune")]
+    Route {
+        method: "POST",
+        path: "/admin/state/prune",
+        handler: |request| {
+            let filter: crate::prune::Filter = request.json()?;
+            let report = crate::prune::prune(&filter)?;
+            Ok(Response::json(200, crate::prune::report_to_vec(&report)?))
+        },
+    },
+    #[cfg(feature = "usage")]
+    Route {
+        method: "GET",
+        path: "/admin/usage",
+        handler: |_| {
+            let usage = crate::usage::usage()?;
+            Ok(Response::json(200, crate::usage::usage_to_vec(&usage)?))
+        },
+    },
+    #[cfg(feature = "accuracy")]
+    Route {
+        method: "GET",
+        path: "/accuracy",
+        handler: |_| {
+            let accuracy = with_handler(|handler| handler.accuracy())?;
+            Ok(Response::json(
+                200,
+                crate::accuracy::accuracy_to_vec(&accuracy)?,
+            ))
+        },
+    },
+    #[cfg(any(feature = "accuracy", feature = "admin", feature = "queue"))]
+    Route {
+        method: "GET",
+        path: "/metrics",
+        handler: |_| {
+            let mut metrics = String::new();
+            #[cfg(feature = "accuracy")]
+            {
+                let accuracy = with_handler(|handler| handler.accuracy())?;
+                metrics.push_str(&crate::accuracy::accuracy_to_prometheus(&accuracy));
+            }
+            #[cfg(feature = "admin")]
+            metrics.push_str(&crate::admin::models_to_prometheus());
+            #[cfg(feature = "queue")]
+            metrics.push_str(&crate::queue::queue_to_prometheus());
+            Ok(Response::new(
+                200,
+                "text/plain; version=0.0.4",
+                metrics.into_bytes(),
+            ))
+        },
+    },
+    #[cfg(feature = "capabilities")]
+    Route {
+        method: "GET",
+        path: "/version",
+        handler: |_| {
+            let version = crate::capabilities::version();
+            Ok(Response::json(
+                200,
+                crate::capabilities::version_to_vec(&version)?,
+            ))
+        },
+    },
+    #[cfg(feature = "text")]
+    Route {
+        method: "POST",
+        path: "/classify-text",
+        handler: |request| {
+            let text = std::str::from_utf8(&request.body)
+                .map_err(|e| Error::BadRequest(format!("Text is not valid UTF-8: {e}")))?;
+            let labels = with_handler(|handler| handler.classify_text(text))?;
+            Ok(Response::json(200, crate::labels::labels_to_vec(&labels)?))
+        },
+    },
+    #[cfg(feature = "aggregate")]
+    Route {
+        method: "POST",
+        path: "/predict/aggregate",
+        handler: |request| {
+            let aggregate: crate::aggregate::AggregateRequest = request.json()?;
+            let model = selected_model(&request)?;
+            let forecast =
+                with_handler(|handler| handler.forecast_aggregate(model.as_ref(), aggregate))?;
+            Ok(Response::json(
+                200,
+                crate::aggregate::forecast_to_vec(&forecast)?,
+            ))
+        },
+    },
+    #[cfg(feature = "batch")]
+    Route {
+        method: "POST",
+        path: "/predict/batch",
+        handler: |request| {
+            let batch: crate::batch::BatchRequest = request.json()?;
+            #[cfg(feature = "strict")]
+            if strict(&request) {
+                crate::batch::check_history_lens(&batch)?;
+            }
+            let model = selected_model(&request)?;
+            let forecast = with_handler(|handler| handler.forecast_batch(model.as_ref(), batch))?;
+            Ok(Response::json(
+                200,
+                crate::batch::forecast_to_vec(&forecast)?,
+            ))
+        },
+    },
+    #[cfg(feature = "anomaly")]
+    Route {
+        method: "POST",
+        path: "/anomalies",
+        handler: |request| {
+            let threshold = request
+                .parsed_param("threshold")?
+                .unwrap_or(crate::anomaly::DEFAULT_THRESHOLD);
+            let input = json::parse_data_window(&request.body)?;
+            let report = with_handler(|handler| handler.detect_anomalies(input, threshold))?;
+            Ok(Response::json(200, crate::anomaly::report_to_vec(&report)?))
+        },
+    },
+    #[cfg(feature = "backtest")]
+    Route {
+        method: "POST",
+        path: "/backtest",
+        handler: |request| {
+            let stride = match request.parsed_param("stride")? {
+                Some(0) => return Err(Error::BadRequest("Invalid stride: 0".into())),
+                Some(stride) => stride,
+                None => crate::model::shape()?.prediction_len as usize,
+            };
+            let input = json::parse_data_window(&request.body)?;
+            let report = with_handler(|handler| handler.backtest(input, stride))?;
+            // Notebooks can import the results directly as CSV
+            if request.accepts("text/csv") {
+                return Ok(Response::new(
+                    200,
+                    "text/csv",
+                    crate::backtest::report_to_csv(&report).into_bytes(),
+                ));
+            }
+            Ok(Response::json(
+                200,
+                crate::backtest::report_to_vec(&report)?,
+            ))
+        },
+    },
+    #[cfg(feature = "cluster")]
+    Route {
+        method: "POST",
+        path: "/cluster",
+        handler: |request| {
+            let clustering: crate::cluster::ClusterRequest = request.json()?;
+            let result = with_handler(|handler| handler.cluster(clustering))?;
+            Ok(Response::json(200, crate::cluster::result_to_vec(&result)?))
+        },
+    },
+    #[cfg(feature = "compare")]
+    Route {
+        method: "POST",
+        path: "/compare",
+        handler: |request| {
+            let input = json::parse_data_window(&request.body)?;
+            let comparison = with_handler(|handler| handler.compare(input))?;
+            Ok(Response::json(
+                200,
+                crate::compare::comparison_to_vec(&comparison)?,
+            ))
+        },
+    },
+    #[cfg(feature = "covariates")]
+    Route {
+        method: "POST",
+        path: "/forecast/covariates",
+        handler: |request| {
+            let covariates: crate::covariates::CovariateRequest = request.json()?;
+            // Holidays within the forecast are reported in a header
+            #[cfg(feature = "calendar")]
+            let holidays = covariates
+                .holidays
+                .iter()
+                .map(|date| date.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            let result = with_handler(|handler| handler.forecast_with_covariates(covariates))?;
+
+            let response = Response::json(200, json::inference_result_to_vec(&result)?);
+            #[cfg(feature = "calendar")]
+            let response = if holidays.is_empty() {
+                response
+            } else {
+                response.with_header("holidays", holidays)
+            };
+            Ok(response)
+        },
+    },
+    #[cfg(feature = "embedding")]
+    Route {
+        method: "POST",
+        path: "/embed",
+        handler: |request| {
+            let input = json::parse_data_window(&request.body)?;
+            let result = with_handler(|handler| handler.embed(input))?;
+            Ok(Response::json(
+                200,
+                crate::embedding::result_to_vec(&result)?,
+            ))
+        },
+    },
+    #[cfg(feature = "search")]
+    Route {
+        method: "POST",
+        path: "/embeddings/upsert",
+        handler: |request| {
+            let upsert: crate::search::UpsertRequest = request.json()?;
+            let result = with_handler(|handler| handler.upsert_embedding(upsert))?;
+            Ok(Response::json(
+                200,
+                crate::search::upsert_result_to_vec(&result)?,
+            ))
+        },
+    },
+    #[cfg(feature = "search")]
+    Route {
+        method: "POST",
+        path: "/embeddings/search",
+        handler: |request| {
+            let search: crate::search::SearchRequest = request.json()?;
+            let result = with_handler(|handler| handler.search_embeddings(search))?;
+            Ok(Response::json(
+                200,
+                crate::search::search_result_to_vec(&result)?,
+            ))
+        },
+    },
+    #[cfg(feature = "grafana")]
+    Route {
+        method: "GET",
+        path: "/grafana",
+        handler: |_| Ok(Response::new(200, "text/plain", b"OK".to_vec())),
+    },
+    #[cfg(feature = "grafana")]
+    Route {
+        method: "POST",
+        path: "/grafana/search",
+        handler: |request| Ok(Response::json(200, crate::grafana::search(&request.body)?)),
+    },
+    #[cfg(feature = "grafana")]
+    Route {
+        method: "POST",
+        path: "/grafana/query",
+        handler: |request| Ok(Response::json(200, crate::grafana::query(&request.body)?)),
+    },
+    #[cfg(feature = "grafana")]
+    Route {
+        method: "POST",
+        path: "/grafana/annotations",
+        handler: |request| {
+            Ok(Response::json(
+                200,
+                crate::grafana::annotations(&request.body)?,
+            ))
+        },
+    },
+    #[cfg(feature = "hierarchy")]
+    Route {
+        method: "POST",
+        path: "/forecast/hierarchy",
+        handler: |request| {
+            let hierarchy: crate::hierarchy::HierarchyRequest = request.json()?;
+            let model = selected_model(&request)?;
+            let forecast =
+                with_handler(|handler| handler.forecast_hierarchy(model.as_ref(), &hierarchy))?;
+            Ok(Response::json(
+                200,
+                crate::hierarchy::forecast_to_vec(&forecast)?,
+            ))
+        },
+    },
+    #[cfg(feature = "generate")]
+    Route {
+        method: "POST",
+        path: "/generate",
+        handler: |request| {
+            let params: crate::generate::GenerateParams = request.json()?;
+            // The tokens are sent as server-sent events as soon as they
+            // are generated
+            Ok(Response::stream("text/event-stream", move |writer| {
+                with_handler(|handler| {
+                    handler.generate(&params, |event| writer.write(&event.to_sse()))
+                })
+                .or_else(|e| {
+                    // The client would not see the error otherwise
+                    let event = crate::generate::Event::Error(&e.to_string()).to_sse();
+                    writer.write(&event).and(Err(e))
+                })
+            }))
+        },
+    },
+    #[cfg(feature = "ingest")]
+    Route {
+        method: "POST",
+        path: "/ingest",
+        handler: |request| ingest(required_series(&request)?, &request),
+    },
+    // Sensors can also post to a URL of their own series
+    #[cfg(feature = "ingest")]
+    Route {
+        method: "POST",
+        path: "/series/**/points",
+        handler: |request| ingest(crate::ingest::series_from_path(&request.path)?, &request),
+    },
+    #[cfg(feature = "ingest")]
+    Route {
+        method: "GET",
+        path: "/predict",
+        handler: |request| {
+            let series = required_series(&request)?;
+            let model = selected_model(&request)?;
+            let result = with_handler(|handler| handler.forecast_ingested(model.as_ref(), series))?;
+            let body = json::inference_result_to_vec(&result)?;
+            // The baseline of the series is returned next to the
+            // forecast (see baseline.rs)
+            #[cfg(feature = "baseline")]
+            let body = {
+                let mut sections = serde_json::Map::new();
+                let horizon = crate::model::shape()?.prediction_len;
+                if let Some(baseline) = crate::baseline::forecast(series, horizon)? {
+                    sections.insert("baseline".into(), to_section(&baseline)?);
+                }
+                with_sections(body, sections)?
+            };
+            Ok(Response::json(200, body))
+        },
+    },
+    #[cfg(feature = "s3")]
+    Route {
+        method: "POST",
+        path: "/models/sync",
+        handler: |_| {
+            let report = crate::s3::sync_models()?;
+            Ok(Response::json(200, crate::s3::report_to_vec(&report)?))
+        },
+    },
+    #[cfg(feature = "iothub")]
+    Route {
+        method: "POST",
+        path: "/iothub",
+        handler: |request| {
+            let (input, envelope) = crate::iothub::parse_message(&request.body)?;
+            let horizon = crate::model::shape()?.prediction_len;
+            let result = with_handler(|handler| handler.forecast(input, horizon))?;
+            let output = json::inference_result_to_vec(&result)?;
+            Ok(Response::json(
+                200,
+                crate::iothub::message_to_vec(&envelope, &output)?,
+            ))
+        },
+    },
+    #[cfg(feature = "quality")]
+    Route {
+        method: "POST",
+        path: "/quality",
+        handler: |request| {
+            let range = crate::quality::Range {
+                min: request.parsed_param("min")?,
+                max: request.parsed_param("max")?,
+            };
+            let input = json::parse_data_window_unchecked(&request.body)?;
+            let history_len = crate::model::shape()?.history_len;
+            let report = crate::quality::report(&input, &range, history_len);
+            Ok(Response::json(200, crate::quality::report_to_vec(&report)?))
+        },
+    },
+    #[cfg(feature = "stitch")]
+    Route {
+        method: "GET",
+        path: "/forecast/**/stitched",
+        handler: |request| {
+            let result = crate::stitch::stitched(&request.path)?;
+            Ok(Response::json(200, json::inference_result_to_vec(&result)?))
+        },
+    },
+    #[cfg(feature = "latest")]
+    Route {
+        method: "GET",
+        path: "/forecast/**",
+        handler: |request| {
+            let latest = crate::latest::get(&request.path)?;
+            Ok(Response::json(200, crate::latest::latest_to_vec(&latest)?))
+        },
+    },
+    #[cfg(feature = "trace")]
+    Route {
+        method: "GET",
+        path: "/traces/**",
+        handler: |request| {
+            let trace = crate::trace::get(&request.path)?;
+            Ok(Response::json(200, crate::trace::trace_to_vec(&trace)?))
+        },
+    },
+    #[cfg(feature = "simulate")]
+    Route {
+        method: "POST",
+        path: "/simulate",
+        handler: |request| {
+            let simulation: crate::simulate::SimulationRequest = request.json()?;
+            let result = with_handler(|handler| handler.simulate(simulation))?;
+            Ok(Response::json(
+                200,
+                crate::simulate::result_to_vec(&result)?,
+            ))
+        },
+    },
+];
+
+// All other requests are handled based on their content type
+fn route_by_content_type(request: Request) -> Result<Response, Error> {
+    match request.content_type() {
+        #[cfg(feature = "vision")]
+        Some("image/jpeg" | "image/png") => {
+            let labels = with_handler(|handler| handler.classify_image(&request.body))?;
+            Ok(Response::json(200, crate::labels::labels_to_vec(&labels)?))
+        }
+        #[cfg(feature = "audio")]
+        Some("audio/wav" | "audio/wave" | "audio/x-wav") => {
+            let scores = with_handler(|handler| handler.score_audio(&request.body))?;
+            Ok(Response::json(200, crate::audio::scores_to_vec(&scores)?))
+        }
+        // Everything else is treated as a JSON data window, for which
+        // we forecast the next values. Note that we do not insist on
+        // "application/json" here, because e.g. `curl -d` sends its
+        // own content type by default.
+        _ => forecast(request),
+    }
+}
+
+// Forecasts the next values of the data window in the request (see
+// `fresh_forecast`). If the device cannot make a new forecast for a
+// series, the most recent one is served instead (see stale.rs).
+#[cfg(feature = "stale")]
+fn forecast(request: Request) -> Result<Response, Error> {
+    let series = request.query_param("series").map(String::from);
+    fresh_forecast(request).or_else(|error| {
+        let stale = series
+            .as_deref()
+            .and_then(|series| crate::stale::serve(series, &error));
+        match stale {
+            Some(stale) => Ok(Response::json(200, stale.body)
+                .with_header("age", stale.age.to_string())
+                .with_header("warning", "110 - \"Response is Stale\"")),
+            None => Err(error),
+        }
+    })
+}
+
+#[cfg(not(feature = "stale"))]
+fn forecast(request: Request) -> Result<Response, Error> {
+    fresh_forecast(request)
+}
+
+// Forecasts the next values of the data window in the request (see
+// `HttpHandler::forecast` in lib.rs). The client can choose how many
+// values using the `horizon` parameter.
+fn fresh_forecast(request: Request) -> Result<Response, Error> {
+    #[cfg(feature = "timing")]
+    let started = std::time::Instant::now();
+    let shape = crate::model::shape()?;
+    let horizon = match request.query_param("horizon") {
+        Some(horizon) => horizon
+            .parse()
+            .ok()
+            .filter(|horizon| (1..=shape.max_horizon()).contains(horizon))
+            .ok_or_else(|| {
+                Error::BadRequest(format!(
+                    "Invalid horizon {horizon}, must be between 1 and {}",
+                    shape.max_horizon()
+                ))
+            })?,
+        None => shape.prediction_len,
+    };
+    // The client can select one of several models by name (see
+    // registry.rs), also in the path `/models/{name}/predict`, which
+    // ends up here like all paths without an endpoint of their own
+    #[cfg(feature = "registry")]
+    let model_name = crate::registry::selected_name(&request.path, request.header("x-model"));
+    #[cfg(feature = "registry")]
+    let model = crate::registry::select(model_name)?;
+    #[cfg(not(feature = "registry"))]
+    let model = None;
+    // The preprocessing of the forecast is traced under the id of the
+    // request (see trace.rs)
+    #[cfg(feature = "trace")]
+    let request_id = crate::trace::request_id(request.header("x-request-id"))?;
+
+    // Exports of historians can be posted as CSV (see csv.rs)
+    let input = match request.content_type() {
+        #[cfg(feature = "csv")]
+        Some(content_type) if content_type.eq_ignore_ascii_case("text/csv") => {
+            crate::csv::parse_data_window(&request.body, request.query_param("series"))?
+        }
+        // Gateways can post the window in a binary encoding instead (see
+        // binary.rs)
+        #[cfg(any(feature = "cbor", feature = "msgpack"))]
+        Some(content_type) => match crate::binary::Format::from_media_type(content_type) {
+            Some(format) => crate::binary::parse_data_window(format, &request.body)?,
+            None => json::parse_data_window(&request.body)?,
+        },
+        _ => json::parse_data_window(&request.body)?,
+    };
+    // The time until the window is parsed is reported to the client
+    // (see timing.rs)
+    #[cfg(feature = "timing")]
+    let parse = started.elapsed();
+
+    // The model takes exactly `history_len` values. Other windows are
+    // rejected with what the model requires, unless the client accepts
+    // with `fit=auto` that the series is padded or truncated, which is
+    // reported in a header. The client can choose how (see fit.rs).
+    let fit =
+        crate::fit::Fit::with_params(request.query_param("truncate"), request.query_param("pad"))?;
+    let window_len = crate::series_from_data_window(&input).len();
+    #[cfg(feature = "strict")]
+    if strict(&request) {
+        crate::check_history_len(&input, &fit, shape.history_len)?;
+    }
+
+    // Identical requests get the same ETag, so that clients can
+    // revalidate a forecast they already have without the model being
+    // run again. The query string, the selected model and the accepted
+    // encodings are part of the identity, since they change the response.
+    #[cfg(feature = "etag")]
+    let etag = format!(
+        "\"{}\"",
+        crate::canonical::hash(
+            &input,
+            &[
+                request.query.as_deref().unwrap_or_default(),
+                #[cfg(feature = "registry")]
+                model_name.unwrap_or_default(),
+                #[cfg(any(feature = "cbor", feature = "msgpack", feature = "ndjson"))]
+                request.header("accept").unwrap_or_default(),
+            ]
+        )
+    );
+    #[cfg(feature = "etag")]
+    if request
+        .header("if-none-match")
+        .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag))
+    {
+        return Ok(Response::new(304, "application/json", Vec::new()).with_header("etag", etag));
+    }
+
+    // Forecasts that would exceed the compute budget wait or are
+    // rejected before any work is done (see budget.rs)
+    #[cfg(feature = "budget")]
+    crate::budget::charge(input.data.len(), horizon, request.query_param("series"))?;
+
+    // For debugging, the outputs of the pipeline stages are returned as
+    // well. The forecast runs the pipeline again, which is acceptable
+    // for debugging and keeps it out of the regular path.
+    #[cfg(feature = "pipeline")]
+    let stages = match request.query_param("debug") {
+        Some("true") => Some(crate::pipeline::run(crate::series_from_data_window(&input))?.1),
+        _ => None,
+    };
+
+    // The timestamps of the forecast must be computed before the window
+    // is consumed by the forecast
+    let timestamps = crate::forecast_timestamps(&input, horizon);
+
+    // The forecast can also be aggregated at coarser resolutions, which
+    // needs the timestamps of the forecast (see resolution.rs)
+    #[cfg(feature = "resolutions")]
+    let resolutions = match request.query_param("resolutions") {
+        Some(resolutions) => {
+            let resolutions = crate::resolution::parse(resolutions)?;
+            let timestamps = timestamps.clone().ok_or_else(|| {
+                Error::BadRequest(
+                    "Resolutions need at least two data points with increasing timestamps".into(),
+                )
+            })?;
+            Some((resolutions, timestamps))
+        }
+        None => None,
+    };
+
+    // A regime change in the window is reported in a header, so that
+    // the body keeps the format of the demo library
+    #[cfg(feature = "changepoint")]
+    let change_point = crate::changepoint::detect(&input);
+
+    // The rules are read before the inference, so that invalid rules
+    // are reported right away
+    #[cfg(feature = "alerts")]
+    let rules = crate::alerts::load_rules()?;
+
+    // The headers of the forecast, which are known before the model
+    // runs, so that streamed forecasts get them as well
+    let headers = |response: Response| {
+        let response = match fit.adjustment(window_len, shape.history_len) {
+            Some(adjustment) => response.with_header("history-adjustment", adjustment),
+            None => response,
+        };
+        #[cfg(feature = "etag")]
+        let response = response.with_header("etag", etag.clone());
+        #[cfg(feature = "trace")]
+        let response = response.with_header("x-request-id", request_id.clone());
+        // The selected model is confirmed, so that clients trying out
+        // several of them can tell which one made the forecast
+        #[cfg(feature = "registry")]
+        let response = match model_name {
+            Some(name) => response.with_header("x-model", name),
+            None => response,
+        };
+        #[cfg(feature = "changepoint")]
+        let response = match change_point {
+            Some(change_point) => response.with_header("change-point", change_point.to_string()),
+            None => response,
+        };
+        response
+    };
+
+    // Long forecasts can be streamed one data point per line instead.
+    // The model then runs while the response is sent, and each of its
+    // steps is written as soon as it is predicted (see ndjson.rs).
+    #[cfg(feature = "ndjson")]
+    if request.accepts(crate::ndjson::MEDIA_TYPE) {
+        let series = request.query_param("series").map(String::from);
+        #[cfg(feature = "trace")]
+        let request_id = request_id.clone();
+        let response = Response::stream(crate::ndjson::MEDIA_TYPE, move |writer| {
+            let mut lines = crate::ndjson::Lines::new(timestamps.clone());
+            #[cfg(feature = "trace")]
+            crate::trace::start();
+            let values = forecast_values(
+                model.as_ref(),
+                &fit,
+                series.as_deref(),
+                input,
+                horizon,
+                |values| {
+                    let lines = lines.next(values)?;
+                    writer.write(&lines)?;
+                    Ok(())
+                },
+            );
+            #[cfg(feature = "trace")]
+            crate::trace::finish(&request_id);
+            #[cfg_attr(
+                not(any(feature = "alerts", feature = "previous", feature = "stitch")),
+                allow(unused_variables)
+            )]
+            let values = values.or_else(|e| {
+                // The client would not see the error otherwise
+                let line = format!("{}\n", e.to_json());
+                writer.write(line.as_bytes()).and(Err(e))
+            })?;
+
+            #[cfg(feature = "alerts")]
+            crate::alerts::notify(&rules, series.as_deref(), &values);
+            #[cfg(feature = "previous")]
+            if let Some(series) = &series {
+                crate::previous::compare_and_store(series, timestamps.clone(), &values)?;
+            }
+            #[cfg(feature = "stitch")]
+            if let Some(series) = &series {
+                crate::stitch::store(series, timestamps, &values)?;
+            }
+            Ok(())
+        });
+        return Ok(headers(response));
+    }
+
+    #[cfg(feature = "trace")]
+    crate::trace::start();
+    #[cfg(feature = "variance")]
+    crate::variance::start();
+    #[cfg(feature = "timing")]
+    crate::timing::start();
+    #[cfg(feature = "latest")]
+    crate::latest::start();
+
+    let values = forecast_values(
+        model.as_ref(),
+        &fit,
+        request.query_param("series"),
+        input,
+        horizon,
+        |_| Ok(()),
+    );
+
+    // Only the decisions made for the forecast itself are traced, not
+    // those made for the other parts of the response. Failed forecasts
+    // are traced as well, since they are the ones that need explaining.
+    #[cfg(feature = "trace")]
+    crate::trace::finish(&request_id);
+    #[cfg(feature = "variance")]
+    let variance = crate::variance::finish();
+    #[cfg(feature = "timing")]
+    let timing = crate::timing::finish();
+    #[cfg(feature = "timing")]
+    let server_timing = crate::timing::server_timing(parse, &timing);
+    let values = values?;
+
+    #[cfg(feature = "alerts")]
+    crate::alerts::notify(&rules, request.query_param("series"), &values);
+
+    // Forecasts can also be returned (and, for a series, pushed) as
+    // Home Assistant sensor states (see homeassistant.rs)
+    #[cfg(feature = "homeassistant")]
+    let sensor =
+        crate::homeassistant::sensor(request.query_param("series"), &values, timestamps.clone());
+
+    // Additional sections of the response, which are returned next to
+    // the forecast (see `with_sections`)
+    #[cfg(any(
+        feature = "pipeline",
+        feature = "previous",
+        feature = "resolutions",
+        feature = "timing",
+        feature = "variance"
+    ))]
+    let mut sections = serde_json::Map::new();
+    #[cfg(feature = "resolutions")]
+    if let Some((resolutions, timestamps)) = resolutions {
+        let periods = crate::resolution::aggregate(&resolutions, &timestamps, &values)?;
+        sections.insert("resolutions".into(), to_section(&periods)?);
+    }
+    // Forecasts for a series are compared with the previous one (see
+    // previous.rs)
+    #[cfg(feature = "previous")]
+    if let Some(series) = request.query_param("series") {
+        if let Some(delta) =
+            crate::previous::compare_and_store(series, timestamps.clone(), &values)?
+        {
+            sections.insert("delta_from_previous".into(), to_section(&delta)?);
+        }
+    }
+
+    // The forecast is kept to be stitched with the next ones (see
+    // stitch.rs)
+    #[cfg(feature = "stitch")]
+    if let Some(series) = request.query_param("series") {
+        crate::stitch::store(series, timestamps.clone(), &values)?;
+    }
+
+    // Models with a variance output return it next to the forecast (see
+    // variance.rs)
+    #[cfg(feature = "variance")]
+    if let Some(variance) = variance {
+        sections.insert("variance".into(), to_section(&variance)?);
+    }
+    // The model that made the forecast is described on request (see
+    // timing.rs)
+    #[cfg(feature = "timing")]
+    if request.query_param("metadata") == Some("true") {
+        #[cfg(feature = "registry")]
+        let name = model_name;
+        #[cfg(not(feature = "registry"))]
+        let name = None;
+        let metadata = crate::timing::metadata(name, timing);
+        sections.insert("metadata".into(), to_section(&metadata)?);
+    }
+
+    let result = inference_result_from_values(values, timestamps);
+    let body = json::inference_result_to_vec(&result)?;
+    #[cfg(feature = "homeassistant")]
+    let body = match request.query_param("format") {
+        Some("homeassistant") => crate::homeassistant::sensor_to_vec(&sensor)?,
+        _ => body,
+    };
+    #[cfg(feature = "homeassistant")]
+    if let Some(series) = request.query_param("series") {
+        crate::homeassistant::push(series, sensor);
+    }
+    #[cfg(feature = "pipeline")]
+    if let Some(stages) = stages {
+        let stages = crate::pipeline::debug_stages(&stages);
+        sections.insert("stages".into(), to_section(&stages)?);
+    }
+    #[cfg(any(
+        feature = "pipeline",
+        feature = "previous",
+        feature = "resolutions",
+        feature = "timing",
+        feature = "variance"
+    ))]
+    let body = with_sections(body, sections)?;
+
+    // Forecasts for a series are also published to a broker topic (see
+    // publish.rs)
+    #[cfg(feature = "publish")]
+    if let Some(series) = request.query_param("series") {
+        crate::publish::publish_forecast(series, &body);
+    }
+
+    // The forecast is kept for consumers that only read it (see
+    // latest.rs)
+    #[cfg(feature = "latest")]
+    if let Some(series) = request.query_param("series") {
+        #[cfg(feature = "registry")]
+        let name = model_name;
+        #[cfg(not(feature = "registry"))]
+        let name = None;
+        crate::latest::store(series, name, &body);
+    }
+
+    // The forecast is kept for when the next one cannot be made (see
+    // stale.rs)
+    #[cfg(feature = "stale")]
+    if let Some(series) = request.query_param("series") {
+        crate::stale::store(series, &body);
+    }
+
+    // The forecast is encoded like the window if the client accepts it
+    // (see binary.rs)
+    #[cfg(any(feature = "cbor", feature = "msgpack"))]
+    let response = match crate::binary::Format::accepted(|media_type| request.accepts(media_type)) {
+        Some(format) => Response::new(
+            200,
+            format.media_type(),
+            crate::binary::from_json(format, &body)?,
+        ),
+        None => Response::json(200, body),
+    };
+    #[cfg(not(any(feature = "cbor", feature = "msgpack")))]
+    let response = Response::json(200, body);
+    #[cfg(feature = "timing")]
+    let response = response.with_header("server-timing", server_timing);
+    Ok(headers(response))
+}
+
+// Runs the forecast of the request and calls `emit` with the values of
+// each step as soon as the model made them (see `forecast_steps` in
+// lib.rs). Forecasts for a series are recorded, so that their accuracy
+// can be tracked (see accuracy.rs).
+fn forecast_values(
+    model: Option<&crate::model::ModelSpec>,
+    fit: &crate::fit::Fit,
+    series: Option<&str>,
+    input: interface::DataWindow,
+    horizon: u32,
+    emit: impl FnMut(&[f32]) -> Result<(), Error>,
+) -> Result<Vec<f32>, Error> {
+    // The model reports errors as `ErrorCode`, so those of `emit` (i.e.
+    // of writing the response) are converted
+    let emit = {
+        let mut emit = emit;
+        move |values: &[f32]| {
+            emit(values).map_err(|e| ErrorCode::InternalError(Some(e.to_string())))
+        }
+    };
+    #[cfg(feature = "accuracy")]
+    if let Some(series) = series {
+        return with_handler(|handler| {
+            handler.forecast_and_record(model, Some(fit), series, input, horizon, emit)
+        });
+    }
+    Ok(with_handler(|handler| {
+        handler.forecast_steps(model, Some(fit), series, input, horizon, emit)
+    })?)
+}
+
+// Wraps the response body of a forecast (JSON) as `result` together
+// with the additional sections, so that the format of the forecast
+// itself stays the same
+#[cfg(any(
+    feature = "baseline",
+    feature = "pipeline",
+    feature = "previous",
+    feature = "resolutions",
+    feature = "timing",
+    feature = "variance"
+))]
+fn with_sections(
+    body: Vec<u8>,
+    mut sections: serde_json::Map<String, serde_json::Value>,
+) -> Result<Vec<u8>, Error> {
+    if sections.is_empty() {
+        return Ok(body);
+    }
+    let result = serde_json::from_slice(&body)
+        .map_err(|e| Error::internal(format!("Invalid response body: {e}")))?;
+    sections.insert("result".into(), result);
+    serde_json::to_vec(&sections)
+        .map_err(|e| Error::internal(format!("Error serializing response: {e}")))
+}
+
+#[cfg(any(
+    feature = "baseline",
+    feature = "pipeline",
+    feature = "previous",
+    feature = "resolutions",
+    feature = "timing",
+    feature = "variance"
+))]
+fn to_section(section: &impl serde::Serialize) -> Result<serde_json::Value, Error> {
+    serde_json::to_value(section)
+        .map_err(|e| Error::internal(format!("Error serializing response: {e}")))
+}
+
+// Whether windows must have exactly the length the model takes. Clients
+// accept that they are fitted with `fit=auto` (or `strict=false`, as it
+// was called before).
+#[cfg(feature = "strict")]
+fn strict(request: &Request) -> bool {
+    request.query_param("fit") != Some("auto") && request.query_param("strict") != Some("false")
+}
+
+// The model the client selected in the path or with the X-Model header,
+// which must be one of the registry (see registry.rs). None means the
+// default model.
+#[cfg(any(
+    feature = "aggregate",
+    feature = "batch",
+    feature = "hierarchy",
+    feature = "ingest"
+))]
+#[cfg_attr(not(feature = "registry"), allow(unused_variables))]
+fn selected_model(request: &Request) -> Result<Option<crate::model::ModelSpec>, Error> {
+    #[cfg(feature = "registry")]
+    return crate::registry::select(crate::registry::selected_name(
+        &request.path,
+        request.header("x-model"),
+    ));
+    #[cfg(not(feature = "registry"))]
+    Ok(None)
+}
+
+// Appends the data points of the window in the body to the series (see
+// ingest.rs)
+#[cfg(feature = "ingest")]
+fn ingest(series: &str, request: &Request) -> Result<Response, Error> {
+    let input = json::parse_data_window(&request.body)?;
+    let ingestion = crate::ingest::ingest(series, &input)?;
+    Ok(Response::json(
+        200,
+        crate::ingest::ingestion_to_vec(&ingestion)?,
+    ))
+}
+
+// The `series` parameter, which identifies the series whose accuracy
+// is tracked (or whose data points are accumulated, see ingest.rs)
+#[cfg(any(feature = "accuracy", feature = "ingest"))]
+fn required_series(request: &Request) -> Result<&str, Error> {
+    request
+        .query_param("series")
+        .filter(|series| !series.is_empty())
+        .ok_or_else(|| Error::BadRequest("Missing series parameter".into()))
+}
+
+// The name of the method, for the routes and the log
+fn method_name(method: &Method) -> &str {
+    match method {
+        Method::Get => "GET",
+        Method::Head => "HEAD",
+        Method::Post => "POST",
+        Method::Put => "PUT",
+        Method::Delete => "DELETE",
+        Method::Connect => "CONNECT",
+        Method::Options => "OPTIONS",
+        Method::Trace => "TRACE",
+        Method::Patch => "PATCH",
+        Method::Other(method) => method,
+    }
+}
+
+// The body of the requests of an endpoint, which is parsed from JSON
+// (see `Request::json`) and then checked for what its type cannot
+// express, e.g. that a batch is not too large
+#[cfg(feature = "serde")]
+#[cfg_attr(
+    not(any(
+        feature = "aggregate",
+        feature = "batch",
+        feature = "cluster",
+        feature = "covariates",
+        feature = "generate",
+        feature = "hierarchy",
+        feature = "prune",
+        feature = "search",
+        feature = "simulate"
+    )),
+    allow(dead_code)
+)]
+pub trait JsonBody: serde::de::DeserializeOwned {
+    // What the body is, for the error message, e.g. "batch request"
+    const NAME: &'static str;
+
+    fn check(self) -> Result<Self, Error> {
+        Ok(self)
+    }
+}
+
+// The parts of an incoming request that we need, with the whole body
+// read into memory
+struct Request {
+    method: Method,
+    // The path without the query string
+    path: String,
+    query: Option<String>,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+    // The client that sent the request, once it is authenticated, so
+    // that endpoints can tell clients apart
+    #[cfg(feature = "auth")]
+    #[cfg_attr(not(feature = "usage"), allow(dead_code))]
+    identity: Option<auth::Identity>,
+}
+
+impl Request {
+    fn read(request: &IncomingRequest) -> Result<Self, Error> {
+        let path_with_query = request.path_with_query().unwrap_or_default();
+        let (path, query) = match path_with_query.split_once('?') {
+            Some((path, query)) => (path.to_string(), Some(query.to_string())),
+            None => (path_with_query, None),
+        };
+
+        Ok(Self {
+            method: request.method(),
+            path,
+            query,
+            headers: request.headers().entries(),
+            body: read_body(request)?,
+            #[cfg(feature = "auth")]
+            identity: None,
+        })
+    }
+
+    // Returns the first value of the header with the given
+    // (case-insensitive) name, if it is valid UTF-8
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .and_then(|(_, value)| std::str::from_utf8(value).ok())
+    }
+
+    // Returns the value of a parameter in the query string. Values
+    // are not percent-decoded, since we only use simple parameters.
+    fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .as_deref()?
+            .split('&')
+            .filter_map(|param| param.split_once('=').or(Some((param, ""))))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    // Returns the parameter parsed as the type the endpoint expects
+    // (e.g. a number), or fails with 400 if it cannot be parsed
+    #[cfg_attr(
+        not(any(feature = "anomaly", feature = "backtest", feature = "quality")),
+        allow(dead_code)
+    )]
+    fn parsed_param<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, Error>
+    where
+        T::Err: std::fmt::Display,
+    {
+        self.query_param(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|e| Error::BadRequest(format!("Invalid {name}: {e}")))
+            })
+            .transpose()
+    }
+
+    // Parses the JSON body into the type the endpoint expects and checks
+    // it, or fails with 400 (see `JsonBody`)
+    #[cfg(feature = "serde")]
+    #[cfg_attr(
+        not(any(
+            feature = "aggregate",
+            feature = "batch",
+            feature = "cluster",
+            feature = "covariates",
+            feature = "generate",
+            feature = "hierarchy",
+            feature = "prune",
+            feature = "search",
+            feature = "simulate"
+        )),
+        allow(dead_code)
+    )]
+    fn json<T: JsonBody>(&self) -> Result<T, Error> {
+        serde_json::from_slice::<T>(&self.body)
+            .map_err(|e| Error::BadRequest(format!("Invalid {}: {e}", T::NAME)))?
+            .check()
+    }
+
+    // The media type of the body, without parameters like the charset
+    fn content_type(&self) -> Option<&str> {
+        self.header("content-type")
+            .and_then(|value| value.split(';').next())
+            .map(str::trim)
+    }
+
+    // Whether the client explicitly accepts the media type. Wildcards
+    // and quality values are ignored, since the alternative is always
+    // JSON.
+    #[cfg_attr(
+        not(any(
+            feature = "backtest",
+            feature = "cbor",
+            feature = "msgpack",
+            feature = "ndjson"
+        )),
+        allow(dead_code)
+    )]
+    fn accepts(&self, media_type: &str) -> bool {
+        self.header("accept").is_some_and(|accept| {
+            accept
+                .split(',')
+                .filter_map(|range| range.split(';').next())
+                .any(|range| range.trim().eq_ignore_ascii_case(media_type))
+        })
+    }
+}
+
+// Reads the whole body of the request into memory, one chunk at a time.
+// With the `body-limit` feature, reading stops as soon as the body
+// exceeds the limit, so that no more than the limit and one chunk are
+// ever buffered. A body that is announced to be too large is not read at
+// all (see body_limit.rs).
+fn read_body(request: &IncomingRequest) -> Result<Vec<u8>, Error> {
+    #[cfg(feature = "body-limit")]
+    let max_bytes = {
+        let max_bytes = crate::body_limit::max_bytes()?;
+        crate::body_limit::check_announced(&request.headers().entries(), max_bytes)?;
+        max_bytes
+    };
+
+    let body = request
+        .consume()
+        .map_err(|()| Error::internal("Request body was already consumed"))?;
+    let stream = body
+        .stream()
+        .map_err(|()| Error::internal("Request body stream was already taken"))?;
+
+    let mut buffer = Vec::new();
+    loop {
+        match stream.blocking_read(CHUNK_SIZE) {
+            Ok(chunk) => {
+                buffer.extend_from_slice(&chunk);
+                #[cfg(feature = "body-limit")]
+                crate::body_limit::check(buffer.len() as u64, max_bytes)?;
+            }
+            Err(StreamError::Closed) => break,
+            Err(StreamError::LastOperationFailed(e)) => {
+                return Err(Error::internal(format!("Error reading request body: {e}")))
+            }
+        }
+    }
+
+    // The stream must be dropped before the body can be finished
+    drop(stream);
+    IncomingBody::finish(body);
+
+    Ok(buffer)
+}
+
+// A HTTP response. The body is either fully known before the response
+// is sent, or written while the response is already being sent.
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    // Headers in addition to the content type
+    headers: Vec<(String, Vec<u8>)>,
+    body: Body,
+}
+
+enum Body {
+    Bytes(Vec<u8>),
+    Stream(WriteBody),
+}
+
+type WriteBody = Box<dyn FnOnce(&mut BodyWriter) -> Result<(), Error>>;
+
+impl Response {
+    fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            content_type,
+            headers: Vec::new(),
+            body: Body::Bytes(body),
+        }
+    }
+
+    fn json(status: u16, body: Vec<u8>) -> Self {
+        Self::new(status, "application/json", body)
+    }
+
+    // A response whose body is produced by `write` after the status
+    // and headers have been sent, e.g. for server-sent events
+    #[cfg_attr(not(any(feature = "generate", feature = "ndjson")), allow(dead_code))]
+    fn stream(
+        content_type: &'static str,
+        write: impl FnOnce(&mut BodyWriter) -> Result<(), Error> + 'static,
+    ) -> Self {
+        Self {
+            status: 200,
+            content_type,
+            headers: Vec::new(),
+            body: Body::Stream(Box::new(write)),
+        }
+    }
+
+    // Keeps the slot of the request in the queue (see queue.rs) until
+    // the body is written. Streamed bodies run the model (or generate
+    // tokens) while they are written, which is after `route` returned.
+    #[cfg(feature = "queue")]
+    fn holding(self, permit: Option<crate::queue::Permit>) -> Self {
+        match self.body {
+            Body::Stream(write) => Self {
+                body: Body::Stream(Box::new(move |writer| {
+                    let _permit = permit;
+                    write(writer)
+                })),
+                ..self
+            },
+            body => Self { body, ..self },
+        }
+    }
+
+    fn with_header(mut self, name: &str, value: impl Into<Vec<u8>>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+
+    // Adds the signature of the body (see sign.rs). Streamed bodies are
+    // not known in advance and therefore not signed. If the response
+    // cannot be signed, an (unsigned) error is returned instead, so
+    // that consumers never get a response without signature that
+    // looks like a forecast.
+    #[cfg(feature = "sign")]
+    fn signed(self) -> Self {
+        let Body::Bytes(body) = &self.body else {
+            return self;
+        };
+        match crate::sign::sign(body) {
+            Ok(signature) => self.with_header("x-signature", signature),
+            Err(e) => Response::json(e.status(), e.to_json().into_bytes()),
+        }
+    }
+
+    // Sends the response using the wasi-http bindings. The response
+    // (even in the case of an error!) must be finalized by setting it
+    // on the outparam before the body is written.
+    fn send(self, response_outparam: ResponseOutparam) {
+        match self.outgoing() {
+            Ok((response, body)) => {
+                ResponseOutparam::set(response_outparam, Ok(response));
+                // At this point, the status has already been sent, so
+                // the best we can do with errors is to report them.
+                if let Err(e) = write_body(body, self.body) {
+                    eprintln!("Error writing response body: {e}");
+                }
+            }
+            Err(e) => ResponseOutparam::set(response_outparam, Err(e)),
+        }
+    }
+
+    fn outgoing(&self) -> Result<(OutgoingResponse, OutgoingBody), ErrorCode> {
+        let mut headers = vec![(
+            "content-type".to_string(),
+            self.content_type.as_bytes().to_vec(),
+        )];
+        headers.extend(self.headers.iter().cloned());
+        let headers = Fields::from_list(&headers)
+            .map_err(|e| ErrorCode::InternalError(Some(format!("Invalid header: {e:?}"))))?;
+
+        let response = OutgoingResponse::new(headers);
+        response.set_status_code(self.status).map_err(|()| {
+            ErrorCode::InternalError(Some(format!("Invalid status {}", self.status)))
+        })?;
+        let body = response
+            .body()
+            .map_err(|()| ErrorCode::InternalError(Some("Response body already taken".into())))?;
+
+        Ok((response, body))
+    }
+}
+
+// Writes to the body of a response that is already being sent
+struct BodyWriter {
+    stream: OutputStream,
+}
+
+impl BodyWriter {
+    fn write(&mut self, contents: &[u8]) -> Result<(), Error> {
+        for chunk in contents.chunks(CHUNK_SIZE as usize) {
+            self.stream
+                .blocking_write_and_flush(chunk)
+                .map_err(|e| Error::internal(format!("{e:?}")))?;
+        }
+        Ok(())
+    }
+}
+
+fn write_body(body: OutgoingBody, contents: Body) -> Result<(), Error> {
+    let stream = body
+        .write()
+        .map_err(|()| Error::internal("Response body stream already taken"))?;
+
+    let mut writer = BodyWriter { stream };
+    match contents {
+        Body::Bytes(bytes) => writer.write(&bytes)?,
+        Body::Stream(write) => write(&mut writer)?,
+    }
+
+    // The stream must be dropped before the body can be finished
+    drop(writer);
+    Ok(OutgoingBody::finish(body, None)?)
+}
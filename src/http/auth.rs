@@ -0,0 +1,581 @@
+// This module authenticates the clients of the HTTP endpoint. Every
+// scheme is an `Authenticator`, which checks the credentials of a
+// request and returns the identity of the client. The schemes are
+// enabled by their section in the `auth` config (see config.rs), and a
+// request is accepted if one of them accepts it:
+//
+// { "api_keys": { "line-3": "3f9c..." },
+//   "jwt": { "secret": "...", "issuer": "factory-sso", "audience": "forecast" },
+//   "mtls": { "subjects": ["CN=line-3,O=Factory"] } }
+//
+// - `api_keys`: The `X-Api-Key` header contains one of the keys. The
+//   identity is the name of the key.
+// - `jwt`: The `Authorization` header contains a bearer token signed
+//   with HS256 using the secret, which has not expired. If configured,
+//   its `iss` and `aud` claims must match. The identity is its `sub`.
+// - `mtls`: A reverse proxy terminated TLS and verified the client
+//   certificate, and forwards its subject in the `X-Client-Cert-Subject`
+//   header and the result in `X-Client-Cert-Verify` (`SUCCESS`). The
+//   headers can be renamed with `subject_header` and `verify_header`.
+//   The identity is the subject, which must be one of `subjects`.
+//
+// The admin endpoints (`/admin/...`, e.g. pruning the state) are only
+// open to admins: the clients whose identity is listed in `admins`,
+// e.g. `"admins": ["ops"]`, and JWTs with the `admin` scope (in their
+// `scope` claim). Other clients get 403.
+//
+// The schemes are tried in the order of SCHEMES. A deployment that
+// configures several of them can select some and set their order with
+// `schemes`, e.g. `"schemes": ["mtls", "jwt"]`, so that sections can
+// stay in the config without being used. Sections of unknown schemes
+// are rejected, so that a typo does not silently leave a scheme out.
+//
+// Other schemes (e.g. the headers of a factory SSO proxy) are added by
+// implementing `Authenticator` for the type of their section and
+// adding them to SCHEMES. Since the proxy headers can be set by anyone
+// who reaches the component directly, mtls must only be used behind a
+// proxy that overwrites them.
+
+use std::collections::BTreeMap;
+
+use serde::{de::DeserializeOwned, Deserialize};
+
+use super::Request;
+use crate::{
+    base64, clock, config,
+    error::Error,
+    sha256::{constant_time_eq, hmac_sha256},
+};
+
+const CONFIG: &str = "auth";
+// The scope of JWTs that may use the admin endpoints
+const ADMIN_SCOPE: &str = "admin";
+
+// The client that sent a request, and the scheme that authenticated it
+pub struct Identity {
+    #[cfg_attr(not(feature = "usage"), allow(dead_code))]
+    pub subject: String,
+    #[allow(dead_code)]
+    pub scheme: &'static str,
+    // Whether the client may use the admin endpoints
+    pub admin: bool,
+}
+
+pub trait Authenticator {
+    // The identity of the client, or Unauthorized if the request does
+    // not carry valid credentials for this scheme
+    fn check(&self, request: &Request) -> Result<Identity, Error>;
+}
+
+// A scheme that can be configured, by the name of its section in the
+// config
+struct Scheme {
+    section: &'static str,
+    // The name of the scheme for clients (see discovery.rs)
+    name: &'static str,
+    build: fn(serde_json::Value) -> Result<Box<dyn Authenticator>, serde_json::Error>,
+}
+
+const SCHEMES: [Scheme; 3] = [
+    Scheme {
+        section: "api_keys",
+        name: "api-key",
+        build: build::<ApiKeys>,
+    },
+    Scheme {
+        section: "jwt",
+        name: "jwt",
+        build: build::<Jwt>,
+    },
+    Scheme {
+        section: "mtls",
+        name: "mtls",
+        build: build::<Mtls>,
+    },
+];
+
+// Creates the authenticator of a scheme from its section
+fn build<T: Authenticator + DeserializeOwned + 'static>(
+    section: serde_json::Value,
+) -> Result<Box<dyn Authenticator>, serde_json::Error> {
+    Ok(Box::new(serde_json::from_value::<T>(section)?))
+}
+
+// A selected scheme with its authenticator
+struct Selected {
+    #[cfg_attr(not(feature = "discovery"), allow(dead_code))]
+    name: &'static str,
+    authenticator: Box<dyn Authenticator>,
+}
+
+#[derive(Deserialize)]
+struct Auth {
+    // The sections of the schemes to use, in the order they are tried
+    schemes: Option<Vec<String>>,
+    // The identities of the admins
+    #[serde(default)]
+    admins: Vec<String>,
+    #[serde(flatten)]
+    sections: BTreeMap<String, serde_json::Value>,
+}
+
+// Authenticates the request with the configured schemes. If none of
+// them accepts it, the error of the first one is returned.
+pub fn authenticate(request: &Request) -> Result<Identity, Error> {
+    let mut auth: Auth = config::require(CONFIG)?;
+    let admins = std::mem::take(&mut auth.admins);
+    let mut first_error = None;
+    for selected in select(auth)? {
+        match selected.authenticator.check(request) {
+            Ok(identity) => {
+                return Ok(Identity {
+                    admin: identity.admin || admins.contains(&identity.subject),
+                    ..identity
+                })
+            }
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    Err(first_error.unwrap_or_else(|| Error::internal("No authentication scheme configured")))
+}
+
+// The names of the configured schemes, for clients (see discovery.rs)
+#[cfg(feature = "discovery")]
+pub fn schemes() -> Result<Vec<&'static str>, Error> {
+    Ok(select(config::require(CONFIG)?)?
+        .into_iter()
+        .map(|selected| selected.name)
+        .collect())
+}
+
+// The selected schemes with their authenticators, in the order they
+// are tried
+fn select(mut auth: Auth) -> Result<Vec<Selected>, Error> {
+    let scheme = |section: &str| {
+        SCHEMES
+            .iter()
+            .find(|scheme| scheme.section == section)
+            .ok_or_else(|| Error::internal(format!("Unknown authentication scheme {section}")))
+    };
+    for section in auth.sections.keys() {
+        scheme(section)?;
+    }
+    let selected = match auth.schemes.take() {
+        Some(selected) => selected,
+        None => SCHEMES
+            .iter()
+            .map(|scheme| scheme.section.to_string())
+            .filter(|section| auth.sections.contains_key(section))
+            .collect(),
+    };
+
+    selected
+        .iter()
+        .map(|section| {
+            let scheme = scheme(section)?;
+            let config = auth.sections.remove(section).ok_or_else(|| {
+                Error::internal(format!(
+                    "Authentication scheme {section} is selected, but not configured"
+                ))
+            })?;
+            let authenticator = (scheme.build)(config)
+                .map_err(|e| Error::internal(format!("Invalid config {CONFIG}: {section}: {e}")))?;
+            Ok(Selected {
+                name: scheme.name,
+                authenticator,
+            })
+        })
+        .collect()
+}
+
+// The API keys by their name
+#[derive(Deserialize)]
+struct ApiKeys(BTreeMap<String, String>);
+
+impl Authenticator for ApiKeys {
+    fn check(&self, request: &Request) -> Result<Identity, Error> {
+        let key = request
+            .header("x-api-key")
+            .ok_or_else(|| Error::Unauthorized("Missing X-Api-Key header".into()))?
+            .trim();
+        // All keys are compared, so that the time it takes does not
+        // reveal which one almost matched
+        self.0
+            .iter()
+            .fold(None, |found, (name, expected)| {
+                if constant_time_eq(key.as_bytes(), expected.as_bytes()) {
+                    Some(name)
+                } else {
+                    found
+                }
+            })
+            .map(|name| Identity {
+                subject: name.clone(),
+                scheme: "api-key",
+                admin: false,
+            })
+            .ok_or_else(|| Error::Unauthorized("Invalid API key".into()))
+    }
+}
+
+#[derive(Deserialize)]
+struct Jwt {
+    secret: String,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+    exp: Option<i64>,
+    nbf: Option<i64>,
+    iss: Option<String>,
+    // The scopes of the token, separated by spaces (RFC 8693)
+    scope: Option<String>,
+    // Either a single audience or a list of them
+    #[serde(default)]
+    aud: Audience,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    #[default]
+    None,
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Authenticator for Jwt {
+    fn check(&self, request: &Request) -> Result<Identity, Error> {
+        let token = request
+            .header("authorization")
+            .and_then(|value| value.trim().strip_prefix("Bearer "))
+            .ok_or_else(|| Error::Unauthorized("Missing bearer token".into()))?
+            .trim();
+        self.verify(token, clock::now().timestamp())
+    }
+}
+
+impl Jwt {
+    // The identity in the token, if it is valid at `now` (in seconds
+    // since the epoch)
+    fn verify(&self, token: &str, now: i64) -> Result<Identity, Error> {
+        let invalid = |reason: &str| Error::Unauthorized(format!("Invalid token: {reason}"));
+
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid("not a JWT"));
+        };
+        let decode = |part: &str| base64::decode(part).ok_or_else(|| invalid("not base64url"));
+
+        // Only HMAC-SHA256 is supported. Checking the algorithm
+        // prevents tokens with `"alg": "none"` from being accepted.
+        let header: JwtHeader =
+            serde_json::from_slice(&decode(header)?).map_err(|_| invalid("malformed header"))?;
+        if header.alg != "HS256" {
+            return Err(invalid("algorithm must be HS256"));
+        }
+        let signed = &token[..token.len() - signature.len() - 1];
+        let expected = hmac_sha256(self.secret.as_bytes(), signed.as_bytes());
+        if !constant_time_eq(&decode(signature)?, &expected) {
+            return Err(invalid("wrong signature"));
+        }
+
+        let claims: Claims =
+            serde_json::from_slice(&decode(payload)?).map_err(|_| invalid("malformed claims"))?;
+        if claims.exp.is_some_and(|exp| now >= exp) {
+            return Err(invalid("expired"));
+        }
+        if claims.nbf.is_some_and(|nbf| now < nbf) {
+            return Err(invalid("not valid yet"));
+        }
+        if let Some(issuer) = &self.issuer {
+            if claims.iss.as_ref() != Some(issuer) {
+                return Err(invalid("wrong issuer"));
+            }
+        }
+        if let Some(audience) = &self.audience {
+            let matches = match &claims.aud {
+                Audience::None => false,
+                Audience::One(aud) => aud == audience,
+                Audience::Many(auds) => auds.contains(audience),
+            };
+            if !matches {
+                return Err(invalid("wrong audience"));
+            }
+        }
+        let admin = claims
+            .scope
+            .is_some_and(|scope| scope.split(' ').any(|scope| scope == ADMIN_SCOPE));
+        Ok(Identity {
+            subject: claims.sub,
+            scheme: "jwt",
+            admin,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct Mtls {
+    #[serde(default = "default_subject_header")]
+    subject_header: String,
+    #[serde(default = "default_verify_header")]
+    verify_header: String,
+    subjects: Vec<String>,
+}
+
+fn default_subject_header() -> String {
+    "x-client-cert-subject".into()
+}
+
+fn default_verify_header() -> String {
+    "x-client-cert-verify".into()
+}
+
+impl Authenticator for Mtls {
+    fn check(&self, request: &Request) -> Result<Identity, Error> {
+        if request.header(&self.verify_header).map(str::trim) != Some("SUCCESS") {
+            return Err(Error::Unauthorized("No verified client certificate".into()));
+        }
+        let subject = request
+            .header(&self.subject_header)
+            .map(str::trim)
+            .ok_or_else(|| Error::Unauthorized("Missing client certificate subject".into()))?;
+        if !self.subjects.iter().any(|allowed| allowed == subject) {
+            return Err(Error::Unauthorized(format!(
+                "Client certificate {subject} is not allowed"
+            )));
+        }
+        Ok(Identity {
+            subject: subject.to_string(),
+            scheme: "mtls",
+            admin: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "secret";
+    // 2024-05-01T00:00:00Z
+    const NOW: i64 = 1_714_521_600;
+
+    // Base64url without padding, as used by JWTs
+    fn encode(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+        let mut encoded = String::new();
+        for chunk in bytes.chunks(3) {
+            let buffer = chunk.iter().enumerate().fold(0u32, |buffer, (i, &byte)| {
+                buffer | u32::from(byte) << (16 - 8 * i)
+            });
+            for i in 0..=chunk.len() {
+                encoded.push(ALPHABET[(buffer >> (18 - 6 * i) & 0x3f) as usize] as char);
+            }
+        }
+        encoded
+    }
+
+    fn token(secret: &str, claims: &str) -> String {
+        let signed = format!(
+            "{}.{}",
+            encode(br#"{"alg":"HS256","typ":"JWT"}"#),
+            encode(claims.as_bytes())
+        );
+        let signature = hmac_sha256(secret.as_bytes(), signed.as_bytes());
+        format!("{signed}.{}", encode(&signature))
+    }
+
+    fn jwt(issuer: Option<&str>, audience: Option<&str>) -> Jwt {
+        Jwt {
+            secret: SECRET.into(),
+            issuer: issuer.map(String::from),
+            audience: audience.map(String::from),
+        }
+    }
+
+    fn reason(result: Result<Identity, Error>) -> String {
+        match result {
+            Err(Error::Unauthorized(message)) => message,
+            Err(e) => panic!("expected Unauthorized, got {e}"),
+            Ok(identity) => panic!("expected an error, got {}", identity.subject),
+        }
+    }
+
+    fn selected(auth: &str) -> Result<Vec<&'static str>, Error> {
+        let auth = serde_json::from_str(auth).unwrap();
+        Ok(select(auth)?
+            .into_iter()
+            .map(|selected| selected.name)
+            .collect())
+    }
+
+    #[test]
+    fn selects_the_configured_schemes() {
+        let sections = r#""api_keys": {"line-3": "key"}, "mtls": {"subjects": []}"#;
+        assert_eq!(
+            selected(&format!("{{{sections}}}")).unwrap(),
+            ["api-key", "mtls"]
+        );
+        assert_eq!(
+            selected(&format!(
+                r#"{{{sections}, "schemes": ["mtls", "api_keys"]}}"#
+            ))
+            .unwrap(),
+            ["mtls", "api-key"]
+        );
+        assert_eq!(
+            selected(&format!(r#"{{{sections}, "schemes": ["mtls"]}}"#)).unwrap(),
+            ["mtls"]
+        );
+        assert!(selected("{}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_or_missing_schemes() {
+        let message = |auth: &str| selected(auth).err().unwrap().to_string();
+        assert_eq!(
+            message(r#"{"api_key": {"line-3": "key"}}"#),
+            "Unknown authentication scheme api_key"
+        );
+        assert_eq!(
+            message(r#"{"api_keys": {"line-3": "key"}, "schemes": ["sso"]}"#),
+            "Unknown authentication scheme sso"
+        );
+        assert_eq!(
+            message(r#"{"api_keys": {"line-3": "key"}, "schemes": ["jwt"]}"#),
+            "Authentication scheme jwt is selected, but not configured"
+        );
+        assert!(message(r#"{"jwt": {"issuer": "factory-sso"}}"#)
+            .starts_with("Invalid config auth: jwt: missing field `secret`"));
+    }
+
+    #[test]
+    fn accepts_a_valid_token() {
+        let valid = token(
+            SECRET,
+            r#"{"sub":"line-3","exp":1714525200,"nbf":1714518000,"iss":"factory-sso","aud":["forecast","other"]}"#,
+        );
+        let identity = jwt(Some("factory-sso"), Some("forecast"))
+            .verify(&valid, NOW)
+            .unwrap();
+        assert_eq!(identity.subject, "line-3");
+        assert_eq!(identity.scheme, "jwt");
+        assert!(!identity.admin);
+
+        // Claims that are not configured are not checked
+        let minimal = token(SECRET, r#"{"sub":"line-3"}"#);
+        assert_eq!(
+            jwt(None, None).verify(&minimal, NOW).unwrap().subject,
+            "line-3"
+        );
+    }
+
+    #[test]
+    fn grants_the_admin_scope() {
+        let admin = token(SECRET, r#"{"sub":"ops","scope":"forecast admin"}"#);
+        assert!(jwt(None, None).verify(&admin, NOW).unwrap().admin);
+
+        for scope in ["forecast", "administrator", ""] {
+            let other = token(SECRET, &format!(r#"{{"sub":"ops","scope":"{scope}"}}"#));
+            assert!(!jwt(None, None).verify(&other, NOW).unwrap().admin);
+        }
+    }
+
+    #[test]
+    fn rejects_a_bad_signature() {
+        let forged = token("other secret", r#"{"sub":"line-3"}"#);
+        assert_eq!(
+            reason(jwt(None, None).verify(&forged, NOW)),
+            "Invalid token: wrong signature"
+        );
+
+        // Changing the claims invalidates the signature
+        let valid = token(SECRET, r#"{"sub":"line-3"}"#);
+        let mut parts: Vec<_> = valid.split('.').collect();
+        let claims = encode(br#"{"sub":"admin"}"#);
+        parts[1] = &claims;
+        assert_eq!(
+            reason(jwt(None, None).verify(&parts.join("."), NOW)),
+            "Invalid token: wrong signature"
+        );
+    }
+
+    #[test]
+    fn rejects_other_algorithms() {
+        let signed = format!(
+            "{}.{}",
+            encode(br#"{"alg":"none"}"#),
+            encode(br#"{"sub":"line-3"}"#)
+        );
+        assert_eq!(
+            reason(jwt(None, None).verify(&format!("{signed}."), NOW)),
+            "Invalid token: algorithm must be HS256"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_tokens() {
+        for malformed in ["", "a.b", "a.b.c.d", "not base64!.a.b"] {
+            assert!(reason(jwt(None, None).verify(malformed, NOW)).starts_with("Invalid token: "));
+        }
+        let not_json = token(SECRET, "not json");
+        assert_eq!(
+            reason(jwt(None, None).verify(&not_json, NOW)),
+            "Invalid token: malformed claims"
+        );
+    }
+
+    #[test]
+    fn rejects_tokens_outside_their_validity() {
+        let expired = token(SECRET, r#"{"sub":"line-3","exp":1714521600}"#);
+        assert_eq!(
+            reason(jwt(None, None).verify(&expired, NOW)),
+            "Invalid token: expired"
+        );
+        assert!(jwt(None, None).verify(&expired, NOW - 1).is_ok());
+
+        let early = token(SECRET, r#"{"sub":"line-3","nbf":1714521601}"#);
+        assert_eq!(
+            reason(jwt(None, None).verify(&early, NOW)),
+            "Invalid token: not valid yet"
+        );
+        assert!(jwt(None, None).verify(&early, NOW + 1).is_ok());
+    }
+
+    #[test]
+    fn rejects_the_wrong_issuer_or_audience() {
+        let other = token(SECRET, r#"{"sub":"line-3","iss":"other","aud":"other"}"#);
+        assert_eq!(
+            reason(jwt(Some("factory-sso"), None).verify(&other, NOW)),
+            "Invalid token: wrong issuer"
+        );
+        assert_eq!(
+            reason(jwt(None, Some("forecast")).verify(&other, NOW)),
+            "Invalid token: wrong audience"
+        );
+
+        // A token without the claims does not match either
+        let minimal = token(SECRET, r#"{"sub":"line-3"}"#);
+        assert_eq!(
+            reason(jwt(Some("factory-sso"), None).verify(&minimal, NOW)),
+            "Invalid token: wrong issuer"
+        );
+        assert_eq!(
+            reason(jwt(None, Some("forecast")).verify(&minimal, NOW)),
+            "Invalid token: wrong audience"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+// This module finds the handler of a request by its method and path.
+// The endpoints are listed as routes (see ROUTES in http.rs), each with
+// a path pattern whose segments are matched one by one:
+//
+// /actuals           only this path
+// /traces/*          `*` matches any one segment, e.g. /traces/5f0c3a1e
+// /series/**/points  `**` matches one or more segments, e.g.
+//                    /series/plant/boiler-1/points
+//
+// The routes are tried in order and the first one that matches wins, so
+// specific paths must come before the patterns that also match them
+// (e.g. /forecast/covariates before /forecast/**). Handlers get the
+// whole request and read the parts of the path they need themselves.
+
+use wasi::http::types::Method;
+
+use super::{Request, Response};
+use crate::error::Error;
+
+pub type Handler = fn(Request) -> Result<Response, Error>;
+
+pub struct Route {
+    // The name of the method, e.g. "POST"
+    pub method: &'static str,
+    pub path: &'static str,
+    pub handler: Handler,
+}
+
+// The first route that matches the request, if any
+pub fn find<'a>(routes: &'a [Route], method: &Method, path: &str) -> Option<&'a Route> {
+    let method = super::method_name(method);
+    let path: Vec<_> = path.split('/').collect();
+    routes.iter().find(|route| {
+        route.method == method && matches(&route.path.split('/').collect::<Vec<_>>(), &path)
+    })
+}
+
+// Whether the segments of a path match those of a pattern
+fn matches(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.split_first(), path.split_first()) {
+        (None, None) => true,
+        (Some((&"**", pattern)), _) => (1..=path.len()).any(|i| matches(pattern, &path[i..])),
+        (Some((&"*", pattern)), Some((segment, path))) => {
+            !segment.is_empty() && matches(pattern, path)
+        }
+        (Some((expected, pattern)), Some((segment, path))) => {
+            expected == segment && matches(pattern, path)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        super::matches(
+            &pattern.split('/').collect::<Vec<_>>(),
+            &path.split('/').collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn matches_exact_paths() {
+        assert!(matches("/actuals", "/actuals"));
+        assert!(matches("/", "/"));
+        assert!(!matches("/actuals", "/actuals/"));
+        assert!(!matches("/actuals", "/actual"));
+        assert!(!matches("/actuals", "/"));
+    }
+
+    #[test]
+    fn matches_one_segment() {
+        assert!(matches("/traces/*", "/traces/5f0c3a1e"));
+        assert!(!matches("/traces/*", "/traces/"));
+        assert!(!matches("/traces/*", "/traces"));
+        assert!(!matches("/traces/*", "/traces/5f0c3a1e/decisions"));
+    }
+
+    #[test]
+    fn matches_several_segments() {
+        assert!(matches("/series/**/points", "/series/boiler-1/points"));
+        assert!(matches(
+            "/series/**/points",
+            "/series/plant/boiler-1/points"
+        ));
+        assert!(!matches("/series/**/points", "/series/points"));
+        assert!(!matches("/series/**/points", "/series/boiler-1"));
+        assert!(matches(
+            "/admin/models/**",
+            "/admin/models/temperature/disable"
+        ));
+        assert!(!matches("/admin/models/**", "/admin/models"));
+        assert!(!matches("/admin/models/**", "/admin/usage"));
+    }
+
+    #[test]
+    fn finds_the_first_matching_route() {
+        fn handler(_: Request) -> Result<Response, Error> {
+            Err(Error::NotFound("No handler".into()))
+        }
+        let routes = [
+            Route {
+                method: "POST",
+                path: "/forecast/covariates",
+                handler,
+            },
+            Route {
+                method: "GET",
+                path: "/forecast/**",
+                handler,
+            },
+        ];
+        let found = |method, path| find(&routes, &method, path).map(|route| route.path);
+        assert_eq!(
+            found(Method::Post, "/forecast/covariates"),
+            Some("/forecast/covariates")
+        );
+        assert_eq!(
+            found(Method::Get, "/forecast/covariates"),
+            Some("/forecast/**")
+        );
+        assert_eq!(found(Method::Post, "/forecast/boiler-1"), None);
+        assert_eq!(found(Method::Delete, "/forecast/boiler-1"), None);
+    }
+}
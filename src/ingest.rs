@@ -0,0 +1,156 @@
+// This module accumulates the data points of a series over many
+// requests, for sensors that send one point at a time instead of a
+// window of `history_len` values (see `Shape` in model.rs). `POST
+// /ingest?series=...` (or `POST /series/{series}/points`) appends the
+// data points in the body (a data window, usually with a single point)
+// to the series, and `GET /predict?series=...` forecasts the next
+// values once the series has `history_len` points:
+//
+// { "series": "boiler-1", "points": 57, "required": 128, "ready": false }
+//
+// is the response to an ingestion, which is cheap since the model is
+// not run. A forecast for a series with fewer
+// points fails with 425 (Too Early). Data points without a timestamp
+// are stamped with the time they are received, and points that are not
+// newer than the last one of the series are rejected with 409
+// (Conflict), since the series must stay in order. Only the last
+// `history_len` points of each series are kept, in the state directory
+// (see state.rs). With the `baseline` feature, the ingested points also
+// update a statistical baseline of the series (see baseline.rs), and
+// with the `trigger` feature, they may cause a forecast (see
+// trigger.rs).
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use wasi_nn_demo_lib::interface;
+
+use crate::{
+    clock,
+    error::Error,
+    forecast_timestamps, inference_result_from_values,
+    model::{self, ModelSpec},
+    numeric_data_points, state, HttpHandler,
+};
+
+const STATE_FILE: &str = "ingest.json";
+
+#[derive(Serialize, Deserialize)]
+struct Point {
+    timestamp: DateTime<Utc>,
+    value: f32,
+}
+
+#[derive(Serialize)]
+pub struct Ingestion {
+    series: String,
+    points: usize,
+    required: u32,
+    // Whether the series can be forecast
+    ready: bool,
+    // Whether the series was forecast automatically
+    #[cfg(feature = "trigger")]
+    forecast: bool,
+}
+
+// Appends the data points of the window to the series
+pub fn ingest(series: &str, input: &interface::DataWindow) -> Result<Ingestion, Error> {
+    let history_len = model::shape()?.history_len;
+    // All series share the state, which is therefore updated under its
+    // lock, so that concurrent requests do not lose each other's points
+    #[cfg_attr(
+        not(any(feature = "baseline", feature = "trigger")),
+        allow(unused_variables)
+    )]
+    let (points, values) = state::update(
+        STATE_FILE,
+        |all_points: &mut BTreeMap<String, Vec<Point>>| {
+            let points = all_points.entry(series.to_string()).or_default();
+
+            let now = clock::now();
+            let mut values = Vec::new();
+            for (data_point, value) in numeric_data_points(input) {
+                let timestamp = data_point.timestamp.unwrap_or(now);
+                if let Some(last) = points.last().filter(|last| last.timestamp >= timestamp) {
+                    return Err(Error::Conflict(format!(
+                        "Data point at {timestamp} is not newer than the last one of {series} at {}",
+                        last.timestamp
+                    )));
+                }
+                points.push(Point { timestamp, value });
+                values.push(value);
+            }
+            let excess = points.len().saturating_sub(history_len as usize);
+            points.drain(..excess);
+            Ok((points.len(), values))
+        },
+    )?;
+
+    let ready = points >= history_len as usize;
+    #[cfg(feature = "baseline")]
+    crate::baseline::update(series, &values)?;
+    Ok(Ingestion {
+        series: series.to_string(),
+        points,
+        required: history_len,
+        ready,
+        // The series may be forecast right away (see trigger.rs)
+        #[cfg(feature = "trigger")]
+        forecast: crate::trigger::ingested(series, values.len(), ready)?,
+    })
+}
+
+// The series in a path like `/series/boiler-1/points`
+pub fn series_from_path(path: &str) -> Result<&str, Error> {
+    path.strip_prefix("/series/")
+        .and_then(|path| path.strip_suffix("/points"))
+        .filter(|series| !series.is_empty() && !series.contains('/'))
+        .ok_or_else(|| Error::BadRequest(format!("Invalid series in {path}")))
+}
+
+pub fn ingestion_to_vec(ingestion: &Ingestion) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(ingestion)
+        .map_err(|e| Error::internal(format!("Error serializing ingestion: {e}")))
+}
+
+impl HttpHandler {
+    // Forecasts the next values of the series, once it has enough data
+    // points
+    pub fn forecast_ingested(
+        &mut self,
+        model: Option<&ModelSpec>,
+        series: &str,
+    ) -> Result<interface::InferenceResult, Error> {
+        let mut all_points: BTreeMap<String, Vec<Point>> = state::load(STATE_FILE)?;
+        let points = all_points.remove(series).unwrap_or_default();
+        let model::Shape {
+            history_len,
+            prediction_len,
+            ..
+        } = model::shape_of(model)?;
+        if points.len() < history_len as usize {
+            return Err(Error::TooEarly(format!(
+                "{series} has {} of {history_len} data points",
+                points.len()
+            )));
+        }
+
+        let data = points
+            .into_iter()
+            .enumerate()
+            .map(|(i, point)| {
+                let data_point = interface::DataPoint {
+                    quality: None,
+                    value: interface::Value::Number(point.value),
+                    timestamp: Some(point.timestamp),
+                };
+                (format!("Input{}", i + 1), data_point)
+            })
+            .collect();
+        let input = interface::DataWindow { data };
+        let timestamps = forecast_timestamps(&input, prediction_len);
+        let values = self.forecast_values(model, None, Some(series), input, prediction_len)?;
+        Ok(inference_result_from_values(values, timestamps))
+    }
+}
@@ -0,0 +1,44 @@
+// This module checks model files against their SHA-256 checksums
+// before they are loaded, so that a corrupted or tampered model on the
+// flash storage of the device is refused with a clear error instead of
+// producing wrong forecasts. The checksums are read from the
+// `integrity` config (see config.rs), which must list every model the
+// component loads:
+//
+// { "models/model.onnx": "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08" }
+//
+// The checksum of a file is computed with `sha256sum models/model.onnx`.
+
+use std::{collections::HashMap, fs, path::Path};
+
+use wasi::http::types::ErrorCode;
+
+use crate::{
+    config,
+    sha256::{hex, sha256},
+};
+
+const CONFIG: &str = "integrity";
+
+// Fails if any of the files does not match its checksum
+pub fn verify<P: AsRef<Path>>(files: &[P]) -> Result<(), ErrorCode> {
+    let error = |message: String| ErrorCode::InternalError(Some(message));
+    let checksums: HashMap<String, String> =
+        config::require(CONFIG).map_err(|e| error(e.to_string()))?;
+
+    for file in files {
+        let file = file.as_ref().to_string_lossy();
+        let expected = checksums
+            .get(file.as_ref())
+            .ok_or_else(|| error(format!("No checksum configured for model {file}")))?;
+        let contents =
+            fs::read(file.as_ref()).map_err(|e| error(format!("Error reading {file}: {e}")))?;
+        let actual = hex(&sha256(&contents));
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            return Err(error(format!(
+                "Checksum of model {file} does not match, expected {expected} but got {actual}"
+            )));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,112 @@
+// This module translates Azure IoT Hub device-to-cloud messages, so
+// that the component can sit behind the IoT Edge hub and consume the
+// messages routed to it. The messages are in the JSON format IoT Hub
+// uses for routed messages, with the data window as body (either as
+// JSON or, for other content encodings, as base64 string):
+//
+// { "EnqueuedTimeUtc": "2024-05-01T12:00:00Z",
+//   "Properties": { "series": "machine-1" },
+//   "SystemProperties": { "connectionDeviceId": "press-7", "messageId": "42",
+//                         "contentType": "application/json", "contentEncoding": "utf-8" },
+//   "Body": { "Input1": { "dataType": "Number", ... } } }
+//
+// The inference result is sent back in the same format, with the
+// application properties of the input message, and the message id of
+// the input as correlation id. They are accepted with `POST /iothub`
+// and, if the messaging feature is enabled as well, by the messaging
+// handler (see messaging.rs).
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use wasi_nn_demo_lib::interface;
+
+use crate::{base64, error::Error, json};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct IncomingMessage {
+    #[serde(default)]
+    properties: BTreeMap<String, String>,
+    #[serde(default)]
+    system_properties: IncomingSystemProperties,
+    body: Value,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IncomingSystemProperties {
+    message_id: Option<String>,
+    connection_device_id: Option<String>,
+    connection_module_id: Option<String>,
+}
+
+// What is kept of the input message to address the result
+pub struct Envelope {
+    properties: BTreeMap<String, String>,
+    system_properties: IncomingSystemProperties,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct OutgoingMessage<'a> {
+    properties: &'a BTreeMap<String, String>,
+    system_properties: OutgoingSystemProperties<'a>,
+    body: Value,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OutgoingSystemProperties<'a> {
+    content_type: &'a str,
+    content_encoding: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    correlation_id: Option<&'a str>,
+    // The device and module the input came from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connection_device_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connection_module_id: Option<&'a str>,
+}
+
+// Parses an IoT Hub message into the data window in its body and the
+// envelope needed to send back the result
+pub fn parse_message(input: &[u8]) -> Result<(interface::DataWindow, Envelope), Error> {
+    let message: IncomingMessage = serde_json::from_slice(input)
+        .map_err(|e| Error::BadRequest(format!("Invalid IoT Hub message: {e}")))?;
+
+    let body = match message.body {
+        Value::String(encoded) => base64::decode(&encoded)
+            .ok_or_else(|| Error::BadRequest("Body is neither JSON nor base64".into()))?,
+        body => serde_json::to_vec(&body)
+            .map_err(|e| Error::internal(format!("Error serializing body: {e}")))?,
+    };
+    let window = json::parse_data_window(&body)?;
+
+    let envelope = Envelope {
+        properties: message.properties,
+        system_properties: message.system_properties,
+    };
+    Ok((window, envelope))
+}
+
+// Wraps the inference result (as JSON) into an IoT Hub message
+pub fn message_to_vec(envelope: &Envelope, result: &[u8]) -> Result<Vec<u8>, Error> {
+    let body = serde_json::from_slice(result)
+        .map_err(|e| Error::internal(format!("Invalid inference result: {e}")))?;
+    let system_properties = &envelope.system_properties;
+    let message = OutgoingMessage {
+        properties: &envelope.properties,
+        system_properties: OutgoingSystemProperties {
+            content_type: "application/json",
+            content_encoding: "utf-8",
+            correlation_id: system_properties.message_id.as_deref(),
+            connection_device_id: system_properties.connection_device_id.as_deref(),
+            connection_module_id: system_properties.connection_module_id.as_deref(),
+        },
+        body,
+    };
+    serde_json::to_vec(&message)
+        .map_err(|e| Error::internal(format!("Error serializing IoT Hub message: {e}")))
+}
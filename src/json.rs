@@ -0,0 +1,68 @@
+// The JSON encoding of data windows and inference results. By default
+// this is done using serde_json and the serde implementations of the
+// demo library. Without the `serde` feature, a minimal hand-rolled
+// parser for exactly the data window schema is used instead (see
+// json/lite.rs), which makes the compiled component considerably
+// smaller.
+
+use {crate::error::Error, wasi_nn_demo_lib::interface};
+
+#[cfg(not(feature = "serde"))]
+mod lite;
+
+pub fn parse_data_window(input: &[u8]) -> Result<interface::DataWindow, Error> {
+    let window = parse_data_window_unchecked(input)?;
+
+    // Values that are not numbers are rejected instead of silently
+    // dropped (see `numeric_data_points` in lib.rs)
+    #[cfg(feature = "strict")]
+    crate::check_values(&window)?;
+
+    Ok(window)
+}
+
+// Parses the data window without rejecting values that are not
+// numbers, for the quality report, which reports them instead
+#[cfg_attr(not(feature = "quality"), allow(dead_code))]
+pub fn parse_data_window_unchecked(input: &[u8]) -> Result<interface::DataWindow, Error> {
+    #[cfg(feature = "serde")]
+    let window: interface::DataWindow = serde_json::from_slice(input)
+        .map_err(|e| Error::BadRequest(format!("Invalid data window: {e}")))?;
+    #[cfg(not(feature = "serde"))]
+    let window = lite::parse_data_window(input)?;
+
+    // The data points are accounted to the client (see usage.rs)
+    #[cfg(feature = "usage")]
+    crate::usage::record_data_points(window.data.len());
+    // The window is forwarded to the collector (see forward.rs)
+    #[cfg(feature = "forward")]
+    crate::forward::record(&window);
+
+    Ok(window)
+}
+
+#[cfg(feature = "serde")]
+pub fn inference_result_to_vec(result: &interface::InferenceResult) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(result)
+        .map_err(|e| Error::internal(format!("Error serializing result: {e}")))
+}
+
+#[cfg(not(feature = "serde"))]
+pub use lite::inference_result_to_vec;
+
+// Appends `value` to `out` as a quoted and escaped JSON string
+pub fn write_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if u32::from(c) < 0x20 => out.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
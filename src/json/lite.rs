@@ -0,0 +1,428 @@
+// A minimal JSON parser and writer for exactly the data window
+// schema, used when the component is built without serde. It only
+// understands what is needed to read a data window like the one in
+// example-input.json: A top-level object whose members are data
+// points. Members that are not data points (i.e. objects without a
+// "dataType", like "previousAlarms") are skipped.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use wasi_nn_demo_lib::interface;
+
+use crate::error::Error;
+
+// Data windows are nested only two levels deep, but members that are
+// skipped may be nested further. Anything deeper than this is rejected
+// instead of risking the stack of the component, since every level is
+// parsed by a recursive call.
+const MAX_DEPTH: usize = 64;
+
+pub fn parse_data_window(input: &[u8]) -> Result<interface::DataWindow, Error> {
+    let mut parser = Parser {
+        input,
+        pos: 0,
+        depth: 0,
+    };
+    let mut data = HashMap::new();
+
+    parser
+        .object(|parser, key| {
+            if let Some(data_point) = parser.data_point()? {
+                data.insert(key, data_point);
+            }
+            Ok(())
+        })
+        .and_then(|_| parser.end())
+        .map_err(|e| {
+            Error::BadRequest(format!("Invalid data window at byte {}: {e}", parser.pos))
+        })?;
+
+    Ok(interface::DataWindow { data })
+}
+
+pub fn inference_result_to_vec(result: &interface::InferenceResult) -> Result<Vec<u8>, Error> {
+    let mut out = String::new();
+    match result {
+        interface::InferenceResult::PredictedValues(data_points) => {
+            out.push_str(r#"{"PredictedValues":["#);
+            for (i, data_point) in data_points.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_data_point(&mut out, data_point);
+            }
+            out.push_str("]}");
+        }
+    }
+    Ok(out.into_bytes())
+}
+
+fn write_data_point(out: &mut String, data_point: &interface::DataPoint) {
+    out.push_str(r#"{"quality":"#);
+    match &data_point.quality {
+        Some(quality) => out.push_str(&quality.to_string()),
+        None => out.push_str("null"),
+    }
+    match &data_point.value {
+        interface::Value::Number(num) => {
+            out.push_str(r#","dataType":"Number","value":"#);
+            // JSON has no representation for NaN or infinity
+            if num.is_finite() {
+                out.push_str(&num.to_string());
+            } else {
+                out.push_str("null");
+            }
+        }
+        interface::Value::String(string) => {
+            out.push_str(r#","dataType":"String","value":"#);
+            super::write_string(out, string);
+        }
+    }
+    out.push_str(r#","timestamp":"#);
+    match &data_point.timestamp {
+        // Same format as the serde implementation of chrono
+        Some(timestamp) => {
+            super::write_string(out, &timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+        }
+        None => out.push_str("null"),
+    }
+    out.push('}');
+}
+
+// The value of a data point, which we only interpret once we know the
+// "dataType" (which may come after the value)
+enum RawValue<'a> {
+    Number(&'a str),
+    String(String),
+    Null,
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    // The number of objects and arrays the parser is in
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    // Parses the members of a data point object. Returns `None` if the
+    // value is not a data point at all.
+    fn data_point(&mut self) -> Result<Option<interface::DataPoint>, String> {
+        if self.peek() != Some(b'{') {
+            self.skip_value()?;
+            return Ok(None);
+        }
+
+        let mut data_type = None;
+        let mut quality = None;
+        let mut timestamp = None;
+        let mut value = RawValue::Null;
+        self.object(|parser, key| {
+            match key.as_str() {
+                "dataType" => data_type = Some(parser.string()?),
+                "quality" => quality = parser.nullable(Parser::number)?,
+                "timestamp" => timestamp = parser.nullable(Parser::string)?,
+                "value" => {
+                    value = match parser.peek() {
+                        Some(b'"') => RawValue::String(parser.string()?),
+                        Some(b'n') => parser.literal("null").map(|_| RawValue::Null)?,
+                        _ => RawValue::Number(parser.number()?),
+                    }
+                }
+                _ => parser.skip_value()?,
+            }
+            Ok(())
+        })?;
+
+        let Some(data_type) = data_type else {
+            return Ok(None);
+        };
+        let value = match (data_type.as_str(), value) {
+            ("Number", RawValue::Number(num)) => interface::Value::Number(
+                num.parse()
+                    .map_err(|e| format!("Invalid number {num}: {e}"))?,
+            ),
+            ("String", RawValue::String(string)) => interface::Value::String(string),
+            (data_type, _) => return Err(format!("Value does not match dataType {data_type}")),
+        };
+        let quality = quality
+            .map(|quality| {
+                quality
+                    .parse()
+                    .map_err(|e| format!("Invalid quality {quality}: {e}"))
+            })
+            .transpose()?;
+        let timestamp = timestamp
+            .map(|timestamp| {
+                DateTime::parse_from_rfc3339(&timestamp)
+                    .map(|timestamp| timestamp.with_timezone(&Utc))
+                    .map_err(|e| format!("Invalid timestamp {timestamp}: {e}"))
+            })
+            .transpose()?;
+
+        Ok(Some(interface::DataPoint {
+            quality,
+            value,
+            timestamp,
+        }))
+    }
+
+    // Calls `member` for every key of an object, which must consume
+    // the corresponding value
+    fn object(
+        &mut self,
+        mut member: impl FnMut(&mut Self, String) -> Result<(), String>,
+    ) -> Result<(), String> {
+        self.expect(b'{')?;
+        self.nested(|parser| {
+            if parser.peek() == Some(b'}') {
+                parser.pos += 1;
+                return Ok(());
+            }
+            loop {
+                let key = parser.string()?;
+                parser.expect(b':')?;
+                member(parser, key)?;
+                match parser.next() {
+                    Some(b',') => continue,
+                    Some(b'}') => return Ok(()),
+                    _ => return Err("Expected , or }".to_string()),
+                }
+            }
+        })
+    }
+
+    // Parses the members or elements of an object or array, one level
+    // deeper than the parser is
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<(), String>,
+    ) -> Result<(), String> {
+        if self.depth == MAX_DEPTH {
+            return Err("Nested too deeply".to_string());
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn skip_value(&mut self) -> Result<(), String> {
+        match self.peek() {
+            Some(b'{') => self.object(|parser, _| parser.skip_value()),
+            Some(b'[') => {
+                self.pos += 1;
+                self.nested(|parser| {
+                    if parser.peek() == Some(b']') {
+                        parser.pos += 1;
+                        return Ok(());
+                    }
+                    loop {
+                        parser.skip_value()?;
+                        match parser.next() {
+                            Some(b',') => continue,
+                            Some(b']') => return Ok(()),
+                            _ => return Err("Expected , or ]".to_string()),
+                        }
+                    }
+                })
+            }
+            Some(b'"') => self.string().map(|_| ()),
+            Some(b't') => self.literal("true"),
+            Some(b'f') => self.literal("false"),
+            Some(b'n') => self.literal("null"),
+            _ => self.number().map(|_| ()),
+        }
+    }
+
+    fn nullable<T>(
+        &mut self,
+        value: fn(&mut Self) -> Result<T, String>,
+    ) -> Result<Option<T>, String> {
+        if self.peek() == Some(b'n') {
+            self.literal("null").map(|_| None)
+        } else {
+            value(self).map(Some)
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut string = Vec::new();
+        loop {
+            match self.input.get(self.pos) {
+                None => return Err("Unterminated string".to_string()),
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    let escaped = *self.input.get(self.pos + 1).ok_or("Unterminated string")?;
+                    self.pos += 2;
+                    let c = match escaped {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err("Invalid escape sequence".to_string()),
+                    };
+                    string.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                Some(&byte) => {
+                    string.push(byte);
+                    self.pos += 1;
+                }
+            }
+        }
+        String::from_utf8(string).map_err(|e| e.to_string())
+    }
+
+    // Parses the XXXX of a \uXXXX escape, including surrogate pairs. A
+    // high surrogate must be followed by the escape of a low one, and a
+    // low surrogate must not appear on its own.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4()?;
+        let code = match high {
+            0xd800..=0xdbff => {
+                if self.input.get(self.pos..self.pos + 2) != Some(b"\\u") {
+                    return Err("Unpaired surrogate".to_string());
+                }
+                self.pos += 2;
+                let low = self.hex4()?;
+                if !(0xdc00..=0xdfff).contains(&low) {
+                    return Err("Unpaired surrogate".to_string());
+                }
+                0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+            }
+            0xdc00..=0xdfff => return Err("Unpaired surrogate".to_string()),
+            _ => high,
+        };
+        char::from_u32(code).ok_or_else(|| "Invalid unicode escape".to_string())
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .ok_or("Invalid unicode escape")?;
+        self.pos += 4;
+        u32::from_str_radix(digits, 16).map_err(|e| e.to_string())
+    }
+
+    // Returns the text of a number, which is parsed by the caller into
+    // whatever type it needs
+    fn number(&mut self) -> Result<&'a str, String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        while let Some(b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E') = self.input.get(self.pos) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err("Expected value".to_string());
+        }
+        std::str::from_utf8(&self.input[start..self.pos]).map_err(|e| e.to_string())
+    }
+
+    fn literal(&mut self, literal: &str) -> Result<(), String> {
+        self.skip_whitespace();
+        let rest = self.input.get(self.pos..).unwrap_or_default();
+        if rest.starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(())
+        } else {
+            Err(format!("Expected {literal}"))
+        }
+    }
+
+    fn end(&mut self) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.pos == self.input.len() {
+            Ok(())
+        } else {
+            Err("Trailing characters".to_string())
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.next() == Some(byte) {
+            Ok(())
+        } else {
+            Err(format!("Expected {}", byte as char))
+        }
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek();
+        self.pos += 1;
+        byte
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.input.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.input.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(json: &str) -> Result<String, String> {
+        Parser {
+            input: json.as_bytes(),
+            pos: 0,
+            depth: 0,
+        }
+        .string()
+    }
+
+    #[test]
+    fn parses_data_window() {
+        let window = parse_data_window(
+            br#"{"Input1": {"quality": 192, "dataType": "Number", "value": 1.5,
+                 "timestamp": "2024-05-01T12:00:00Z"},
+                 "previousAlarms": {"list": [[1, {"a": null}], true]}}"#,
+        )
+        .unwrap();
+        let data_point = &window.data["Input1"];
+        assert_eq!(data_point.quality, Some(192));
+        assert!(matches!(data_point.value, interface::Value::Number(v) if v == 1.5));
+        assert_eq!(window.data.len(), 1);
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let depth = |n| format!(r#"{{"a": {}{}}}"#, "[".repeat(n), "]".repeat(n));
+        assert!(parse_data_window(depth(MAX_DEPTH - 1).as_bytes()).is_ok());
+        assert!(parse_data_window(depth(MAX_DEPTH).as_bytes()).is_err());
+        // Far deeper than the stack would allow
+        assert!(parse_data_window(depth(1_000_000).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parses_surrogate_pairs() {
+        assert_eq!(string(r#""\ud83d\ude00""#).unwrap(), "\u{1f600}");
+        assert_eq!(string(r#""\u00e9""#).unwrap(), "\u{e9}");
+    }
+
+    #[test]
+    fn rejects_unpaired_surrogates() {
+        assert!(string(r#""\ud83d""#).is_err());
+        assert!(string(r#""\ud83dA""#).is_err());
+        assert!(string(r#""\ud83d\ud83d""#).is_err());
+        assert!(string(r#""\ude00""#).is_err());
+    }
+}
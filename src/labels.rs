@@ -0,0 +1,56 @@
+// Turning the raw scores of a classification model into the most
+// likely labels. This is shared by all classification models (images,
+// text).
+
+use serde::Serialize;
+
+use crate::error::Error;
+
+#[derive(Serialize)]
+pub struct Label {
+    pub label: String,
+    pub score: f32,
+}
+
+// Reads the labels of the classes from a file with one label per
+// line, in the order of the model output
+pub fn read_labels(file: &str) -> Result<Vec<String>, Error> {
+    std::fs::read_to_string(file)
+        .map(|labels| labels.lines().map(str::to_string).collect())
+        .map_err(|e| Error::internal(format!("Error reading {file}: {e}")))
+}
+
+// Returns the `count` most likely labels, given the unnormalized
+// scores (logits) of the model for each class
+pub fn top_labels(logits: &[f32], labels: &[String], count: usize) -> Vec<Label> {
+    // We use softmax to turn the scores into probabilities
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exp: Vec<_> = logits.iter().map(|logit| (logit - max).exp()).collect();
+    let sum: f32 = exp.iter().sum();
+
+    let mut scores: Vec<_> = exp.into_iter().map(|e| e / sum).enumerate().collect();
+    scores.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+    scores
+        .into_iter()
+        .take(count)
+        .map(|(class, score)| Label {
+            // Fall back to the class index if there is no label for it
+            label: labels
+                .get(class)
+                .cloned()
+                .unwrap_or_else(|| class.to_string()),
+            score,
+        })
+        .collect()
+}
+
+pub fn labels_to_vec(labels: &[Label]) -> Result<Vec<u8>, Error> {
+    #[derive(Serialize)]
+    struct Response<'a> {
+        labels: &'a [Label],
+    }
+
+    serde_json::to_vec(&Response { labels })
+        .map_err(|e| Error::internal(format!("Error serializing labels: {e}")))
+}
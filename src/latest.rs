@@ -0,0 +1,104 @@
+// This module keeps the most recent forecast of each series, so that
+// read-only consumers (e.g. dashboards) can fetch it with
+// `GET /forecast/{series}` without running the model. Every forecast
+// with a `series` parameter replaces the one before, and is returned
+// with when it was made and the model that made it:
+//
+// { "series": "machine-1", "created": "2024-05-01T12:00:03Z",
+//   "model": "pump", "model_version": "9f86d0...", "forecast": { ... } }
+//
+// `model` is the name of the model if it was selected from the registry
+// (see registry.rs), and `model_version` is the SHA-256 of its file
+// (see `checksum` in model.rs), if it can be read. `forecast` is the
+// body of the forecast response. The model is recorded by the inference
+// (see `model_forecast` in lib.rs) like the variance (see variance.rs),
+// and forecasts are stored in the state directory (see state.rs).
+
+use std::{collections::BTreeMap, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{clock, error::Error, model::ModelSpec, state};
+
+const STATE_FILE: &str = "latest.json";
+
+// The first file of the model that made the running forecast, once it
+// ran
+static MODEL: Mutex<Option<Option<String>>> = Mutex::new(None);
+
+#[derive(Serialize, Deserialize)]
+pub struct Latest {
+    #[serde(skip_deserializing)]
+    series: String,
+    created: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model_version: Option<String>,
+    forecast: serde_json::Value,
+}
+
+// Starts recording the model of a forecast
+pub fn start() {
+    if let Ok(mut model) = MODEL.lock() {
+        *model = Some(None);
+    }
+}
+
+// Records the model if a forecast is running
+pub fn record_model(spec: &ModelSpec) {
+    if let Ok(mut model) = MODEL.lock() {
+        if let Some(model) = model.as_mut() {
+            *model = spec.files.first().cloned();
+        }
+    }
+}
+
+// Stores the response body of the forecast for the series, with the
+// name of the model if it was selected by name. The forecast has been
+// made at this point, so errors are only logged.
+pub fn store(series: &str, name: Option<&str>, body: &[u8]) {
+    let file = MODEL
+        .lock()
+        .ok()
+        .and_then(|mut model| model.take())
+        .flatten();
+    let result = serde_json::from_slice(body)
+        .map_err(|e| Error::internal(format!("Invalid response body: {e}")))
+        .and_then(|forecast| {
+            let mut latest: BTreeMap<String, Latest> = state::load(STATE_FILE)?;
+            let forecast = Latest {
+                series: series.to_string(),
+                created: clock::now(),
+                model: name.map(String::from),
+                model_version: file.as_deref().and_then(crate::model::checksum),
+                forecast,
+            };
+            latest.insert(series.to_string(), forecast);
+            state::save(STATE_FILE, &latest)
+        });
+    if let Err(e) = result {
+        eprintln!("Error storing the forecast of {series}: {e}");
+    }
+}
+
+// The latest forecast of the series in the path
+pub fn get(path: &str) -> Result<Latest, Error> {
+    of(path.strip_prefix("/forecast/").unwrap_or_default())
+}
+
+// The latest forecast of the series
+pub fn of(series: &str) -> Result<Latest, Error> {
+    let mut latest: BTreeMap<String, Latest> = state::load(STATE_FILE)?;
+    let mut forecast = latest
+        .remove(series)
+        .ok_or_else(|| Error::NotFound(format!("No forecast of {series}")))?;
+    forecast.series = series.to_string();
+    Ok(forecast)
+}
+
+pub fn latest_to_vec(latest: &Latest) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(latest)
+        .map_err(|e| Error::internal(format!("Error serializing forecast: {e}")))
+}
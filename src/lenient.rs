@@ -0,0 +1,103 @@
+// This module parses values that are sent as strings instead of
+// numbers, which several SCADA exports do, often formatted for a
+// locale (e.g. "1.234,56" in Germany). Without the `lenient` feature,
+// string values are ignored (see `numeric_data_points` in lib.rs).
+//
+// The separators are read from the optional `number-format` config
+// (see config.rs) and default to the English format:
+//
+// { "decimal_separator": ",", "thousands_separator": "." }
+//
+// Spaces (also non-breaking ones) and apostrophes are always accepted
+// as thousands separators, as in "1 234,56" or "1'234.56".
+
+use serde::Deserialize;
+
+use crate::config;
+
+const CONFIG: &str = "number-format";
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct NumberFormat {
+    decimal_separator: char,
+    thousands_separator: char,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        Self {
+            decimal_separator: '.',
+            thousands_separator: ',',
+        }
+    }
+}
+
+impl NumberFormat {
+    // Loads the number format from the config. An invalid config is
+    // reported and the default is used instead, since the callers
+    // cannot report errors.
+    pub fn load() -> Self {
+        config::load(CONFIG)
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                None
+            })
+            .unwrap_or_default()
+    }
+
+    // Parses a number in this format, returns `None` if the string is
+    // not a number
+    pub fn parse(&self, value: &str) -> Option<f32> {
+        let normalized: String = value
+            .trim()
+            .chars()
+            .filter(|&c| {
+                c != self.thousands_separator && !matches!(c, ' ' | '\u{a0}' | '\u{202f}' | '\'')
+            })
+            .map(|c| if c == self.decimal_separator { '.' } else { c })
+            .collect();
+        normalized.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_default_format() {
+        let format = NumberFormat::default();
+        assert_eq!(format.parse("1234.5"), Some(1234.5));
+        assert_eq!(format.parse(" -1,234.5 "), Some(-1234.5));
+        assert_eq!(format.parse("1e3"), Some(1000.0));
+    }
+
+    #[test]
+    fn parses_a_configured_format() {
+        let format = NumberFormat {
+            decimal_separator: ',',
+            thousands_separator: '.',
+        };
+        assert_eq!(format.parse("1.234,56"), Some(1234.56));
+        assert_eq!(format.parse("0,5"), Some(0.5));
+    }
+
+    #[test]
+    fn accepts_spaces_and_apostrophes_as_thousands_separators() {
+        let format = NumberFormat::default();
+        assert_eq!(format.parse("1 234.5"), Some(1234.5));
+        assert_eq!(format.parse("1\u{a0}234.5"), Some(1234.5));
+        assert_eq!(format.parse("1\u{202f}234.5"), Some(1234.5));
+        assert_eq!(format.parse("1'234.5"), Some(1234.5));
+    }
+
+    #[test]
+    fn rejects_what_is_not_a_number() {
+        let format = NumberFormat::default();
+        assert_eq!(format.parse(""), None);
+        assert_eq!(format.parse("n/a"), None);
+        assert_eq!(format.parse("12 %"), None);
+        assert_eq!(format.parse("1.2.3"), None);
+    }
+}
@@ -1,56 +1,327 @@
-use std::sync::Mutex;
-
-// We need to use some functions from the bare wasi bindings
-use wasi::{
-    exports::http::incoming_handler::{Guest, IncomingRequest, ResponseOutparam},
-    http::{proxy::export, types::ErrorCode},
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, Mutex},
 };
 
-// The rest are high-level definitions by the demo library
-use wasi_nn_demo_lib::{
-    http::RequestHandler,
-    interface,
-    nn::{GraphBuilder, GraphEncoding, Tensor},
-};
+use chrono::{DateTime, TimeDelta, Utc};
+
+// We need to use some error types from the bare wasi bindings
+use wasi::http::types::ErrorCode;
+
+// The rest are high-level definitions by the demo library, except for
+// wasi-nn, which the component binds itself (see nn.rs)
+use wasi_nn_demo_lib::{http::RequestHandler, interface};
+
+use nn::{Graph, GraphBuilder, GraphEncoding, Tensor};
+
+// The component can be instantiated in different WASI worlds. Each
+// world we support lives in its own module that implements the
+// corresponding `Guest` trait for `Component` and invokes the
+// `export!` macro of that world. Which of them are compiled into the
+// component is selected by cargo features (see Cargo.toml), so the
+// same code base can for example serve HTTP requests and at the same
+// time be run as a batch command. Further worlds can be added the same
+// way.
+#[cfg(feature = "cli")]
+mod cli;
+#[cfg(feature = "forecaster")]
+mod forecaster;
+#[cfg(feature = "http")]
+mod http;
+#[cfg(feature = "messaging")]
+mod messaging;
+
+// Spin and wasmCloud run the component as HTTP handler and do not
+// provide wasi:messaging, so the component would fail to instantiate
+// if it imported it
+#[cfg(all(any(feature = "spin", feature = "wasmcloud"), feature = "messaging"))]
+compile_error!("The messaging feature cannot be used with Spin or wasmCloud");
+
+#[cfg(feature = "accuracy")]
+mod accuracy;
+#[cfg(feature = "admin")]
+mod admin;
+#[cfg(feature = "encryption")]
+mod aes_gcm;
+#[cfg(feature = "aggregate")]
+mod aggregate;
+#[cfg(feature = "alerts")]
+mod alerts;
+#[cfg(feature = "anomaly")]
+mod anomaly;
+#[cfg(feature = "audio")]
+mod audio;
+#[cfg(feature = "backtest")]
+mod backtest;
+#[cfg(any(feature = "auth", feature = "iothub"))]
+mod base64;
+#[cfg(feature = "baseline")]
+mod baseline;
+#[cfg(feature = "batch")]
+mod batch;
+#[cfg(any(feature = "cbor", feature = "msgpack"))]
+mod binary;
+#[cfg(feature = "body-limit")]
+mod body_limit;
+#[cfg(feature = "breaker")]
+mod breaker;
+#[cfg(feature = "budget")]
+mod budget;
+#[cfg(feature = "etag")]
+mod canonical;
+#[cfg(feature = "capabilities")]
+mod capabilities;
+#[cfg(feature = "changepoint")]
+mod changepoint;
+#[cfg(any(
+    feature = "admin",
+    feature = "auth",
+    feature = "breaker",
+    feature = "budget",
+    feature = "ingest",
+    feature = "latest",
+    feature = "previous",
+    feature = "prune",
+    feature = "queue",
+    feature = "replay",
+    feature = "retention",
+    feature = "stale",
+    feature = "stitch",
+    feature = "trace",
+    feature = "usage"
+))]
+mod clock;
+#[cfg(feature = "cluster")]
+mod cluster;
+#[cfg(feature = "compare")]
+mod compare;
+#[cfg(any(
+    feature = "alerts",
+    feature = "auth",
+    feature = "body-limit",
+    feature = "breaker",
+    feature = "budget",
+    feature = "calendar",
+    feature = "cors",
+    feature = "csv",
+    feature = "encryption",
+    feature = "fit-config",
+    feature = "forward",
+    feature = "homeassistant",
+    feature = "integrity",
+    feature = "lenient",
+    feature = "model-config",
+    feature = "model-limits",
+    feature = "named-models",
+    feature = "pipeline",
+    feature = "pushgateway",
+    feature = "queue",
+    feature = "registry",
+    feature = "resample",
+    feature = "retention",
+    feature = "routing",
+    feature = "s3",
+    feature = "schedule",
+    feature = "sign",
+    feature = "signature",
+    feature = "trigger"
+))]
+mod config;
+#[cfg(feature = "cors")]
+mod cors;
+#[cfg(feature = "covariates")]
+mod covariates;
+#[cfg(feature = "csv")]
+mod csv;
+#[cfg(feature = "discovery")]
+mod discovery;
+#[cfg(feature = "embedding")]
+mod embedding;
+#[cfg(feature = "encryption")]
+mod encryption;
+mod error;
+#[cfg(feature = "fallback")]
+mod fallback;
+mod fit;
+#[cfg(feature = "forward")]
+mod forward;
+#[cfg(feature = "generate")]
+mod generate;
+#[cfg(feature = "grafana")]
+mod grafana;
+#[cfg(feature = "health")]
+mod health;
+#[cfg(feature = "hierarchy")]
+mod hierarchy;
+#[cfg(feature = "homeassistant")]
+mod homeassistant;
+#[cfg(feature = "ingest")]
+mod ingest;
+#[cfg(feature = "integrity")]
+mod integrity;
+#[cfg(feature = "iothub")]
+mod iothub;
+mod json;
+#[cfg(any(feature = "text", feature = "vision"))]
+mod labels;
+#[cfg(feature = "latest")]
+mod latest;
+#[cfg(feature = "lenient")]
+mod lenient;
+#[cfg(feature = "model-limits")]
+mod limits;
+#[cfg(feature = "logging")]
+mod logging;
+#[cfg(any(feature = "accuracy", feature = "backtest"))]
+mod measures;
+mod model;
+#[cfg(feature = "named-models")]
+mod named_models;
+#[cfg(feature = "ndjson")]
+mod ndjson;
+mod nn;
+#[cfg(feature = "introspect")]
+mod onnx;
+#[cfg(any(
+    feature = "alerts",
+    feature = "forward",
+    feature = "homeassistant",
+    feature = "pushgateway",
+    feature = "s3",
+    feature = "schedule",
+    feature = "trigger"
+))]
+mod outgoing;
+#[cfg(feature = "pipeline")]
+mod pipeline;
+#[cfg(feature = "previous")]
+mod previous;
+#[cfg(feature = "prune")]
+mod prune;
+#[cfg(feature = "publish")]
+mod publish;
+#[cfg(feature = "pushgateway")]
+mod pushgateway;
+#[cfg(feature = "quality")]
+mod quality;
+#[cfg(feature = "queue")]
+mod queue;
+#[cfg(feature = "registry")]
+mod registry;
+#[cfg(feature = "replay")]
+mod replay;
+#[cfg(feature = "resample")]
+mod resample;
+#[cfg(feature = "resolutions")]
+mod resolution;
+#[cfg(feature = "retention")]
+mod retention;
+#[cfg(feature = "routing")]
+mod routing;
+#[cfg(feature = "s3")]
+mod s3;
+#[cfg(feature = "schedule")]
+mod schedule;
+#[cfg(feature = "search")]
+mod search;
+#[cfg(any(
+    feature = "auth",
+    feature = "etag",
+    feature = "integrity",
+    feature = "latest",
+    feature = "s3",
+    feature = "sign",
+    feature = "signature",
+    feature = "timing"
+))]
+mod sha256;
+#[cfg(feature = "sign")]
+mod sign;
+#[cfg(feature = "signature")]
+mod signature;
+#[cfg(feature = "simulate")]
+mod simulate;
+#[cfg(feature = "stale")]
+mod stale;
+#[cfg(any(
+    feature = "accuracy",
+    feature = "admin",
+    feature = "breaker",
+    feature = "budget",
+    feature = "ingest",
+    feature = "latest",
+    feature = "previous",
+    feature = "prune",
+    feature = "replay",
+    feature = "retention",
+    feature = "search",
+    feature = "stale",
+    feature = "stitch",
+    feature = "trace",
+    feature = "trigger",
+    feature = "usage"
+))]
+mod state;
+#[cfg(feature = "stitch")]
+mod stitch;
+#[cfg(feature = "tcp")]
+mod tcp;
+#[cfg(feature = "text")]
+mod text;
+#[cfg(feature = "timing")]
+mod timing;
+#[cfg(any(feature = "text", feature = "generate"))]
+mod tokenizer;
+#[cfg(feature = "trace")]
+mod trace;
+#[cfg(feature = "tracecontext")]
+mod tracecontext;
+#[cfg(feature = "trigger")]
+mod trigger;
+#[cfg(feature = "usage")]
+mod usage;
+#[cfg(feature = "variance")]
+mod variance;
+#[cfg(feature = "vision")]
+mod vision;
 
 // This is a failed attempt to carry state across invocations of
 // `Compontent::handle`. Sadly, it does not work as it seems the
 // component is reinitialized on every http request. As of the date of
 // this report, the WASI-NN specification does not support an explicit
 // way of carrying state. An option would be to store state on disk.
+// Features that need state do so now, in files or in the key-value
+// store of the host (see state.rs).
 //
 // The idea is to store the state in the handler object as a static
 // variable. We need to guard this with a Mutex even though we have no
 // real concurrency because safe Rust requires it.
 static HANDLER: Mutex<HttpHandler> = Mutex::new(HttpHandler::new());
 
-// To create a HTTP service in WASM, we need to create a type that
-// represents our component that implements the `Guest` trait. We then
-// need to "mark" this type using the `export!` macro provided by the
-// wasi crate. The `handle` function of this struct will be invoked by
-// the WASM runtime.
-struct Component;
-export!(Component);
-
-impl Guest for Component {
-    fn handle(request: IncomingRequest, response_outparam: ResponseOutparam) {
-        // Working with the `IncomingRequest` and `ResponseOutparam`
-        // types from the wasi-http is quite cumbersome. Luckily,
-        // wasi_nn_demo_lib does all that for us and we only need to
-        // call `handle_request` on our `HttpHandler` (as long as it
-        // implements the `RequestHandler` trait, see below.
+// The models that were already loaded by this instance of the
+// component, by their files, so that each model is only parsed and
+// compiled once. `wasmtime serve` creates a new instance for every
+// request (see above), but instances that live longer (e.g. in batch
+// mode with `--listen`, or with hosts that reuse instances) profit from
+// it.
+static MODELS: Mutex<BTreeMap<Vec<String>, Arc<Graph>>> = Mutex::new(BTreeMap::new());
 
-        let response = HANDLER
-            // We aquire the lock for the handler ...
-            .lock()
-            .map_err(|e| ErrorCode::InternalError(Some(format!("Error locking state: {e}"))))
-            // ... and then we call the handler function (provided by the wasi_nn_demo_lib)
-            .and_then(|mut handler| handler.handle_request(request));
+// This type represents our component. It is shared by all exported
+// worlds, the world-specific modules each implement the `Guest` trait
+// of their world for it and "mark" it using the `export!` macro
+// provided by the wasi crate.
+struct Component;
 
-        // Finally (and even in the case of an error!) the result must
-        // be finalized using this function from the wasi-http bindings:
-        ResponseOutparam::set(response_outparam, response);
-    }
+// All exported worlds share the same handler, this function acquires
+// the lock for it and runs the given closure with it.
+fn with_handler<T, E: From<ErrorCode>>(
+    f: impl FnOnce(&mut HttpHandler) -> Result<T, E>,
+) -> Result<T, E> {
+    HANDLER
+        .lock()
+        .map_err(|e| ErrorCode::InternalError(Some(format!("Error locking state: {e}"))).into())
+        .and_then(|mut handler| f(&mut handler))
 }
 
 struct HttpHandler {}
@@ -61,103 +332,728 @@ impl HttpHandler {
     }
 }
 
-// These constants are the parameters that are specific to the model
+// These constants are the parameters that are specific to the model.
+// The files and tensor names can also be configured at runtime (see
+// model.rs).
 const MODEL_FORMAT: GraphEncoding = GraphEncoding::Onnx;
 const MODEL_FILES: [&str; 1] = ["models/model.onnx"];
 // The labels of the input and output tensors in the model
 const INPUT_TENSOR_NAME: &str = "l_past_values_";
 const OUTPUT_TENSOR_NAME: &str = "add_8";
-// These last three constants make up the shape of the input tensors
-// (16 batches of length 128: 16 x 128 x 1) and output tensors (16
-// batches of length 24: 16 x 24 x 1)
+// These three constants make up the shape of the input tensors (16
+// batches of length 128: 16 x 128 x 1) and output tensors (16 batches
+// of length 24: 16 x 24 x 1) of the included model. Other shapes can
+// be configured (see `Shape` in model.rs).
 const NUM_BATCHES: u32 = 16;
 const HISTORY_LEN: u32 = 128;
 const PREDICTION_LEN: u32 = 24;
+// Clients can request forecasts longer than the prediction of the
+// model, up to this many times as long (see `HttpHandler::forecast`)
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+const MAX_STEPS: u32 = 4;
 
 impl RequestHandler for HttpHandler {
     // This function is called by the `handle_request` function which
-    // we called in the `Guest::handle` implementation above. This way
-    // we don't have to work with HTTP requests, but only the actual
-    // data contained in the `interface::DataWindow` parameter.
+    // we called in the `Guest::handle` implementation in http.rs (and
+    // directly by the batch mode in cli.rs). This way we don't have to
+    // work with HTTP requests, but only the actual data contained in
+    // the `interface::DataWindow` parameter.
     fn handle_data(
         &mut self,
         input: interface::DataWindow,
     ) -> Result<interface::InferenceResult, ErrorCode> {
-        // We use the default execution target (cpu), but have to set
-        // the model format and of course load the model files.
-        let graph = GraphBuilder::default()
-            .encoding(MODEL_FORMAT)
-            .from_files(MODEL_FILES)?
-            .build()?;
-        let ctx = graph.init_execution_context()?;
+        self.forecast(input, model::shape()?.prediction_len)
+    }
+}
 
-        let input_tensor = tensor_from_data_window(input)?;
+impl HttpHandler {
+    // This function forecasts the next `horizon` values. The model
+    // always predicts `prediction_len` values, so shorter horizons are
+    // served by cutting off the prediction. Longer horizons are
+    // forecast autoregressively: The predicted values are appended to
+    // the history (dropping the oldest values) and the model is run
+    // again, until enough values have been predicted. Note that the
+    // forecast gets less accurate with every step, because errors
+    // accumulate.
+    fn forecast(
+        &mut self,
+        input: interface::DataWindow,
+        horizon: u32,
+    ) -> Result<interface::InferenceResult, ErrorCode> {
+        let timestamps = forecast_timestamps(&input, horizon);
+        let predictions = self.forecast_values(None, None, None, input, horizon)?;
+        Ok(inference_result_from_values(predictions, timestamps))
+    }
 
-        // The model has only one input tensor and one output tensor.
-        let output_tensors =
-            &ctx.run([(INPUT_TENSOR_NAME, input_tensor)], &[OUTPUT_TENSOR_NAME])?;
+    // The forecast values, without converting them into an
+    // `interface::InferenceResult`. The model is the one the client
+    // selected (see registry.rs), otherwise the id of the series (if
+    // known) selects it (see routing.rs). The history is fitted to the
+    // model as the client chose, or as configured (see fit.rs).
+    fn forecast_values(
+        &mut self,
+        model: Option<&model::ModelSpec>,
+        fit: Option<&fit::Fit>,
+        series: Option<&str>,
+        input: interface::DataWindow,
+        horizon: u32,
+    ) -> Result<Vec<f32>, ErrorCode> {
+        self.forecast_steps(model, fit, series, input, horizon, |_| Ok(()))
+    }
 
-        inference_result_from_tensor(&output_tensors[OUTPUT_TENSOR_NAME])
+    // Like `forecast_values`, but `emit` is called with the values of
+    // each step as soon as the model made them, e.g. to stream them
+    // (see ndjson.rs). Values that were not made step by step, like
+    // those of the fallback, are emitted at once in the end.
+    #[cfg_attr(not(feature = "routing"), allow(unused_variables))]
+    fn forecast_steps(
+        &mut self,
+        model: Option<&model::ModelSpec>,
+        fit: Option<&fit::Fit>,
+        series: Option<&str>,
+        input: interface::DataWindow,
+        horizon: u32,
+        mut emit: impl FnMut(&[f32]) -> Result<(), ErrorCode>,
+    ) -> Result<Vec<f32>, ErrorCode> {
+        // The data points that are dropped from the series are traced
+        // here, where the window of the forecast becomes its series,
+        // and not every time the window is looked at
+        #[cfg(feature = "trace")]
+        {
+            let (_, dropped) = partition_data_points(&input);
+            if !dropped.is_empty() {
+                trace::record(trace::Decision::Dropped { keys: dropped });
+            }
+        }
+        let mut history = series_from_data_window(&input);
+        let fit = fit.copied().unwrap_or_else(fit::Fit::load);
+        let history_len = model::shape_of(model)?.history_len;
+        #[cfg(feature = "trace")]
+        if let Some(adjustment) = fit.adjustment(history.len(), history_len) {
+            trace::record(trace::Decision::Fitted {
+                values: history.len(),
+                adjustment,
+            });
+        }
+        fit.apply(&mut history, history_len);
+
+        // The history can be preprocessed by a pipeline of models
+        // first (see pipeline.rs)
+        #[cfg(feature = "pipeline")]
+        let (history, _) = pipeline::run(history)?;
+
+        let mut emitted = 0;
+        let emit_step = |values: &[f32]| {
+            emitted += values.len();
+            emit(values)
+        };
+        let forecast = || {
+            let mut emit_step = emit_step;
+            if let Some(model) = model {
+                let graph = load_model(MODEL_FORMAT, &model.files)?;
+                return model_forecast(&graph, model, &history, horizon, &mut emit_step);
+            }
+            #[cfg(feature = "routing")]
+            let graph = routing::load_graph(series)?;
+            #[cfg(not(feature = "routing"))]
+            let graph = load_graph()?;
+            model_forecast(&graph, &model::spec()?, &history, horizon, &mut emit_step)
+        };
+
+        // Repeated failures of the model open the circuit breaker,
+        // which then fails at once (see breaker.rs)
+        #[cfg(feature = "breaker")]
+        let forecast = breaker::call(forecast);
+        #[cfg(not(feature = "breaker"))]
+        let forecast = forecast();
+
+        // If the model cannot be loaded or run, a statistical forecast
+        // is returned instead of an error (see fallback.rs)
+        #[cfg(feature = "fallback")]
+        let forecast = forecast.or_else(|e| fallback::forecast(&history, horizon, e));
+
+        let forecast = forecast?;
+        if let Some(rest) = forecast.get(emitted..).filter(|rest| !rest.is_empty()) {
+            emit(rest)?;
+        }
+        Ok(forecast)
     }
 }
 
-// This function takes the raw data and converts it to a tensor that
-// fits the model.
-fn tensor_from_data_window(input: interface::DataWindow) -> Result<Tensor<f32>, ErrorCode> {
-    // We need to make sure that the data is chronologically ordered
-    let mut sorted_data_points: Vec<_> = input.data.values().collect();
-    sorted_data_points.sort_by_key(|data_point| data_point.timestamp);
+// This function runs the model on the history (as many times as
+// necessary for the horizon, see `HttpHandler::forecast`). The values
+// of each step are passed to `emit` as soon as they are predicted.
+fn model_forecast(
+    graph: &Graph,
+    spec: &model::ModelSpec,
+    history: &[f32],
+    horizon: u32,
+    emit: &mut dyn FnMut(&[f32]) -> Result<(), ErrorCode>,
+) -> Result<Vec<f32>, ErrorCode> {
+    let ctx = graph.init_execution_context()?;
+
+    let model::Shape {
+        history_len,
+        prediction_len,
+        ..
+    } = spec.shape;
+    let mut history = history.to_vec();
+    fit_to_history_len(&mut history, history_len);
+    #[cfg(feature = "timing")]
+    timing::record_model(spec);
+    #[cfg(feature = "latest")]
+    latest::record_model(spec);
+
+    // The model has one input tensor and returns the forecast, and
+    // possibly its variance (see variance.rs), as output tensors
+    #[cfg(feature = "variance")]
+    let outputs: Vec<&str> = std::iter::once(spec.output_tensor.as_str())
+        .chain(spec.variance_tensor.as_deref())
+        .collect();
+    #[cfg(not(feature = "variance"))]
+    let outputs = [spec.output_tensor.as_str()];
+    #[cfg(feature = "variance")]
+    let mut variances = Vec::new();
+
+    let mut predictions = Vec::with_capacity(horizon as usize);
+    while predictions.len() < horizon as usize {
+        let input_tensor = tensor_from_series(history.clone(), spec.batch_size(), history_len);
+
+        let output_tensors = &infer(graph, || {
+            ctx.run([(spec.input_tensor.as_str(), input_tensor)], &outputs)
+        })?;
+        let values = predicted_values(
+            &output_tensors[spec.output_tensor.as_str()],
+            spec.batch_size(),
+            prediction_len,
+        )?;
+        #[cfg(feature = "variance")]
+        if let Some(variance_tensor) = &spec.variance_tensor {
+            variances.extend(predicted_values(
+                &output_tensors[variance_tensor.as_str()],
+                spec.batch_size(),
+                prediction_len,
+            )?);
+        }
+
+        let needed = horizon as usize - predictions.len();
+        emit(&values[..needed.min(values.len())])?;
+        predictions.extend(&values);
+        // The history keeps its length, also if the model predicts
+        // more values than it is given
+        history.extend(values);
+        history.drain(..history.len() - history_len as usize);
+    }
+    predictions.truncate(horizon as usize);
+    #[cfg(feature = "variance")]
+    if spec.variance_tensor.is_some() {
+        variances.truncate(horizon as usize);
+        variance::record(variances);
+    }
+
+    Ok(predictions)
+}
+
+// This function forecasts `prediction_len` values for each of the
+// given histories. The model processes `num_batches` batches at once
+// (16 for the included model), so we forecast up to that many series
+// per inference. Unused batches are filled with zeros.
+// The model is the one the client selected (see registry.rs), if any.
+#[cfg_attr(
+    not(any(feature = "aggregate", feature = "batch", feature = "hierarchy")),
+    allow(dead_code)
+)]
+fn forecast_histories(
+    model: Option<&model::ModelSpec>,
+    histories: &[Vec<f32>],
+) -> Result<Vec<Vec<f32>>, ErrorCode> {
+    let default;
+    let spec = match model {
+        Some(model) => model,
+        None => {
+            default = model::spec()?;
+            &default
+        }
+    };
+    let model::Shape {
+        num_batches,
+        history_len,
+        prediction_len,
+    } = spec.shape;
+    let graph = load_model(MODEL_FORMAT, &spec.files)?;
+    let ctx = graph.init_execution_context()?;
+
+    let mut forecasts = Vec::with_capacity(histories.len());
+    for chunk in histories.chunks(num_batches as usize) {
+        let mut data = Vec::with_capacity((num_batches * history_len) as usize);
+        for history in chunk {
+            let mut history = history.clone();
+            fit_to_history_len(&mut history, history_len);
+            data.extend(history);
+        }
+        data.resize((num_batches * history_len) as usize, 0.0);
+        let input_tensor = Tensor::new(data, vec![num_batches, history_len, 1]);
+
+        let output_tensors = &infer(&graph, || {
+            ctx.run(
+                [(spec.input_tensor.as_str(), input_tensor)],
+                &[spec.output_tensor.as_str()],
+            )
+        })?;
+        let predictions = output_rows(
+            &output_tensors[spec.output_tensor.as_str()],
+            num_batches,
+            prediction_len,
+        )?;
+
+        forecasts.extend(predictions[..chunk.len()].iter().map(|p| p.to_vec()));
+    }
+    Ok(forecasts)
+}
+
+// This function loads the forecasting model.
+fn load_graph() -> Result<Arc<Graph>, ErrorCode> {
+    load_model(MODEL_FORMAT, &model::spec()?.files)
+}
+
+// All models (also those of the optional stages) are loaded by this
+// function, so that the files can be checked before they are used and
+// loaded models can be reused (see `MODELS`).
+fn load_model<P: AsRef<Path>>(
+    encoding: GraphEncoding,
+    files: &[P],
+) -> Result<Arc<Graph>, ErrorCode> {
+    let key: Vec<String> = files
+        .iter()
+        .map(|file| file.as_ref().to_string_lossy().into_owned())
+        .collect();
+    // Disabled models (and models that exceed their limits) are not
+    // used, even if they are already loaded (see admin.rs)
+    #[cfg(feature = "admin")]
+    admin::check(&key)?;
+
+    let mut models = MODELS
+        .lock()
+        .map_err(|e| ErrorCode::InternalError(Some(format!("Error locking models: {e}"))))?;
+    if let Some(graph) = models.get(&key) {
+        return Ok(graph.clone());
+    }
+
+    // The outcome of loading the model is recorded for GET
+    // /admin/models (see admin.rs) and logged (see logging.rs)
+    #[cfg(any(feature = "admin", feature = "logging"))]
+    let start = std::time::Instant::now();
+    let graph = build_model(encoding, files);
+    #[cfg(feature = "admin")]
+    admin::record_load(&key, start.elapsed(), graph.as_ref().err());
+    #[cfg(feature = "logging")]
+    logging::model_load(&key, start.elapsed(), graph.as_ref().err());
+    let graph = Arc::new(graph?);
+    models.insert(key, graph.clone());
+    Ok(graph)
+}
+
+// Loads the model from its files (or by its name), without the cache
+fn build_model<P: AsRef<Path>>(encoding: GraphEncoding, files: &[P]) -> Result<Graph, ErrorCode> {
+    // Models that the host registered under a name are loaded by that
+    // name instead of from their files (see named_models.rs)
+    #[cfg(feature = "named-models")]
+    if let Some(name) = named_models::name(files)? {
+        return Graph::load_by_name(&name);
+    }
+
+    // The checksums of the files must match the configured ones (see
+    // integrity.rs)
+    #[cfg(feature = "integrity")]
+    integrity::verify(files)?;
+
+    // We use the default execution target (cpu), but have to set the
+    // model format and of course load the model files.
+    let builder = GraphBuilder::default().encoding(encoding);
+
+    // Encrypted files are decrypted in memory (see encryption.rs)
+    #[cfg(feature = "encryption")]
+    let builder = builder.bytes(
+        files
+            .iter()
+            .map(encryption::read)
+            .collect::<Result<Vec<_>, _>>()?,
+    );
+    #[cfg(not(feature = "encryption"))]
+    let builder = builder.files(files)?;
+
+    builder.build()
+}
+
+// Runs an inference of the graph, which was loaded with `load_model`.
+// With the admin feature, the number of inferences of each model and
+// their latency are recorded (see admin.rs). With the usage feature,
+// the latency is also accounted to the client (see usage.rs). With the
+// logging feature, it is logged (see logging.rs), and with the timing
+// feature, it is returned to the client (see timing.rs).
+fn infer<T>(
+    #[cfg_attr(not(feature = "admin"), allow(unused_variables))] graph: &Graph,
+    run: impl FnOnce() -> T,
+) -> T {
+    #[cfg(any(
+        feature = "admin",
+        feature = "logging",
+        feature = "timing",
+        feature = "usage"
+    ))]
+    let start = std::time::Instant::now();
+    let result = run();
+    #[cfg(feature = "admin")]
+    admin::record_inference(graph, start.elapsed());
+    #[cfg(feature = "usage")]
+    usage::record_inference(start.elapsed());
+    #[cfg(feature = "logging")]
+    logging::inference(start.elapsed());
+    #[cfg(feature = "timing")]
+    timing::record_inference(start.elapsed());
+    result
+}
+
+// Forgets the loaded models, so that they are loaded from their files
+// again, e.g. after new files were downloaded (see s3.rs)
+#[cfg_attr(not(feature = "s3"), allow(dead_code))]
+fn unload_models() {
+    if let Ok(mut models) = MODELS.lock() {
+        models.clear();
+    }
+    #[cfg(any(feature = "latest", feature = "timing"))]
+    model::forget_checksums();
+}
+
+// This function takes the raw data and converts it to the series of
+// values the model works with.
+fn series_from_data_window(input: &interface::DataWindow) -> Vec<f32> {
+    let data_points = numeric_data_points(input);
 
     // The model has no time features, it simply assumes that all the
-    // data points are equidistant, so we just strip of all the
-    // timestamps from the data and only work with the actual values.
-    // A better way would be to either check that the timestamps are
-    // equidistant or convert the received data series to an by
-    // interpolating values to make it equidistant.
-    let mut single_data_series: Vec<_> = sorted_data_points
+    // data points are equidistant. If all of them have timestamps, the
+    // window can be resampled to make it equidistant (see
+    // resample.rs).
+    #[cfg(feature = "resample")]
+    if let Some(points) = data_points
+        .iter()
+        .map(|(data_point, num)| data_point.timestamp.map(|timestamp| (timestamp, *num)))
+        .collect::<Option<Vec<_>>>()
+    {
+        return resample::resample(&points);
+    }
+
+    // Otherwise we just strip of all the timestamps from the data and
+    // only work with the actual values.
+    data_points.into_iter().map(|(_, num)| num).collect()
+}
+
+// This function converts a single series of values into the input
+// tensor of the model, which takes `batch_size` series of `history_len`
+// values (see `ModelSpec::batch_size`).
+fn tensor_from_series(
+    mut single_data_series: Vec<f32>,
+    batch_size: u32,
+    history_len: u32,
+) -> Tensor<f32> {
+    fit_to_history_len(&mut single_data_series, history_len);
+    // Models without a dynamic batch axis want 16 batches as inputs.
+    // Since we only have the one, we just repeat that 16 times.
+    let all_data_series = single_data_series.repeat(batch_size as usize);
+    let dims = vec![batch_size, history_len, 1];
+
+    Tensor::new(all_data_series, dims)
+}
+
+// This function forces the length of the series to the length
+// required by the model (128 values for the included model), using the
+// configured strategies (see fit.rs). With the strict feature,
+// forecasts over HTTP check that exactly that many values have been
+// sent and return an error otherwise (see `check_history_len`).
+fn fit_to_history_len(series: &mut Vec<f32>, history_len: u32) {
+    if series.len() != history_len as usize {
+        fit::Fit::load().apply(series, history_len);
+    }
+}
+
+// Fails unless the series of the window has exactly `history_len`
+// values, so that it is not fitted silently. The error tells how it
+// would be fitted, and how much history the client must send instead.
+#[cfg(feature = "strict")]
+#[cfg_attr(not(any(feature = "forecaster", feature = "http")), allow(dead_code))]
+fn check_history_len(
+    input: &interface::DataWindow,
+    fit: &fit::Fit,
+    history_len: u32,
+) -> Result<(), error::Error> {
+    let len = series_from_data_window(input).len();
+    match fit.adjustment(len, history_len) {
+        None => Ok(()),
+        Some(adjustment) => Err(error::Error::WindowLength(error::WindowLength {
+            window: None,
+            len,
+            required: history_len,
+            adjustment,
+            cadence: window_cadence(input),
+        })),
+    }
+}
+
+// The median interval between the timestamps of the window, i.e. the
+// resolution the client sends its data at
+#[cfg(feature = "strict")]
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+fn window_cadence(input: &interface::DataWindow) -> Option<chrono::TimeDelta> {
+    let timestamps: Vec<_> = numeric_data_points(input)
         .into_iter()
-        .filter_map(|data_point| match data_point.value {
-            interface::Value::Number(num) => Some(num),
-            // We simply ignore all string values, a better way would
-            // be to return an error
-            interface::Value::String(_) => None,
+        .filter_map(|(data_point, _)| data_point.timestamp)
+        .collect();
+    let mut intervals: Vec<_> = timestamps
+        .windows(2)
+        .map(|w| w[1] - w[0])
+        .filter(|interval| *interval > chrono::TimeDelta::zero())
+        .collect();
+    intervals.sort();
+    intervals.get(intervals.len() / 2).copied()
+}
+
+// This function returns the data points of the window that have a
+// numeric value (together with that value) in chronological order.
+fn numeric_data_points(input: &interface::DataWindow) -> Vec<(&interface::DataPoint, f32)> {
+    partition_data_points(input).0
+}
+
+// Like `numeric_data_points`, but also returns the keys of the data
+// points that are dropped, since their values are not numbers. They
+// are traced once per forecast (see `HttpHandler::forecast_values`).
+fn partition_data_points(
+    input: &interface::DataWindow,
+) -> (Vec<(&interface::DataPoint, f32)>, Vec<String>) {
+    // We need to make sure that the data is chronologically ordered
+    let mut sorted_data_points: Vec<_> = input.data.iter().collect();
+    sorted_data_points.sort_by_key(|(_, data_point)| data_point.timestamp);
+
+    // String values are parsed as numbers in the configured format
+    // (see lenient.rs)
+    #[cfg(feature = "lenient")]
+    let number_format = lenient::NumberFormat::load();
+
+    let mut dropped = Vec::new();
+    let data_points = sorted_data_points
+        .into_iter()
+        .filter_map(|(key, data_point)| {
+            let num = match &data_point.value {
+                interface::Value::Number(num) => Some(*num),
+                #[cfg(feature = "lenient")]
+                interface::Value::String(string) => number_format.parse(string),
+                // We simply ignore all string values. With the strict
+                // feature, windows with string values are rejected when
+                // they are parsed (see `check_values`).
+                #[cfg(not(feature = "lenient"))]
+                interface::Value::String(_) => None,
+            };
+            if num.is_none() {
+                dropped.push(key.clone());
+            }
+            num.map(|num| (data_point, num))
         })
         .collect();
+    (data_points, dropped)
+}
 
-    // No we force the length of the series to the batch size required
-    // by the model. This strips it of at the end (discarding the most
-    // recent values), a better way would probably be to strip of the
-    // oldest values or just check that exactly 128 values have been
-    // sent and return an error otherwise.
-    single_data_series.resize(HISTORY_LEN as usize, 0f32);
-    // The model wants 16 batches as inputs. Since we only have the
-    // one, we just repeat that 16 times.
-    let all_data_series = single_data_series.repeat(NUM_BATCHES as usize);
-    let dims = vec![NUM_BATCHES, HISTORY_LEN, 1];
+// Fails with the data points whose values are not numbers (or, with the
+// lenient feature, cannot be parsed as one), which `numeric_data_points`
+// would drop from the series
+#[cfg(feature = "strict")]
+fn check_values(input: &interface::DataWindow) -> Result<(), error::Error> {
+    #[cfg(feature = "lenient")]
+    let number_format = lenient::NumberFormat::load();
 
-    Ok(Tensor::new(all_data_series, dims))
+    let mut invalid: Vec<_> = input
+        .data
+        .iter()
+        .filter_map(|(key, data_point)| match &data_point.value {
+            interface::Value::Number(_) => None,
+            #[cfg(feature = "lenient")]
+            interface::Value::String(string) if number_format.parse(string).is_some() => None,
+            interface::Value::String(string) => Some(error::InvalidValue {
+                key: key.clone(),
+                timestamp: data_point.timestamp,
+                value: string.clone(),
+            }),
+        })
+        .collect();
+    if invalid.is_empty() {
+        return Ok(());
+    }
+    invalid.sort_by(|a, b| (a.timestamp, &a.key).cmp(&(b.timestamp, &b.key)));
+    Err(error::Error::InvalidValues(invalid))
 }
 
-// This function takes the tensor inferred by the model and converts
-// it into data that can be returned
-fn inference_result_from_tensor(
-    tensor: &Tensor<f32>,
-) -> Result<interface::InferenceResult, ErrorCode> {
-    let predictions: &[[f32; PREDICTION_LEN as usize]; NUM_BATCHES as usize] = tensor.try_into()?;
+// This function takes the values predicted by the model and converts
+// them into data that can be returned. The model simply continues the
+// time step of the window, so the values get the timestamps calculated
+// by `forecast_timestamps`, if the window has them.
+fn inference_result_from_values(
+    values: Vec<f32>,
+    timestamps: Option<Vec<DateTime<Utc>>>,
+) -> interface::InferenceResult {
+    let timestamps = timestamps.unwrap_or_default();
+    let data_points = values
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| predicted_data_point(value, timestamps.get(i).copied()))
+        .collect();
+
+    interface::InferenceResult::PredictedValues(data_points)
+}
 
-    // We only look at the first of the 16 batches
-    let data_points = predictions[0]
+// A predicted value as data point, also for streamed forecasts (see
+// ndjson.rs)
+fn predicted_data_point(value: f32, timestamp: Option<DateTime<Utc>>) -> interface::DataPoint {
+    interface::DataPoint {
+        quality: None,
+        value: interface::Value::Number(value),
+        timestamp,
+    }
+}
+
+// This function calculates the timestamps of the next `horizon` values
+// after the window, by continuing its time step from the last data
+// point. The time step is the median interval between the data points,
+// so that a single late or missing value does not throw it off.
+// Returns `None` if there are not enough increasing timestamps for
+// this.
+fn forecast_timestamps(input: &interface::DataWindow, horizon: u32) -> Option<Vec<DateTime<Utc>>> {
+    let timestamps: Vec<_> = numeric_data_points(input)
         .into_iter()
-        .map(|value| interface::DataPoint {
-            quality: None,
-            value: interface::Value::Number(value),
-            // Instead of returning no timestamp, it would be possible
-            // to calculate them based on the most recent timestamp in
-            // the equidistant input data, since the model simply
-            // continues the same time step length in its predictions.
-            timestamp: None,
-        })
+        .filter_map(|(data_point, _)| data_point.timestamp)
         .collect();
+    let last = *timestamps.last()?;
+
+    let mut steps: Vec<_> = timestamps
+        .windows(2)
+        .map(|w| w[1] - w[0])
+        .filter(|step| *step > TimeDelta::zero())
+        .collect();
+    steps.sort();
+    let step = *steps.get(steps.len() / 2)?;
+    Some((1..=horizon as i32).map(|i| last + step * i).collect())
+}
+
+// This function extracts the `prediction_len` predicted values from
+// the output tensor of the model, which has `batch_size` batches like
+// its input (see `tensor_from_series`).
+fn predicted_values(
+    tensor: &Tensor<f32>,
+    batch_size: u32,
+    prediction_len: u32,
+) -> Result<Vec<f32>, ErrorCode> {
+    let predictions = output_rows(tensor, batch_size, prediction_len)?;
+
+    // We only look at the first of the batches, since they all contain
+    // the same series
+    Ok(predictions[0].to_vec())
+}
+
+// The start of the errors of model outputs that do not have the shape
+// the component expects. They are answered with 502 (see error.rs),
+// since the model is at fault, not the client.
+const OUTPUT_SHAPE_ERROR: &str = "Unexpected shape of the model output";
 
-    Ok(interface::InferenceResult::PredictedValues(data_points))
+// This function views the output tensor of a model with a fixed shape
+// (e.g. the embedding model) as M rows of N values. The conversion (see
+// nn.rs) only knows the tensor, so the expected shape and number of
+// values are added to its error, e.g. for a model that was exported
+// with another batch size.
+#[cfg_attr(
+    not(any(
+        feature = "anomaly",
+        feature = "audio",
+        feature = "covariates",
+        feature = "embedding",
+        feature = "generate",
+        feature = "text",
+        feature = "vision"
+    )),
+    allow(dead_code)
+)]
+fn reshape_output<const N: usize, const M: usize>(
+    tensor: &Tensor<f32>,
+) -> Result<&[[f32; N]; M], ErrorCode> {
+    tensor.try_into().map_err(|e| {
+        ErrorCode::InternalError(Some(format!(
+            "{OUTPUT_SHAPE_ERROR}: expected [{M}, {N}] ({} values): {}",
+            M * N,
+            error::Error::Internal(e)
+        )))
+    })
+}
+
+// Like `reshape_output`, but for outputs whose shape is only known at
+// runtime, e.g. the configured shape of the forecasting model (see
+// `Shape` in model.rs)
+fn output_rows(tensor: &Tensor<f32>, rows: u32, len: u32) -> Result<Vec<&[f32]>, ErrorCode> {
+    tensor.rows(rows as usize, len as usize).map_err(|e| {
+        ErrorCode::InternalError(Some(format!(
+            "{OUTPUT_SHAPE_ERROR}: expected [{rows}, {len}] ({} values): {}",
+            rows * len,
+            error::Error::Internal(e)
+        )))
+    })
+}
+
+// Matches the series id against a pattern with `*` wildcards, e.g. in
+// the routing rules (see routing.rs)
+#[cfg(any(feature = "prune", feature = "routing"))]
+fn series_matches(pattern: &str, series: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let series = series.as_bytes();
+    let (mut p, mut s) = (0, 0);
+    // The position of the last `*` in the pattern and the position in
+    // the series it was matched at, to backtrack to if the rest of the
+    // pattern does not match
+    let mut star = None;
+
+    while s < series.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, s));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == series[s] {
+            p += 1;
+            s += 1;
+        } else if let Some((star_p, star_s)) = star {
+            // Let the `*` match one more character
+            p = star_p + 1;
+            s = star_s + 1;
+            star = Some((star_p, star_s + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(any(feature = "prune", feature = "routing"))]
+    #[test]
+    fn matches_series_against_patterns() {
+        use super::series_matches;
+
+        assert!(series_matches("boiler-1", "boiler-1"));
+        assert!(!series_matches("boiler-1", "boiler-10"));
+        assert!(series_matches("boiler-*", "boiler-10"));
+        assert!(series_matches("boiler-*", "boiler-"));
+        assert!(!series_matches("boiler-*", "chiller-1"));
+        assert!(series_matches("*-1", "plant/boiler-1"));
+        assert!(series_matches("*", ""));
+        assert!(series_matches("*", "anything"));
+        // A `*` must backtrack when the rest of the pattern fails
+        assert!(series_matches("*a*b", "aaxab"));
+        assert!(!series_matches("*a*b", "aaxa"));
+        assert!(series_matches("b**r", "boiler"));
+        assert!(!series_matches("", "boiler"));
+        assert!(!series_matches("boiler", ""));
+    }
 }
@@ -0,0 +1,67 @@
+// This module enforces per-model resource caps, so that one heavyweight
+// model cannot starve the others on the same device. The caps are read
+// from the `model-limits` config (see config.rs), by the id of the model
+// (see admin.rs):
+//
+// { "energy": { "max_size_mb": 200, "max_inference_seconds_per_minute": 20 } }
+//
+// wasi-nn does not tell how much memory a model takes, so the size of
+// its files stands in for it: Larger models are not loaded at all. The
+// inference time of a model is summed up per minute (see admin.rs), and
+// a model that used up its time is not run again until the minute is
+// over. Models without caps are not limited.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Deserialize;
+use wasi::http::types::ErrorCode;
+
+use crate::config;
+
+const CONFIG: &str = "model-limits";
+
+// The inference time is limited per window of this length
+pub const WINDOW: TimeDelta = TimeDelta::minutes(1);
+
+#[derive(Deserialize)]
+struct Limits {
+    max_size_mb: Option<f64>,
+    max_inference_seconds_per_minute: Option<f64>,
+}
+
+fn limits(id: &str) -> Result<Option<Limits>, ErrorCode> {
+    let mut limits: HashMap<String, Limits> = config::load(CONFIG)
+        .map_err(|e| ErrorCode::InternalError(Some(e.to_string())))?
+        .unwrap_or_default();
+    Ok(limits.remove(id))
+}
+
+// Fails if the files of the model are larger than its cap
+pub fn check_size(id: &str, size_bytes: Option<u64>) -> Result<(), ErrorCode> {
+    let max = limits(id)?.and_then(|limits| limits.max_size_mb);
+    match (size_bytes, max) {
+        (Some(size), Some(max)) if size as f64 > max * 1_000_000.0 => {
+            Err(ErrorCode::InternalError(Some(format!(
+                "Model {id} is larger than its limit of {max} MB"
+            ))))
+        }
+        _ => Ok(()),
+    }
+}
+
+// The reason why the model must not run now, if it used up its
+// inference time in the current window
+pub fn time_exceeded(
+    id: &str,
+    window_start: Option<DateTime<Utc>>,
+    window_latency_ms: f64,
+    now: DateTime<Utc>,
+) -> Result<Option<String>, ErrorCode> {
+    let Some(max) = limits(id)?.and_then(|limits| limits.max_inference_seconds_per_minute) else {
+        return Ok(None);
+    };
+    let in_window = window_start.is_some_and(|start| now - start < WINDOW);
+    Ok((in_window && window_latency_ms >= max * 1000.0)
+        .then(|| format!("{max} s of inference time per minute")))
+}
@@ -0,0 +1,146 @@
+// This module writes structured log lines to the log of the host through
+// wasi:logging, so that requests and failures can be followed on hosts
+// that collect logs (e.g. wasmCloud). Without it, the only trace of a
+// failure is the error in the response and a line on stderr, which many
+// hosts discard.
+//
+// Each line is a list of `key=value` pairs (logfmt), and its context
+// names what it is about:
+//
+// request  event=start method=POST path=/
+// request  event=error status=422 message="The window has 100 data points, ..."
+// model    event=load files=models/model.onnx duration_ms=812.4 ok=true
+// model    event=inference duration_ms=35.1
+// request  event=end method=POST path=/ status=200 duration_ms=851.0
+//
+// Failed requests are logged as warnings if the client is to blame
+// (4xx) and as errors otherwise. Model loads and inferences are also
+// logged in the other worlds (e.g. batch mode). The query string is not logged, since
+// it may contain credentials (see http/auth.rs).
+
+use std::time::Duration;
+
+use wasi::http::types::ErrorCode;
+
+use crate::error::Error;
+
+mod bindings {
+    wit_bindgen::generate!({
+        path: "wit",
+        world: "logger",
+        generate_all,
+    });
+}
+
+use bindings::wasi::logging::logging::{log, Level};
+
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+pub fn request_start(method: &str, path: &str) {
+    write(
+        Level::Info,
+        "request",
+        &[("event", "start"), ("method", method), ("path", path)],
+    );
+}
+
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+pub fn request_end(method: &str, path: &str, status: u16, duration: Duration) {
+    write(
+        Level::Info,
+        "request",
+        &[
+            ("event", "end"),
+            ("method", method),
+            ("path", path),
+            ("status", &status.to_string()),
+            ("duration_ms", &milliseconds(duration)),
+        ],
+    );
+}
+
+// Logs the error a request failed with, e.g. an invalid window
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+pub fn request_error(error: &Error) {
+    let status = error.status();
+    let level = if status < 500 {
+        Level::Warn
+    } else {
+        Level::Error
+    };
+    write(
+        level,
+        "request",
+        &[
+            ("event", "error"),
+            ("status", &status.to_string()),
+            ("message", &error.to_string()),
+        ],
+    );
+}
+
+// Logs the outcome of loading a model from its files (see `load_model`
+// in lib.rs)
+pub fn model_load(files: &[String], duration: Duration, error: Option<&ErrorCode>) {
+    let files = files.join(",");
+    let duration = milliseconds(duration);
+    match error {
+        None => write(
+            Level::Info,
+            "model",
+            &[
+                ("event", "load"),
+                ("files", &files),
+                ("duration_ms", &duration),
+                ("ok", "true"),
+            ],
+        ),
+        Some(error) => write(
+            Level::Error,
+            "model",
+            &[
+                ("event", "load"),
+                ("files", &files),
+                ("duration_ms", &duration),
+                ("ok", "false"),
+                ("error", &Error::from(error.clone()).to_string()),
+            ],
+        ),
+    }
+}
+
+pub fn inference(duration: Duration) {
+    write(
+        Level::Debug,
+        "model",
+        &[
+            ("event", "inference"),
+            ("duration_ms", &milliseconds(duration)),
+        ],
+    );
+}
+
+// Writes the fields as logfmt. Values with spaces, quotes or equals
+// signs are quoted. Lines logged while a request is handled end with
+// its trace and span (see tracecontext.rs).
+fn write(level: Level, context: &str, fields: &[(&str, &str)]) {
+    #[cfg_attr(not(feature = "tracecontext"), allow(unused_mut))]
+    let mut message: Vec<_> = fields
+        .iter()
+        .map(|(key, value)| {
+            if value.is_empty() || value.contains([' ', '"', '=', '\n']) {
+                format!("{key}={value:?}")
+            } else {
+                format!("{key}={value}")
+            }
+        })
+        .collect();
+    #[cfg(feature = "tracecontext")]
+    if let Some((trace_id, span_id)) = crate::tracecontext::ids() {
+        message.push(format!("trace_id={trace_id} span_id={span_id}"));
+    }
+    log(level, context, &message.join(" "));
+}
+
+fn milliseconds(duration: Duration) -> String {
+    format!("{:.1}", duration.as_secs_f64() * 1000.0)
+}
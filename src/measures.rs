@@ -0,0 +1,25 @@
+// Measures for the error of a forecast compared to the values that
+// actually occurred, used by backtesting and accuracy tracking
+
+// Mean absolute error
+pub fn mae(forecast: &[f32], actual: &[f32]) -> f32 {
+    let sum: f32 = forecast
+        .iter()
+        .zip(actual)
+        .map(|(f, a)| (f - a).abs())
+        .sum();
+    sum / actual.len() as f32
+}
+
+// Mean absolute percentage error (in percent). Actual values of zero
+// are skipped, since the percentage error is not defined for them.
+pub fn mape(forecast: &[f32], actual: &[f32]) -> Option<f32> {
+    let errors: Vec<_> = forecast
+        .iter()
+        .zip(actual)
+        .filter(|(_, a)| **a != 0.0)
+        .map(|(f, a)| ((f - a) / a).abs())
+        .collect();
+
+    (!errors.is_empty()).then(|| errors.iter().sum::<f32>() / errors.len() as f32 * 100.0)
+}
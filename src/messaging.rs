@@ -0,0 +1,103 @@
+// This module exports the wasi:messaging incoming handler, which lets
+// the component consume data windows from a message broker (e.g. NATS
+// or Kafka, connected by the host) instead of HTTP requests. The host
+// subscribes the component to the input topics, and every message is
+// expected to contain a JSON data window. The inference result is
+// published to OUTPUT_TOPIC in the same format as the HTTP response.
+//
+// The wasi crate has no bindings for wasi:messaging (which is still a
+// draft), so we generate them from the WIT files in the wit directory.
+
+use wasi_nn_demo_lib::http::RequestHandler;
+
+use crate::{error::Error, json, with_handler, Component};
+
+mod bindings {
+    wit_bindgen::generate!({
+        path: "wit",
+        world: "messaging-handler",
+        generate_all,
+    });
+}
+
+use bindings::{
+    exports::wasi::messaging::incoming_handler::Guest,
+    wasi::messaging::{
+        producer,
+        types::{Client, Error as MessagingError, Message},
+    },
+};
+
+bindings::export!(Component with_types_in bindings);
+
+// The name under which the host has configured the broker connection
+const CLIENT_NAME: &str = "default";
+// The topic the inference results are published to
+const OUTPUT_TOPIC: &str = "inference-results";
+
+impl Guest for Component {
+    fn handle(message: Message) -> Result<(), MessagingError> {
+        let error = |e: Error| MessagingError::Other(e.to_string());
+
+        // The input has the same format as the body of a HTTP request,
+        // or that of an IoT Hub message (see iothub.rs)
+        let data = message.data();
+        #[cfg(not(feature = "iothub"))]
+        let input = json::parse_data_window(&data).map_err(error)?;
+        #[cfg(feature = "iothub")]
+        let (input, envelope) = crate::iothub::parse_message(&data).map_err(error)?;
+
+        let output = with_handler(|handler| Ok(handler.handle_data(input)?))
+            .and_then(|result| json::inference_result_to_vec(&result))
+            .map_err(error)?;
+
+        // The series can be identified in the metadata of the message,
+        // in which case the forecast is also published for it (see
+        // publish.rs)
+        #[cfg(feature = "publish")]
+        if let Some((_, series)) = message
+            .metadata()
+            .unwrap_or_default()
+            .into_iter()
+            .find(|(key, _)| key == "series")
+        {
+            crate::publish::publish_forecast(&series, &output);
+        }
+
+        #[cfg(feature = "iothub")]
+        let output = crate::iothub::message_to_vec(&envelope, &output).map_err(error)?;
+
+        // This allows consumers of the output topic to tell which
+        // input the result belongs to
+        let metadata = message
+            .topic()
+            .map(|topic| vec![("source-topic".to_string(), topic)])
+            .unwrap_or_default();
+        send(OUTPUT_TOPIC, &output, &metadata)
+    }
+}
+
+// Publishes JSON data with the given metadata to the topic
+pub fn send(topic: &str, data: &[u8], metadata: &[(String, String)]) -> Result<(), MessagingError> {
+    let message = Message::new(data);
+    message.set_content_type("application/json");
+    for (key, value) in metadata {
+        message.add_metadata(key, value);
+    }
+
+    let client = Client::connect(CLIENT_NAME)?;
+    producer::send(&client, &topic.to_string(), &message)
+}
+
+// Whether the broker connection can be opened, probed once per instance
+// (see capabilities.rs)
+#[cfg(feature = "capabilities")]
+pub fn probe() -> bool {
+    static AVAILABLE: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+
+    *AVAILABLE.get_or_init(|| {
+        Client::connect(CLIENT_NAME)
+            .inspect_err(|e| eprintln!("Broker not available, not publishing forecasts: {e:?}"))
+            .is_ok()
+    })
+}
@@ -0,0 +1,420 @@
+// The parameters of the forecasting model that can be changed without
+// recompiling the component. By default, these are the constants in
+// lib.rs. With the `model-config` feature, they are read from the
+// optional `model` config (see config.rs), and each of them can also be
+// overridden by an environment variable (`--env MODEL_FILES=...`):
+//
+// { "files": ["models/model.onnx"], "input_tensor": "l_past_values_",
+//   "output_tensor": "add_8", "dynamic_batch": false,
+//   "num_batches": 16, "history_len": 128, "prediction_len": 24 }
+//
+// The variables are MODEL_FILES (comma separated), MODEL_INPUT_TENSOR,
+// MODEL_OUTPUT_TENSOR, MODEL_VARIANCE_TENSOR, MODEL_DYNAMIC_BATCH,
+// MODEL_NUM_BATCHES, MODEL_HISTORY_LEN and MODEL_PREDICTION_LEN.
+// Models with a second output for the variance of the forecast name it
+// in `variance_tensor` (see variance.rs). The shape of the tensors is
+// the same for all models the component serves, since the series are
+// fitted to it before the model is chosen (see `Shape`). Models that
+// were exported with a dynamic batch axis (`dynamic_batch`) are given a
+// single series instead of `num_batches` copies of it (see
+// `tensor_from_series` in lib.rs). With the `introspect` feature, the
+// names and the batch axis that are not configured are read from the
+// model file instead (see onnx.rs), and the configured ones and the
+// shape are checked against it. Otherwise, wasi-nn cannot tell us
+// anything about the model, so they must be configured.
+
+use wasi::http::types::ErrorCode;
+
+use crate::{
+    HISTORY_LEN, INPUT_TENSOR_NAME, MAX_STEPS, MODEL_FILES, NUM_BATCHES, OUTPUT_TENSOR_NAME,
+    PREDICTION_LEN,
+};
+
+// The shape of the tensors: The model is given `num_batches` series of
+// `history_len` values, and predicts `prediction_len` values for each
+// of them. By default, it is the shape of the included model.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shape {
+    pub num_batches: u32,
+    pub history_len: u32,
+    pub prediction_len: u32,
+}
+
+impl Default for Shape {
+    fn default() -> Self {
+        Self {
+            num_batches: NUM_BATCHES,
+            history_len: HISTORY_LEN,
+            prediction_len: PREDICTION_LEN,
+        }
+    }
+}
+
+impl Shape {
+    // Clients can request forecasts longer than `prediction_len`, up to
+    // this maximum (see `HttpHandler::forecast` in lib.rs)
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub fn max_horizon(&self) -> u32 {
+        MAX_STEPS * self.prediction_len
+    }
+}
+
+pub struct ModelSpec {
+    pub files: Vec<String>,
+    pub input_tensor: String,
+    pub output_tensor: String,
+    // The output with the variance of each forecast value, if the model
+    // has one
+    #[cfg_attr(not(feature = "variance"), allow(dead_code))]
+    pub variance_tensor: Option<String>,
+    // Whether the batch axis of the tensors is dynamic
+    pub dynamic_batch: bool,
+    pub shape: Shape,
+}
+
+// The parameters that were set explicitly, in the config, the
+// environment or the model registry (see registry.rs)
+#[derive(Default)]
+pub struct Overrides {
+    pub input_tensor: Option<String>,
+    pub output_tensor: Option<String>,
+    pub variance_tensor: Option<String>,
+    pub dynamic_batch: Option<bool>,
+}
+
+impl ModelSpec {
+    // The spec of the model in the files. The parameters that are not
+    // overridden are taken from the model if its metadata is known, and
+    // are those of the included model otherwise.
+    pub fn resolve(
+        files: Vec<String>,
+        shape: Shape,
+        overrides: Overrides,
+    ) -> Result<Self, ErrorCode> {
+        #[cfg(feature = "introspect")]
+        if let Some(metadata) = crate::onnx::metadata(&files) {
+            return introspected(files, shape, overrides, &metadata);
+        }
+        Ok(Self {
+            files,
+            input_tensor: overrides
+                .input_tensor
+                .unwrap_or_else(|| INPUT_TENSOR_NAME.to_string()),
+            output_tensor: overrides
+                .output_tensor
+                .unwrap_or_else(|| OUTPUT_TENSOR_NAME.to_string()),
+            variance_tensor: overrides.variance_tensor,
+            dynamic_batch: overrides.dynamic_batch.unwrap_or(false),
+            shape,
+        })
+    }
+
+    // The number of series the model is given per inference
+    pub fn batch_size(&self) -> u32 {
+        if self.dynamic_batch {
+            1
+        } else {
+            self.shape.num_batches
+        }
+    }
+}
+
+// The spec of a model whose inputs and outputs are known. A tensor that
+// is not overridden must be the only input (or output) of the model,
+// one that is must exist. The shapes must match the tensors that the
+// component passes to the model and expects from it, except for axes
+// that are dynamic or unknown.
+#[cfg(feature = "introspect")]
+fn introspected(
+    files: Vec<String>,
+    shape: Shape,
+    overrides: Overrides,
+    metadata: &crate::onnx::Metadata,
+) -> Result<ModelSpec, ErrorCode> {
+    let Shape {
+        num_batches,
+        history_len,
+        prediction_len,
+    } = shape;
+    let error = |message: String| {
+        ErrorCode::InternalError(Some(format!("Model {}: {message}", files.join(","))))
+    };
+    let input = match &overrides.input_tensor {
+        Some(name) => metadata
+            .input(name)
+            .ok_or_else(|| error(format!("No input tensor {name}")))?,
+        None => match metadata.inputs.as_slice() {
+            [input] => input,
+            inputs => {
+                return Err(error(format!(
+                    "{} input tensors, choose one with input_tensor",
+                    inputs.len()
+                )))
+            }
+        },
+    };
+    let variance = overrides
+        .variance_tensor
+        .as_deref()
+        .map(|name| {
+            metadata
+                .output(name)
+                .ok_or_else(|| error(format!("No output tensor {name}")))
+        })
+        .transpose()?;
+    // The variance is not a candidate for the forecast
+    let candidates: Vec<_> = metadata
+        .outputs
+        .iter()
+        .filter(|output| variance.is_none_or(|variance| variance.name != output.name))
+        .collect();
+    let output = match &overrides.output_tensor {
+        Some(name) => metadata
+            .output(name)
+            .ok_or_else(|| error(format!("No output tensor {name}")))?,
+        None => match candidates[..] {
+            [output] => output,
+            _ => {
+                return Err(error(format!(
+                    "{} output tensors, choose one with output_tensor",
+                    candidates.len()
+                )))
+            }
+        },
+    };
+
+    // The series is passed to the model as f32, so the input must be
+    // float. Outputs of other types (e.g. doubles or integers) are
+    // converted to f32 (see nn.rs), unless wasi-nn cannot represent
+    // them. Tensors of unknown type are not checked.
+    if input
+        .elem_type
+        .is_some_and(|elem_type| elem_type != crate::onnx::FLOAT)
+    {
+        return Err(error(format!(
+            "Input tensor {} has type {}, expected float",
+            input.name,
+            input.elem_type_string()
+        )));
+    }
+    for output in std::iter::once(output).chain(variance) {
+        if output
+            .elem_type
+            .is_some_and(|elem_type| !crate::onnx::OUTPUT_TYPES.contains(&elem_type))
+        {
+            return Err(error(format!(
+                "Output tensor {} has type {}, expected a number",
+                output.name,
+                output.elem_type_string()
+            )));
+        }
+    }
+
+    // The input is a batch of series of `history_len` values, and the
+    // output a batch of `prediction_len` values each. Tensors without a
+    // shape are not checked.
+    let fits =
+        |size: Option<u64>, expected: u32| size.is_none_or(|size| size == u64::from(expected));
+    let batch = match input.shape[..] {
+        [] => None,
+        [batch, history, features] if fits(history, history_len) && fits(features, 1) => batch,
+        _ => {
+            return Err(error(format!(
+                "Input tensor {} has shape {}, expected [{num_batches}, {history_len}, 1]",
+                input.name,
+                input.shape_string()
+            )))
+        }
+    };
+    for output in std::iter::once(output).chain(variance) {
+        if !output.shape.is_empty()
+            && !output
+                .shape
+                .get(1)
+                .is_some_and(|size| fits(*size, prediction_len))
+        {
+            return Err(error(format!(
+                "Output tensor {} has shape {}, expected [{num_batches}, {prediction_len}, ...]",
+                output.name,
+                output.shape_string()
+            )));
+        }
+    }
+
+    // A dynamic batch axis, or one of size 1, takes a single series
+    let dynamic_batch = match (batch, overrides.dynamic_batch) {
+        (Some(size), _) if size != 1 && size != u64::from(num_batches) => {
+            return Err(error(format!(
+                "Input tensor {} has a batch of {size}, expected {num_batches}",
+                input.name
+            )))
+        }
+        (Some(size), Some(dynamic_batch)) if dynamic_batch != (size == 1) => {
+            return Err(error(format!(
+                "dynamic_batch is {dynamic_batch}, but input tensor {} has a batch of {size}",
+                input.name
+            )))
+        }
+        (_, Some(dynamic_batch)) => dynamic_batch,
+        (Some(size), None) => size == 1,
+        (None, None) => !input.shape.is_empty(),
+    };
+
+    Ok(ModelSpec {
+        input_tensor: input.name.clone(),
+        output_tensor: output.name.clone(),
+        variance_tensor: variance.map(|variance| variance.name.clone()),
+        dynamic_batch,
+        files,
+        shape,
+    })
+}
+
+#[cfg(not(feature = "model-config"))]
+pub fn spec() -> Result<ModelSpec, ErrorCode> {
+    ModelSpec::resolve(
+        MODEL_FILES.map(String::from).to_vec(),
+        Shape::default(),
+        Overrides::default(),
+    )
+}
+
+#[cfg(not(feature = "model-config"))]
+pub fn shape() -> Result<Shape, ErrorCode> {
+    Ok(Shape::default())
+}
+
+// The shape of the selected model, or the configured one if no model
+// was selected (see registry.rs)
+pub fn shape_of(model: Option<&ModelSpec>) -> Result<Shape, ErrorCode> {
+    model.map_or_else(shape, |model| Ok(model.shape))
+}
+
+#[cfg(feature = "model-config")]
+#[derive(Default, serde::Deserialize)]
+struct Config {
+    files: Option<Vec<String>>,
+    input_tensor: Option<String>,
+    output_tensor: Option<String>,
+    variance_tensor: Option<String>,
+    dynamic_batch: Option<bool>,
+    num_batches: Option<u32>,
+    history_len: Option<u32>,
+    prediction_len: Option<u32>,
+}
+
+#[cfg(feature = "model-config")]
+fn config() -> Result<Config, ErrorCode> {
+    Ok(crate::config::load("model")
+        .map_err(|e| ErrorCode::InternalError(Some(e.to_string())))?
+        .unwrap_or_default())
+}
+
+// The value of an environment variable, unless it is not set or empty
+#[cfg(feature = "model-config")]
+fn variable(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+// The value of an environment variable parsed as `T`, which is
+// described by `expected` if it cannot be parsed
+#[cfg(feature = "model-config")]
+fn parsed<T: std::str::FromStr>(name: &str, expected: &str) -> Result<Option<T>, ErrorCode> {
+    variable(name)
+        .map(|value| {
+            value.parse().map_err(|_| {
+                ErrorCode::InternalError(Some(format!(
+                    "Invalid {name} {value}, must be {expected}"
+                )))
+            })
+        })
+        .transpose()
+}
+
+#[cfg(feature = "model-config")]
+pub fn spec() -> Result<ModelSpec, ErrorCode> {
+    let config = config()?;
+    let shape = configured_shape(&config)?;
+
+    let files = variable("MODEL_FILES")
+        .map(|files| {
+            files
+                .split(',')
+                .map(|file| file.trim().to_string())
+                .collect()
+        })
+        .or(config.files)
+        .unwrap_or_else(|| MODEL_FILES.map(String::from).to_vec());
+    let dynamic_batch = parsed("MODEL_DYNAMIC_BATCH", "true or false")?;
+    let overrides = Overrides {
+        input_tensor: variable("MODEL_INPUT_TENSOR").or(config.input_tensor),
+        output_tensor: variable("MODEL_OUTPUT_TENSOR").or(config.output_tensor),
+        variance_tensor: variable("MODEL_VARIANCE_TENSOR").or(config.variance_tensor),
+        dynamic_batch: dynamic_batch.or(config.dynamic_batch),
+    };
+    ModelSpec::resolve(files, shape, overrides)
+}
+
+// The shape of the tensors, without resolving the rest of the spec, for
+// the parts of the component that only need to know the length of the
+// series (e.g. ingest.rs)
+#[cfg(feature = "model-config")]
+pub fn shape() -> Result<Shape, ErrorCode> {
+    configured_shape(&config()?)
+}
+
+#[cfg(feature = "model-config")]
+fn configured_shape(config: &Config) -> Result<Shape, ErrorCode> {
+    let default = Shape::default();
+    let size = |variable: &str, configured: Option<u32>, default: u32| match parsed(
+        variable,
+        "a positive number",
+    )?
+    .or(configured)
+    {
+        Some(0) => Err(ErrorCode::InternalError(Some(format!(
+            "Invalid model shape: {variable} must be a positive number"
+        )))),
+        size => Ok(size.unwrap_or(default)),
+    };
+    Ok(Shape {
+        num_batches: size("MODEL_NUM_BATCHES", config.num_batches, default.num_batches)?,
+        history_len: size("MODEL_HISTORY_LEN", config.history_len, default.history_len)?,
+        prediction_len: size(
+            "MODEL_PREDICTION_LEN",
+            config.prediction_len,
+            default.prediction_len,
+        )?,
+    })
+}
+
+// The checksums of the model files read so far, `None` for files that
+// cannot be read (e.g. models the host loads by name). They are kept
+// until the models are unloaded (see `unload_models` in lib.rs).
+#[cfg(any(feature = "latest", feature = "timing"))]
+static CHECKSUMS: std::sync::Mutex<std::collections::BTreeMap<String, Option<String>>> =
+    std::sync::Mutex::new(std::collections::BTreeMap::new());
+
+// The SHA-256 of the model file, computed once, which identifies the
+// version of a model
+#[cfg(any(feature = "latest", feature = "timing"))]
+pub fn checksum(path: &str) -> Option<String> {
+    use crate::sha256::{hex, sha256};
+
+    let mut checksums = CHECKSUMS.lock().ok()?;
+    checksums
+        .entry(path.to_string())
+        .or_insert_with(|| {
+            std::fs::read(path)
+                .ok()
+                .map(|contents| hex(&sha256(&contents)))
+        })
+        .clone()
+}
+
+#[cfg(any(feature = "latest", feature = "timing"))]
+pub fn forget_checksums() {
+    if let Ok(mut checksums) = CHECKSUMS.lock() {
+        checksums.clear();
+    }
+}
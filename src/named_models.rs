@@ -0,0 +1,30 @@
+// Some runtimes (e.g. WasmEdge, or wasmtime with `-S nn-graph`)
+// preload models and register them under a name, which the component
+// can load with `load-by-name` of wasi-nn. The model files then do not
+// have to be part of the filesystem of the component. The names are
+// read from the `named-models` config (see config.rs), which maps the
+// files of a model to the name it is registered under:
+//
+// { "models/model.onnx": "forecast" }
+//
+// Models that are not in the config are still loaded from their files.
+
+use std::{collections::HashMap, path::Path};
+
+use wasi::http::types::ErrorCode;
+
+use crate::config;
+
+const CONFIG: &str = "named-models";
+
+// The name of the model with the given files, if it is registered
+// under one. Only models that consist of a single file can be named.
+pub fn name<P: AsRef<Path>>(files: &[P]) -> Result<Option<String>, ErrorCode> {
+    let [file] = files else {
+        return Ok(None);
+    };
+    let mut names: HashMap<String, String> = config::load(CONFIG)
+        .map_err(|e| ErrorCode::InternalError(Some(e.to_string())))?
+        .unwrap_or_default();
+    Ok(names.remove(file.as_ref().to_string_lossy().as_ref()))
+}
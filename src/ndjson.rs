@@ -0,0 +1,58 @@
+// This module streams forecasts as newline-delimited JSON, one data
+// point per line, for clients that ask for it with `Accept:
+// application/x-ndjson`. The model runs while the response is already
+// being sent (see `Response::stream` in http.rs), and the values of
+// each of its steps are written as soon as they are predicted (see
+// `model_forecast` in lib.rs), so with long horizons, clients can
+// process the first data points while the model is still forecasting
+// the others:
+//
+// {"quality":null,"dataType":"Number","value":21.3,"timestamp":"2024-05-01T12:15:00Z"}
+// {"quality":null,"dataType":"Number","value":21.4,"timestamp":"2024-05-01T12:30:00Z"}
+//
+// Only the data points are streamed. Additional sections of the
+// response (e.g. the variance) have no line of their own and are left
+// out, and the buffered response is not built at all. Neither is the
+// forecast published or kept as the latest one, since those need the
+// whole response. If the model fails after some of its steps were
+// written, the fallback (see fallback.rs) continues after them, and
+// otherwise the error is written as the last line. Since the model runs
+// while the response is sent, the request keeps its slot in the queue
+// until the last line is written (see `Response::holding` in http.rs).
+
+use chrono::{DateTime, Utc};
+
+use crate::{error::Error, predicted_data_point};
+
+pub const MEDIA_TYPE: &str = "application/x-ndjson";
+
+// The lines of a forecast, which are produced a step at a time
+pub struct Lines {
+    timestamps: Vec<DateTime<Utc>>,
+    // The number of values that have been turned into lines
+    written: usize,
+}
+
+impl Lines {
+    // The timestamps are those of the whole forecast (see
+    // `forecast_timestamps` in lib.rs)
+    pub fn new(timestamps: Option<Vec<DateTime<Utc>>>) -> Self {
+        Self {
+            timestamps: timestamps.unwrap_or_default(),
+            written: 0,
+        }
+    }
+
+    // The lines of the next values of the forecast
+    pub fn next(&mut self, values: &[f32]) -> Result<Vec<u8>, Error> {
+        let mut lines = Vec::new();
+        for value in values {
+            let timestamp = self.timestamps.get(self.written).copied();
+            serde_json::to_writer(&mut lines, &predicted_data_point(*value, timestamp))
+                .map_err(|e| Error::internal(format!("Error serializing data point: {e}")))?;
+            lines.push(b'\n');
+            self.written += 1;
+        }
+        Ok(lines)
+    }
+}
@@ -0,0 +1,291 @@
+// The component talks to wasi-nn through its own bindings instead of
+// the ones of the demo library, since it needs parts of wasi-nn the
+// library does not offer, e.g. `load-by-name` for models that the host
+// registered under a name (see named_models.rs). The types mirror those
+// of the library, so that the rest of the component uses them the same
+// way: A `GraphBuilder` loads a `Graph` from files (or bytes), and an
+// `ExecutionContext` of the graph runs the model on named input tensors
+// and returns the requested output tensors.
+
+use std::{collections::HashMap, path::Path};
+
+use wasi::http::types::ErrorCode;
+
+mod bindings {
+    wit_bindgen::generate!({
+        path: "wit",
+        world: "nn",
+        generate_all,
+    });
+}
+
+use bindings::wasi::nn::{
+    errors,
+    graph::{self, ExecutionTarget},
+    inference::GraphExecutionContext,
+    tensor::{self, TensorType},
+};
+
+pub use graph::GraphEncoding;
+
+// Turns an error of wasi-nn into the error the component reports
+fn error(context: &str, e: errors::Error) -> ErrorCode {
+    ErrorCode::InternalError(Some(format!("{context}: {:?}: {}", e.code(), e.data())))
+}
+
+// Collects the files (or bytes) of a model, which are then loaded as a
+// graph in the given format on the cpu
+pub struct GraphBuilder {
+    encoding: GraphEncoding,
+    builders: Vec<Vec<u8>>,
+}
+
+impl Default for GraphBuilder {
+    fn default() -> Self {
+        Self {
+            encoding: GraphEncoding::Autodetect,
+            builders: Vec::new(),
+        }
+    }
+}
+
+impl GraphBuilder {
+    pub fn encoding(self, encoding: GraphEncoding) -> Self {
+        Self { encoding, ..self }
+    }
+
+    #[cfg_attr(feature = "encryption", allow(dead_code))]
+    pub fn files<P: AsRef<Path>>(
+        mut self,
+        files: impl IntoIterator<Item = P>,
+    ) -> Result<Self, ErrorCode> {
+        for file in files {
+            let file = file.as_ref();
+            let contents = std::fs::read(file).map_err(|e| {
+                ErrorCode::InternalError(Some(format!("Error reading {}: {e}", file.display())))
+            })?;
+            self.builders.push(contents);
+        }
+        Ok(self)
+    }
+
+    #[cfg_attr(not(feature = "encryption"), allow(dead_code))]
+    pub fn bytes(mut self, bytes: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        self.builders.extend(bytes);
+        self
+    }
+
+    pub fn build(self) -> Result<Graph, ErrorCode> {
+        graph::load(&self.builders, self.encoding, ExecutionTarget::Cpu)
+            .map(Graph)
+            .map_err(|e| error("Error loading model", e))
+    }
+}
+
+pub struct Graph(graph::Graph);
+
+impl Graph {
+    // Loads the model that the host registered under the name
+    #[cfg(feature = "named-models")]
+    pub fn load_by_name(name: &str) -> Result<Self, ErrorCode> {
+        graph::load_by_name(name)
+            .map(Graph)
+            .map_err(|e| error(&format!("Error loading model {name}"), e))
+    }
+
+    pub fn init_execution_context(&self) -> Result<ExecutionContext, ErrorCode> {
+        self.0
+            .init_execution_context()
+            .map(ExecutionContext)
+            .map_err(|e| error("Error creating execution context", e))
+    }
+}
+
+pub struct ExecutionContext(GraphExecutionContext);
+
+impl ExecutionContext {
+    // Runs the model on the input tensors and returns the output
+    // tensors with the given names, which must all be among those of
+    // the model
+    pub fn run<T: Element, const N: usize>(
+        &self,
+        inputs: [(&str, Tensor<T>); N],
+        outputs: &[&str],
+    ) -> Result<HashMap<String, Tensor<f32>>, ErrorCode> {
+        let inputs = inputs
+            .into_iter()
+            .map(|(name, input)| (name.to_string(), input.into_tensor()))
+            .collect::<Vec<_>>();
+        let mut results: HashMap<String, tensor::Tensor> = self
+            .0
+            .compute(inputs)
+            .map_err(|e| error("Error running model", e))?
+            .into_iter()
+            .collect();
+
+        outputs
+            .iter()
+            .map(|&name| {
+                let output = results.remove(name).ok_or_else(|| {
+                    ErrorCode::InternalError(Some(format!("Model has no output tensor {name}")))
+                })?;
+                Ok((name.to_string(), Tensor::from_tensor(name, &output)?))
+            })
+            .collect()
+    }
+}
+
+// The types of the values of tensors, with their type in wasi-nn. The
+// values are passed to wasi-nn as little-endian bytes.
+pub trait Element: Copy {
+    const TYPE: TensorType;
+    fn extend_bytes(self, bytes: &mut Vec<u8>);
+}
+
+impl Element for f32 {
+    const TYPE: TensorType = TensorType::Fp32;
+    fn extend_bytes(self, bytes: &mut Vec<u8>) {
+        bytes.extend(self.to_le_bytes());
+    }
+}
+
+impl Element for i64 {
+    const TYPE: TensorType = TensorType::I64;
+    fn extend_bytes(self, bytes: &mut Vec<u8>) {
+        bytes.extend(self.to_le_bytes());
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Tensor<T> {
+    data: Vec<T>,
+    dims: Vec<u32>,
+}
+
+impl<T> Tensor<T> {
+    pub fn new(data: Vec<T>, dims: Vec<u32>) -> Self {
+        Self { data, dims }
+    }
+}
+
+impl<T: Element> Tensor<T> {
+    fn into_tensor(self) -> tensor::Tensor {
+        let mut bytes = Vec::with_capacity(self.data.len() * size_of::<T>());
+        for value in self.data {
+            value.extend_bytes(&mut bytes);
+        }
+        tensor::Tensor::new(&self.dims, T::TYPE, &bytes)
+    }
+}
+
+impl Tensor<f32> {
+    // Reads the values of an output tensor of the model. Models may
+    // return other types than f32 (e.g. doubles or integers), which are
+    // converted, so that the rest of the component only deals with f32.
+    fn from_tensor(name: &str, tensor: &tensor::Tensor) -> Result<Self, ErrorCode> {
+        let bytes = tensor.data();
+        let data = match tensor.ty() {
+            TensorType::Fp32 => convert(&bytes, f32::from_le_bytes),
+            TensorType::Fp64 => convert(&bytes, |bytes| f64::from_le_bytes(bytes) as f32),
+            TensorType::Fp16 => convert(&bytes, |bytes| f16_to_f32(u16::from_le_bytes(bytes))),
+            TensorType::Bf16 => convert(&bytes, |bytes| {
+                f32::from_bits(u32::from(u16::from_le_bytes(bytes)) << 16)
+            }),
+            TensorType::U8 => bytes.iter().map(|&value| f32::from(value)).collect(),
+            TensorType::I32 => convert(&bytes, |bytes| i32::from_le_bytes(bytes) as f32),
+            TensorType::I64 => convert(&bytes, |bytes| i64::from_le_bytes(bytes) as f32),
+        };
+        let dims = tensor.dimensions();
+        if data.len() != dims.iter().map(|&size| size as usize).product::<usize>() {
+            return Err(ErrorCode::InternalError(Some(format!(
+                "Output tensor {name} of shape {dims:?} has {} bytes of type {:?}",
+                bytes.len(),
+                tensor.ty()
+            ))));
+        }
+        Ok(Self::new(data, dims))
+    }
+}
+
+// Converts the little-endian bytes of a tensor to f32, with N bytes per
+// value
+fn convert<const N: usize>(bytes: &[u8], value: impl Fn([u8; N]) -> f32) -> Vec<f32> {
+    bytes
+        .as_chunks()
+        .0
+        .iter()
+        .map(|&bytes| value(bytes))
+        .collect()
+}
+
+// Rust has no stable f16 yet, so half precision floats are widened by
+// hand: The exponent is rebiased from 15 to 127 and the mantissa shifted
+// from 10 to 23 bits. Subnormals are normalized, since every one of
+// them is a normal f32.
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = u32::from(half >> 15) << 31;
+    let exponent = u32::from(half >> 10) & 0x1f;
+    let mantissa = u32::from(half) & 0x3ff;
+    let bits = match (exponent, mantissa) {
+        (0, 0) => sign,
+        (0, _) => {
+            let shift = mantissa.leading_zeros() - 21;
+            sign | ((113 - shift) << 23) | ((mantissa << shift) & 0x3ff) << 13
+        }
+        (0x1f, _) => sign | 0x7f80_0000 | mantissa << 13,
+        _ => sign | ((exponent + 112) << 23) | mantissa << 13,
+    };
+    f32::from_bits(bits)
+}
+
+// Views the values of a tensor as M rows of N values, e.g. the 16
+// embeddings of 64 values of the embedding model
+impl<'a, const N: usize, const M: usize> TryFrom<&'a Tensor<f32>> for &'a [[f32; N]; M] {
+    type Error = ErrorCode;
+
+    fn try_from(tensor: &'a Tensor<f32>) -> Result<Self, ErrorCode> {
+        let (rows, []) = tensor.data.as_chunks::<N>() else {
+            return Err(shape_error(tensor));
+        };
+        rows.try_into().map_err(|_| shape_error(tensor))
+    }
+}
+
+impl Tensor<f32> {
+    // Views the values of a tensor as `rows` rows of `len` values, for
+    // shapes that are only known at runtime (see `Shape` in model.rs)
+    pub fn rows(&self, rows: usize, len: usize) -> Result<Vec<&[f32]>, ErrorCode> {
+        if len == 0 || self.data.len() != rows * len {
+            return Err(shape_error(self));
+        }
+        Ok(self.data.chunks_exact(len).collect())
+    }
+}
+
+fn shape_error(tensor: &Tensor<f32>) -> ErrorCode {
+    ErrorCode::InternalError(Some(format!(
+        "Tensor of shape {:?} has {} values",
+        tensor.dims,
+        tensor.data.len()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widens_half_precision() {
+        assert_eq!(f16_to_f32(0x0000), 0.0);
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        assert_eq!(f16_to_f32(0x3555), 0.333_251_95);
+        assert_eq!(f16_to_f32(0x7bff), 65504.0);
+        assert_eq!(f16_to_f32(0x0400), 2f32.powi(-14));
+        assert_eq!(f16_to_f32(0x0200), 2f32.powi(-15));
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0x83ff), -1023.0 * 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert!(f16_to_f32(0x7e00).is_nan());
+    }
+}
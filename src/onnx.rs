@@ -0,0 +1,407 @@
+// wasi-nn does not tell us anything about a graph, not even the names
+// of its tensors, so the component used to rely on those of the
+// included model (`l_past_values_` and `add_8`). This module reads the
+// names, types and shapes of the inputs and outputs from the ONNX file
+// of a model instead, so that a re-exported model works without
+// configuration (see `ModelSpec::resolve` in model.rs).
+//
+// An ONNX file is a protobuf encoded `ModelProto`. Only the few fields
+// that describe the inputs and outputs of its graph are decoded, all
+// others are skipped:
+//
+// ModelProto { graph (7): GraphProto }
+// GraphProto { initializer (5): TensorProto, input (11), output (12): ValueInfoProto }
+// TensorProto { name (8) }
+// ValueInfoProto { name (1), type (2): TypeProto }
+// TypeProto { tensor_type (1): Tensor { elem_type (1), shape (2): TensorShapeProto } }
+// TensorShapeProto { dim (1): Dimension { dim_value (1), dim_param (2) } }
+//
+// Files that cannot be decoded (e.g. models the host loads by name) have
+// no metadata, and the component falls back to the configured names.
+
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+// The metadata of the models read so far, by their first file
+static METADATA: Mutex<BTreeMap<String, Option<Arc<Metadata>>>> = Mutex::new(BTreeMap::new());
+
+pub struct Metadata {
+    pub inputs: Vec<TensorInfo>,
+    pub outputs: Vec<TensorInfo>,
+}
+
+pub struct TensorInfo {
+    pub name: String,
+    // The type of the elements as numbered by ONNX (e.g. FLOAT), `None`
+    // for types other than tensors
+    pub elem_type: Option<u64>,
+    // The size of each axis, `None` for dynamic axes (like a batch axis
+    // with a symbolic size)
+    pub shape: Vec<Option<u64>>,
+}
+
+// The type of the elements of the input tensor, since the component
+// passes the series as f32 (see `model_forecast` in lib.rs)
+pub const FLOAT: u64 = 1;
+// The types of the elements of output tensors that wasi-nn can return
+// and the component converts to f32 (see `Tensor::from_tensor` in
+// nn.rs): float, uint8, int32, int64, float16, double and bfloat16
+pub const OUTPUT_TYPES: [u64; 7] = [FLOAT, 2, 6, 7, 10, 11, 16];
+
+impl Metadata {
+    pub fn input(&self, name: &str) -> Option<&TensorInfo> {
+        self.inputs.iter().find(|tensor| tensor.name == name)
+    }
+
+    pub fn output(&self, name: &str) -> Option<&TensorInfo> {
+        self.outputs.iter().find(|tensor| tensor.name == name)
+    }
+}
+
+impl TensorInfo {
+    // The name of the element type like `double`, for error messages
+    pub fn elem_type_string(&self) -> String {
+        let name = match self.elem_type {
+            Some(1) => "float",
+            Some(2) => "uint8",
+            Some(3) => "int8",
+            Some(4) => "uint16",
+            Some(5) => "int16",
+            Some(6) => "int32",
+            Some(7) => "int64",
+            Some(8) => "string",
+            Some(9) => "bool",
+            Some(10) => "float16",
+            Some(11) => "double",
+            Some(12) => "uint32",
+            Some(13) => "uint64",
+            Some(16) => "bfloat16",
+            Some(other) => return format!("type {other}"),
+            None => "unknown",
+        };
+        name.to_string()
+    }
+
+    // The shape like `[batch, 128, 1]`, for error messages
+    pub fn shape_string(&self) -> String {
+        let axes: Vec<_> = self
+            .shape
+            .iter()
+            .map(|size| size.map_or("?".to_string(), |size| size.to_string()))
+            .collect();
+        format!("[{}]", axes.join(", "))
+    }
+}
+
+// The metadata of the model in the files, if they contain an ONNX model
+// that can be decoded. The model is in the first file, the others
+// contain external data.
+pub fn metadata<P: AsRef<Path>>(files: &[P]) -> Option<Arc<Metadata>> {
+    let file = files.first()?.as_ref();
+    let key = file.to_string_lossy().into_owned();
+    let mut cache = METADATA.lock().ok()?;
+    cache
+        .entry(key)
+        .or_insert_with(|| read(file).map(Arc::new))
+        .clone()
+}
+
+fn read(file: &Path) -> Option<Metadata> {
+    // Encrypted files are decrypted in memory (see encryption.rs)
+    #[cfg(feature = "encryption")]
+    let contents = crate::encryption::read(file).ok()?;
+    #[cfg(not(feature = "encryption"))]
+    let contents = std::fs::read(file).ok()?;
+    decode_model(&contents)
+}
+
+fn decode_model(model: &[u8]) -> Option<Metadata> {
+    let graph = Fields(model)
+        .filter_map(Result::ok)
+        .find_map(|field| match field {
+            (7, Wire::Bytes(graph)) => Some(graph),
+            _ => None,
+        })?;
+
+    let mut initializers = Vec::new();
+    let mut inputs = Vec::new();
+    let mut outputs = Vec::new();
+    for field in Fields(graph) {
+        match field.ok()? {
+            (5, Wire::Bytes(tensor)) => initializers.push(decode_name(tensor, 8)?),
+            (11, Wire::Bytes(value_info)) => inputs.push(decode_value_info(value_info)?),
+            (12, Wire::Bytes(value_info)) => outputs.push(decode_value_info(value_info)?),
+            _ => {}
+        }
+    }
+    // Models exported with older versions of ONNX also list their
+    // weights as inputs
+    inputs.retain(|input| !initializers.contains(&input.name));
+    Some(Metadata { inputs, outputs })
+}
+
+fn decode_name(message: &[u8], number: u32) -> Option<String> {
+    for field in Fields(message) {
+        if let (n, Wire::Bytes(name)) = field.ok()? {
+            if n == number {
+                return String::from_utf8(name.to_vec()).ok();
+            }
+        }
+    }
+    Some(String::new())
+}
+
+fn decode_value_info(value_info: &[u8]) -> Option<TensorInfo> {
+    let mut name = String::new();
+    let mut elem_type = None;
+    let mut shape = Vec::new();
+    for field in Fields(value_info) {
+        match field.ok()? {
+            (1, Wire::Bytes(bytes)) => name = String::from_utf8(bytes.to_vec()).ok()?,
+            (2, Wire::Bytes(type_proto)) => (elem_type, shape) = decode_type(type_proto)?,
+            _ => {}
+        }
+    }
+    Some(TensorInfo {
+        name,
+        elem_type,
+        shape,
+    })
+}
+
+// The element type and shape in a TypeProto, which are unknown and
+// empty for types other than tensors
+fn decode_type(type_proto: &[u8]) -> Option<(Option<u64>, Vec<Option<u64>>)> {
+    let mut elem_type = None;
+    let mut shape = Vec::new();
+    for field in Fields(type_proto) {
+        let (1, Wire::Bytes(tensor_type)) = field.ok()? else {
+            continue;
+        };
+        for field in Fields(tensor_type) {
+            let tensor_shape = match field.ok()? {
+                (1, Wire::Varint(value)) => {
+                    elem_type = Some(value);
+                    continue;
+                }
+                (2, Wire::Bytes(tensor_shape)) => tensor_shape,
+                _ => continue,
+            };
+            for field in Fields(tensor_shape) {
+                let (1, Wire::Bytes(dimension)) = field.ok()? else {
+                    continue;
+                };
+                let mut size = None;
+                for field in Fields(dimension) {
+                    if let (1, Wire::Varint(value)) = field.ok()? {
+                        size = Some(value);
+                    }
+                }
+                shape.push(size);
+            }
+        }
+    }
+    Some((elem_type, shape))
+}
+
+// The value of a protobuf field, by its wire type
+enum Wire<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+// The fields of a protobuf message with their numbers. Yields an error
+// and stops if the message is malformed.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u32, Wire<'a>), ()>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let field = self.field();
+        if field.is_err() {
+            self.0 = &[];
+        }
+        Some(field)
+    }
+}
+
+impl<'a> Fields<'a> {
+    fn field(&mut self) -> Result<(u32, Wire<'a>), ()> {
+        let key = self.varint()?;
+        let number = u32::try_from(key >> 3).map_err(|_| ())?;
+        let wire = match key & 0x7 {
+            0 => Wire::Varint(self.varint()?),
+            1 => self.skip(8).map(|_| Wire::Fixed)?,
+            2 => {
+                let len = usize::try_from(self.varint()?).map_err(|_| ())?;
+                Wire::Bytes(self.skip(len)?)
+            }
+            5 => self.skip(4).map(|_| Wire::Fixed)?,
+            // Groups are deprecated and not used by ONNX
+            _ => return Err(()),
+        };
+        Ok((number, wire))
+    }
+
+    fn varint(&mut self) -> Result<u64, ()> {
+        let mut value = 0;
+        for (i, byte) in self.0.iter().enumerate().take(10) {
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.0 = &self.0[i + 1..];
+                return Ok(value);
+            }
+        }
+        Err(())
+    }
+
+    fn skip(&mut self, len: usize) -> Result<&'a [u8], ()> {
+        if len > self.0.len() {
+            return Err(());
+        }
+        let (skipped, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(skipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(mut value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while value >= 0x80 {
+            bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value as u8);
+        bytes
+    }
+
+    // A field with a varint value
+    fn number(number: u32, value: u64) -> Vec<u8> {
+        [varint(u64::from(number) << 3), varint(value)].concat()
+    }
+
+    // A field with a length-delimited value, like a message or a string
+    fn bytes(number: u32, value: &[u8]) -> Vec<u8> {
+        [
+            varint(u64::from(number) << 3 | 2),
+            varint(value.len() as u64),
+            value.to_vec(),
+        ]
+        .concat()
+    }
+
+    // A ValueInfoProto of a tensor. Dimensions without a size are
+    // symbolic.
+    fn value_info(name: &str, elem_type: u64, shape: &[Option<u64>]) -> Vec<u8> {
+        let dims: Vec<u8> = shape
+            .iter()
+            .flat_map(|size| {
+                let dimension = match size {
+                    Some(size) => number(1, *size),
+                    None => bytes(2, b"batch"),
+                };
+                bytes(1, &dimension)
+            })
+            .collect();
+        let tensor_type = [number(1, elem_type), bytes(2, &dims)].concat();
+        [bytes(1, name.as_bytes()), bytes(2, &bytes(1, &tensor_type))].concat()
+    }
+
+    fn model(graph: &[u8]) -> Vec<u8> {
+        // ir_version, producer_name and the graph
+        [number(1, 8), bytes(2, b"pytorch"), bytes(7, graph)].concat()
+    }
+
+    #[test]
+    fn decodes_inputs_and_outputs() {
+        let graph = [
+            bytes(1, b"node"),
+            bytes(
+                11,
+                &value_info("l_past_values_", FLOAT, &[None, Some(128), Some(1)]),
+            ),
+            bytes(12, &value_info("add_8", 11, &[None, Some(24)])),
+        ]
+        .concat();
+        let metadata = decode_model(&model(&graph)).unwrap();
+
+        let input = metadata.input("l_past_values_").unwrap();
+        assert_eq!(input.elem_type, Some(FLOAT));
+        assert_eq!(input.shape, [None, Some(128), Some(1)]);
+        assert_eq!(input.shape_string(), "[?, 128, 1]");
+        let output = metadata.output("add_8").unwrap();
+        assert_eq!(output.elem_type_string(), "double");
+        assert_eq!(output.shape_string(), "[?, 24]");
+        assert!(metadata.input("add_8").is_none());
+    }
+
+    // Older exports list the weights (initializers) as inputs as well
+    #[test]
+    fn drops_initializers_from_the_inputs() {
+        let graph = [
+            bytes(5, &bytes(8, b"weight")),
+            bytes(11, &value_info("weight", FLOAT, &[Some(128)])),
+            bytes(11, &value_info("input", FLOAT, &[Some(128)])),
+        ]
+        .concat();
+        let metadata = decode_model(&model(&graph)).unwrap();
+        let names: Vec<_> = metadata.inputs.iter().map(|input| &input.name).collect();
+        assert_eq!(names, ["input"]);
+    }
+
+    #[test]
+    fn skips_fixed_width_fields() {
+        // A double (wire type 1) and a float (wire type 5)
+        let fixed = [vec![0x09], vec![0; 8], vec![0x15], vec![0; 4]].concat();
+        let graph = [fixed.clone(), bytes(11, &value_info("input", FLOAT, &[]))].concat();
+        let metadata = decode_model(&[fixed, model(&graph)].concat()).unwrap();
+        assert_eq!(metadata.inputs.len(), 1);
+        assert_eq!(metadata.inputs[0].shape_string(), "[]");
+    }
+
+    #[test]
+    fn names_element_types() {
+        let info = |elem_type| TensorInfo {
+            name: String::new(),
+            elem_type,
+            shape: Vec::new(),
+        };
+        assert_eq!(info(Some(FLOAT)).elem_type_string(), "float");
+        assert_eq!(info(Some(16)).elem_type_string(), "bfloat16");
+        assert_eq!(info(Some(99)).elem_type_string(), "type 99");
+        assert_eq!(info(None).elem_type_string(), "unknown");
+    }
+
+    #[test]
+    fn rejects_malformed_models() {
+        let graph = bytes(11, &value_info("input", FLOAT, &[Some(128)]));
+        let encoded = model(&graph);
+        // Cut off in the middle of the graph
+        assert!(decode_model(&encoded[..encoded.len() - 3]).is_none());
+        // Without a graph
+        assert!(decode_model(&number(1, 8)).is_none());
+        assert!(decode_model(&[]).is_none());
+        // A group (wire type 3), which ONNX does not use
+        assert!(decode_model(&[0x0b]).is_none());
+        // A varint longer than 10 bytes
+        assert!(decode_model(&[
+            0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01
+        ])
+        .is_none());
+        // A value info whose name is not UTF-8
+        let invalid_name = bytes(11, &bytes(1, &[0xff, 0xfe]));
+        assert!(decode_model(&model(&invalid_name)).is_none());
+    }
+}
@@ -0,0 +1,174 @@
+// This module sends HTTP requests to other services using the
+// wasi:http/outgoing-handler interface, e.g. to deliver alert
+// notifications to webhooks or to pull data for scheduled forecasts. The host must allow outgoing requests
+// (`wasmtime serve` does by default, `wasmtime run` with `-S http`).
+
+use wasi::{
+    http::{
+        outgoing_handler,
+        types::{
+            Fields, IncomingBody, IncomingResponse, Method, OutgoingBody, OutgoingRequest, Scheme,
+        },
+    },
+    io::streams::StreamError,
+};
+
+use crate::error::Error;
+
+// wasi-io does not allow writing more than 4096 bytes at once
+const CHUNK_SIZE: usize = 4096;
+
+// The status, headers and the whole body of a response
+pub struct Response {
+    pub status: u16,
+    #[cfg_attr(not(feature = "s3"), allow(dead_code))]
+    pub headers: Vec<(String, Vec<u8>)>,
+    #[cfg_attr(not(any(feature = "s3", feature = "schedule")), allow(dead_code))]
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    // The first value of the header with the given (lowercase) name
+    #[cfg_attr(not(feature = "s3"), allow(dead_code))]
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_slice())
+    }
+}
+
+// Sends a GET request to the URL and returns the response
+#[cfg_attr(not(feature = "schedule"), allow(dead_code))]
+pub fn get(url: &str) -> Result<Response, Error> {
+    request(Method::Get, url, &[], &[])
+}
+
+// Sends a POST request with the given body to the URL and returns the
+// status code of the response
+#[cfg_attr(
+    not(any(
+        feature = "alerts",
+        feature = "forward",
+        feature = "pushgateway",
+        feature = "schedule",
+        feature = "trigger"
+    )),
+    allow(dead_code)
+)]
+pub fn post(url: &str, content_type: &str, body: &[u8]) -> Result<u16, Error> {
+    let headers = [("content-type".to_string(), content_type.as_bytes().to_vec())];
+    request(Method::Post, url, &headers, body).map(|response| response.status)
+}
+
+// Sends a request with the given headers and body to the URL and waits
+// for the response
+pub fn request(
+    method: Method,
+    url: &str,
+    headers: &[(String, Vec<u8>)],
+    body: &[u8],
+) -> Result<Response, Error> {
+    let (scheme, authority, path_with_query) = split_url(url)?;
+
+    // The request is part of the trace of the request being handled
+    // (see tracecontext.rs)
+    #[cfg(feature = "tracecontext")]
+    let headers = &[headers, &crate::tracecontext::headers()].concat();
+    let headers = Fields::from_list(headers)
+        .map_err(|e| Error::internal(format!("Invalid header: {e:?}")))?;
+    let request = OutgoingRequest::new(headers);
+    request
+        .set_method(&method)
+        .and_then(|()| request.set_scheme(Some(&scheme)))
+        .and_then(|()| request.set_authority(Some(authority)))
+        .and_then(|()| request.set_path_with_query(Some(path_with_query)))
+        .map_err(|()| Error::internal(format!("Invalid URL {url}")))?;
+    let outgoing_body = request
+        .body()
+        .map_err(|()| Error::internal("Request body already taken"))?;
+
+    // The request is sent before its body is written, so that the
+    // body can be streamed to the other side
+    let future_response = outgoing_handler::handle(request, None)?;
+    write_body(outgoing_body, body)?;
+
+    future_response.subscribe().block();
+    let response = future_response
+        .get()
+        .ok_or_else(|| Error::internal("Response not ready"))?
+        .map_err(|()| Error::internal("Response already taken"))??;
+
+    Ok(Response {
+        status: response.status(),
+        headers: response.headers().entries(),
+        body: read_body(response)?,
+    })
+}
+
+fn write_body(outgoing_body: OutgoingBody, body: &[u8]) -> Result<(), Error> {
+    let stream = outgoing_body
+        .write()
+        .map_err(|()| Error::internal("Request body stream already taken"))?;
+    for chunk in body.chunks(CHUNK_SIZE) {
+        stream
+            .blocking_write_and_flush(chunk)
+            .map_err(|e| match e {
+                StreamError::LastOperationFailed(e) => Error::internal(format!(
+                    "Error writing request body: {}",
+                    e.to_debug_string()
+                )),
+                StreamError::Closed => Error::internal("Request body stream closed"),
+            })?;
+    }
+
+    // The stream must be dropped before the body can be finished
+    drop(stream);
+    Ok(OutgoingBody::finish(outgoing_body, None)?)
+}
+
+fn read_body(response: IncomingResponse) -> Result<Vec<u8>, Error> {
+    let body = response
+        .consume()
+        .map_err(|()| Error::internal("Response body already consumed"))?;
+    let stream = body
+        .stream()
+        .map_err(|()| Error::internal("Response body stream already taken"))?;
+
+    let mut buffer = Vec::new();
+    loop {
+        match stream.blocking_read(CHUNK_SIZE as u64) {
+            Ok(chunk) => buffer.extend_from_slice(&chunk),
+            Err(StreamError::Closed) => break,
+            Err(StreamError::LastOperationFailed(e)) => {
+                return Err(Error::internal(format!(
+                    "Error reading response body: {}",
+                    e.to_debug_string()
+                )))
+            }
+        }
+    }
+
+    // The stream must be dropped before the body can be finished
+    drop(stream);
+    IncomingBody::finish(body);
+    Ok(buffer)
+}
+
+// Splits a URL like `https://example.com:8443/hook?key=value` into the
+// parts wasi-http expects
+fn split_url(url: &str) -> Result<(Scheme, &str, &str), Error> {
+    let (scheme, rest) = url
+        .split_once("://")
+        .ok_or_else(|| Error::internal(format!("URL {url} has no scheme")))?;
+    let scheme = match scheme {
+        "http" => Scheme::Http,
+        "https" => Scheme::Https,
+        other => Scheme::Other(other.to_string()),
+    };
+    let (authority, path_with_query) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    Ok((scheme, authority, path_with_query))
+}
@@ -0,0 +1,155 @@
+// This module runs a pipeline of models on the history before it is
+// passed to the forecasting model, e.g. a denoiser that removes sensor
+// noise. The pipeline is defined in the `pipeline` config (see
+// config.rs):
+//
+// { "stages": [
+//     { "name": "denoiser", "model": "models/denoiser.onnx" },
+//     { "name": "detrender", "model": "models/detrender.onnx", "from": "denoiser" }],
+//   "forecast_from": "detrender" }
+//
+// Every stage is a model that maps a series to a series of the same
+// shape as the input of the forecasting model (16 x 128 x 1 for the
+// included model, see `Shape` in model.rs). Its input
+// is the output of the stage named in `from`, which defaults to the
+// previous stage, or the history itself for `"window"`. Since stages
+// can only take the output of an earlier stage, they form a DAG and
+// are simply run in the order they are defined. The forecasting model
+// gets the output of the stage named in `forecast_from`, which defaults
+// to the last stage. Forecasts requested with `debug=true` also return
+// the outputs of all stages.
+
+use serde::{Deserialize, Serialize};
+use wasi::http::types::ErrorCode;
+
+use crate::{config, model::Shape, tensor_from_series, MODEL_FORMAT};
+
+const CONFIG: &str = "pipeline";
+// The name by which stages refer to the history
+const WINDOW: &str = "window";
+
+#[derive(Default, Deserialize)]
+struct Pipeline {
+    stages: Vec<Stage>,
+    forecast_from: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Stage {
+    name: String,
+    model: String,
+    from: Option<String>,
+    #[serde(default = "default_input_tensor")]
+    input_tensor: String,
+    #[serde(default = "default_output_tensor")]
+    output_tensor: String,
+}
+
+fn default_input_tensor() -> String {
+    "input".into()
+}
+
+fn default_output_tensor() -> String {
+    "output".into()
+}
+
+// The output of each stage by name, in the order they were run
+pub type StageOutputs = Vec<(String, Vec<f32>)>;
+
+// Runs the pipeline on the history. Returns the series for the
+// forecasting model and the outputs of all stages. Without a pipeline,
+// this is just the history.
+pub fn run(history: Vec<f32>) -> Result<(Vec<f32>, StageOutputs), ErrorCode> {
+    let pipeline: Pipeline = config::load(CONFIG)
+        .map_err(|e| ErrorCode::InternalError(Some(e.to_string())))?
+        .unwrap_or_default();
+    let shape = crate::model::shape()?;
+
+    let mut outputs = StageOutputs::with_capacity(pipeline.stages.len());
+    for stage in &pipeline.stages {
+        if stage.name == WINDOW || outputs.iter().any(|(name, _)| *name == stage.name) {
+            return Err(pipeline_error(format!(
+                "Invalid or duplicate stage name {}",
+                stage.name
+            )));
+        }
+        let input = match stage.from.as_deref() {
+            None => outputs.last().map_or(&history, |(_, output)| output),
+            Some(from) => output_of(&history, &outputs, from)?,
+        };
+        let output = run_stage(stage, &shape, input.clone())?;
+        outputs.push((stage.name.clone(), output));
+    }
+
+    #[cfg(feature = "trace")]
+    if !outputs.is_empty() {
+        crate::trace::record(crate::trace::Decision::Pipeline {
+            stages: outputs.iter().map(|(name, _)| name.clone()).collect(),
+        });
+    }
+
+    let series = match pipeline.forecast_from.as_deref() {
+        None => outputs.last().map_or(&history, |(_, output)| output),
+        Some(from) => output_of(&history, &outputs, from)?,
+    };
+    Ok((series.clone(), outputs))
+}
+
+fn output_of<'a>(
+    history: &'a Vec<f32>,
+    outputs: &'a StageOutputs,
+    name: &str,
+) -> Result<&'a Vec<f32>, ErrorCode> {
+    if name == WINDOW {
+        return Ok(history);
+    }
+    outputs
+        .iter()
+        .find(|(stage, _)| stage == name)
+        .map(|(_, output)| output)
+        .ok_or_else(|| pipeline_error(format!("Unknown or later stage {name}")))
+}
+
+fn run_stage(stage: &Stage, shape: &Shape, series: Vec<f32>) -> Result<Vec<f32>, ErrorCode> {
+    let graph = crate::load_model(MODEL_FORMAT, &[&stage.model])?;
+    let ctx = graph.init_execution_context()?;
+
+    let output_tensors = &crate::infer(&graph, || {
+        ctx.run(
+            [(
+                stage.input_tensor.as_str(),
+                tensor_from_series(series, shape.num_batches, shape.history_len),
+            )],
+            &[stage.output_tensor.as_str()],
+        )
+    })?;
+    let output = crate::output_rows(
+        &output_tensors[stage.output_tensor.as_str()],
+        shape.num_batches,
+        shape.history_len,
+    )?;
+
+    // All batches contain the same series (see `tensor_from_series`)
+    Ok(output[0].to_vec())
+}
+
+fn pipeline_error(message: String) -> ErrorCode {
+    ErrorCode::InternalError(Some(format!("Invalid pipeline: {message}")))
+}
+
+// The outputs of the stages, as they are returned with the forecast
+// for `debug=true`
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+#[derive(Serialize)]
+pub struct DebugStage<'a> {
+    name: &'a str,
+    output: &'a [f32],
+}
+
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+pub fn debug_stages(stages: &StageOutputs) -> Vec<DebugStage<'_>> {
+    stages
+        .iter()
+        .map(|(name, output)| DebugStage { name, output })
+        .collect()
+}
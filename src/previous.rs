@@ -0,0 +1,119 @@
+// This module remembers the last forecast of each series and compares
+// new forecasts with it, so that consumers can tell when the outlook of
+// the model changes significantly between runs. Forecasts with a
+// `series` parameter then have a `delta_from_previous` section:
+//
+// { "result": <forecast>, "delta_from_previous": { "previous_created": "...",
+//   "steps": [{ "timestamp": "...", "previous": 1.0, "current": 1.5, "delta": 0.5 }, ...],
+//   "max_abs_delta": 0.5, "mean_abs_delta": 0.25 } }
+//
+// If both forecasts have timestamps (see `forecast_timestamps` in
+// lib.rs), the steps they have in common are compared. Otherwise, they
+// are compared step by step from the start. The forecasts are stored in
+// the state directory (see state.rs).
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{clock, error::Error, state};
+
+const STATE_FILE: &str = "previous.json";
+
+#[derive(Serialize, Deserialize)]
+struct Forecast {
+    created: DateTime<Utc>,
+    timestamps: Option<Vec<DateTime<Utc>>>,
+    values: Vec<f32>,
+}
+
+#[derive(Serialize)]
+pub struct Delta {
+    previous_created: DateTime<Utc>,
+    steps: Vec<Step>,
+    max_abs_delta: f32,
+    mean_abs_delta: f32,
+}
+
+#[derive(Serialize)]
+struct Step {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<DateTime<Utc>>,
+    previous: f32,
+    current: f32,
+    delta: f32,
+}
+
+// Compares the forecast with the previous one of the series (if any)
+// and stores it in its place
+pub fn compare_and_store(
+    series: &str,
+    timestamps: Option<Vec<DateTime<Utc>>>,
+    values: &[f32],
+) -> Result<Option<Delta>, Error> {
+    let mut forecasts: BTreeMap<String, Forecast> = state::load(STATE_FILE)?;
+    let current = Forecast {
+        created: clock::now(),
+        timestamps,
+        values: values.to_vec(),
+    };
+    let delta = forecasts
+        .insert(series.to_string(), current)
+        .map(|previous| compare(&previous, &forecasts[series]));
+    state::save(STATE_FILE, &forecasts)?;
+    Ok(delta)
+}
+
+// Deletes the forecasts created before the cutoff (see retention.rs)
+#[cfg(feature = "retention")]
+pub fn expire(cutoff: DateTime<Utc>) -> Result<(), Error> {
+    let mut forecasts: BTreeMap<String, Forecast> = state::load(STATE_FILE)?;
+    forecasts.retain(|_, forecast| forecast.created >= cutoff);
+    state::save(STATE_FILE, &forecasts)
+}
+
+fn compare(previous: &Forecast, current: &Forecast) -> Delta {
+    let steps: Vec<Step> = match (&previous.timestamps, &current.timestamps) {
+        (Some(previous_timestamps), Some(current_timestamps)) => {
+            let previous_values: BTreeMap<_, _> =
+                previous_timestamps.iter().zip(&previous.values).collect();
+            current_timestamps
+                .iter()
+                .zip(&current.values)
+                .filter_map(|(timestamp, &current)| {
+                    let &previous = *previous_values.get(timestamp)?;
+                    Some(step(Some(*timestamp), previous, current))
+                })
+                .collect()
+        }
+        _ => previous
+            .values
+            .iter()
+            .zip(&current.values)
+            .map(|(&previous, &current)| step(None, previous, current))
+            .collect(),
+    };
+
+    let max_abs_delta = steps.iter().fold(0.0, |max: f32, s| max.max(s.delta.abs()));
+    let mean_abs_delta = if steps.is_empty() {
+        0.0
+    } else {
+        steps.iter().map(|s| s.delta.abs()).sum::<f32>() / steps.len() as f32
+    };
+    Delta {
+        previous_created: previous.created,
+        steps,
+        max_abs_delta,
+        mean_abs_delta,
+    }
+}
+
+fn step(timestamp: Option<DateTime<Utc>>, previous: f32, current: f32) -> Step {
+    Step {
+        timestamp,
+        previous,
+        current,
+        delta: current - previous,
+    }
+}
@@ -0,0 +1,172 @@
+// This module deletes stored data in bulk with `POST
+// /admin/state/prune`, for manual recovery when the flash storage of a
+// device fills up. The body selects what to delete by series (a pattern
+// with `*` wildcards) and by age (the time since the data was last
+// updated), or both:
+//
+// { "series": "boiler-*", "older_than_days": 7 }
+//
+// The data of each series is deleted from all of the state files that
+// keep data per series: recorded forecasts and actual values (see
+// accuracy.rs), accumulated data points and their baseline (see
+// ingest.rs and baseline.rs) and the stored forecasts (see previous.rs,
+// latest.rs, stale.rs and stitch.rs). Traces are not kept per series,
+// so they are only deleted by age (see trace.rs). The response reports
+// how many entries were deleted from each file and how many bytes that
+// freed:
+//
+// { "deleted": { "ingest.json": 3, "previous.json": 3 }, "freed_bytes": 18204 }
+//
+// The state files are read as plain JSON, so that this module does not
+// depend on the features that write them. The age of an entry is that
+// of its newest `created`, `recorded_at` or `timestamp` field (also in
+// the elements of a list), or of its newest recorded actual value.
+// Entries without any of them, like the baseline of a series, are only
+// deleted by series.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{clock, error::Error, http::JsonBody, series_matches, state};
+
+// The state files with data per series, and the field that holds the
+// series if they are not at the top level
+const SERIES_STATE: [(&str, Option<&str>); 7] = [
+    ("accuracy.json", Some("series")),
+    ("baseline.json", None),
+    ("ingest.json", None),
+    ("latest.json", None),
+    ("previous.json", None),
+    ("stale.json", None),
+    ("stitch.json", None),
+];
+// The state files with data that is not kept per series
+const OTHER_STATE: [&str; 1] = ["traces.json"];
+
+// The fields that tell when an entry was updated
+const TIME_FIELDS: [&str; 3] = ["created", "recorded_at", "timestamp"];
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Filter {
+    series: Option<String>,
+    older_than_days: Option<u32>,
+}
+
+#[derive(Default, Serialize)]
+pub struct Report {
+    deleted: BTreeMap<&'static str, usize>,
+    freed_bytes: usize,
+}
+
+impl JsonBody for Filter {
+    const NAME: &'static str = "prune request";
+
+    fn check(self) -> Result<Self, Error> {
+        // Deleting everything must be asked for explicitly, with the
+        // series `*`
+        if self.series.is_none() && self.older_than_days.is_none() {
+            return Err(Error::BadRequest(
+                "Select the data to delete with series or older_than_days".into(),
+            ));
+        }
+        Ok(self)
+    }
+}
+
+// Deletes the data that matches the filter
+pub fn prune(filter: &Filter) -> Result<Report, Error> {
+    let cutoff = filter
+        .older_than_days
+        .map(|days| clock::now() - TimeDelta::days(days.into()));
+    let expired = |entry: &Value| match cutoff {
+        Some(cutoff) => updated(entry).is_some_and(|updated| updated < cutoff),
+        None => true,
+    };
+
+    let mut report = Report::default();
+    for (name, field) in SERIES_STATE {
+        prune_file(&mut report, name, field, |series, entry| {
+            filter
+                .series
+                .as_deref()
+                .is_none_or(|pattern| series_matches(pattern, series))
+                && expired(entry)
+        })?;
+    }
+    if filter.series.is_none() {
+        for name in OTHER_STATE {
+            prune_file(&mut report, name, None, |_, entry| expired(entry))?;
+        }
+    }
+    Ok(report)
+}
+
+pub fn report_to_vec(report: &Report) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(report)
+        .map_err(|e| Error::internal(format!("Error serializing report: {e}")))
+}
+
+// Deletes the entries of the state file (or of its field) for which
+// `matches` is true by their keys
+fn prune_file(
+    report: &mut Report,
+    name: &'static str,
+    field: Option<&str>,
+    matches: impl Fn(&str, &Value) -> bool,
+) -> Result<(), Error> {
+    let mut contents: Map<String, Value> = state::load(name)?;
+    let entries = match field {
+        Some(field) => match contents.get_mut(field) {
+            Some(Value::Object(entries)) => entries,
+            _ => return Ok(()),
+        },
+        None => &mut contents,
+    };
+    let before = entries.len();
+    let size = serialized_len(entries);
+    entries.retain(|key, entry| !matches(key, entry));
+    let deleted = before - entries.len();
+    if deleted == 0 {
+        return Ok(());
+    }
+    report.freed_bytes += size.saturating_sub(serialized_len(entries));
+    report.deleted.insert(name, deleted);
+    state::save(name, &contents)
+}
+
+// When the entry was last updated, if it tells
+fn updated(entry: &Value) -> Option<DateTime<Utc>> {
+    let times = |object: &Map<String, Value>| -> Vec<DateTime<Utc>> {
+        let fields = TIME_FIELDS
+            .iter()
+            .filter_map(|field| object.get(*field)?.as_str());
+        // The actual values of a series are recorded by their
+        // timestamps (see accuracy.rs)
+        let history = object
+            .get("history")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flat_map(|history| history.keys().map(String::as_str));
+        fields
+            .chain(history)
+            .filter_map(|time| time.parse().ok())
+            .collect()
+    };
+    match entry {
+        Value::Object(object) => times(object).into_iter().max(),
+        Value::Array(elements) => elements
+            .iter()
+            .filter_map(Value::as_object)
+            .flat_map(times)
+            .max(),
+        _ => None,
+    }
+}
+
+fn serialized_len(entries: &Map<String, Value>) -> usize {
+    serde_json::to_vec(entries).map_or(0, |json| json.len())
+}
@@ -0,0 +1,36 @@
+// This module publishes every forecast for a series to a broker topic
+// using wasi:messaging (see messaging.rs), so that subscribers like
+// PLCs or SCADA systems (e.g. via an MQTT bridge of the host) receive
+// the forecasts without polling. Forecasts are published for HTTP
+// requests with a `series` parameter and for messages with `series`
+// metadata.
+
+use crate::messaging;
+
+// The topic a forecast is published to, `{series}` is replaced by the
+// series id
+const TOPIC_TEMPLATE: &str = "site/{series}/forecast";
+
+// Publishes the forecast (as JSON) for the series. The forecast has
+// already been made at this point, so failures are only reported on
+// stderr.
+pub fn publish_forecast(series: &str, forecast: &[u8]) {
+    // Without a broker, every forecast would fail to be published (see
+    // capabilities.rs)
+    #[cfg(feature = "capabilities")]
+    if !messaging::probe() {
+        return;
+    }
+    let topic = TOPIC_TEMPLATE.replace("{series}", &topic_level(series));
+    let metadata = [("series".to_string(), series.to_string())];
+    if let Err(e) = messaging::send(&topic, forecast, &metadata) {
+        eprintln!("Error publishing forecast to {topic}: {e:?}");
+    }
+}
+
+// The series id as a single topic level. Separators and wildcards
+// (in MQTT syntax) would change the meaning of the topic, so they are
+// replaced.
+fn topic_level(series: &str) -> String {
+    series.replace(['/', '+', '#'], "_")
+}
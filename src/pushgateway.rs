@@ -0,0 +1,75 @@
+// This module pushes the accuracy metrics (see accuracy.rs) to a
+// Prometheus Pushgateway, for devices that cannot be scraped by
+// Prometheus, e.g. because they are behind NAT. The metrics are pushed
+// whenever they change, i.e. after `POST /actuals`, and after every
+// scheduled forecast (see schedule.rs). The Pushgateway is configured
+// in the `pushgateway` config (see config.rs):
+//
+// { "url": "http://pushgateway.local:9091", "job": "wasi-nn-demo",
+//   "instance": "edge-1" }
+
+use serde::Deserialize;
+
+use crate::{accuracy, config, error::Error, outgoing, with_handler};
+
+const CONFIG: &str = "pushgateway";
+
+#[derive(Deserialize)]
+struct Pushgateway {
+    url: String,
+    job: String,
+    // Distinguishes the devices that push under the same job
+    instance: Option<String>,
+}
+
+// Pushes the current metrics if a Pushgateway is configured. Like
+// alert notifications, failed pushes are only reported on stderr and
+// do not fail the request.
+pub fn push_metrics() {
+    if let Err(e) = try_push_metrics() {
+        eprintln!("Error pushing metrics: {e}");
+    }
+}
+
+fn try_push_metrics() -> Result<(), Error> {
+    let Some(pushgateway) = config::load::<Pushgateway>(CONFIG)? else {
+        return Ok(());
+    };
+
+    let accuracy = with_handler(|handler| handler.accuracy())?;
+    let metrics = accuracy::accuracy_to_prometheus(&accuracy);
+
+    // The grouping labels are part of the path, and all metrics of the
+    // group are replaced by a push
+    let mut url = format!(
+        "{}/metrics/job/{}",
+        pushgateway.url.trim_end_matches('/'),
+        encode_path_segment(&pushgateway.job)
+    );
+    if let Some(instance) = &pushgateway.instance {
+        url.push_str(&format!("/instance/{}", encode_path_segment(instance)));
+    }
+
+    let status = outgoing::post(&url, "text/plain; version=0.0.4", metrics.as_bytes())?;
+    if !(200..300).contains(&status) {
+        return Err(Error::internal(format!(
+            "Pushgateway {url} responded with {status}"
+        )));
+    }
+    Ok(())
+}
+
+// Percent-encodes everything except unreserved characters, so that a
+// label value cannot change the structure of the path
+fn encode_path_segment(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            byte => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
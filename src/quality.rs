@@ -0,0 +1,153 @@
+// This module implements `POST /quality`, which reports how well a
+// data window fits what the forecast expects, before (or instead of)
+// running the model: The model assumes exactly `history_len` numeric,
+// equidistant values (see `series_from_data_window` and
+// `fit_to_history_len` in lib.rs), and silently pads, truncates or
+// ignores data that does not (unless it is resampled, see
+// resample.rs). The report lists these problems and sums
+// them up in a score from 0 (unusable) to 100 (perfect).
+
+use chrono::TimeDelta;
+use serde::Serialize;
+use wasi_nn_demo_lib::interface;
+
+use crate::{error::Error, numeric_data_points};
+
+// An interval this many times longer than the typical interval is
+// counted as a gap
+const GAP_FACTOR: f64 = 1.5;
+
+#[derive(Serialize)]
+pub struct QualityReport {
+    pub score: u8,
+    // The number of data points, and how many of them the model uses
+    pub points: usize,
+    pub numeric_points: usize,
+    // The number of values the model input is padded or truncated by
+    // (see fit.rs)
+    pub padded: usize,
+    pub truncated: usize,
+    pub missing_timestamps: usize,
+    pub duplicate_timestamps: usize,
+    // The number of gaps and the (estimated) number of values missing
+    // in them
+    pub gaps: usize,
+    pub missing_values: usize,
+    // Values that are not finite or outside of the range given by the
+    // client
+    pub out_of_range: usize,
+    // The typical (median) sampling interval and the standard
+    // deviation of the intervals from it, in seconds
+    pub interval: Option<f64>,
+    pub jitter: Option<f64>,
+}
+
+// The range of plausible values, e.g. the measuring range of a sensor
+pub struct Range {
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+}
+
+pub fn report(input: &interface::DataWindow, range: &Range, history_len: u32) -> QualityReport {
+    let data_points = numeric_data_points(input);
+    let points = input.data.len();
+    let numeric_points = data_points.len();
+
+    let out_of_range = data_points
+        .iter()
+        .filter(|(_, value)| {
+            !value.is_finite()
+                || range.min.is_some_and(|min| *value < min)
+                || range.max.is_some_and(|max| *value > max)
+        })
+        .count();
+
+    // The data points are already sorted by timestamp
+    let timestamps: Vec<_> = data_points
+        .iter()
+        .filter_map(|(data_point, _)| data_point.timestamp)
+        .collect();
+    let missing_timestamps = numeric_points - timestamps.len();
+    let intervals: Vec<_> = timestamps.windows(2).map(|w| w[1] - w[0]).collect();
+    let duplicate_timestamps = intervals.iter().filter(|i| i.is_zero()).count();
+
+    let interval = median_interval(&intervals);
+    let (mut gaps, mut missing_values, mut jitter) = (0, 0, None);
+    if let Some(interval) = interval {
+        for i in &intervals {
+            let ratio = seconds(*i) / interval;
+            if ratio > GAP_FACTOR {
+                gaps += 1;
+                missing_values += ratio.round() as usize - 1;
+            }
+        }
+        // Duplicates and gaps are already counted on their own
+        let regular: Vec<_> = intervals
+            .iter()
+            .map(|i| seconds(*i))
+            .filter(|i| *i > 0.0 && i / interval <= GAP_FACTOR)
+            .collect();
+        let variance = regular.iter().map(|i| (i - interval).powi(2)).sum::<f64>()
+            / regular.len().max(1) as f64;
+        jitter = Some(variance.sqrt());
+    }
+
+    let mut report = QualityReport {
+        score: 0,
+        points,
+        numeric_points,
+        padded: (history_len as usize).saturating_sub(numeric_points),
+        truncated: numeric_points.saturating_sub(history_len as usize),
+        missing_timestamps,
+        duplicate_timestamps,
+        gaps,
+        missing_values,
+        out_of_range,
+        interval,
+        jitter,
+    };
+    report.score = score(&report, history_len);
+    report
+}
+
+// Each problem reduces the score by the fraction of the values it
+// affects, jitter by at most half of the score
+fn score(report: &QualityReport, history_len: u32) -> u8 {
+    let total = report.points.max(1) as f64;
+
+    let penalties = [
+        (report.points - report.numeric_points) as f64 / total,
+        report.missing_timestamps as f64 / total,
+        report.duplicate_timestamps as f64 / total,
+        report.out_of_range as f64 / total,
+        report.missing_values as f64 / (total + report.missing_values as f64),
+        (report.padded + report.truncated) as f64 / f64::from(history_len),
+        match (report.jitter, report.interval) {
+            (Some(jitter), Some(interval)) => (jitter / interval).min(1.0) / 2.0,
+            _ => 0.0,
+        },
+    ];
+    let penalty: f64 = penalties.iter().sum();
+    (100.0 * (1.0 - penalty.min(1.0))).round() as u8
+}
+
+// The median of the intervals between distinct timestamps in seconds,
+// if there are any
+fn median_interval(intervals: &[TimeDelta]) -> Option<f64> {
+    let mut intervals: Vec<_> = intervals
+        .iter()
+        .filter(|i| !i.is_zero())
+        .map(|i| seconds(*i))
+        .collect();
+    intervals.sort_by(f64::total_cmp);
+    intervals.get(intervals.len() / 2).copied()
+}
+
+fn seconds(interval: TimeDelta) -> f64 {
+    interval.num_milliseconds() as f64 / 1000.0
+}
+
+pub fn report_to_vec(report: &QualityReport) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(report)
+        .map_err(|e| Error::internal(format!("Error serializing quality report: {e}")))
+}
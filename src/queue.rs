@@ -0,0 +1,226 @@
+// This module limits how many requests run the model at the same time.
+// The host creates a new instance of the component for every request
+// (see the comment on `HANDLER` in lib.rs), and these instances run
+// concurrently without knowing about each other. On a small device,
+// many concurrent inferences make all of them slow, so only a few
+// requests are admitted at once and the others wait in a bounded
+// queue. If the queue is full, or a request waits for too long, it is
+// rejected with 503 and a `Retry-After` header.
+//
+// Since the instances share nothing but the filesystem, running and
+// waiting requests are tracked as files in `state/queue` (see
+// state.rs): A request takes a slot by creating its file, which fails
+// if another request already holds it. Slots of instances that were
+// killed before they could release them expire after STALE_AFTER.
+//
+// Requests can have a priority in the `X-Priority` header (`high`,
+// `normal` or `low`), so that e.g. interactive queries of an operator
+// are not stuck behind bulk backtests. A waiting request only takes a
+// free slot if no request with a higher priority is waiting. Requests
+// without the header have normal priority.
+//
+// The limits can be changed in the optional `queue` config (see
+// config.rs):
+//
+// { "concurrency": 1, "depth": 8, "timeout_seconds": 30 }
+
+use std::{
+    fmt::Write as _,
+    fs::{self, OpenOptions},
+    io::{self, Write as _},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use wasi::clocks::monotonic_clock::subscribe_duration;
+
+use crate::{clock, config, error::Error};
+
+const CONFIG: &str = "queue";
+const DIRECTORY: &str = "state/queue";
+// How often a waiting request checks for a free slot
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// No request takes this long, so the slot must have been leaked
+const STALE_AFTER: Duration = Duration::from_secs(600);
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct Queue {
+    // The number of requests that run at the same time
+    concurrency: u32,
+    // The number of requests that wait for a slot
+    depth: u32,
+    timeout_seconds: u32,
+}
+
+impl Default for Queue {
+    fn default() -> Self {
+        Self {
+            concurrency: 1,
+            depth: 8,
+            timeout_seconds: 30,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+impl Priority {
+    // Parses the value of the `X-Priority` header
+    pub fn parse(priority: Option<&str>) -> Result<Self, Error> {
+        match priority.map(str::trim) {
+            None | Some("normal") => Ok(Priority::Normal),
+            Some("high") => Ok(Priority::High),
+            Some("low") => Ok(Priority::Low),
+            Some(other) => Err(Error::BadRequest(format!(
+                "Invalid priority {other}, must be high, normal or low"
+            ))),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+}
+
+// A slot held by this request, which is released when it is dropped
+pub struct Permit {
+    path: String,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            eprintln!("Error releasing {}: {e}", self.path);
+        }
+    }
+}
+
+// Waits until the request may run. The returned permit must be kept
+// until the request is done.
+pub fn admit(priority: Priority) -> Result<Permit, Error> {
+    let queue: Queue = config::load(CONFIG)?.unwrap_or_default();
+    fs::create_dir_all(DIRECTORY)
+        .map_err(|e| Error::internal(format!("Error creating {DIRECTORY}: {e}")))?;
+
+    if !is_waiting(priority, None) {
+        if let Some(permit) = take_slot("running", queue.concurrency, priority)? {
+            return Ok(permit);
+        }
+    }
+    let Some(waiting) = take_slot("waiting", queue.depth, priority)? else {
+        return Err(unavailable("Too many requests are waiting", 1));
+    };
+
+    let timeout = Duration::from_secs(queue.timeout_seconds.into());
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        subscribe_duration(POLL_INTERVAL.as_nanos() as u64).block();
+        if is_waiting(priority, Some(&waiting.path)) {
+            continue;
+        }
+        if let Some(permit) = take_slot("running", queue.concurrency, priority)? {
+            drop(waiting);
+            return Ok(permit);
+        }
+    }
+    Err(unavailable(
+        "Timed out waiting for other requests",
+        queue.timeout_seconds.into(),
+    ))
+}
+
+// Tries to take one of the slots with the given kind. The file of the
+// slot holds the priority of the request.
+fn take_slot(kind: &str, slots: u32, priority: Priority) -> Result<Option<Permit>, Error> {
+    for i in 0..slots {
+        let path = format!("{DIRECTORY}/{kind}-{i}");
+        if is_stale(&path) {
+            let _ = fs::remove_file(&path);
+        }
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                let permit = Permit { path };
+                file.write_all(priority.as_str().as_bytes())
+                    .map_err(|e| Error::internal(format!("Error writing {}: {e}", permit.path)))?;
+                return Ok(Some(permit));
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(Error::internal(format!("Error creating {path}: {e}"))),
+        }
+    }
+    Ok(None)
+}
+
+// Whether another request with a higher priority than the given one is
+// waiting
+fn is_waiting(priority: Priority, own: Option<&str>) -> bool {
+    fs::read_dir(DIRECTORY)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("waiting-"))
+        .map(|entry| format!("{DIRECTORY}/{}", entry.file_name().to_string_lossy()))
+        .filter(|path| Some(path.as_str()) != own && !is_stale(path))
+        .filter_map(|path| fs::read_to_string(path).ok())
+        // A slot that was just taken may not have its priority yet
+        .map(|waiting| Priority::parse(Some(&waiting)).unwrap_or(Priority::Normal))
+        .any(|waiting| waiting > priority)
+}
+
+fn is_stale(path: &str) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| {
+            (clock::now() - DateTime::<Utc>::from(modified))
+                .to_std()
+                .ok()
+        })
+        .is_some_and(|age| age > STALE_AFTER)
+}
+
+fn unavailable(message: &str, retry_after: u64) -> Error {
+    Error::Unavailable {
+        message: message.to_string(),
+        retry_after,
+    }
+}
+
+// The number of running and waiting requests in the Prometheus text
+// format, for GET /metrics
+pub fn queue_to_prometheus() -> String {
+    let (mut running, mut waiting) = (0, 0);
+    for entry in fs::read_dir(DIRECTORY).into_iter().flatten().flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("running-") {
+            running += 1;
+        } else if name.starts_with("waiting-") {
+            waiting += 1;
+        }
+    }
+
+    let mut out = String::new();
+    // Writing to a String cannot fail
+    let _ = writeln!(
+        out,
+        "# HELP forecast_requests_running Number of requests that are running\n\
+         # TYPE forecast_requests_running gauge\n\
+         forecast_requests_running {running}\n\
+         # HELP forecast_requests_waiting Number of requests that wait for a slot\n\
+         # TYPE forecast_requests_waiting gauge\n\
+         forecast_requests_waiting {waiting}"
+    );
+    out
+}
@@ -0,0 +1,105 @@
+// This module lets a single component serve several forecasting models,
+// which the client selects by name, either in the path (`POST
+// /models/{name}/predict`) or with the `x-model` header on any forecast
+// request. The models are listed in the `models` config (see
+// config.rs), the manifest of the registry:
+//
+// { "default": "temperature",
+//   "models": {
+//     "temperature": { "files": ["models/temperature.onnx"] },
+//     "energy": { "files": ["models/energy.onnx"], "input_tensor": "input",
+//                 "output_tensor": "output" } } }
+//
+// The tensor names default to those of the default model (see
+// model.rs). Requests that do not select a model use the `default`
+// entry, or the default model (and the routing rules, see routing.rs)
+// if there is none. Like the default model, all models must have the
+// shape the component was compiled for.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::{
+    config,
+    error::Error,
+    model::{ModelSpec, Overrides},
+};
+
+const CONFIG: &str = "models";
+
+#[derive(Default, Deserialize)]
+struct Manifest {
+    default: Option<String>,
+    #[serde(default)]
+    models: BTreeMap<String, Entry>,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    files: Vec<String>,
+    input_tensor: Option<String>,
+    output_tensor: Option<String>,
+    variance_tensor: Option<String>,
+    dynamic_batch: Option<bool>,
+}
+
+// The name of the model selected by the path or the header, if any
+pub fn selected_name<'a>(path: &'a str, header: Option<&'a str>) -> Option<&'a str> {
+    path.strip_prefix("/models/")
+        .and_then(|rest| rest.strip_suffix("/predict"))
+        .or(header)
+}
+
+// The model with the given name, or the default entry of the manifest.
+// None means that the model is not chosen by the registry.
+pub fn select(name: Option<&str>) -> Result<Option<ModelSpec>, Error> {
+    let manifest: Manifest = config::load(CONFIG)?.unwrap_or_default();
+    let Some(name) = name.or(manifest.default.as_deref()) else {
+        return Ok(None);
+    };
+    let entry = manifest
+        .models
+        .get(name)
+        .ok_or_else(|| Error::BadRequest(format!("Unknown model {name}")))?;
+    Ok(Some(spec(entry)?))
+}
+
+// All models of the registry
+#[cfg_attr(not(feature = "admin"), allow(dead_code))]
+pub fn models() -> Result<Vec<ModelSpec>, Error> {
+    let manifest: Manifest = config::load(CONFIG)?.unwrap_or_default();
+    manifest.models.values().map(spec).collect()
+}
+
+// The names of the models in the manifest
+#[cfg(feature = "discovery")]
+pub fn names() -> Result<Vec<String>, Error> {
+    let manifest: Manifest = config::load(CONFIG)?.unwrap_or_default();
+    Ok(manifest.models.into_keys().collect())
+}
+
+fn spec(entry: &Entry) -> Result<ModelSpec, Error> {
+    let overrides = Overrides {
+        input_tensor: entry.input_tensor.clone(),
+        output_tensor: entry.output_tensor.clone(),
+        variance_tensor: entry.variance_tensor.clone(),
+        dynamic_batch: entry.dynamic_batch,
+    };
+    // Without the metadata of the model, what is not given is taken
+    // from the default model
+    #[cfg(not(feature = "introspect"))]
+    let overrides = {
+        let default = crate::model::spec()?;
+        Overrides {
+            input_tensor: overrides.input_tensor.or(Some(default.input_tensor)),
+            output_tensor: overrides.output_tensor.or(Some(default.output_tensor)),
+            variance_tensor: overrides.variance_tensor.or(default.variance_tensor),
+            dynamic_batch: overrides.dynamic_batch.or(Some(default.dynamic_batch)),
+        }
+    };
+    // All models take series of the same length (see `Shape` in
+    // model.rs)
+    let shape = crate::model::shape()?;
+    Ok(ModelSpec::resolve(entry.files.clone(), shape, overrides)?)
+}
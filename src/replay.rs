@@ -0,0 +1,125 @@
+// This module protects the HTTP endpoint against replayed requests,
+// for deployments where it is reachable over untrusted networks. Every
+// request must carry the time it was sent (in seconds since the epoch)
+// and a nonce that is unique for each request:
+//
+// X-Request-Timestamp: 1733240118
+// X-Request-Nonce: 9b2f6c1e-4d1a-4c3b-a0a5-0e8f3f9d7c21
+//
+// Requests whose timestamp is more than MAX_AGE away from the clock of
+// the device are rejected, and so are requests with a nonce that was
+// already seen. The nonces are kept in the state directory (see
+// state.rs) only as long as their timestamp is recent, since older
+// requests are rejected anyway. Concurrent instances of the component
+// see the nonces of each other, since they are recorded under the lock
+// of the state.
+
+use std::collections::BTreeMap;
+
+use chrono::TimeDelta;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clock::{self, Clock},
+    error::Error,
+    state,
+};
+
+const STATE_FILE: &str = "nonces.json";
+// Allows for this much clock skew between client and device
+const MAX_AGE: TimeDelta = TimeDelta::minutes(5);
+const MAX_NONCE_LEN: usize = 128;
+
+#[derive(Default, Serialize, Deserialize)]
+struct Nonces {
+    // The timestamp of the request each nonce was seen in
+    seen: BTreeMap<String, i64>,
+}
+
+// Checks the timestamp and nonce of a request and records the nonce
+pub fn check(timestamp: Option<&str>, nonce: Option<&str>) -> Result<(), Error> {
+    let timestamp: i64 = timestamp
+        .ok_or_else(|| Error::Unauthorized("Missing X-Request-Timestamp header".into()))?
+        .trim()
+        .parse()
+        .map_err(|e| Error::Unauthorized(format!("Invalid X-Request-Timestamp: {e}")))?;
+    let nonce = nonce
+        .map(str::trim)
+        .filter(|nonce| (1..=MAX_NONCE_LEN).contains(&nonce.len()))
+        .ok_or_else(|| Error::Unauthorized("Missing or invalid X-Request-Nonce header".into()))?;
+
+    // The nonces are locked while the nonce is looked up and recorded,
+    // so that two copies of a request that arrive at the same time
+    // cannot both find it new
+    state::update(STATE_FILE, |nonces: &mut Nonces| {
+        nonces.record(timestamp, nonce, clock::clock())
+    })
+}
+
+impl Nonces {
+    // Records the nonce of a request sent at `timestamp`, unless the
+    // request is too old or the nonce was already seen
+    fn record(&mut self, timestamp: i64, nonce: &str, clock: &dyn Clock) -> Result<(), Error> {
+        let now = clock.now().timestamp();
+        if (now - timestamp).abs() > MAX_AGE.num_seconds() {
+            return Err(Error::Unauthorized(
+                "X-Request-Timestamp is too far from the current time".into(),
+            ));
+        }
+        self.seen
+            .retain(|_, seen| (now - *seen).abs() <= MAX_AGE.num_seconds());
+        if self.seen.insert(nonce.to_string(), timestamp).is_some() {
+            return Err(Error::Unauthorized("Replayed request".into()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::clock::FixedClock;
+
+    const NOW: i64 = 1_714_564_800;
+
+    fn at(timestamp: i64) -> FixedClock {
+        FixedClock(DateTime::from_timestamp(timestamp, 0).unwrap())
+    }
+
+    #[test]
+    fn accepts_requests_within_the_window() {
+        let mut nonces = Nonces::default();
+        assert!(nonces.record(NOW, "a", &at(NOW)).is_ok());
+        assert!(nonces.record(NOW - 300, "b", &at(NOW)).is_ok());
+        // Clients whose clock is ahead
+        assert!(nonces.record(NOW + 300, "c", &at(NOW)).is_ok());
+    }
+
+    #[test]
+    fn rejects_requests_outside_the_window() {
+        let mut nonces = Nonces::default();
+        assert!(nonces.record(NOW - 301, "a", &at(NOW)).is_err());
+        assert!(nonces.record(NOW + 301, "b", &at(NOW)).is_err());
+        assert!(nonces.seen.is_empty());
+    }
+
+    #[test]
+    fn rejects_replayed_nonces() {
+        let mut nonces = Nonces::default();
+        assert!(nonces.record(NOW, "a", &at(NOW)).is_ok());
+        assert!(nonces.record(NOW + 10, "a", &at(NOW + 10)).is_err());
+        assert!(nonces.record(NOW + 10, "b", &at(NOW + 10)).is_ok());
+    }
+
+    #[test]
+    fn forgets_nonces_once_they_are_too_old() {
+        let mut nonces = Nonces::default();
+        assert!(nonces.record(NOW, "a", &at(NOW)).is_ok());
+        assert!(nonces.record(NOW + 301, "b", &at(NOW + 301)).is_ok());
+        assert_eq!(nonces.seen.keys().collect::<Vec<_>>(), ["b"]);
+        // A replay of the old request is still rejected for its timestamp
+        assert!(nonces.record(NOW, "a", &at(NOW + 301)).is_err());
+    }
+}
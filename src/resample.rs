@@ -0,0 +1,334 @@
+// The model has no time features and assumes that the values of the
+// window are equidistant. Sensors do not always deliver that: Values
+// go missing, arrive late or are sampled irregularly. This module
+// resamples the window to its dominant cadence, the median interval
+// between the timestamps (like in quality.rs), so that a gap is not
+// silently read as if the values on both sides were neighbours.
+//
+// The values on the regular grid are interpolated linearly between the
+// surrounding data points by default. The optional `resample` config
+// (see config.rs) can fill them with the previous value instead, which
+// suits series that change in steps (e.g. set points):
+//
+// { "method": "previous" }
+//
+// Sources that only report on change (COV reporting) send bursts of
+// values when the signal moves and nothing while it is steady. For
+// them, `time-weighted` holds each value until the next one and takes
+// the average over the interval that ends at each point of the grid,
+// weighted by how long each value was held, so that a burst counts for
+// as long as it lasted rather than by its number of values. Since the
+// intervals between their reports say little about the signal, the
+// cadence can be set as well:
+//
+// { "method": "time-weighted", "cadence_seconds": 60 }
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Deserialize;
+
+use crate::config;
+
+const CONFIG: &str = "resample";
+
+// Windows with a few points far apart from the rest would otherwise
+// make the grid arbitrarily long
+const MAX_POINTS: usize = 10_000;
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Method {
+    #[default]
+    Linear,
+    Previous,
+    TimeWeighted,
+}
+
+#[derive(Default, Deserialize)]
+struct Resample {
+    #[serde(default)]
+    method: Method,
+    // The interval of the grid, instead of the dominant cadence of the
+    // window
+    cadence_seconds: Option<f64>,
+}
+
+// Resamples the points, which must be sorted by timestamp, to an
+// equidistant series ending at the last point. With fewer than two
+// points, or without a cadence (e.g. fewer than two distinct
+// timestamps), the values are returned as they are.
+pub fn resample(points: &[(DateTime<Utc>, f32)]) -> Vec<f32> {
+    match config::load::<Resample>(CONFIG) {
+        Ok(resample) => resample.unwrap_or_default().apply(points),
+        Err(e) => {
+            eprintln!("Not resampling the window: {e}");
+            values(points)
+        }
+    }
+}
+
+fn values(points: &[(DateTime<Utc>, f32)]) -> Vec<f32> {
+    points.iter().map(|&(_, value)| value).collect()
+}
+
+impl Resample {
+    fn apply(&self, points: &[(DateTime<Utc>, f32)]) -> Vec<f32> {
+        // A grid needs a first and a last point, even with a configured
+        // cadence
+        if points.len() < 2 {
+            return values(points);
+        }
+        let configured = self
+            .cadence_seconds
+            .and_then(|seconds| TimeDelta::try_milliseconds((seconds * 1000.0) as i64))
+            .filter(|cadence| *cadence > TimeDelta::zero());
+        let Some(cadence) = configured.or_else(|| cadence(points)) else {
+            return values(points);
+        };
+
+        let (first, last) = (points[0].0, points[points.len() - 1].0);
+        let mut series = Vec::new();
+        let mut time = last;
+        // The index of the first point after `time`, which moves
+        // backwards together with it
+        let mut next = points.len();
+        // The times of the values that fall between data points, for
+        // the trace (see trace.rs)
+        #[cfg(feature = "trace")]
+        let mut imputed = Vec::new();
+        while time >= first && series.len() < MAX_POINTS {
+            while next > 0 && points[next - 1].0 > time {
+                next -= 1;
+            }
+            // There is always a point at or before `time`, since `time`
+            // is not before the first point
+            let (before_time, before) = points[next - 1];
+            #[cfg(feature = "trace")]
+            if before_time < time {
+                imputed.push(time);
+            }
+            let value = match (&self.method, points.get(next)) {
+                (Method::Linear, Some(&(after_time, after))) if before_time < time => {
+                    let fraction = seconds(time - before_time) / seconds(after_time - before_time);
+                    before + (after - before) * fraction as f32
+                }
+                (Method::TimeWeighted, _) => {
+                    time_weighted(points, (time - cadence).max(first), time)
+                }
+                _ => before,
+            };
+            series.push(value);
+            time -= cadence;
+        }
+        series.reverse();
+
+        #[cfg(feature = "trace")]
+        crate::trace::record(crate::trace::Decision::Resampled {
+            cadence_seconds: seconds(cadence),
+            method: match self.method {
+                Method::Linear => "linear",
+                Method::Previous => "previous",
+                Method::TimeWeighted => "time-weighted",
+            }
+            .into(),
+            points: points.len(),
+            values: series.len(),
+            imputed: ranges(imputed, cadence),
+        });
+        series
+    }
+}
+
+// Joins the times of consecutive imputed values into ranges. The times
+// are in descending order, like the grid is built.
+#[cfg(feature = "trace")]
+fn ranges(times: Vec<DateTime<Utc>>, cadence: TimeDelta) -> Vec<crate::trace::Range> {
+    let mut ranges: Vec<crate::trace::Range> = Vec::new();
+    for time in times.into_iter().rev() {
+        match ranges.last_mut() {
+            Some(range) if time - range.to == cadence => range.to = time,
+            _ => ranges.push(crate::trace::Range {
+                from: time,
+                to: time,
+            }),
+        }
+    }
+    ranges
+}
+
+// The average of the values over the interval from `from` to `to`, each
+// held until the next one and weighted by how long it was held. `from`
+// must not be before the first point.
+fn time_weighted(points: &[(DateTime<Utc>, f32)], from: DateTime<Utc>, to: DateTime<Utc>) -> f32 {
+    // The point whose value is held at `from`
+    let start = points.partition_point(|&(time, _)| time <= from) - 1;
+    let (mut held_since, mut value) = (from, points[start].1);
+    let mut sum = 0.0;
+    for &(time, next_value) in points[start + 1..]
+        .iter()
+        .take_while(|&&(time, _)| time <= to)
+    {
+        sum += f64::from(value) * seconds(time - held_since);
+        (held_since, value) = (time, next_value);
+    }
+    sum += f64::from(value) * seconds(to - held_since);
+
+    let duration = seconds(to - from);
+    if duration > 0.0 {
+        (sum / duration) as f32
+    } else {
+        value
+    }
+}
+
+// The median of the intervals between distinct timestamps
+fn cadence(points: &[(DateTime<Utc>, f32)]) -> Option<TimeDelta> {
+    let mut intervals: Vec<_> = points
+        .windows(2)
+        .map(|w| w[1].0 - w[0].0)
+        .filter(|interval| *interval > TimeDelta::zero())
+        .collect();
+    intervals.sort();
+    intervals.get(intervals.len() / 2).copied()
+}
+
+fn seconds(interval: TimeDelta) -> f64 {
+    interval.num_seconds() as f64 + f64::from(interval.subsec_nanos()) / 1e9
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time_weighted(cadence_seconds: f64) -> Resample {
+        Resample {
+            method: Method::TimeWeighted,
+            cadence_seconds: Some(cadence_seconds),
+        }
+    }
+
+    // The values at the given seconds since the epoch
+    fn points(points: &[(i64, f32)]) -> Vec<(DateTime<Utc>, f32)> {
+        points
+            .iter()
+            .map(|&(seconds, value)| (DateTime::from_timestamp(seconds, 0).unwrap(), value))
+            .collect()
+    }
+
+    fn assert_close(actual: Vec<f32>, expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len(), "{actual:?} != {expected:?}");
+        for (actual_value, expected_value) in actual.iter().zip(expected) {
+            assert!(
+                (actual_value - expected_value).abs() < 1e-5,
+                "{actual:?} != {expected:?}"
+            );
+        }
+    }
+
+    // A window with a gap of three minutes in a cadence of one minute
+    fn gap() -> Vec<(DateTime<Utc>, f32)> {
+        points(&[(0, 0.0), (60, 1.0), (120, 2.0), (300, 5.0), (360, 6.0)])
+    }
+
+    #[test]
+    fn interpolates_gaps_linearly() {
+        let linear = Resample::default();
+        assert_close(linear.apply(&gap()), &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn fills_gaps_with_the_previous_value() {
+        let previous = Resample {
+            method: Method::Previous,
+            cadence_seconds: None,
+        };
+        assert_close(previous.apply(&gap()), &[0.0, 1.0, 2.0, 2.0, 2.0, 5.0, 6.0]);
+    }
+
+    // The grid ends at the last point, so a window whose length is not a
+    // multiple of the cadence loses the start of its first interval
+    #[test]
+    fn ends_the_grid_at_the_last_point() {
+        let window = points(&[(0, 0.0), (30, 3.0), (90, 9.0), (150, 15.0)]);
+        assert_close(Resample::default().apply(&window), &[3.0, 9.0, 15.0]);
+    }
+
+    #[test]
+    fn weights_bursts_by_their_duration() {
+        // A spike that lasts one second in a series reported on change
+        let window = points(&[(0, 1.0), (30, 3.0), (31, 100.0), (32, 3.0), (120, 5.0)]);
+        assert_close(
+            time_weighted(60.0).apply(&window),
+            &[1.0, (30.0 + 3.0 + 100.0 + 28.0 * 3.0) / 60.0, 3.0],
+        );
+    }
+
+    #[test]
+    fn holds_values_across_gaps() {
+        let window = points(&[(0, 2.0), (60, 4.0), (300, 8.0)]);
+        assert_close(
+            time_weighted(60.0).apply(&window),
+            &[2.0, 2.0, 4.0, 4.0, 4.0, 4.0],
+        );
+    }
+
+    #[test]
+    fn averages_over_the_cadence_of_the_window() {
+        // The median interval is 10 seconds
+        let window = points(&[(0, 0.0), (10, 10.0), (20, 20.0), (25, 0.0), (30, 30.0)]);
+        let resample = Resample {
+            method: Method::TimeWeighted,
+            cadence_seconds: None,
+        };
+        assert_close(resample.apply(&window), &[0.0, 0.0, 10.0, 10.0]);
+    }
+
+    #[test]
+    fn takes_the_median_interval_as_cadence() {
+        let cadence = |window: &[(i64, f32)]| cadence(&points(window)).map(seconds);
+        assert_eq!(
+            cadence(&[(0, 0.0), (60, 0.0), (120, 0.0), (500, 0.0)]),
+            Some(60.0)
+        );
+        // Repeated timestamps are not intervals
+        assert_eq!(
+            cadence(&[(0, 0.0), (0, 0.0), (0, 0.0), (30, 0.0)]),
+            Some(30.0)
+        );
+        assert_eq!(cadence(&[(0, 0.0), (0, 1.0)]), None);
+    }
+
+    #[test]
+    fn keeps_windows_without_a_cadence() {
+        let window = points(&[(0, 1.0), (0, 2.0)]);
+        assert_close(Resample::default().apply(&window), &[1.0, 2.0]);
+    }
+
+    #[test]
+    fn ignores_invalid_configured_cadences() {
+        for cadence_seconds in [0.0, -60.0] {
+            let resample = Resample {
+                method: Method::Linear,
+                cadence_seconds: Some(cadence_seconds),
+            };
+            assert_close(resample.apply(&gap()), &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        }
+    }
+
+    #[test]
+    fn limits_the_length_of_the_grid() {
+        // The cadence of one second would give a million values
+        let window = points(&[(0, 0.0), (1, 1.0), (2, 2.0), (1_000_000, 3.0)]);
+        let series = Resample::default().apply(&window);
+        assert_eq!(series.len(), MAX_POINTS);
+        assert_eq!(series.last(), Some(&3.0));
+    }
+
+    // Windows without points, or whose values are all strings, have no
+    // first and last point to build the grid between
+    #[test]
+    fn keeps_windows_too_short_for_a_grid() {
+        assert_eq!(time_weighted(60.0).apply(&[]), Vec::<f32>::new());
+        let point = (DateTime::from_timestamp(0, 0).unwrap(), 1.0);
+        assert_eq!(time_weighted(60.0).apply(&[point]), vec![1.0]);
+    }
+}
@@ -0,0 +1,98 @@
+// This module aggregates a forecast at coarser resolutions, e.g. the
+// mean of every hour and every day of a forecast with 15 minute steps,
+// so that clients on constrained devices do not have to do this
+// themselves. It is requested with the `resolutions` parameter of the
+// forecast, e.g. `?horizon=96&resolutions=hour,day`, and the response
+// then has the form
+//
+// { "result": <forecast>, "resolutions": { "hour": [{ "start": "...",
+//   "mean": 1.5, "min": 1.0, "max": 2.0, "count": 4 }, ...], "day": ... } }
+//
+// Periods are aligned to full hours and days in UTC, so the first and
+// last period may only be covered partially, which is shown by `count`.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use serde::Serialize;
+
+use crate::error::Error;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    Hour,
+    Day,
+}
+
+impl Resolution {
+    fn period(self) -> TimeDelta {
+        match self {
+            Resolution::Hour => TimeDelta::hours(1),
+            Resolution::Day => TimeDelta::days(1),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Period {
+    start: DateTime<Utc>,
+    mean: f32,
+    min: f32,
+    max: f32,
+    // The number of forecast values in the period
+    count: usize,
+}
+
+// Parses a comma separated list like `hour,day`
+pub fn parse(resolutions: &str) -> Result<Vec<Resolution>, Error> {
+    resolutions
+        .split(',')
+        .map(|resolution| match resolution {
+            "hour" => Ok(Resolution::Hour),
+            "day" => Ok(Resolution::Day),
+            other => Err(Error::BadRequest(format!(
+                "Invalid resolution {other}, must be hour or day"
+            ))),
+        })
+        .collect()
+}
+
+// Aggregates the forecast values at each of the resolutions
+pub fn aggregate(
+    resolutions: &[Resolution],
+    timestamps: &[DateTime<Utc>],
+    values: &[f32],
+) -> Result<BTreeMap<Resolution, Vec<Period>>, Error> {
+    resolutions
+        .iter()
+        .map(|&resolution| {
+            let mut periods: Vec<Period> = Vec::new();
+            for (timestamp, &value) in timestamps.iter().zip(values) {
+                let start = timestamp
+                    .duration_trunc(resolution.period())
+                    .map_err(|e| Error::internal(format!("Invalid timestamp {timestamp}: {e}")))?;
+                match periods.last_mut() {
+                    Some(period) if period.start == start => {
+                        // The mean is the sum until all values are added
+                        period.mean += value;
+                        period.min = period.min.min(value);
+                        period.max = period.max.max(value);
+                        period.count += 1;
+                    }
+                    _ => periods.push(Period {
+                        start,
+                        mean: value,
+                        min: value,
+                        max: value,
+                        count: 1,
+                    }),
+                }
+            }
+            for period in &mut periods {
+                period.mean /= period.count as f32;
+            }
+            Ok((resolution, periods))
+        })
+        .collect()
+}
@@ -0,0 +1,139 @@
+// This module deletes stored data once it is older than the retention
+// configured for it, so that devices running for years comply with
+// data-retention requirements. The optional `retention` config (see
+// config.rs) sets the retention of each kind of data in days:
+//
+// { "accuracy": { "soft_days": 7, "hard_days": 30 },
+//   "previous": { "soft_days": 1 },
+//   "usage": { "soft_days": 90, "hard_days": 90 } }
+//
+// - `accuracy`: The recorded forecasts and actual values of each series
+//   (see accuracy.rs)
+// - `previous`: The last forecast of each series (see previous.rs)
+// - `usage`: The usage of clients that have not sent a request since
+//   (see usage.rs)
+//
+// Data older than `soft_days` is deleted by a cleanup pass that runs
+// after the response to a request has been sent, at most once per
+// CLEANUP_INTERVAL, so that it does not delay the requests. On an idle
+// device, data can outlive its soft retention until the next request.
+// `hard_days` is the age data must never reach while a request is
+// handled: If the last pass was so long ago that data may be older than
+// that, the pass runs before the request is handled instead, and the
+// request fails if the pass does. Data without a retention is kept.
+// Traces are always deleted after 15 minutes (see trace.rs).
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{clock, config, error::Error, state};
+
+const CONFIG: &str = "retention";
+const STATE_FILE: &str = "retention.json";
+const CLEANUP_INTERVAL: TimeDelta = TimeDelta::hours(1);
+
+#[derive(Deserialize)]
+struct Retention {
+    accuracy: Option<Policy>,
+    previous: Option<Policy>,
+    usage: Option<Policy>,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+struct Policy {
+    soft_days: u32,
+    hard_days: Option<u32>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Cleanup {
+    last: Option<DateTime<Utc>>,
+}
+
+impl Retention {
+    fn policies(&self) -> impl Iterator<Item = &Policy> {
+        [&self.accuracy, &self.previous, &self.usage]
+            .into_iter()
+            .flatten()
+    }
+}
+
+impl Policy {
+    // The age after which the pass deletes data. A hard retention that
+    // is shorter than the soft one takes precedence.
+    fn max_age(&self) -> TimeDelta {
+        let days = self
+            .hard_days
+            .map_or(self.soft_days, |hard| hard.min(self.soft_days));
+        TimeDelta::days(days.into())
+    }
+
+    // Whether data may have reached the hard retention, given when the
+    // last pass deleted everything older than `max_age`
+    fn overdue(&self, last: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+        match (self.hard_days, last) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(hard), Some(last)) => {
+                self.max_age() + (now - last) >= TimeDelta::days(hard.into())
+            }
+        }
+    }
+}
+
+// Runs the cleanup pass before a request is handled if data may have
+// reached its hard retention
+pub fn enforce() -> Result<(), Error> {
+    let Some(retention) = config::load::<Retention>(CONFIG)? else {
+        return Ok(());
+    };
+    let now = clock::now();
+    let last = state::load::<Cleanup>(STATE_FILE)?.last;
+    if retention.policies().any(|policy| policy.overdue(last, now)) {
+        run(&retention, now)?;
+    }
+    Ok(())
+}
+
+// Runs the cleanup pass after a request if the last one was at least
+// CLEANUP_INTERVAL ago. The response has been sent at this point, so
+// errors are only logged.
+pub fn cleanup() {
+    let result = config::load::<Retention>(CONFIG).and_then(|retention| {
+        let Some(retention) = retention else {
+            return Ok(());
+        };
+        let now = clock::now();
+        let last = state::load::<Cleanup>(STATE_FILE)?.last;
+        if last.is_some_and(|last| now - last < CLEANUP_INTERVAL) {
+            return Ok(());
+        }
+        run(&retention, now)
+    });
+    if let Err(e) = result {
+        eprintln!("Error deleting expired data: {e}");
+    }
+}
+
+// Deletes the data that is older than its retention. Kinds of data
+// whose feature is not enabled are not stored, so there is nothing to
+// delete.
+#[cfg_attr(
+    not(any(feature = "accuracy", feature = "previous", feature = "usage")),
+    allow(unused_variables)
+)]
+fn run(retention: &Retention, now: DateTime<Utc>) -> Result<(), Error> {
+    #[cfg(feature = "accuracy")]
+    if let Some(policy) = retention.accuracy {
+        crate::accuracy::expire(now - policy.max_age())?;
+    }
+    #[cfg(feature = "previous")]
+    if let Some(policy) = retention.previous {
+        crate::previous::expire(now - policy.max_age())?;
+    }
+    #[cfg(feature = "usage")]
+    if let Some(policy) = retention.usage {
+        crate::usage::expire(now - policy.max_age())?;
+    }
+    state::save(STATE_FILE, &Cleanup { last: Some(now) })
+}
@@ -0,0 +1,61 @@
+// This module assigns series to forecasting models, so that a single
+// component can e.g. forecast temperatures with one model and energy
+// consumption with another, without the clients having to choose. The
+// rules are read from the `routing` config (see config.rs), the first
+// rule whose pattern matches the series id wins:
+//
+// [{ "series": "temperature-*", "model": "models/temperature.onnx" },
+//  { "series": "energy-*", "model": "models/energy.onnx" }]
+//
+// Patterns may contain `*`, which matches any sequence of characters.
+// The models must have the same input and output tensors as the
+// default model, which is used for series without a matching rule and
+// for forecasts without series id.
+
+use std::sync::Arc;
+
+use serde::Deserialize;
+use wasi::http::types::ErrorCode;
+
+use crate::{config, nn::Graph, series_matches, MODEL_FORMAT};
+
+const CONFIG: &str = "routing";
+
+#[derive(Deserialize)]
+struct Rule {
+    series: String,
+    model: String,
+}
+
+// Loads the model assigned to the series
+pub fn load_graph(series: Option<&str>) -> Result<Arc<Graph>, ErrorCode> {
+    let Some(series) = series else {
+        return crate::load_graph();
+    };
+    let rules: Vec<Rule> = config::load(CONFIG)
+        .map_err(|e| ErrorCode::InternalError(Some(e.to_string())))?
+        .unwrap_or_default();
+
+    match rules
+        .iter()
+        .find(|rule| series_matches(&rule.series, series))
+    {
+        // Series of a disabled model (or one that exceeds its limits)
+        // are forecast by the default model (see admin.rs)
+        #[cfg(feature = "admin")]
+        Some(rule) if crate::admin::check(std::slice::from_ref(&rule.model)).is_err() => {
+            crate::load_graph()
+        }
+        Some(rule) => crate::load_model(MODEL_FORMAT, &[&rule.model]),
+        None => crate::load_graph(),
+    }
+}
+
+// The models of all rules
+#[cfg_attr(not(feature = "admin"), allow(dead_code))]
+pub fn models() -> Result<Vec<String>, ErrorCode> {
+    let rules: Vec<Rule> = config::load(CONFIG)
+        .map_err(|e| ErrorCode::InternalError(Some(e.to_string())))?
+        .unwrap_or_default();
+    Ok(rules.into_iter().map(|rule| rule.model).collect())
+}
@@ -0,0 +1,171 @@
+// This module downloads the model files from S3-compatible object
+// storage (AWS S3, MinIO, Ceph, ...), so that new models can be rolled
+// out centrally without rebuilding the component or the device image.
+// A sync is triggered with `POST /models/sync` or, in batch mode, with
+// the `--sync-models` argument (e.g. from cron). Files are only
+// downloaded if their ETag changed since the last sync, and they
+// replace the local files atomically, so requests never see a
+// half-written model. The models directory must therefore be writable.
+//
+// The bucket is configured in the `s3` config (see config.rs), the
+// credentials are read from the usual
+// environment variables (`--env AWS_ACCESS_KEY_ID` etc.):
+//
+// { "endpoint": "https://s3.eu-central-1.amazonaws.com",
+//   "region": "eu-central-1", "bucket": "edge-models", "prefix": "forecast/v2/" }
+
+mod sigv4;
+
+use std::{env, fs, io};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use wasi::http::types::Method;
+
+use crate::{config, error::Error, model, outgoing};
+
+const CONFIG: &str = "s3";
+
+#[derive(Deserialize)]
+struct Source {
+    endpoint: String,
+    region: String,
+    bucket: String,
+    // Prepended to the file names to get the object keys
+    #[serde(default)]
+    prefix: String,
+    // The model files to download, relative to the models directory.
+    // By default, these are the files of the forecast model.
+    files: Option<Vec<String>>,
+}
+
+#[derive(Default, Serialize)]
+pub struct SyncReport {
+    updated: Vec<String>,
+    unchanged: Vec<String>,
+}
+
+pub fn sync_models() -> Result<SyncReport, Error> {
+    let source: Source = config::require(CONFIG)?;
+    let credentials = credentials()?;
+
+    let files = match source.files.clone() {
+        Some(files) => files,
+        None => model::spec()?
+            .files
+            .iter()
+            .map(|path| path.trim_start_matches("models/").to_string())
+            .collect(),
+    };
+
+    let mut report = SyncReport::default();
+    for file in files {
+        if sync_file(&source, &credentials, &file)? {
+            report.updated.push(file);
+        } else {
+            report.unchanged.push(file);
+        }
+    }
+
+    // Models that were loaded before are outdated now
+    if !report.updated.is_empty() {
+        crate::unload_models();
+    }
+    Ok(report)
+}
+
+// Downloads a single file unless it is unchanged. Returns whether the
+// local file was replaced.
+fn sync_file(source: &Source, credentials: &sigv4::Credentials, file: &str) -> Result<bool, Error> {
+    if file.split('/').any(|segment| segment == "..") {
+        return Err(Error::internal(format!("Invalid model file {file}")));
+    }
+    let path = format!("models/{file}");
+    let etag_path = format!("{path}.etag");
+
+    // Path-style addressing works with every S3-compatible storage,
+    // virtual-hosted-style does not
+    let endpoint = source.endpoint.trim_end_matches('/');
+    let authority = endpoint
+        .split_once("://")
+        .map_or(endpoint, |(_, rest)| rest);
+    let object_path = format!(
+        "/{}/{}",
+        encode_path(&source.bucket),
+        encode_path(&format!("{}{file}", source.prefix))
+    );
+
+    let mut headers = sigv4::sign(
+        credentials,
+        &source.region,
+        "GET",
+        authority,
+        &object_path,
+        // Always the wall clock, since S3 rejects requests that were
+        // signed at a different time (see clock.rs)
+        Utc::now(),
+    );
+    match fs::read(&etag_path) {
+        Ok(etag) => headers.push(("if-none-match".to_string(), etag)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(Error::internal(format!("Error reading {etag_path}: {e}"))),
+    }
+
+    let url = format!("{endpoint}{object_path}");
+    let response = outgoing::request(Method::Get, &url, &headers, &[])?;
+    match response.status {
+        200 => {}
+        304 => return Ok(false),
+        status => {
+            // S3 explains errors in the body
+            return Err(Error::internal(format!(
+                "Downloading {url} failed with {status}: {}",
+                String::from_utf8_lossy(&response.body)
+            )));
+        }
+    }
+
+    // Like the state files, the model is written to a temporary file
+    // first, so that it is never left half-written
+    let temporary = format!("{path}.tmp");
+    fs::write(&temporary, &response.body)
+        .and_then(|()| fs::rename(&temporary, &path))
+        .map_err(|e| Error::internal(format!("Error writing {path}: {e}")))?;
+    if let Some(etag) = response.header("etag") {
+        fs::write(&etag_path, etag)
+            .map_err(|e| Error::internal(format!("Error writing {etag_path}: {e}")))?;
+    }
+    Ok(true)
+}
+
+fn credentials() -> Result<sigv4::Credentials, Error> {
+    let var = |name: &str| {
+        env::var(name).map_err(|_| Error::internal(format!("Missing environment variable {name}")))
+    };
+    Ok(sigv4::Credentials {
+        access_key_id: var("AWS_ACCESS_KEY_ID")?,
+        secret_access_key: var("AWS_SECRET_ACCESS_KEY")?,
+        session_token: env::var("AWS_SESSION_TOKEN").ok(),
+    })
+}
+
+// URI-encodes everything except unreserved characters and the slashes
+// that separate the segments of the key, as Signature Version 4
+// expects for S3
+fn encode_path(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            byte => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+pub fn report_to_vec(report: &SyncReport) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(report)
+        .map_err(|e| Error::internal(format!("Error serializing sync report: {e}")))
+}
@@ -0,0 +1,181 @@
+// AWS Signature Version 4 for requests without a body, as described in
+// https://docs.aws.amazon.com/IAM/latest/UserGuide/reference_sigv_create-signed-request.html
+// S3-compatible storage like MinIO or Ceph accepts the same signatures.
+
+use chrono::{DateTime, Utc};
+
+use crate::sha256::{hex, hmac_sha256, sha256};
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+const SERVICE: &str = "s3";
+
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    // Only needed for temporary credentials
+    pub session_token: Option<String>,
+}
+
+// Returns the headers that authenticate a request with the given
+// method, authority and (already URI-encoded) path. The host header is
+// part of the signature, but not returned, since wasi-http sets it from
+// the authority of the request.
+pub fn sign(
+    credentials: &Credentials,
+    region: &str,
+    method: &str,
+    authority: &str,
+    path: &str,
+    now: DateTime<Utc>,
+) -> Vec<(String, Vec<u8>)> {
+    let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let payload_hash = hex(&sha256(b""));
+
+    // The headers must be sorted by name
+    let mut headers = vec![
+        ("host".to_string(), authority.to_string()),
+        ("x-amz-content-sha256".to_string(), payload_hash.clone()),
+        ("x-amz-date".to_string(), timestamp.clone()),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token".to_string(), token.clone()));
+    }
+
+    let signed_headers = signed_headers(&headers);
+    let canonical_request = canonical_request(method, path, &headers, &payload_hash);
+    let scope = format!("{date}/{region}/{SERVICE}/aws4_request");
+    let signature = signature(
+        &credentials.secret_access_key,
+        &timestamp,
+        &scope,
+        &canonical_request,
+    );
+
+    let authorization = format!(
+        "{ALGORITHM} Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+        credentials.access_key_id
+    );
+    headers.push(("authorization".to_string(), authorization));
+
+    headers
+        .into_iter()
+        .filter(|(name, _)| name != "host")
+        .map(|(name, value)| (name, value.into_bytes()))
+        .collect()
+}
+
+// The names of the headers that are signed, which must be sorted
+fn signed_headers(headers: &[(String, String)]) -> String {
+    headers
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+// The request as it is signed, for requests without a query string
+fn canonical_request(
+    method: &str,
+    path: &str,
+    headers: &[(String, String)],
+    payload_hash: &str,
+) -> String {
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect();
+    let signed_headers = signed_headers(headers);
+    format!("{method}\n{path}\n\n{canonical_headers}\n{signed_headers}\n{payload_hash}")
+}
+
+// The signature of the canonical request with a key that is derived
+// from the secret for the scope (date, region and service)
+fn signature(
+    secret_access_key: &str,
+    timestamp: &str,
+    scope: &str,
+    canonical_request: &str,
+) -> String {
+    let string_to_sign = format!(
+        "{ALGORITHM}\n{timestamp}\n{scope}\n{}",
+        hex(&sha256(canonical_request.as_bytes()))
+    );
+    let key = format!("AWS4{secret_access_key}");
+    let key = scope.split('/').fold(key.into_bytes(), |key, part| {
+        hmac_sha256(&key, part.as_bytes()).to_vec()
+    });
+    hex(&hmac_sha256(&key, string_to_sign.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The get-vanilla case of the AWS Signature Version 4 test suite
+    #[test]
+    fn signs_get_vanilla() {
+        let headers = [
+            ("host".to_string(), "example.amazonaws.com".to_string()),
+            ("x-amz-date".to_string(), "20150830T123600Z".to_string()),
+        ];
+        let canonical_request = canonical_request("GET", "/", &headers, &hex(&sha256(b"")));
+        assert_eq!(
+            canonical_request,
+            "GET\n/\n\nhost:example.amazonaws.com\nx-amz-date:20150830T123600Z\n\n\
+             host;x-amz-date\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&sha256(canonical_request.as_bytes())),
+            "bb579772317eb040ac9ed261061d46c1f17a8133879d6129b6e1c25292927e63"
+        );
+        assert_eq!(
+            signature(
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                "20150830T123600Z",
+                "20150830/us-east-1/service/aws4_request",
+                &canonical_request,
+            ),
+            "5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[test]
+    fn signs_s3_requests() {
+        let credentials = Credentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        };
+        let now = "2015-08-30T12:36:00Z".parse().unwrap();
+        let headers = sign(
+            &credentials,
+            "us-east-1",
+            "GET",
+            "example.com",
+            "/bucket/model.onnx",
+            now,
+        );
+        let headers: Vec<_> = headers
+            .into_iter()
+            .map(|(name, value)| (name, String::from_utf8(value).unwrap()))
+            .collect();
+        assert_eq!(
+            headers,
+            [
+                (
+                    "x-amz-content-sha256".to_string(),
+                    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string()
+                ),
+                ("x-amz-date".to_string(), "20150830T123600Z".to_string()),
+                (
+                    "authorization".to_string(),
+                    "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/s3/aws4_request, \
+                     SignedHeaders=host;x-amz-content-sha256;x-amz-date, \
+                     Signature=ce896689b3f754fe1bec7383196e25ce27980666b35e6a20acba4fa8d029794f"
+                        .to_string()
+                ),
+            ]
+        );
+    }
+}
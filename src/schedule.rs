@@ -0,0 +1,69 @@
+// This module runs a forecast when the component is invoked by a
+// scheduler, e.g. cron or a systemd timer running
+// `wasmtime run ... --scheduled` every 15 minutes (see cli.rs). Instead
+// of reading the data window from stdin, it is pulled from a data
+// source (e.g. the HTTP API of a historian), and the forecast is pushed
+// to a sink (e.g. a SCADA system) via outgoing HTTP requests.
+//
+// The URLs are configured in the `schedule` config (see config.rs):
+//
+// { "source": "http://historian.local/window", "sink": "http://scada.local/forecast",
+//   "series": "machine-1" }
+
+use serde::Deserialize;
+
+use crate::{
+    config, error::Error, forecast_timestamps, inference_result_from_values, json, model, outgoing,
+    with_handler,
+};
+
+const CONFIG: &str = "schedule";
+
+#[derive(Deserialize)]
+struct Schedule {
+    // Returns the latest data window on a GET request
+    source: String,
+    // Receives the forecast as a POST request
+    sink: String,
+    // The id of the series, which selects the model (see routing.rs)
+    // and is used when publishing the forecast (see publish.rs)
+    series: Option<String>,
+}
+
+pub fn run() -> Result<(), Error> {
+    let schedule: Schedule = config::require(CONFIG)?;
+
+    let response = outgoing::get(&schedule.source)?;
+    if !(200..300).contains(&response.status) {
+        return Err(Error::internal(format!(
+            "Data source {} responded with {}",
+            schedule.source, response.status
+        )));
+    }
+
+    let input = json::parse_data_window(&response.body)?;
+    let horizon = model::shape()?.prediction_len;
+    let timestamps = forecast_timestamps(&input, horizon);
+    let values = with_handler(|handler| {
+        handler.forecast_values(None, None, schedule.series.as_deref(), input, horizon)
+    })?;
+    let output = json::inference_result_to_vec(&inference_result_from_values(values, timestamps))?;
+
+    #[cfg(feature = "publish")]
+    if let Some(series) = &schedule.series {
+        crate::publish::publish_forecast(series, &output);
+    }
+
+    let status = outgoing::post(&schedule.sink, "application/json", &output)?;
+    if !(200..300).contains(&status) {
+        return Err(Error::internal(format!(
+            "Sink {} responded with {status}",
+            schedule.sink
+        )));
+    }
+
+    #[cfg(feature = "pushgateway")]
+    crate::pushgateway::push_metrics();
+
+    Ok(())
+}
@@ -0,0 +1,181 @@
+// This module implements a small vector store for similarity search
+// on the device, e.g. to match the vibration signature of a machine to
+// known fault patterns. `POST /embeddings/upsert` stores a vector under
+// an id, `POST /embeddings/search` returns the stored vectors that are
+// most similar to a query. Vectors can either be sent directly or
+// computed from a data window using the embedding model (see
+// embedding.rs).
+//
+// The store is a flat list that is searched exhaustively, which is
+// fast enough for the few thousand vectors an edge device will hold.
+// It is kept in the state directory (see state.rs).
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use wasi_nn_demo_lib::interface;
+
+use crate::{
+    embedding::{normalize, EmbeddingResult, EMBEDDING_DIM},
+    error::Error,
+    http::JsonBody,
+    state, HttpHandler,
+};
+
+const STATE_FILE: &str = "embeddings.json";
+// The number of matches returned by default and at most
+const DEFAULT_K: usize = 5;
+const MAX_K: usize = 100;
+
+#[derive(Default, Serialize, Deserialize)]
+struct Store {
+    entries: BTreeMap<String, Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    // Normalized to unit length
+    vector: Vec<f32>,
+    // Arbitrary data stored with the vector, e.g. the fault type
+    metadata: Option<serde_json::Value>,
+}
+
+// Either a vector or a data window to compute the vector from
+#[derive(Deserialize)]
+pub struct Query {
+    vector: Option<Vec<f32>>,
+    window: Option<interface::DataWindow>,
+}
+
+#[derive(Deserialize)]
+pub struct UpsertRequest {
+    id: String,
+    #[serde(flatten)]
+    query: Query,
+    metadata: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+pub struct SearchRequest {
+    #[serde(flatten)]
+    query: Query,
+    k: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct UpsertResult {
+    id: String,
+    // The number of vectors in the store
+    entries: usize,
+}
+
+#[derive(Serialize)]
+pub struct SearchResult {
+    matches: Vec<Match>,
+}
+
+#[derive(Serialize)]
+struct Match {
+    id: String,
+    // The cosine similarity to the query, from -1 to 1
+    score: f32,
+    metadata: Option<serde_json::Value>,
+}
+
+impl JsonBody for UpsertRequest {
+    const NAME: &'static str = "upsert request";
+}
+
+impl JsonBody for SearchRequest {
+    const NAME: &'static str = "search request";
+
+    fn check(self) -> Result<Self, Error> {
+        if self.k.is_some_and(|k| k == 0 || k > MAX_K) {
+            return Err(Error::BadRequest(format!(
+                "k must be between 1 and {MAX_K}"
+            )));
+        }
+        Ok(self)
+    }
+}
+
+pub fn upsert_result_to_vec(result: &UpsertResult) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(result)
+        .map_err(|e| Error::internal(format!("Error serializing upsert result: {e}")))
+}
+
+pub fn search_result_to_vec(result: &SearchResult) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(result)
+        .map_err(|e| Error::internal(format!("Error serializing search result: {e}")))
+}
+
+impl HttpHandler {
+    pub fn upsert_embedding(&mut self, request: UpsertRequest) -> Result<UpsertResult, Error> {
+        let vector = self.query_vector(request.query)?;
+
+        let mut store: Store = state::load(STATE_FILE)?;
+        store.entries.insert(
+            request.id.clone(),
+            Entry {
+                vector,
+                metadata: request.metadata,
+            },
+        );
+        state::save(STATE_FILE, &store)?;
+
+        Ok(UpsertResult {
+            id: request.id,
+            entries: store.entries.len(),
+        })
+    }
+
+    pub fn search_embeddings(&mut self, request: SearchRequest) -> Result<SearchResult, Error> {
+        let query = self.query_vector(request.query)?;
+        let store: Store = state::load(STATE_FILE)?;
+
+        // Since all vectors have unit length, their dot product is the
+        // cosine similarity
+        let mut matches: Vec<_> = store
+            .entries
+            .into_iter()
+            .map(|(id, entry)| Match {
+                id,
+                score: entry.vector.iter().zip(&query).map(|(a, b)| a * b).sum(),
+                metadata: entry.metadata,
+            })
+            .collect();
+        matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+        matches.truncate(request.k.unwrap_or(DEFAULT_K));
+
+        Ok(SearchResult { matches })
+    }
+
+    // Returns the normalized vector of the query, computing it with the
+    // embedding model if necessary
+    fn query_vector(&mut self, query: Query) -> Result<Vec<f32>, Error> {
+        match query {
+            Query {
+                vector: Some(vector),
+                window: None,
+            } => {
+                if vector.len() != EMBEDDING_DIM {
+                    return Err(Error::BadRequest(format!(
+                        "Vector must have {EMBEDDING_DIM} dimensions, got {}",
+                        vector.len()
+                    )));
+                }
+                Ok(normalize(&vector))
+            }
+            Query {
+                vector: None,
+                window: Some(window),
+            } => {
+                let EmbeddingResult::Embedding(vector) = self.embed(window)?;
+                Ok(vector)
+            }
+            _ => Err(Error::BadRequest(
+                "Either a vector or a window must be given".into(),
+            )),
+        }
+    }
+}
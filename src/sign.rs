@@ -0,0 +1,35 @@
+// This module signs response bodies, so that consumers further down
+// can verify that a forecast comes from this device and was not
+// modified in transit (e.g. by a proxy). The `X-Signature` header of
+// every response contains the HMAC-SHA256 of the body using the device
+// key, in the same format that signature.rs expects for requests:
+//
+// X-Signature: sha256=5d41402abc4b2a76b9719d911017c592...
+//
+// The key is read from the `sign` config (see config.rs):
+//
+// { "key": "..." }
+
+use serde::Deserialize;
+
+use crate::{
+    config,
+    error::Error,
+    sha256::{hex, hmac_sha256},
+};
+
+const CONFIG: &str = "sign";
+
+#[derive(Deserialize)]
+struct Sign {
+    key: String,
+}
+
+// Returns the value of the signature header for the body
+pub fn sign(body: &[u8]) -> Result<String, Error> {
+    let config: Sign = config::require(CONFIG)?;
+    Ok(format!(
+        "sha256={}",
+        hex(&hmac_sha256(config.key.as_bytes(), body))
+    ))
+}
@@ -0,0 +1,57 @@
+// This module verifies that requests were sent by someone who knows a
+// shared secret, which is how gateways usually sign webhook requests.
+// The `X-Signature` header must contain the HMAC-SHA256 of the request
+// body using the secret, hex encoded and optionally prefixed with
+// `sha256=`:
+//
+// X-Signature: sha256=5d41402abc4b2a76b9719d911017c592...
+//
+// The secret is read from the `signature` config (see config.rs):
+//
+// { "secret": "..." }
+//
+// Without the `replay` feature, only the body is signed, so a captured
+// request can be sent again. With it, the timestamp and nonce of the
+// request (see replay.rs) are signed as well, separated by dots, like
+// webhooks of Stripe or Slack:
+//
+// {X-Request-Timestamp}.{X-Request-Nonce}.{body}
+//
+// Otherwise, a captured request could be sent again with a new
+// timestamp and nonce, and replay protection would not protect
+// anything.
+
+use serde::Deserialize;
+
+use crate::{
+    config,
+    error::Error,
+    sha256::{constant_time_eq, hex, hmac_sha256},
+};
+
+const CONFIG: &str = "signature";
+
+#[derive(Deserialize)]
+struct Signature {
+    secret: String,
+}
+
+// Checks the signature header against the signed parts of the request,
+// which are joined with dots
+pub fn verify(signature: Option<&str>, parts: &[&[u8]]) -> Result<(), Error> {
+    let config: Signature = config::require(CONFIG)?;
+
+    let signature = signature
+        .ok_or_else(|| Error::Unauthorized("Missing X-Signature header".into()))?
+        .trim();
+    let signature = signature.strip_prefix("sha256=").unwrap_or(signature);
+    let expected = hex(&hmac_sha256(config.secret.as_bytes(), &parts.join(&b'.')));
+
+    if !constant_time_eq(
+        signature.to_ascii_lowercase().as_bytes(),
+        expected.as_bytes(),
+    ) {
+        return Err(Error::Unauthorized("Invalid X-Signature".into()));
+    }
+    Ok(())
+}
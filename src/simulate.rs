@@ -0,0 +1,160 @@
+// This module implements `POST /simulate`, which answers what-if
+// questions: The client sends a data window together with a number of
+// scenarios, each of which modifies the window in some way (e.g.
+// "+10% on the last 12 points"). The forecast for the unmodified
+// window and for each scenario are returned side by side.
+//
+// Since the model always processes `num_batches` batches at once (16
+// for the included model, see lib.rs), we can run the unmodified
+// window and up to 15 scenarios in a single inference.
+
+use serde::{Deserialize, Serialize};
+use wasi_nn_demo_lib::interface;
+
+use crate::{
+    error::Error, fit_to_history_len, http::JsonBody, load_graph, model, nn::Tensor,
+    numeric_data_points, HttpHandler,
+};
+
+#[derive(Deserialize)]
+pub struct SimulationRequest {
+    window: interface::DataWindow,
+    scenarios: Vec<Scenario>,
+}
+
+#[derive(Deserialize)]
+struct Scenario {
+    name: String,
+    modifications: Vec<Modification>,
+}
+
+// A modification of the values of the window. It applies to the
+// `last` n values, or to all values if `last` is not given.
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Modification {
+    // Multiply by a factor, e.g. 1.1 for +10%
+    Scale { factor: f32, last: Option<usize> },
+    // Add a constant
+    Offset { amount: f32, last: Option<usize> },
+    // Replace by a constant
+    Set { value: f32, last: Option<usize> },
+}
+
+impl Modification {
+    fn apply(&self, series: &mut [f32]) {
+        let (Modification::Scale { last, .. }
+        | Modification::Offset { last, .. }
+        | Modification::Set { last, .. }) = *self;
+        let start = last.map_or(0, |last| series.len().saturating_sub(last));
+
+        for x in &mut series[start..] {
+            *x = match *self {
+                Modification::Scale { factor, .. } => *x * factor,
+                Modification::Offset { amount, .. } => *x + amount,
+                Modification::Set { value, .. } => value,
+            };
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SimulationResult {
+    baseline: Vec<f32>,
+    scenarios: Vec<ScenarioResult>,
+}
+
+#[derive(Serialize)]
+struct ScenarioResult {
+    name: String,
+    forecast: Vec<f32>,
+    // The difference to the baseline forecast for each step
+    delta: Vec<f32>,
+}
+
+impl JsonBody for SimulationRequest {
+    const NAME: &'static str = "simulation request";
+
+    fn check(self) -> Result<Self, Error> {
+        #[cfg(feature = "usage")]
+        crate::usage::record_data_points(self.window.data.len());
+
+        // The unmodified window takes one of the batches
+        let max_scenarios = model::shape()?.num_batches as usize - 1;
+        if self.scenarios.len() > max_scenarios {
+            return Err(Error::BadRequest(format!(
+                "At most {max_scenarios} scenarios are supported"
+            )));
+        }
+        Ok(self)
+    }
+}
+
+pub fn result_to_vec(result: &SimulationResult) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(result)
+        .map_err(|e| Error::internal(format!("Error serializing simulation result: {e}")))
+}
+
+impl HttpHandler {
+    pub fn simulate(&mut self, request: SimulationRequest) -> Result<SimulationResult, Error> {
+        let baseline: Vec<_> = numeric_data_points(&request.window)
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect();
+
+        // The first batch is the unmodified window, followed by one
+        // batch per scenario. The remaining batches are filled with
+        // the unmodified window again.
+        let mut batches = vec![baseline.clone()];
+        for scenario in &request.scenarios {
+            let mut series = baseline.clone();
+            for modification in &scenario.modifications {
+                modification.apply(&mut series);
+            }
+            batches.push(series);
+        }
+        let spec = model::spec()?;
+        let model::Shape {
+            num_batches,
+            history_len,
+            prediction_len,
+        } = spec.shape;
+        batches.resize(num_batches as usize, baseline);
+
+        let mut data = Vec::with_capacity((num_batches * history_len) as usize);
+        for mut series in batches {
+            fit_to_history_len(&mut series, history_len);
+            data.extend(series);
+        }
+        let input_tensor = Tensor::new(data, vec![num_batches, history_len, 1]);
+
+        let graph = load_graph()?;
+        let ctx = graph.init_execution_context()?;
+        let output_tensors = &crate::infer(&graph, || {
+            ctx.run(
+                [(spec.input_tensor.as_str(), input_tensor)],
+                &[spec.output_tensor.as_str()],
+            )
+        })?;
+        let predictions = crate::output_rows(
+            &output_tensors[spec.output_tensor.as_str()],
+            num_batches,
+            prediction_len,
+        )?;
+
+        let baseline = predictions[0];
+        Ok(SimulationResult {
+            baseline: baseline.to_vec(),
+            scenarios: request
+                .scenarios
+                .into_iter()
+                .zip(&predictions[1..])
+                .map(|(scenario, forecast)| ScenarioResult {
+                    name: scenario.name,
+                    forecast: forecast.to_vec(),
+                    delta: forecast.iter().zip(baseline).map(|(f, b)| f - b).collect(),
+                })
+                .collect(),
+        })
+    }
+}
@@ -0,0 +1,73 @@
+// This module keeps the most recent forecast of each series, so that it
+// can be served while the device cannot make a new one, e.g. because
+// the model fails, the circuit breaker is open (see breaker.rs) or the
+// compute budget is used up (see budget.rs). Dashboards then keep
+// showing the last known outlook instead of an error while the device
+// recovers. The cached forecast is returned with 200 and marked as
+// stale by two headers: `Age` is the number of seconds since it was
+// made, and `Warning: 110 - "Response is Stale"`.
+//
+// Only forecasts for a series (with the `series` parameter) are cached,
+// since the series is what identifies the forecast a dashboard shows.
+// Errors caused by the request itself (e.g. an invalid window) are
+// returned as they are, and forecasts older than MAX_AGE are not
+// served, since they say more about the past than the future. The
+// forecasts are stored in the state directory (see state.rs).
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{clock, error::Error, state};
+
+const STATE_FILE: &str = "stale.json";
+const MAX_AGE: TimeDelta = TimeDelta::hours(24);
+
+#[derive(Serialize, Deserialize)]
+struct Cached {
+    created: DateTime<Utc>,
+    body: String,
+}
+
+// The response that is served instead of the error, with its age in
+// seconds
+pub struct Stale {
+    pub body: Vec<u8>,
+    pub age: i64,
+}
+
+// Stores the response body of a forecast for the series. The forecast
+// has been made at this point, so errors are only logged.
+pub fn store(series: &str, body: &[u8]) {
+    let result = state::load(STATE_FILE).and_then(|mut cached: BTreeMap<String, Cached>| {
+        cached.insert(
+            series.to_string(),
+            Cached {
+                created: clock::now(),
+                body: String::from_utf8_lossy(body).into_owned(),
+            },
+        );
+        state::save(STATE_FILE, &cached)
+    });
+    if let Err(e) = result {
+        eprintln!("Error caching the forecast of {series}: {e}");
+    }
+}
+
+// The cached forecast of the series, if the error means that the device
+// could not make a new one and there is a recent enough forecast
+pub fn serve(series: &str, error: &Error) -> Option<Stale> {
+    if !matches!(error, Error::Unavailable { .. } | Error::Internal(_)) {
+        return None;
+    }
+    let mut cached: BTreeMap<String, Cached> = state::load(STATE_FILE)
+        .inspect_err(|e| eprintln!("Error reading the cached forecasts: {e}"))
+        .ok()?;
+    let cached = cached.remove(series)?;
+    let age = clock::now() - cached.created;
+    (age <= MAX_AGE).then(|| Stale {
+        body: cached.body.into_bytes(),
+        age: age.num_seconds().max(0),
+    })
+}
@@ -0,0 +1,259 @@
+// Since the component does not keep state across requests (see the
+// comment on `HANDLER` in lib.rs), features that need to remember
+// something (e.g. accuracy tracking) store it as JSON in files in the
+// state directory. The directory must be preopened for the component
+// (`--dir state::state`).
+//
+// Hosts without a filesystem (e.g. wasmCloud) provide a key-value store
+// instead. With the `keyvalue` feature, the state is stored in the
+// BUCKET of the wasi:keyvalue store of the host, under the same names as
+// the files (e.g. `accuracy.json`). If the host has no such bucket, the
+// state directory is used after all, so that the same build also runs
+// with `wasmtime serve`. The same goes for hosts that cannot open the
+// bucket (see capabilities.rs).
+//
+// Instances of the component run concurrently, so state that is updated
+// by every request (e.g. the usage of clients) is locked while it is
+// loaded, updated and saved (see `update`). Otherwise, two instances
+// could load the same state, and the one that saves it last would undo
+// the update of the other.
+
+use std::{
+    fs::{self, OpenOptions},
+    io,
+    time::{Duration, Instant},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use wasi::clocks::monotonic_clock::subscribe_duration;
+
+use crate::error::Error;
+
+// How often an instance tries to take a lock that another one holds
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(5);
+// No update takes this long, so the instance that holds the lock must
+// have been killed before it could release it
+const LOCK_STALE_AFTER: Duration = Duration::from_secs(10);
+
+// Reads the state with the given name. If nothing has been stored yet,
+// the default is returned.
+pub fn load<T: DeserializeOwned + Default>(name: &str) -> Result<T, Error> {
+    match read(name)? {
+        Some(contents) => serde_json::from_slice(&contents)
+            .map_err(|e| Error::internal(format!("Invalid state in {name}: {e}"))),
+        None => Ok(T::default()),
+    }
+}
+
+pub fn save<T: Serialize>(name: &str, state: &T) -> Result<(), Error> {
+    let contents = serde_json::to_vec(state)
+        .map_err(|e| Error::internal(format!("Error serializing state: {e}")))?;
+    write(name, &contents)
+}
+
+// Loads the state with the given name, updates it and saves it again,
+// while no other instance can update it. Nothing is saved if the update
+// fails.
+#[cfg_attr(
+    not(any(feature = "budget", feature = "replay", feature = "usage")),
+    allow(dead_code)
+)]
+pub fn update<T, R>(name: &str, update: impl FnOnce(&mut T) -> Result<R, Error>) -> Result<R, Error>
+where
+    T: Serialize + DeserializeOwned + Default,
+{
+    let _lock = Lock::take(name)?;
+    let mut state = load(name)?;
+    let result = update(&mut state)?;
+    save(name, &state)?;
+    Ok(result)
+}
+
+// The lock of a state, which is held until it is dropped. It is a file
+// next to the state file (or a key next to the state) that only one
+// instance can create.
+struct Lock {
+    name: String,
+}
+
+impl Lock {
+    fn take(name: &str) -> Result<Self, Error> {
+        let name = format!("{name}.lock");
+        let mut start = Instant::now();
+        while !try_lock(&name)? {
+            if start.elapsed() > LOCK_STALE_AFTER {
+                eprintln!("Breaking stale lock {name}");
+                unlock(&name)?;
+                start = Instant::now();
+                continue;
+            }
+            subscribe_duration(LOCK_POLL_INTERVAL.as_nanos() as u64).block();
+        }
+        Ok(Self { name })
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let Err(e) = unlock(&self.name) {
+            eprintln!("Error releasing lock {}: {e}", self.name);
+        }
+    }
+}
+
+#[cfg(not(feature = "keyvalue"))]
+fn try_lock(name: &str) -> Result<bool, Error> {
+    try_lock_file(name)
+}
+
+#[cfg(not(feature = "keyvalue"))]
+fn unlock(name: &str) -> Result<(), Error> {
+    unlock_file(name)
+}
+
+#[cfg(not(feature = "keyvalue"))]
+fn read(name: &str) -> Result<Option<Vec<u8>>, Error> {
+    read_file(name)
+}
+
+#[cfg(not(feature = "keyvalue"))]
+fn write(name: &str, contents: &[u8]) -> Result<(), Error> {
+    write_file(name, contents)
+}
+
+fn read_file(name: &str) -> Result<Option<Vec<u8>>, Error> {
+    let path = format!("state/{name}");
+    match fs::read(&path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::internal(format!("Error reading {path}: {e}"))),
+    }
+}
+
+fn write_file(name: &str, contents: &[u8]) -> Result<(), Error> {
+    let path = format!("state/{name}");
+    // Writing to a temporary file first makes sure that the state file
+    // is never left half-written
+    let temporary = format!("{path}.tmp");
+    fs::write(&temporary, contents)
+        .and_then(|()| fs::rename(&temporary, &path))
+        .map_err(|e| Error::internal(format!("Error writing {path}: {e}")))
+}
+
+fn try_lock_file(name: &str) -> Result<bool, Error> {
+    let path = format!("state/{name}");
+    match OpenOptions::new().write(true).create_new(true).open(&path) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(Error::internal(format!("Error creating {path}: {e}"))),
+    }
+}
+
+fn unlock_file(name: &str) -> Result<(), Error> {
+    let path = format!("state/{name}");
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(Error::internal(format!("Error removing {path}: {e}")))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(feature = "keyvalue")]
+mod bindings {
+    wit_bindgen::generate!({
+        path: "wit",
+        world: "keyvalue-store",
+        generate_all,
+    });
+}
+
+#[cfg(feature = "keyvalue")]
+use bindings::wasi::keyvalue::{atomics, store};
+
+// The bucket of the key-value store that holds the state. Spin and
+// wasmCloud both provide a bucket with this name by default.
+#[cfg(feature = "keyvalue")]
+const BUCKET: &str = "default";
+
+#[cfg(feature = "keyvalue")]
+fn read(name: &str) -> Result<Option<Vec<u8>>, Error> {
+    match bucket()? {
+        Some(bucket) => bucket
+            .get(name)
+            .map_err(|e| store_error(format!("Error reading state {name}"), e)),
+        None => read_file(name),
+    }
+}
+
+#[cfg(feature = "keyvalue")]
+fn write(name: &str, contents: &[u8]) -> Result<(), Error> {
+    match bucket()? {
+        // A value is always replaced as a whole, so it cannot be left
+        // half-written
+        Some(bucket) => bucket
+            .set(name, contents)
+            .map_err(|e| store_error(format!("Error writing state {name}"), e)),
+        None => write_file(name, contents),
+    }
+}
+
+// The key of a lock is incremented by every instance that tries to take
+// it, so only the instance that creates it gets 1
+#[cfg(feature = "keyvalue")]
+fn try_lock(name: &str) -> Result<bool, Error> {
+    match bucket()? {
+        Some(bucket) => atomics::increment(&bucket, name, 1)
+            .map(|count| count == 1)
+            .map_err(|e| store_error(format!("Error taking lock {name}"), e)),
+        None => try_lock_file(name),
+    }
+}
+
+#[cfg(feature = "keyvalue")]
+fn unlock(name: &str) -> Result<(), Error> {
+    match bucket()? {
+        Some(bucket) => bucket
+            .delete(name)
+            .map_err(|e| store_error(format!("Error releasing lock {name}"), e)),
+        None => unlock_file(name),
+    }
+}
+
+// The bucket, or `None` if the host does not have it and the state
+// directory is used instead
+#[cfg(feature = "keyvalue")]
+fn bucket() -> Result<Option<store::Bucket>, Error> {
+    #[cfg(feature = "capabilities")]
+    if !probe() {
+        return Ok(None);
+    }
+    match store::open(BUCKET) {
+        Ok(bucket) => Ok(Some(bucket)),
+        Err(store::Error::NoSuchStore) => Ok(None),
+        Err(e) => Err(store_error(format!("Error opening bucket {BUCKET}"), e)),
+    }
+}
+
+// Whether the bucket can be opened, probed once per instance (see
+// capabilities.rs)
+#[cfg(all(feature = "keyvalue", feature = "capabilities"))]
+pub fn probe() -> bool {
+    static AVAILABLE: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+
+    *AVAILABLE.get_or_init(|| {
+        store::open(BUCKET)
+            .inspect_err(|e| eprintln!("Bucket {BUCKET} not available, using files instead: {e:?}"))
+            .is_ok()
+    })
+}
+
+#[cfg(feature = "keyvalue")]
+fn store_error(context: String, error: store::Error) -> Error {
+    let message = match error {
+        store::Error::NoSuchStore => "no such store".to_string(),
+        store::Error::AccessDenied => "access denied".to_string(),
+        store::Error::Other(message) => message,
+    };
+    Error::internal(format!("{context}: {message}"))
+}
@@ -0,0 +1,96 @@
+// This module stitches the forecasts of a series into a single
+// continuous forecast, for clients that request forecasts more often
+// than the horizon is long. Each forecast with a `series` parameter and
+// timestamps (see `forecast_timestamps` in lib.rs) is kept, and
+// `GET /forecast/{series}/stitched` returns the steps of the newest one, each
+// blended with the values older forecasts predicted for the same
+// timestamp. Older forecasts weigh less: each one half as much as the
+// next newer one, so the blend smooths the jumps between consecutive
+// forecasts while following the newest one.
+//
+// The result has the format of a forecast. Only the last MAX_FORECASTS
+// forecasts of each series are kept, in the state directory (see
+// state.rs), and those that do not overlap the newest one are dropped.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use wasi_nn_demo_lib::interface;
+
+use crate::{clock, error::Error, inference_result_from_values, state};
+
+const STATE_FILE: &str = "stitch.json";
+const MAX_FORECASTS: usize = 8;
+// The weight of a forecast relative to the next newer one
+const DECAY: f32 = 0.5;
+
+#[derive(Serialize, Deserialize)]
+struct Forecast {
+    created: DateTime<Utc>,
+    timestamps: Vec<DateTime<Utc>>,
+    values: Vec<f32>,
+}
+
+// Keeps the forecast of the series for stitching, if it has timestamps
+pub fn store(
+    series: &str,
+    timestamps: Option<Vec<DateTime<Utc>>>,
+    values: &[f32],
+) -> Result<(), Error> {
+    let Some(timestamps) = timestamps else {
+        return Ok(());
+    };
+    let mut all_forecasts: BTreeMap<String, Vec<Forecast>> = state::load(STATE_FILE)?;
+    let forecasts = all_forecasts.entry(series.to_string()).or_default();
+    // Forecasts that end before the new one starts have nothing to add
+    if let Some(&start) = timestamps.first() {
+        forecasts.retain(|forecast| forecast.timestamps.last() >= Some(&start));
+    }
+    forecasts.push(Forecast {
+        created: clock::now(),
+        timestamps,
+        values: values.to_vec(),
+    });
+    let excess = forecasts.len().saturating_sub(MAX_FORECASTS);
+    forecasts.drain(..excess);
+    state::save(STATE_FILE, &all_forecasts)
+}
+
+// The stitched forecast of the series
+pub fn stitched(path: &str) -> Result<interface::InferenceResult, Error> {
+    let series = path
+        .strip_prefix("/forecast/")
+        .and_then(|path| path.strip_suffix("/stitched"))
+        .unwrap_or_default();
+    let mut all_forecasts: BTreeMap<String, Vec<Forecast>> = state::load(STATE_FILE)?;
+    let forecasts = all_forecasts.remove(series).unwrap_or_default();
+    let Some((newest, older)) = forecasts.split_last() else {
+        return Err(Error::NotFound(format!("No forecast of {series}")));
+    };
+
+    // The weighted sum of the values for each timestamp, and the sum of
+    // the weights
+    let mut blend: BTreeMap<DateTime<Utc>, (f32, f32)> = newest
+        .timestamps
+        .iter()
+        .zip(&newest.values)
+        .map(|(&timestamp, &value)| (timestamp, (value, 1.0)))
+        .collect();
+    let mut weight = 1.0;
+    for forecast in older.iter().rev() {
+        weight *= DECAY;
+        for (timestamp, &value) in forecast.timestamps.iter().zip(&forecast.values) {
+            if let Some((sum, weights)) = blend.get_mut(timestamp) {
+                *sum += weight * value;
+                *weights += weight;
+            }
+        }
+    }
+
+    let (timestamps, values) = blend
+        .into_iter()
+        .map(|(timestamp, (sum, weights))| (timestamp, sum / weights))
+        .unzip();
+    Ok(inference_result_from_values(values, Some(timestamps)))
+}
@@ -0,0 +1,202 @@
+// This module serves forecasts over a plain TCP connection using the
+// wasi:sockets interfaces, for equipment that can write lines to a
+// socket but does not speak HTTP. It is started from batch mode with
+// `--listen <address>:<port>` (see cli.rs), and the host must allow
+// the component to use the network (`wasmtime run -S inherit-network`).
+//
+// The protocol is line based: The client sends one record per line,
+// consisting of an RFC 3339 timestamp and a value separated by
+// whitespace. The most recent records of a connection make up its data
+// window. An empty line requests a forecast for the window, which is
+// sent back as lines in the same format, terminated by an empty line.
+// If something goes wrong, a single line starting with `error` is sent
+// instead. Connections are served one after another.
+
+use std::{collections::VecDeque, net::SocketAddr};
+
+use chrono::{DateTime, Utc};
+use wasi::{
+    io::streams::{InputStream, OutputStream, StreamError},
+    sockets::{
+        instance_network::instance_network,
+        network::{IpAddressFamily, IpSocketAddress, Ipv4SocketAddress, Ipv6SocketAddress},
+        tcp::ErrorCode,
+        tcp_create_socket::create_tcp_socket,
+    },
+};
+use wasi_nn_demo_lib::interface;
+
+use crate::{error::Error, forecast_timestamps, model, with_handler};
+
+// Longer lines are rejected, so that a client cannot make us buffer an
+// unbounded amount of data
+const MAX_LINE_LEN: usize = 256;
+// wasi-io does not allow writing more than 4096 bytes at once
+const CHUNK_SIZE: usize = 4096;
+
+// Listens on the address and serves connections until the listener
+// fails. Errors on a single connection only close that connection.
+pub fn listen(address: &str) -> Result<(), String> {
+    let address: SocketAddr = address
+        .parse()
+        .map_err(|e| format!("Invalid listen address {address}: {e}"))?;
+    let (family, local_address) = match address {
+        SocketAddr::V4(address) => {
+            let [a, b, c, d] = address.ip().octets();
+            let local_address = IpSocketAddress::Ipv4(Ipv4SocketAddress {
+                port: address.port(),
+                address: (a, b, c, d),
+            });
+            (IpAddressFamily::Ipv4, local_address)
+        }
+        SocketAddr::V6(address) => {
+            let [a, b, c, d, e, f, g, h] = address.ip().segments();
+            let local_address = IpSocketAddress::Ipv6(Ipv6SocketAddress {
+                port: address.port(),
+                flow_info: address.flowinfo(),
+                address: (a, b, c, d, e, f, g, h),
+                scope_id: address.scope_id(),
+            });
+            (IpAddressFamily::Ipv6, local_address)
+        }
+    };
+
+    let socket_error = |e: ErrorCode| format!("Error listening on {address}: {e}");
+    let socket = create_tcp_socket(family).map_err(socket_error)?;
+    let network = instance_network();
+    socket
+        .start_bind(&network, local_address)
+        .map_err(socket_error)?;
+    socket.subscribe().block();
+    socket.finish_bind().map_err(socket_error)?;
+    socket.start_listen().map_err(socket_error)?;
+    socket.subscribe().block();
+    socket.finish_listen().map_err(socket_error)?;
+    eprintln!("Listening on {address}");
+
+    loop {
+        socket.subscribe().block();
+        let (connection, input, output) = match socket.accept() {
+            Ok(accepted) => accepted,
+            Err(ErrorCode::WouldBlock) => continue,
+            Err(e) => return Err(socket_error(e)),
+        };
+        if let Err(e) = serve(&input, &output) {
+            eprintln!("Error serving connection: {e}");
+        }
+
+        // The streams are children of the connection and must be
+        // dropped first
+        drop((input, output));
+        drop(connection);
+    }
+}
+
+// Handles the lines of one connection until the client closes it
+fn serve(input: &InputStream, output: &OutputStream) -> Result<(), String> {
+    // The model only looks at the last `history_len` values, so older
+    // records are dropped
+    let shape = model::shape().map_err(|e| Error::from(e).to_string())?;
+    let max_records = shape.history_len as usize;
+    let mut records = VecDeque::with_capacity(max_records);
+    let mut buffer = Vec::new();
+
+    loop {
+        let chunk = match input.blocking_read(CHUNK_SIZE as u64) {
+            Ok(chunk) => chunk,
+            Err(StreamError::Closed) => return Ok(()),
+            Err(StreamError::LastOperationFailed(e)) => {
+                return Err(format!("Error reading: {}", e.to_debug_string()))
+            }
+        };
+        buffer.extend_from_slice(&chunk);
+
+        while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+
+            if line.is_empty() {
+                let response = match forecast(&records, shape.prediction_len) {
+                    Ok(forecast) => forecast,
+                    Err(e) => format!("error {e}\n"),
+                };
+                write(output, response.as_bytes())?;
+                continue;
+            }
+
+            match parse_record(line) {
+                Ok(record) => {
+                    if records.len() == max_records {
+                        records.pop_front();
+                    }
+                    records.push_back(record);
+                }
+                Err(e) => write(output, format!("error {e}\n").as_bytes())?,
+            }
+        }
+
+        if buffer.len() > MAX_LINE_LEN {
+            write(output, b"error Line too long\n")?;
+            return Ok(());
+        }
+    }
+}
+
+// Parses a line like `2024-05-01T12:00:00Z 42.5`
+fn parse_record(line: &str) -> Result<(DateTime<Utc>, f32), String> {
+    let (timestamp, value) = line
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| format!("Expected a timestamp and a value: {line}"))?;
+    let timestamp = DateTime::parse_from_rfc3339(timestamp)
+        .map_err(|e| format!("Invalid timestamp {timestamp}: {e}"))?
+        .with_timezone(&Utc);
+    let value = value
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid value {value}: {e}"))?;
+    Ok((timestamp, value))
+}
+
+// Forecasts the records and formats the forecast as response lines
+fn forecast(records: &VecDeque<(DateTime<Utc>, f32)>, horizon: u32) -> Result<String, String> {
+    let data = records
+        .iter()
+        .enumerate()
+        .map(|(i, &(timestamp, value))| {
+            let data_point = interface::DataPoint {
+                quality: None,
+                value: interface::Value::Number(value),
+                timestamp: Some(timestamp),
+            };
+            (i.to_string(), data_point)
+        })
+        .collect();
+    let input = interface::DataWindow { data };
+
+    let timestamps = forecast_timestamps(&input, horizon)
+        .ok_or("The window needs at least two records with increasing timestamps")?;
+    let values = with_handler(|handler| handler.forecast_values(None, None, None, input, horizon))
+        .map_err(|e| Error::from(e).to_string())?;
+
+    let mut response = String::new();
+    for (timestamp, value) in timestamps.iter().zip(values) {
+        response.push_str(&format!("{} {value}\n", timestamp.to_rfc3339()));
+    }
+    response.push('\n');
+    Ok(response)
+}
+
+fn write(output: &OutputStream, bytes: &[u8]) -> Result<(), String> {
+    for chunk in bytes.chunks(CHUNK_SIZE) {
+        output
+            .blocking_write_and_flush(chunk)
+            .map_err(|e| match e {
+                StreamError::LastOperationFailed(e) => {
+                    format!("Error writing: {}", e.to_debug_string())
+                }
+                StreamError::Closed => "Connection closed".to_string(),
+            })?;
+    }
+    Ok(())
+}
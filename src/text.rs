@@ -0,0 +1,100 @@
+// This module adds a text classification model to the component (e.g.
+// a sentiment or intent classifier based on RoBERTa). The text is
+// split into tokens using the tokenizer of the model (see
+// tokenizer.rs), which is served by `POST /classify-text`.
+
+use crate::{
+    error::Error,
+    labels::{self, Label},
+    nn::{GraphEncoding, Tensor},
+    tokenizer::Tokenizer,
+    HttpHandler,
+};
+
+// These constants are the parameters that are specific to the text
+// model. No such model is included in this repository, place your own
+// model, the files of its tokenizer and its labels (one per line, in
+// the order of the model output) in the models directory.
+const MODEL_FORMAT: GraphEncoding = GraphEncoding::Onnx;
+const MODEL_FILES: [&str; 1] = ["models/text.onnx"];
+const VOCAB_FILE: &str = "models/vocab.json";
+const MERGES_FILE: &str = "models/merges.txt";
+const LABELS_FILE: &str = "models/text-labels.txt";
+// The labels of the input and output tensors in the model
+const INPUT_IDS_TENSOR_NAME: &str = "input_ids";
+const ATTENTION_MASK_TENSOR_NAME: &str = "attention_mask";
+const OUTPUT_TENSOR_NAME: &str = "logits";
+// The special tokens that mark the beginning and end of the text, and
+// that are used for padding
+const BOS_TOKEN: &str = "<s>";
+const EOS_TOKEN: &str = "</s>";
+const PAD_TOKEN: &str = "<pad>";
+// The input is padded (or truncated) to this number of tokens
+const SEQUENCE_LEN: u32 = 128;
+// The number of classes the model distinguishes, and the number of
+// most likely ones that we return
+const NUM_CLASSES: usize = 2;
+const TOP_K: usize = 2;
+
+impl HttpHandler {
+    // This is the equivalent of `handle_data` for text
+    pub fn classify_text(&mut self, text: &str) -> Result<Vec<Label>, Error> {
+        let tokenizer = Tokenizer::from_files(VOCAB_FILE, MERGES_FILE)?;
+
+        let graph = crate::load_model(MODEL_FORMAT, &MODEL_FILES)?;
+        let ctx = graph.init_execution_context()?;
+
+        let (input_ids, attention_mask) = tensors_from_text(&tokenizer, text)?;
+
+        let output_tensors = &crate::infer(&graph, || {
+            ctx.run(
+                [
+                    (INPUT_IDS_TENSOR_NAME, input_ids),
+                    (ATTENTION_MASK_TENSOR_NAME, attention_mask),
+                ],
+                &[OUTPUT_TENSOR_NAME],
+            )
+        })?;
+
+        let logits: &[[f32; NUM_CLASSES]; 1] =
+            crate::reshape_output(&output_tensors[OUTPUT_TENSOR_NAME])?;
+        let labels = labels::read_labels(LABELS_FILE)?;
+
+        Ok(labels::top_labels(&logits[0], &labels, TOP_K))
+    }
+}
+
+// This function converts the text into the two inputs of the model:
+// The ids of the tokens, and a mask that tells the model which of
+// them are padding (0) and which are not (1).
+fn tensors_from_text(
+    tokenizer: &Tokenizer,
+    text: &str,
+) -> Result<(Tensor<i64>, Tensor<i64>), Error> {
+    let special_token = |token| {
+        tokenizer
+            .token_id(token)
+            .ok_or_else(|| Error::internal(format!("Token {token} missing in {VOCAB_FILE}")))
+    };
+
+    // Texts that are too long are truncated, leaving room for the
+    // special tokens at the beginning and end
+    let mut input_ids = vec![special_token(BOS_TOKEN)?];
+    input_ids.extend(
+        tokenizer
+            .encode(text)
+            .into_iter()
+            .take(SEQUENCE_LEN as usize - 2),
+    );
+    input_ids.push(special_token(EOS_TOKEN)?);
+
+    let mut attention_mask = vec![1; input_ids.len()];
+    attention_mask.resize(SEQUENCE_LEN as usize, 0);
+    input_ids.resize(SEQUENCE_LEN as usize, special_token(PAD_TOKEN)?);
+
+    let dims = vec![1, SEQUENCE_LEN];
+    Ok((
+        Tensor::new(input_ids, dims.clone()),
+        Tensor::new(attention_mask, dims),
+    ))
+}
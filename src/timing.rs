@@ -0,0 +1,140 @@
+// This module reports where the time of a forecast went, so that
+// clients and gateways can attribute latency without external tracing.
+// Forecasts have a Server-Timing header with the time spent parsing the
+// window and running the model:
+//
+// Server-Timing: parse;dur=1.2, infer;dur=35.1
+//
+// With `metadata=true`, the response also has a `metadata` section with
+// the model that made the forecast (its name, if it was selected from
+// the registry, and the SHA-256 of its files), the shape of its input
+// and the time of the inference in milliseconds:
+//
+// { "result": { ... }, "metadata": { "model": "pump",
+//   "files": [{ "path": "models/model.onnx", "sha256": "9f86d0..." }],
+//   "input_tensor": "l_past_values_", "input_shape": [16, 128, 1],
+//   "inference_ms": 35.1 } }
+//
+// The inferences (there can be several for a long horizon) are recorded
+// by the model (see `infer` in lib.rs) while the forecast of a HTTP
+// request is computed, and ignored otherwise. The checksums of the
+// files are computed once (see `checksum` in model.rs).
+
+use std::{sync::Mutex, time::Duration};
+
+use serde::Serialize;
+
+use crate::model::{checksum, ModelSpec};
+
+// The timing of the running forecast
+static TIMING: Mutex<Option<Timing>> = Mutex::new(None);
+
+#[derive(Default)]
+pub struct Timing {
+    inference: Duration,
+    model: Option<Model>,
+}
+
+struct Model {
+    files: Vec<String>,
+    input_tensor: String,
+    input_shape: [u32; 3],
+}
+
+#[derive(Serialize)]
+pub struct Metadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    files: Vec<File>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input_tensor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input_shape: Option<[u32; 3]>,
+    inference_ms: f64,
+}
+
+#[derive(Serialize)]
+struct File {
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+}
+
+// Starts recording the timing of a forecast
+pub fn start() {
+    if let Ok(mut timing) = TIMING.lock() {
+        *timing = Some(Timing::default());
+    }
+}
+
+// Records the model that runs the forecast, if a forecast is running
+pub fn record_model(spec: &ModelSpec) {
+    if let Ok(mut timing) = TIMING.lock() {
+        if let Some(timing) = timing.as_mut() {
+            timing.model = Some(Model {
+                files: spec.files.clone(),
+                input_tensor: spec.input_tensor.clone(),
+                input_shape: [spec.batch_size(), spec.shape.history_len, 1],
+            });
+        }
+    }
+}
+
+// Adds an inference to the timing, if a forecast is running
+pub fn record_inference(duration: Duration) {
+    if let Ok(mut timing) = TIMING.lock() {
+        if let Some(timing) = timing.as_mut() {
+            timing.inference += duration;
+        }
+    }
+}
+
+// Stops recording and returns the timing
+pub fn finish() -> Timing {
+    TIMING
+        .lock()
+        .ok()
+        .and_then(|mut timing| timing.take())
+        .unwrap_or_default()
+}
+
+// The value of the Server-Timing header
+pub fn server_timing(parse: Duration, timing: &Timing) -> String {
+    format!(
+        "parse;dur={:.1}, infer;dur={:.1}",
+        milliseconds(parse),
+        milliseconds(timing.inference)
+    )
+}
+
+// The metadata of the forecast, with the name of the model if it was
+// selected by name
+pub fn metadata(name: Option<&str>, timing: Timing) -> Metadata {
+    let (files, input_tensor, input_shape) = match timing.model {
+        Some(model) => (
+            model.files,
+            Some(model.input_tensor),
+            Some(model.input_shape),
+        ),
+        // The model did not run, e.g. because the forecast fell back
+        // to a statistical one (see fallback.rs)
+        None => (Vec::new(), None, None),
+    };
+    Metadata {
+        model: name.map(String::from),
+        files: files
+            .into_iter()
+            .map(|path| File {
+                sha256: checksum(&path),
+                path,
+            })
+            .collect(),
+        input_tensor,
+        input_shape,
+        inference_ms: milliseconds(timing.inference),
+    }
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,176 @@
+// A byte-level BPE tokenizer as used by GPT-2 and RoBERTa style
+// models. It is loaded from the usual `vocab.json` (token to id) and
+// `merges.txt` (one merge rule per line, ordered by priority) files
+// that come with these models.
+
+use std::collections::HashMap;
+
+use crate::error::Error;
+
+pub struct Tokenizer {
+    vocab: HashMap<String, i64>,
+    // The inverse of the vocabulary, only needed to decode tokens
+    #[cfg(feature = "generate")]
+    tokens: HashMap<i64, String>,
+    // The rank of each merge rule, lower ranks are merged first
+    merges: HashMap<(String, String), usize>,
+    // Maps each byte to the printable character that represents it in
+    // the vocabulary
+    byte_chars: [char; 256],
+}
+
+impl Tokenizer {
+    pub fn from_files(vocab_file: &str, merges_file: &str) -> Result<Self, Error> {
+        let read = |file: &str| {
+            std::fs::read_to_string(file)
+                .map_err(|e| Error::internal(format!("Error reading {file}: {e}")))
+        };
+
+        let vocab: HashMap<String, i64> = serde_json::from_str(&read(vocab_file)?)
+            .map_err(|e| Error::internal(format!("Invalid vocabulary {vocab_file}: {e}")))?;
+
+        let merges = read(merges_file)?
+            .lines()
+            // The first line of merges.txt is usually a version comment
+            .filter(|line| !line.starts_with("#version"))
+            .filter_map(|line| line.split_once(' '))
+            .enumerate()
+            .map(|(rank, (a, b))| ((a.to_string(), b.to_string()), rank))
+            .collect();
+
+        Ok(Self {
+            #[cfg(feature = "generate")]
+            tokens: vocab
+                .iter()
+                .map(|(token, id)| (*id, token.clone()))
+                .collect(),
+            vocab,
+            merges,
+            byte_chars: byte_chars(),
+        })
+    }
+
+    // Returns the id of a token of the vocabulary, e.g. a special
+    // token like "<s>"
+    pub fn token_id(&self, token: &str) -> Option<i64> {
+        self.vocab.get(token).copied()
+    }
+
+    // Splits the text into tokens and returns their ids. Special
+    // tokens are not added.
+    pub fn encode(&self, text: &str) -> Vec<i64> {
+        pre_tokenize(text)
+            .into_iter()
+            .flat_map(|word| {
+                let symbols = word
+                    .bytes()
+                    .map(|byte| self.byte_chars[usize::from(byte)].to_string())
+                    .collect();
+                self.merge(symbols)
+            })
+            .filter_map(|token| self.token_id(&token).or_else(|| self.token_id("<unk>")))
+            .collect()
+    }
+
+    // Returns the bytes of the text represented by a token. Note that
+    // a token can end in the middle of a multi-byte UTF-8 character.
+    #[cfg(feature = "generate")]
+    pub fn decode(&self, id: i64) -> Vec<u8> {
+        self.tokens
+            .get(&id)
+            .into_iter()
+            .flat_map(|token| token.chars())
+            .filter_map(|c| self.byte_chars.iter().position(|&byte_char| byte_char == c))
+            .map(|byte| byte as u8)
+            .collect()
+    }
+
+    // Repeatedly merges the adjacent pair of symbols with the lowest
+    // rank, until no merge rule applies anymore
+    fn merge(&self, mut symbols: Vec<String>) -> Vec<String> {
+        loop {
+            let best = symbols
+                .windows(2)
+                .enumerate()
+                .filter_map(|(i, pair)| {
+                    self.merges
+                        .get(&(pair[0].clone(), pair[1].clone()))
+                        .map(|rank| (rank, i))
+                })
+                .min();
+
+            let Some((_, i)) = best else {
+                return symbols;
+            };
+            let second = symbols.remove(i + 1);
+            symbols[i].push_str(&second);
+        }
+    }
+}
+
+// Splits the text into words, numbers and runs of punctuation. Like in
+// GPT-2, a single space before a word belongs to that word. This is a
+// simplification of the regular expression used by GPT-2, which e.g.
+// also splits off contractions like "'s".
+fn pre_tokenize(text: &str) -> Vec<&str> {
+    #[derive(PartialEq)]
+    enum Class {
+        Letter,
+        Number,
+        Other,
+    }
+    let class = |c: char| match c {
+        c if c.is_alphabetic() => Class::Letter,
+        c if c.is_numeric() => Class::Number,
+        _ => Class::Other,
+    };
+
+    let chars: Vec<_> = text.char_indices().collect();
+    let is_space = |i: usize| chars.get(i).is_some_and(|(_, c)| c.is_whitespace());
+    let starts_word = |i: usize| chars.get(i).is_some_and(|(_, c)| *c == ' ') && !is_space(i + 1);
+
+    let mut words = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        if starts_word(i) && i + 1 < chars.len() {
+            i += 1;
+        }
+        if !is_space(i) {
+            let word_class = class(chars[i].1);
+            while i < chars.len() && !is_space(i) && class(chars[i].1) == word_class {
+                i += 1;
+            }
+        } else {
+            // Whitespace, except for a last space that belongs to the
+            // next word
+            while is_space(i) && !(starts_word(i) && i + 1 < chars.len()) {
+                i += 1;
+            }
+            i = i.max(start + 1);
+        }
+        let end = chars.get(i).map_or(text.len(), |(offset, _)| *offset);
+        words.push(&text[chars[start].0..end]);
+    }
+    words
+}
+
+// The byte-level BPE of GPT-2 does not work on the bytes directly, but
+// maps each byte to a printable unicode character: The printable ASCII
+// and Latin-1 characters represent themselves, all other bytes are
+// mapped to the characters starting at U+0100.
+fn byte_chars() -> [char; 256] {
+    let printable = |byte: u8| matches!(byte, b'!'..=b'~' | 0xa1..=0xac | 0xae..=0xff);
+
+    let mut chars = ['\0'; 256];
+    let mut next = 256;
+    for byte in 0..=255u8 {
+        chars[usize::from(byte)] = if printable(byte) {
+            char::from(byte)
+        } else {
+            next += 1;
+            char::from_u32(next - 1).unwrap_or(char::REPLACEMENT_CHARACTER)
+        };
+    }
+    chars
+}
@@ -0,0 +1,229 @@
+// This module records how the window of a forecast was preprocessed
+// before the model saw it, to answer "why did it predict that?" for a
+// request after the fact. Every forecast over HTTP is traced under the
+// id of the request, which is taken from the X-Request-Id header or
+// generated, and returned in the X-Request-Id header of the response.
+// The trace can then be retrieved with `GET /traces/{request_id}`:
+//
+// { "request_id": "5f0c3a1e9b7d2c44", "recorded_at": "2024-05-01T12:00:03Z",
+//   "decisions": [
+//     { "step": "dropped", "keys": ["Input7"] },
+//     { "step": "resampled", "cadence_seconds": 60.0, "method": "linear", ... },
+//     { "step": "fitted", "values": 120, "adjustment": "padded with 8 zeros ..." }] }
+//
+// The stages that make a decision (see lib.rs, resample.rs, pipeline.rs
+// and fallback.rs) record it while a trace is running, i.e. while the
+// forecast of a HTTP request is computed, and do nothing otherwise.
+// Traces are kept in the state directory (see state.rs) for RETENTION
+// only, since they are meant for looking into a recent forecast, not
+// as an audit log.
+
+use std::{collections::BTreeMap, sync::Mutex};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use wasi::random::random::get_random_u64;
+
+use crate::{
+    clock::{self, Clock},
+    error::Error,
+    state,
+};
+
+const STATE_FILE: &str = "traces.json";
+const RETENTION: TimeDelta = TimeDelta::minutes(15);
+// The oldest traces are dropped beyond this, so that a busy device does
+// not fill its storage within the retention
+const MAX_TRACES: usize = 1000;
+const MAX_ID_LEN: usize = 128;
+
+// The decisions recorded for the running trace, if any
+static TRACE: Mutex<Option<Vec<Decision>>> = Mutex::new(None);
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum Decision {
+    // Data points whose values are not numbers were dropped from the
+    // series
+    Dropped {
+        keys: Vec<String>,
+    },
+    // The window was resampled to its cadence, which imputed the
+    // values in the given ranges
+    Resampled {
+        cadence_seconds: f64,
+        method: String,
+        points: usize,
+        values: usize,
+        imputed: Vec<Range>,
+    },
+    // The series was passed through the pipeline (see pipeline.rs)
+    Pipeline {
+        stages: Vec<String>,
+    },
+    // The series was padded or truncated to the length of the history
+    Fitted {
+        values: usize,
+        adjustment: String,
+    },
+    // The model failed and the statistical forecast was returned
+    Fallback {
+        reason: String,
+        method: String,
+    },
+}
+
+// The timestamps of the first and last value of a range
+#[derive(Serialize, Deserialize)]
+pub struct Range {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Trace {
+    request_id: String,
+    recorded_at: DateTime<Utc>,
+    decisions: Vec<Decision>,
+}
+
+// The id of the request, either the one chosen by the client or a new
+// random one
+pub fn request_id(header: Option<&str>) -> Result<String, Error> {
+    match header.map(str::trim) {
+        Some(id) if (1..=MAX_ID_LEN).contains(&id.len()) && !id.contains('/') => Ok(id.into()),
+        Some(_) => Err(Error::BadRequest("Invalid X-Request-Id header".into())),
+        None => Ok(format!("{:016x}", get_random_u64())),
+    }
+}
+
+// Starts recording decisions, discarding those of an earlier trace
+pub fn start() {
+    if let Ok(mut trace) = TRACE.lock() {
+        *trace = Some(Vec::new());
+    }
+}
+
+// Records the decision if a trace is running
+pub fn record(decision: Decision) {
+    if let Ok(mut trace) = TRACE.lock() {
+        if let Some(decisions) = trace.as_mut() {
+            decisions.push(decision);
+        }
+    }
+}
+
+// Stops recording and stores the trace under the id of the request.
+// The forecast has already been made at this point, so errors are only
+// logged.
+pub fn finish(request_id: &str) {
+    let Some(decisions) = TRACE.lock().ok().and_then(|mut trace| trace.take()) else {
+        return;
+    };
+    if let Err(e) = store(request_id, decisions) {
+        eprintln!("Error storing the trace of request {request_id}: {e}");
+    }
+}
+
+fn store(request_id: &str, decisions: Vec<Decision>) -> Result<(), Error> {
+    let mut traces: BTreeMap<String, Trace> = state::load(STATE_FILE)?;
+    insert(&mut traces, request_id, decisions, clock::clock());
+    state::save(STATE_FILE, &traces)
+}
+
+// Adds a trace, dropping the expired traces and, beyond MAX_TRACES, the
+// oldest ones
+fn insert(
+    traces: &mut BTreeMap<String, Trace>,
+    request_id: &str,
+    decisions: Vec<Decision>,
+    clock: &dyn Clock,
+) {
+    traces.retain(|_, trace| is_retained(trace, clock));
+    while traces.len() >= MAX_TRACES {
+        let oldest = traces
+            .iter()
+            .min_by_key(|(_, trace)| trace.recorded_at)
+            .map(|(id, _)| id.clone());
+        traces.remove(&oldest.unwrap_or_default());
+    }
+    traces.insert(
+        request_id.into(),
+        Trace {
+            request_id: request_id.into(),
+            recorded_at: clock.now(),
+            decisions,
+        },
+    );
+}
+
+fn is_retained(trace: &Trace, clock: &dyn Clock) -> bool {
+    clock.now() - trace.recorded_at <= RETENTION
+}
+
+// The trace of the request with the id in the path `/traces/{id}`
+pub fn get(path: &str) -> Result<Trace, Error> {
+    let request_id = path.strip_prefix("/traces/").unwrap_or_default();
+    let mut traces: BTreeMap<String, Trace> = state::load(STATE_FILE)?;
+    traces
+        .remove(request_id)
+        .filter(|trace| is_retained(trace, clock::clock()))
+        .ok_or_else(|| Error::NotFound(format!("No trace of request {request_id}")))
+}
+
+pub fn trace_to_vec(trace: &Trace) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(trace).map_err(|e| Error::internal(format!("Error serializing trace: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+
+    fn at(time: &str) -> FixedClock {
+        FixedClock(
+            DateTime::parse_from_rfc3339(time)
+                .unwrap()
+                .with_timezone(&Utc),
+        )
+    }
+
+    fn pipeline() -> Vec<Decision> {
+        vec![Decision::Pipeline {
+            stages: vec!["detrend".into()],
+        }]
+    }
+
+    #[test]
+    fn keeps_traces_for_the_retention() {
+        let mut traces = BTreeMap::new();
+        insert(&mut traces, "a", pipeline(), &at("2024-05-01T12:00:00Z"));
+        let trace = &traces["a"];
+        assert_eq!(trace.recorded_at, at("2024-05-01T12:00:00Z").0);
+        assert!(is_retained(trace, &at("2024-05-01T12:15:00Z")));
+        assert!(!is_retained(trace, &at("2024-05-01T12:15:01Z")));
+    }
+
+    #[test]
+    fn drops_expired_traces() {
+        let mut traces = BTreeMap::new();
+        insert(&mut traces, "a", pipeline(), &at("2024-05-01T12:00:00Z"));
+        insert(&mut traces, "b", pipeline(), &at("2024-05-01T12:10:00Z"));
+        insert(&mut traces, "c", pipeline(), &at("2024-05-01T12:20:00Z"));
+        assert_eq!(traces.keys().collect::<Vec<_>>(), ["b", "c"]);
+    }
+
+    #[test]
+    fn drops_the_oldest_traces_beyond_the_limit() {
+        let mut traces = BTreeMap::new();
+        let clock = at("2024-05-01T12:00:00Z");
+        for i in 0..MAX_TRACES {
+            let clock = FixedClock(clock.0 + TimeDelta::milliseconds(i as i64));
+            insert(&mut traces, &format!("{i:04}"), Vec::new(), &clock);
+        }
+        insert(&mut traces, "new", pipeline(), &at("2024-05-01T12:01:00Z"));
+        assert_eq!(traces.len(), MAX_TRACES);
+        assert!(!traces.contains_key("0000"));
+        assert!(traces.contains_key("0001") && traces.contains_key("new"));
+    }
+}
@@ -0,0 +1,155 @@
+// This module lets the component take part in distributed traces at the
+// edge, following W3C Trace Context. The `traceparent` header of a
+// request names the trace and the span of the caller:
+//
+// traceparent: 00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01
+//
+// Each request is a span of its own, with a random span id in the trace
+// of the caller, or in a new trace if the request has no valid
+// `traceparent`. The span is returned in the `traceparent` header of the
+// response, added to the log lines (see logging.rs) and passed on as
+// the parent of the requests the component sends while handling it
+// (see outgoing.rs), together with the `tracestate` of the caller.
+
+use std::sync::Mutex;
+
+use wasi::random::random::get_random_u64;
+
+// The span of the request being handled, if any
+static SPAN: Mutex<Option<Span>> = Mutex::new(None);
+
+#[derive(Clone)]
+struct Span {
+    trace_id: String,
+    span_id: String,
+    flags: String,
+    // Only passed on to outgoing requests (see outgoing.rs)
+    #[cfg_attr(
+        not(any(
+            feature = "alerts",
+            feature = "forward",
+            feature = "homeassistant",
+            feature = "pushgateway",
+            feature = "s3",
+            feature = "schedule",
+            feature = "trigger"
+        )),
+        allow(dead_code)
+    )]
+    state: Option<String>,
+}
+
+// Starts the span of a request with the given headers
+pub fn start(headers: &[(String, Vec<u8>)]) {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .and_then(|(_, value)| std::str::from_utf8(value).ok())
+    };
+    let span = match header("traceparent").and_then(parse) {
+        Some((trace_id, flags)) => Span {
+            trace_id,
+            span_id: random_id(1),
+            flags,
+            state: header("tracestate").map(String::from),
+        },
+        None => Span {
+            trace_id: random_id(2),
+            span_id: random_id(1),
+            flags: "00".into(),
+            state: None,
+        },
+    };
+    if let Ok(mut current) = SPAN.lock() {
+        *current = Some(span);
+    }
+}
+
+// Ends the span of the request
+pub fn finish() {
+    if let Ok(mut current) = SPAN.lock() {
+        *current = None;
+    }
+}
+
+// The `traceparent` of the span, for the response
+pub fn traceparent() -> Option<String> {
+    current().map(|span| traceparent_of(&span))
+}
+
+// The headers that make the span the parent of an outgoing request
+#[cfg_attr(
+    not(any(
+        feature = "alerts",
+        feature = "forward",
+        feature = "homeassistant",
+        feature = "pushgateway",
+        feature = "s3",
+        feature = "schedule",
+        feature = "trigger"
+    )),
+    allow(dead_code)
+)]
+pub fn headers() -> Vec<(String, Vec<u8>)> {
+    let Some(span) = current() else {
+        return Vec::new();
+    };
+    let mut headers = vec![("traceparent".into(), traceparent_of(&span).into_bytes())];
+    if let Some(state) = span.state {
+        headers.push(("tracestate".into(), state.into_bytes()));
+    }
+    headers
+}
+
+// The trace and span id, for the log
+#[cfg_attr(not(feature = "logging"), allow(dead_code))]
+pub fn ids() -> Option<(String, String)> {
+    current().map(|span| (span.trace_id, span.span_id))
+}
+
+fn current() -> Option<Span> {
+    SPAN.lock().ok()?.clone()
+}
+
+fn traceparent_of(span: &Span) -> String {
+    format!("00-{}-{}-{}", span.trace_id, span.span_id, span.flags)
+}
+
+// The trace id and flags of a `traceparent` header, if it is valid.
+// Later versions of the format may append fields, which are ignored.
+fn parse(traceparent: &str) -> Option<(String, String)> {
+    let mut fields = traceparent.trim().split('-');
+    let version = fields.next()?;
+    let trace_id = fields.next()?;
+    let parent_id = fields.next()?;
+    let flags = fields.next()?;
+    let valid = is_hex(version, 2)
+        && version != "ff"
+        && (version != "00" || fields.next().is_none())
+        && is_hex(trace_id, 32)
+        && is_hex(parent_id, 16)
+        && is_hex(flags, 2)
+        // All-zero ids are invalid
+        && trace_id.bytes().any(|byte| byte != b'0')
+        && parent_id.bytes().any(|byte| byte != b'0');
+    valid.then(|| (trace_id.to_string(), flags.to_string()))
+}
+
+fn is_hex(field: &str, len: usize) -> bool {
+    field.len() == len
+        && field
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+}
+
+// A random id of the given number of 8-byte words in lowercase hex,
+// which is never all zeros
+fn random_id(words: usize) -> String {
+    loop {
+        let words: Vec<_> = (0..words).map(|_| get_random_u64()).collect();
+        if words.iter().any(|&word| word != 0) {
+            return words.iter().map(|word| format!("{word:016x}")).collect();
+        }
+    }
+}
@@ -0,0 +1,90 @@
+// This module forecasts ingested series automatically (see ingest.rs),
+// so that consumers of `GET /forecast/{series}` (see latest.rs) get
+// fresh forecasts without anyone requesting them. Once a series has
+// enough data points, it is forecast whenever it gained `every` new
+// ones since its last forecast, right after they are ingested. The rule
+// is read from the `trigger` config (see config.rs):
+//
+// { "every": 12, "webhook": "http://dashboard.local/forecasts" }
+//
+// The forecast is stored as the latest one of the series and, with a
+// webhook, posted to it in the format of `GET /forecast/{series}`. The
+// response to the ingestion tells whether the series was forecast. A
+// forecast that fails does not fail the ingestion, it is only logged
+// and tried again with the next one. Without the config, series are
+// only forecast on request.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::{config, error::Error, json, latest, outgoing, state, with_handler};
+
+const CONFIG: &str = "trigger";
+
+// The number of data points each series gained since its last forecast
+const STATE_FILE: &str = "trigger.json";
+
+#[derive(Deserialize)]
+struct Trigger {
+    every: usize,
+    webhook: Option<String>,
+}
+
+// Counts the data points that were added to the series, and forecasts
+// it if it is ready and gained enough of them. Returns whether it was
+// forecast.
+pub fn ingested(series: &str, added: usize, ready: bool) -> Result<bool, Error> {
+    let Some(trigger) = config::load::<Trigger>(CONFIG)? else {
+        return Ok(false);
+    };
+    // The counts are updated under the lock of the state, so that
+    // concurrent requests do not lose each other's points. A series
+    // that is due is taken out of it, so that only one of them
+    // forecasts it.
+    let due = state::update(STATE_FILE, |gained: &mut BTreeMap<String, usize>| {
+        let count = gained.entry(series.to_string()).or_default();
+        *count += added;
+        Ok(if ready && *count >= trigger.every.max(1) {
+            gained.remove(series)
+        } else {
+            None
+        })
+    })?;
+    let Some(count) = due else {
+        return Ok(false);
+    };
+
+    let forecast = forecast(series, trigger.webhook.as_deref())
+        .inspect_err(|e| eprintln!("Error forecasting {series} automatically: {e}"))
+        .is_ok();
+    if !forecast {
+        // The points still count towards the next attempt
+        state::update(STATE_FILE, |gained: &mut BTreeMap<String, usize>| {
+            *gained.entry(series.to_string()).or_default() += count;
+            Ok(())
+        })?;
+    }
+    Ok(forecast)
+}
+
+fn forecast(series: &str, webhook: Option<&str>) -> Result<(), Error> {
+    latest::start();
+    let result = with_handler(|handler| handler.forecast_ingested(None, series))?;
+    latest::store(series, None, &json::inference_result_to_vec(&result)?);
+
+    let Some(webhook) = webhook else {
+        return Ok(());
+    };
+    // The forecast has been stored at this point, so failed
+    // notifications are only logged
+    let result = latest::of(series)
+        .and_then(|latest| latest::latest_to_vec(&latest))
+        .and_then(|body| outgoing::post(webhook, "application/json", &body));
+    match result {
+        Ok(status) if (200..300).contains(&status) => {}
+        Ok(status) => eprintln!("Webhook of the forecast of {series} responded with {status}"),
+        Err(e) => eprintln!("Error notifying the webhook of the forecast of {series}: {e}"),
+    }
+    Ok(())
+}
@@ -0,0 +1,111 @@
+// This module accounts the usage of the component per client, so that
+// the cost of a shared device can be charged back to the teams using
+// it. Each authenticated client (see http/auth.rs) is accounted by its
+// identity with the number of its requests, the time the models spent
+// on them and the number of data points in the windows it sent. The
+// counters are kept in the state directory (see state.rs) and listed
+// with `GET /admin/usage`:
+//
+// { "line-3": { "requests": 1440, "inference_seconds": 51.2,
+//               "data_points": 184320, "last_request": "2024-05-01T12:00:00Z" } }
+//
+// The request being handled is accounted while it runs, like a trace
+// (see trace.rs), and its counters are added to those of the client
+// once the response has been sent, also if the request failed. The
+// counters are updated under a lock of the state, so that requests
+// that finish at the same time in other instances are not lost.
+
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{clock, error::Error, state};
+
+const STATE_FILE: &str = "usage.json";
+
+// The usage of the request being handled, if any
+static CURRENT: Mutex<Option<Current>> = Mutex::new(None);
+
+struct Current {
+    subject: String,
+    inference: Duration,
+    data_points: usize,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Usage {
+    requests: u64,
+    inference_seconds: f64,
+    data_points: u64,
+    last_request: Option<DateTime<Utc>>,
+}
+
+pub type UsageBySubject = BTreeMap<String, Usage>;
+
+// Starts accounting a request of the client
+pub fn start(subject: &str) {
+    if let Ok(mut current) = CURRENT.lock() {
+        *current = Some(Current {
+            subject: subject.to_string(),
+            inference: Duration::ZERO,
+            data_points: 0,
+        });
+    }
+}
+
+// Adds the time of an inference to the request being accounted
+pub fn record_inference(latency: Duration) {
+    update_current(|current| current.inference += latency);
+}
+
+// Adds the data points of a window to the request being accounted
+pub fn record_data_points(count: usize) {
+    update_current(|current| current.data_points += count);
+}
+
+fn update_current(f: impl FnOnce(&mut Current)) {
+    if let Ok(mut current) = CURRENT.lock() {
+        if let Some(current) = current.as_mut() {
+            f(current);
+        }
+    }
+}
+
+// Stops accounting the request and adds it to the usage of its client.
+// The response has been sent at this point, so errors are only logged.
+pub fn finish() {
+    let Some(current) = CURRENT.lock().ok().and_then(|mut current| current.take()) else {
+        return;
+    };
+    let result = state::update(STATE_FILE, |usage: &mut UsageBySubject| {
+        let entry = usage.entry(current.subject).or_default();
+        entry.requests += 1;
+        entry.inference_seconds += current.inference.as_secs_f64();
+        entry.data_points += current.data_points as u64;
+        entry.last_request = Some(clock::now());
+        Ok(())
+    });
+    if let Err(e) = result {
+        eprintln!("Error accounting usage: {e}");
+    }
+}
+
+// The usage of all clients, for `GET /admin/usage`
+pub fn usage() -> Result<UsageBySubject, Error> {
+    state::load(STATE_FILE)
+}
+
+// Deletes the usage of clients without a request since the cutoff (see
+// retention.rs)
+#[cfg(feature = "retention")]
+pub fn expire(cutoff: DateTime<Utc>) -> Result<(), Error> {
+    state::update(STATE_FILE, |usage: &mut UsageBySubject| {
+        usage.retain(|_, usage| usage.last_request.is_some_and(|last| last >= cutoff));
+        Ok(())
+    })
+}
+
+pub fn usage_to_vec(usage: &UsageBySubject) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(usage).map_err(|e| Error::internal(format!("Error serializing usage: {e}")))
+}
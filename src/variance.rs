@@ -0,0 +1,38 @@
+// This module returns the variance of a forecast for models that have a
+// second output next to the forecast itself, e.g. models trained with a
+// Gaussian likelihood that predict a mean and a variance for each step.
+// The output is named in `variance_tensor` (see model.rs), and its
+// values are returned next to the forecast of a HTTP request:
+//
+// { "result": { ... }, "variance": [0.8, 0.9, 1.1, ...] }
+//
+// The variance is recorded by the inference (see `model_forecast` in
+// lib.rs) while the forecast of a HTTP request is computed, and ignored
+// otherwise. Models without a variance output return the forecast
+// only.
+
+use std::sync::Mutex;
+
+// The variance of the running forecast, once the model returned it
+static VARIANCE: Mutex<Option<Option<Vec<f32>>>> = Mutex::new(None);
+
+// Starts recording the variance of a forecast
+pub fn start() {
+    if let Ok(mut variance) = VARIANCE.lock() {
+        *variance = Some(None);
+    }
+}
+
+// Records the variance if a forecast is running
+pub fn record(values: Vec<f32>) {
+    if let Ok(mut variance) = VARIANCE.lock() {
+        if let Some(variance) = variance.as_mut() {
+            *variance = Some(values);
+        }
+    }
+}
+
+// Stops recording and returns the variance, if the model returned one
+pub fn finish() -> Option<Vec<f32>> {
+    VARIANCE.lock().ok()?.take().flatten()
+}
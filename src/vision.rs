@@ -0,0 +1,85 @@
+// This module adds a second kind of model to the component: An image
+// classification model (e.g. a ResNet or MobileNet exported to ONNX).
+// It is used instead of the forecasting model when the request body
+// is a JPEG or PNG image instead of a JSON data window.
+
+use crate::{
+    error::Error,
+    labels::{self, Label},
+    nn::{GraphEncoding, Tensor},
+    HttpHandler,
+};
+use image::imageops::FilterType;
+
+// These constants are the parameters that are specific to the vision
+// model. No such model is included in this repository, place your own
+// model and its labels (one per line, in the order of the model
+// output) in the models directory.
+const MODEL_FORMAT: GraphEncoding = GraphEncoding::Onnx;
+const MODEL_FILES: [&str; 1] = ["models/vision.onnx"];
+const LABELS_FILE: &str = "models/labels.txt";
+// The labels of the input and output tensors in the model
+const INPUT_TENSOR_NAME: &str = "input";
+const OUTPUT_TENSOR_NAME: &str = "output";
+// The images are resized to this size (in pixels) ...
+const IMAGE_HEIGHT: u32 = 224;
+const IMAGE_WIDTH: u32 = 224;
+// ... and each color channel (R, G, B) is normalized using this mean
+// and standard deviation after scaling it to [0, 1]. These are the
+// values commonly used by models trained on ImageNet.
+const CHANNEL_MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+const CHANNEL_STD: [f32; 3] = [0.229, 0.224, 0.225];
+// The number of classes the model distinguishes, and the number of
+// most likely ones that we return
+const NUM_CLASSES: usize = 1000;
+const TOP_K: usize = 5;
+
+impl HttpHandler {
+    // This is the equivalent of `handle_data` for images
+    pub fn classify_image(&mut self, image: &[u8]) -> Result<Vec<Label>, Error> {
+        let graph = crate::load_model(MODEL_FORMAT, &MODEL_FILES)?;
+        let ctx = graph.init_execution_context()?;
+
+        let input_tensor = tensor_from_image(image)?;
+
+        let output_tensors = &crate::infer(&graph, || {
+            ctx.run([(INPUT_TENSOR_NAME, input_tensor)], &[OUTPUT_TENSOR_NAME])
+        })?;
+
+        labels_from_tensor(&output_tensors[OUTPUT_TENSOR_NAME])
+    }
+}
+
+// This function decodes the image (the format is detected
+// automatically), and converts it to a tensor of shape 1 x 3 x H x W
+// (a batch of one image with three color channels), which is the
+// layout expected by most vision models.
+fn tensor_from_image(image: &[u8]) -> Result<Tensor<f32>, Error> {
+    let image = image::load_from_memory(image)
+        .map_err(|e| Error::BadRequest(format!("Invalid image: {e}")))?
+        .resize_exact(IMAGE_WIDTH, IMAGE_HEIGHT, FilterType::Triangle)
+        .to_rgb8();
+
+    // The pixels of the decoded image are interleaved (RGBRGB...), but
+    // the model wants all values of a channel next to each other.
+    let plane_size = (IMAGE_HEIGHT * IMAGE_WIDTH) as usize;
+    let mut data = vec![0f32; 3 * plane_size];
+    for (i, pixel) in image.pixels().enumerate() {
+        for channel in 0..3 {
+            let value = f32::from(pixel[channel]) / 255.0;
+            data[channel * plane_size + i] = (value - CHANNEL_MEAN[channel]) / CHANNEL_STD[channel];
+        }
+    }
+
+    Ok(Tensor::new(data, vec![1, 3, IMAGE_HEIGHT, IMAGE_WIDTH]))
+}
+
+// This function turns the raw scores of the model into the most
+// likely labels
+fn labels_from_tensor(tensor: &Tensor<f32>) -> Result<Vec<Label>, Error> {
+    let logits: &[[f32; NUM_CLASSES]; 1] = crate::reshape_output(tensor)?;
+
+    let labels = labels::read_labels(LABELS_FILE)?;
+
+    Ok(labels::top_labels(&logits[0], &labels, TOP_K))
+}